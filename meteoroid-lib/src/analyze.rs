@@ -1,47 +1,570 @@
+pub(crate) mod agreement;
+mod config_bisect;
+mod formatted_output;
 pub(crate) mod report;
-mod similarity;
+pub(crate) mod similarity;
+mod whitespace;
 
-use crate::analyze::report::{CrateAnalysis, DivergingDiff, RustfmtAnalysis};
-use crate::cmd::{RustFmtBuildOutputs, RustfmtOutput, run_rustfmt};
+use crate::analyze::agreement::OutcomeKey;
+use crate::analyze::report::{
+    CrateAnalysis, DivergingDiff, OutputLayout, ReportFilter, ReportFormat, ReportSort,
+    RustfmtAnalysis, diff_by_file,
+};
+use crate::analyze::similarity::ErrorComparator;
+use crate::cmd::{
+    CargoCommand, CheckOutput, EnvironmentalFailure, RustFmtBuildOutputs, RustfmtPanic,
+    RustfmtTimeout, run_rustfmt,
+};
+use crate::fs::{
+    find_rs_files, has_rustfmt_config, read_member_edition, read_root_edition, read_virtual_members,
+};
 use crate::git::CrateReadyForAnalysis;
+use crate::unpack;
+use anyhow::{Context, bail};
+use cargo_toml::Edition;
 use dashmap::DashSet;
 use rustc_hash::FxBuildHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct AnalyzeArgs {
     pub rustfmt_repo: PathBuf,
     pub rustfmt_upstream_repo: PathBuf,
     pub report_dest: Option<PathBuf>,
+    /// Which reports to write when finished, `report_dest` applies to the `Json` report
+    pub report_formats: Vec<ReportFormat>,
+    /// Only include crates matching any of these in the reports, all crates if empty
+    pub report_filters: Vec<ReportFilter>,
+    pub report_sort: ReportSort,
     pub config: Option<String>,
     pub write_outputs: bool,
     pub skip_non_diverging_diffs: bool,
+    /// How many times to re-run `rustfmt` on diverging crates,
+    /// if any re-run differs from the first the crate is marked as flaky
+    pub recheck_divergences: usize,
+    /// For diverging crates, search for the smallest subset of the options in `config`
+    /// that still diverges
+    pub bisect_config: bool,
+    /// Copy the files that diverged for each diverging crate to the output dir,
+    /// with instructions on how to reproduce
+    pub write_repros: bool,
     pub diff_tool: Option<PathBuf>,
+    /// Open the html report in the default browser when done
+    pub open_report: bool,
+    /// More `rustfmt` repos to compare with local and upstream, the report shows which
+    /// builds agree on each crate
+    pub extra_rustfmt_repos: Vec<LabeledRustfmtRepo>,
+    /// When the `--check` diffs match, also format each file with both builds and compare
+    /// the outputs, slower but catches divergences the diffs hide
+    pub compare_formatted_output: bool,
+    /// Leave `.rs` files larger than this out when running `rustfmt` on files directly
+    pub max_file_bytes: Option<u64>,
+    /// Pass `--unstable-features` to `rustfmt`, needed for unstable config options
+    pub unstable_features: bool,
+    /// Leave out `config` for crates that have their own `rustfmt.toml`
+    pub respect_crate_config: bool,
+    /// Don't count divergences that only change trailing whitespace or blank lines, such as
+    /// a final newline, the diffs are still written as they are
+    pub ignore_whitespace_diffs: bool,
+    /// How many times to redo the analysis of a crate when `rustfmt` fails on it for what looks
+    /// like an environmental reason, ex: a held lock or a full disk, rather than a bug
+    pub analysis_retries: usize,
+    /// Keep a checkpoint in the output dir while running, and resume from it if one was
+    /// left by a run that didn't finish
+    pub checkpoint: bool,
+    pub output_layout: OutputLayout,
+    /// Run these `rustfmt` binaries instead of building `rustfmt_repo` and
+    /// `rustfmt_upstream_repo`
+    pub prebuilt_rustfmt: Option<PrebuiltRustfmt>,
+    /// Decides which crates failed with similar errors on both sides,
+    /// `LevenshteinComparator` unless something else is needed
+    pub error_comparator: Arc<dyn ErrorComparator>,
+    /// Used both to build `rustfmt` and to run `cargo fmt`
+    pub cargo: CargoCommand,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrebuiltRustfmt {
+    pub local: PathBuf,
+    pub upstream: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabeledRustfmtRepo {
+    /// Shown in the report, can't be `local` or `upstream`
+    pub label: String,
+    pub repo: PathBuf,
+}
+
+pub(crate) struct LabeledBuild {
+    pub(crate) label: String,
+    pub(crate) build: RustFmtBuildOutputs,
+}
+
+/// How `rustfmt` is run on a crate
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct FmtOpts<'a> {
+    pub(crate) config: Option<&'a str>,
+    pub(crate) timeout: Duration,
+    /// Files larger than this are left out when `rustfmt` is run on files directly
+    pub(crate) max_file_bytes: Option<u64>,
+    pub(crate) unstable_features: bool,
+    pub(crate) cargo: &'a CargoCommand,
+}
+
+/// Grows the analysis timeout with the size of the published crate,
+/// crates without a known size get the base timeout
+#[derive(Debug, Copy, Clone)]
+pub struct TimeoutScaling {
+    /// Added to the base timeout for every MiB of the published `.crate`
+    pub per_mib: Duration,
+    /// The scaled timeout never goes above this, or the base timeout if it's larger
+    pub max: Duration,
+}
+
+impl TimeoutScaling {
+    pub(crate) fn timeout_for(&self, base: Duration, crate_size: u64) -> Duration {
+        #[allow(clippy::cast_precision_loss)]
+        let mib = crate_size as f64 / (1024.0 * 1024.0);
+        (base + self.per_mib.mul_f64(mib)).min(self.max.max(base))
+    }
+}
+
+/// Settings shared by every crate analysis in a run
+#[allow(clippy::struct_excessive_bools)]
+pub(crate) struct AnalysisSettings {
+    pub(crate) config: Option<String>,
+    pub(crate) timeout: Duration,
+    pub(crate) timeout_scaling: Option<TimeoutScaling>,
+    pub(crate) recheck_divergences: usize,
+    pub(crate) bisect_config: bool,
+    pub(crate) compare_formatted_output: bool,
+    pub(crate) max_file_bytes: Option<u64>,
+    pub(crate) unstable_features: bool,
+    pub(crate) respect_crate_config: bool,
+    pub(crate) ignore_whitespace_diffs: bool,
+    pub(crate) analysis_retries: usize,
+    pub(crate) cargo: CargoCommand,
+    pub(crate) extra_builds: Vec<LabeledBuild>,
+}
+
+impl AnalysisSettings {
+    pub(crate) fn fmt_opts(&self) -> FmtOpts<'_> {
+        FmtOpts {
+            config: self.config.as_deref(),
+            timeout: self.timeout,
+            max_file_bytes: self.max_file_bytes,
+            unstable_features: self.unstable_features,
+            cargo: &self.cargo,
+        }
+    }
+
+    /// Like `fmt_opts`, with the timeout scaled to the crate's size if configured
+    fn fmt_opts_for(&self, target: &CrateReadyForAnalysis) -> FmtOpts<'_> {
+        let opts = self.fmt_opts();
+        let (Some(scaling), Some(crate_size)) =
+            (self.timeout_scaling, target.pruned_crate.crate_size)
+        else {
+            return opts;
+        };
+        let timeout = scaling.timeout_for(self.timeout, crate_size);
+        tracing::trace!(
+            "using a timeout of {timeout:?} for '{}' at {crate_size} bytes",
+            target.pruned_crate.crate_name
+        );
+        FmtOpts { timeout, ..opts }
+    }
 }
 
-#[allow(clippy::too_many_lines)]
 pub(crate) async fn analyze_crate(
     target: &CrateReadyForAnalysis,
     rustfmt_build_outputs: &RustFmtBuildOutputs,
     upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
-    config: Option<&str>,
+    settings: &AnalysisSettings,
     seen: Arc<DashSet<String, FxBuildHasher>>,
-    timeout: Duration,
 ) -> anyhow::Result<Option<CrateAnalysis>> {
     tracing::trace!("analyzing '{}'", target.pruned_crate.crate_name);
-    if !seen.insert(target.repo_root.display().to_string()) {
-        tracing::trace!("skipping seen workspace at {}", target.repo_root.display(),);
+    if !seen.insert(target.crate_root.display().to_string()) {
+        tracing::trace!("skipping seen workspace at {}", target.crate_root.display(),);
         return Ok(None);
     }
+    let mut retries_left = settings.analysis_retries;
+    loop {
+        let analysis = analyze_once(
+            target,
+            rustfmt_build_outputs,
+            upstream_rustfmt_build_outputs,
+            settings,
+        )
+        .await;
+        if retries_left == 0 || !analysis.failed_environmentally() {
+            return Ok(Some(analysis));
+        }
+        retries_left -= 1;
+        tracing::warn!(
+            "rustfmt failed on '{}' for what looks like an environmental reason, \
+             retrying the analysis ({retries_left} retries left)",
+            target.pruned_crate.crate_name
+        );
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Gives a held lock or a busy filesystem a moment to clear up
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[allow(clippy::too_many_lines)]
+async fn analyze_once(
+    target: &CrateReadyForAnalysis,
+    rustfmt_build_outputs: &RustFmtBuildOutputs,
+    upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
+    settings: &AnalysisSettings,
+) -> CrateAnalysis {
+    let used_crate_config = settings.respect_crate_config
+        && settings.config.is_some()
+        && has_crate_rustfmt_config(target).await;
+    let opts = if used_crate_config {
+        FmtOpts {
+            config: None,
+            ..settings.fmt_opts_for(target)
+        }
+    } else {
+        settings.fmt_opts_for(target)
+    };
+    let edition = match read_root_edition(&target.crate_root).await {
+        Ok(edition) => edition,
+        Err(e) => {
+            tracing::debug!(
+                "failed to read edition of '{}': {}",
+                target.pruned_crate.crate_name,
+                unpack(&*e)
+            );
+            None
+        }
+    };
+    let virtual_members = virtual_workspace_members(target).await;
+    let (mut diverging_diff, upstream_rustfmt_analysis, local_rustfmt_analysis) = compare_rustfmt(
+        target,
+        virtual_members.as_deref(),
+        rustfmt_build_outputs,
+        upstream_rustfmt_build_outputs,
+        opts,
+    )
+    .await;
+    if settings.ignore_whitespace_diffs
+        && diverging_diff.diverged()
+        && whitespace::same_ignoring_whitespace(
+            upstream_rustfmt_analysis.diff_output.as_deref(),
+            local_rustfmt_analysis.diff_output.as_deref(),
+        )
+    {
+        tracing::debug!(
+            "ignoring whitespace-only divergence on '{}'",
+            target.pruned_crate.crate_name
+        );
+        diverging_diff = DivergingDiff::None;
+    }
+    let line_ending_divergence = diverging_diff.diverged()
+        && whitespace::same_ignoring_line_endings(
+            upstream_rustfmt_analysis.diff_output.as_deref(),
+            local_rustfmt_analysis.diff_output.as_deref(),
+        );
+    let skipped_large_files = find_skipped_large_files(target, settings).await;
+    if let Some(max) = settings.max_file_bytes
+        && target.has_manifest
+    {
+        warn_large_diffs(
+            target,
+            max,
+            &upstream_rustfmt_analysis,
+            &local_rustfmt_analysis,
+        );
+    }
+    let mut flaky = false;
+    if diverging_diff.diverged() {
+        for attempt in 1..=settings.recheck_divergences {
+            let (recheck_diff, recheck_upstream, recheck_local) = compare_rustfmt(
+                target,
+                virtual_members.as_deref(),
+                rustfmt_build_outputs,
+                upstream_rustfmt_build_outputs,
+                opts,
+            )
+            .await;
+            if recheck_diff != diverging_diff
+                || recheck_upstream.diff_output != upstream_rustfmt_analysis.diff_output
+                || recheck_local.diff_output != local_rustfmt_analysis.diff_output
+            {
+                tracing::warn!(
+                    "divergence on '{}'({}) did not reproduce on recheck {attempt}, marking as flaky",
+                    target.pruned_crate.crate_name,
+                    target.repo_root.display()
+                );
+                flaky = true;
+                break;
+            }
+        }
+    }
+    let mut output_diverged_files = vec![];
+    if settings.compare_formatted_output
+        && !diverging_diff.diverged()
+        && upstream_rustfmt_analysis.rustfmt_error.is_none()
+        && local_rustfmt_analysis.rustfmt_error.is_none()
+    {
+        let member_editions = match &virtual_members {
+            Some(members) => member_editions(target, members).await,
+            None => vec![],
+        };
+        match formatted_output::diverging_files(
+            target,
+            rustfmt_build_outputs,
+            upstream_rustfmt_build_outputs,
+            edition,
+            &member_editions,
+            opts,
+        )
+        .await
+        {
+            Ok(files) if !files.is_empty() => {
+                tracing::info!(
+                    "formatted output of {} files diverged on '{}' while the diffs matched",
+                    files.len(),
+                    target.pruned_crate.crate_name
+                );
+                diverging_diff = DivergingDiff::FormattedOutput;
+                output_diverged_files = files;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(
+                    "failed to compare formatted output on '{}': {}",
+                    target.pruned_crate.crate_name,
+                    unpack(&*e)
+                );
+            }
+        }
+    }
+    let triggering_config = if settings.bisect_config
+        && diverging_diff.diverged()
+        && let Some(config) = opts.config
+    {
+        let minimal = config_bisect::minimize_config(
+            target,
+            virtual_members.as_deref(),
+            rustfmt_build_outputs,
+            upstream_rustfmt_build_outputs,
+            config,
+            opts,
+        )
+        .await;
+        tracing::info!(
+            "minimal config for divergence on '{}': '{}'",
+            target.pruned_crate.crate_name,
+            minimal.join(",")
+        );
+        Some(minimal)
+    } else {
+        None
+    };
+    let build_agreement = if settings.extra_builds.is_empty() {
+        None
+    } else {
+        let mut keyed = vec![
+            (
+                "local".to_string(),
+                OutcomeKey::from_analysis(&local_rustfmt_analysis),
+            ),
+            (
+                "upstream".to_string(),
+                OutcomeKey::from_analysis(&upstream_rustfmt_analysis),
+            ),
+        ];
+        for extra in &settings.extra_builds {
+            let result =
+                run_local_rustfmt_build(target, virtual_members.as_deref(), &extra.build, opts)
+                    .await
+                    .map(|out| out.diff);
+            keyed.push((extra.label.clone(), OutcomeKey::from_result(result)));
+        }
+        Some(agreement::cluster(&keyed))
+    };
+    tracing::debug!(
+        "finished {} at {}",
+        target.pruned_crate.crate_name,
+        target.repo_root.display()
+    );
+    CrateAnalysis::new(
+        target.pruned_crate.crate_name.clone(),
+        target.crate_root.clone(),
+        target.pruned_crate.repository.clone(),
+        target.origin,
+        target.head_branch.clone(),
+        target.head_commit.clone(),
+        target.pruned_crate.version.clone(),
+        target.pruned_crate.description.clone(),
+        edition,
+        diverging_diff,
+        flaky,
+        line_ending_divergence,
+        triggering_config,
+        used_crate_config,
+        virtual_members.as_ref().map(Vec::len),
+        target.oversized_repo_bytes,
+        build_agreement,
+        output_diverged_files,
+        skipped_large_files,
+        upstream_rustfmt_analysis,
+        local_rustfmt_analysis,
+    )
+}
+
+async fn has_crate_rustfmt_config(target: &CrateReadyForAnalysis) -> bool {
+    match has_rustfmt_config(&target.crate_root).await {
+        Ok(has) => has,
+        Err(e) => {
+            tracing::debug!(
+                "failed to look for a rustfmt config in '{}', applying ours: {}",
+                target.pruned_crate.crate_name,
+                unpack(&*e)
+            );
+            false
+        }
+    }
+}
+
+/// Member roots of a virtual workspace, `None` if the crate has a `[package]` at its root.
+/// Read once per target, the result is passed on to every `rustfmt` run on it
+pub(crate) async fn virtual_workspace_members(
+    target: &CrateReadyForAnalysis,
+) -> Option<Vec<PathBuf>> {
+    if !target.has_manifest {
+        return None;
+    }
+    match read_virtual_members(&target.crate_root).await {
+        Ok(members) => members,
+        Err(e) => {
+            tracing::debug!(
+                "failed to read workspace members of '{}', formatting from the root: {}",
+                target.pruned_crate.crate_name,
+                unpack(&*e)
+            );
+            None
+        }
+    }
+}
+
+/// Editions of the members that declare or inherit one, members can differ from each other
+async fn member_editions(
+    target: &CrateReadyForAnalysis,
+    members: &[PathBuf],
+) -> Vec<(PathBuf, Edition)> {
+    let mut editions = Vec::with_capacity(members.len());
+    for member in members {
+        match read_member_edition(&target.crate_root, member).await {
+            Ok(Some(edition)) => editions.push((member.clone(), edition)),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::debug!(
+                    "failed to read edition of member {} in '{}': {}",
+                    member.display(),
+                    target.pruned_crate.crate_name,
+                    unpack(&*e)
+                );
+            }
+        }
+    }
+    editions
+}
+
+/// Files are only left out when `rustfmt` runs on them directly, `cargo fmt` picks its own files
+async fn find_skipped_large_files(
+    target: &CrateReadyForAnalysis,
+    settings: &AnalysisSettings,
+) -> Vec<PathBuf> {
+    let Some(max) = settings.max_file_bytes else {
+        return vec![];
+    };
+    if target.has_manifest && !settings.compare_formatted_output {
+        return vec![];
+    }
+    match find_rs_files(&target.crate_root, Some(max)).await {
+        Ok(found) => found.oversized,
+        Err(e) => {
+            tracing::debug!(
+                "failed to look for large files in '{}': {}",
+                target.pruned_crate.crate_name,
+                unpack(&*e)
+            );
+            vec![]
+        }
+    }
+}
+
+// `cargo fmt` can't be told to skip files, so at least point out the ones likely to be generated
+fn warn_large_diffs(
+    target: &CrateReadyForAnalysis,
+    max_bytes: u64,
+    upstream: &RustfmtAnalysis,
+    local: &RustfmtAnalysis,
+) {
+    for diff in [&upstream.diff_output, &local.diff_output]
+        .into_iter()
+        .flatten()
+    {
+        for (file, file_diff) in diff_by_file(diff) {
+            if file_diff.len() as u64 > max_bytes {
+                tracing::warn!(
+                    "diff of {} in '{}' is {} bytes, larger than the max file size, likely generated",
+                    file.display(),
+                    target.pruned_crate.crate_name,
+                    file_diff.len()
+                );
+            }
+        }
+    }
+}
+
+/// Whether local `rustfmt` diverges from upstream on the target,
+/// or fails on it while upstream doesn't
+pub(crate) async fn regresses(
+    target: &CrateReadyForAnalysis,
+    virtual_members: Option<&[PathBuf]>,
+    rustfmt_build_outputs: &RustFmtBuildOutputs,
+    upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> bool {
+    let (diverging_diff, upstream, local) = compare_rustfmt(
+        target,
+        virtual_members,
+        rustfmt_build_outputs,
+        upstream_rustfmt_build_outputs,
+        opts,
+    )
+    .await;
+    diverging_diff.diverged() || (local.rustfmt_error.is_some() && upstream.rustfmt_error.is_none())
+}
+
+#[allow(clippy::too_many_lines)]
+async fn compare_rustfmt(
+    target: &CrateReadyForAnalysis,
+    virtual_members: Option<&[PathBuf]>,
+    rustfmt_build_outputs: &RustFmtBuildOutputs,
+    upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> (DivergingDiff, RustfmtAnalysis, RustfmtAnalysis) {
     let TimedOutput { output, elapsed } = timed(run_local_rustfmt_build(
-        &target.repo_root,
+        target,
+        virtual_members,
         upstream_rustfmt_build_outputs,
-        config,
-        timeout,
+        opts,
     ))
     .await;
+    let (output, warnings) = split_warnings(output);
     let (upstream_diff_output, rustfmt_error) = match output {
         Ok(None) => {
             tracing::trace!("upstream rustfmt succeeded");
@@ -59,15 +582,17 @@ pub(crate) async fn analyze_crate(
     let upstream_rustfmt_analysis = RustfmtAnalysis {
         diff_output: upstream_diff_output.clone(),
         rustfmt_error,
+        warnings,
         elapsed,
     };
     let TimedOutput { output, elapsed } = timed(run_local_rustfmt_build(
-        &target.repo_root,
+        target,
+        virtual_members,
         rustfmt_build_outputs,
-        config,
-        timeout,
+        opts,
     ))
     .await;
+    let (output, warnings) = split_warnings(output);
     let mut diverging_diff = DivergingDiff::None;
     let (local_diff_output, rustfmt_error) = match output {
         Ok(None) => {
@@ -114,31 +639,120 @@ pub(crate) async fn analyze_crate(
     let local_rustfmt_analysis = RustfmtAnalysis {
         diff_output: local_diff_output,
         rustfmt_error,
+        warnings,
         elapsed,
     };
-    tracing::debug!(
-        "finished {} at {}",
-        target.pruned_crate.crate_name,
-        target.repo_root.display()
-    );
-    Ok(Some(CrateAnalysis::new(
-        target.pruned_crate.crate_name.clone(),
-        target.repo_root.clone(),
-        target.pruned_crate.repository.clone(),
-        target.head_branch.clone(),
+    (
         diverging_diff,
         upstream_rustfmt_analysis,
         local_rustfmt_analysis,
-    )))
+    )
+}
+
+fn split_warnings(
+    output: anyhow::Result<CheckOutput>,
+) -> (anyhow::Result<Option<String>>, Option<String>) {
+    match output {
+        Ok(CheckOutput { diff, warnings }) => (Ok(diff), warnings),
+        Err(e) => (Err(e), None),
+    }
 }
 
 async fn run_local_rustfmt_build(
+    target: &CrateReadyForAnalysis,
+    virtual_members: Option<&[PathBuf]>,
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<CheckOutput> {
+    if !target.has_manifest {
+        return run_rustfmt_directly(target, rust_fmt_build_outputs, opts).await;
+    }
+    if let Some(members) = virtual_members
+        && !members.is_empty()
+    {
+        return run_per_member(target, members, rust_fmt_build_outputs, opts).await;
+    }
+    // From a subdirectory `--all` would format the whole workspace it's in
+    let all = target.crate_root == target.repo_root;
+    run_cargo_fmt(&target.crate_root, all, rust_fmt_build_outputs, opts).await
+}
+
+/// `cargo fmt --all` from a virtual manifest can miss members depending on the layout,
+/// so each member is formatted from its own root and the outputs are joined,
+/// `cargo fmt` picks up each member's own edition
+async fn run_per_member(
+    target: &CrateReadyForAnalysis,
+    members: &[PathBuf],
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<CheckOutput> {
+    let mut diffs = String::new();
+    let mut warnings = String::new();
+    let mut errors = vec![];
+    let mut panics = vec![];
+    let mut timeout = None;
+    let mut environmental = false;
+    for member in members {
+        match run_cargo_fmt(
+            &target.crate_root.join(member),
+            false,
+            rust_fmt_build_outputs,
+            opts,
+        )
+        .await
+        {
+            Ok(out) => {
+                if let Some(diff) = out.diff {
+                    diffs.push_str(&diff);
+                }
+                if let Some(member_warnings) = out.warnings {
+                    let _ = writeln!(warnings, "{}: {member_warnings}", member.display());
+                }
+            }
+            Err(e) => {
+                if let Some(panic) = e.downcast_ref::<RustfmtPanic>() {
+                    panics.push(format!("{}: {}", member.display(), panic.0));
+                } else if let Some(RustfmtTimeout(after)) = e.downcast_ref::<RustfmtTimeout>() {
+                    timeout = Some(*after);
+                } else if e.is::<EnvironmentalFailure>() {
+                    environmental = true;
+                }
+                errors.push(format!("{}: {}", member.display(), unpack(&*e)));
+            }
+        }
+    }
+    // A panic in any member is what matters most, keep it recognizable as one
+    if !panics.is_empty() {
+        return Err(RustfmtPanic(panics.join("\n")).into());
+    }
+    if let Some(after) = timeout {
+        return Err(RustfmtTimeout(after).into());
+    }
+    if !errors.is_empty() {
+        let message = format!(
+            "rustfmt failed on {} of {} workspace members:\n{}",
+            errors.len(),
+            members.len(),
+            errors.join("\n")
+        );
+        if environmental {
+            return Err(EnvironmentalFailure(message).into());
+        }
+        bail!(message);
+    }
+    Ok(CheckOutput {
+        diff: (!diffs.is_empty()).then_some(diffs),
+        warnings: (!warnings.is_empty()).then_some(warnings),
+    })
+}
+
+async fn run_cargo_fmt(
     target_repo: &Path,
+    all: bool,
     rust_fmt_build_outputs: &RustFmtBuildOutputs,
-    config: Option<&str>,
-    timeout: Duration,
-) -> anyhow::Result<Option<String>> {
-    let mut cmd = tokio::process::Command::new("cargo");
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<CheckOutput> {
+    let mut cmd = opts.cargo.command();
     cmd.env(
         "LD_LIBRARY_PATH",
         rust_fmt_build_outputs.toolchain_lib_path.ld_library_path(),
@@ -146,20 +760,175 @@ async fn run_local_rustfmt_build(
     .env("RUSTFMT", &rust_fmt_build_outputs.built_binary_path)
     .env_remove("RUSTUP_TOOLCHAIN")
     .current_dir(target_repo)
-    .arg("fmt")
-    .arg("--all")
-    .arg("--check");
+    .arg("fmt");
+    if all {
+        cmd.arg("--all");
+    }
+    cmd.arg("--check");
     // For some reason that I can't figure out RUSTUP_TOOLCHAIN gets set and overrides `rustfmt`'s
     // required default
-    if let Some(cfg) = config {
-        cmd.arg("--").arg("--config").arg(cfg);
+    let fmt_args = rustfmt_args(opts);
+    if !fmt_args.is_empty() {
+        cmd.arg("--").args(fmt_args);
+    }
+
+    run_rustfmt(&mut cmd, opts.timeout).await.into_result()
+}
+
+/// Without a manifest there's nothing for `cargo fmt` to go on,
+/// so every `.rs` file in the repo is passed to `rustfmt` instead
+async fn run_rustfmt_directly(
+    target: &CrateReadyForAnalysis,
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<CheckOutput> {
+    let files = find_rs_files(&target.crate_root, opts.max_file_bytes)
+        .await?
+        .files;
+    if files.is_empty() {
+        return Ok(CheckOutput::default());
+    }
+    let mut diffs = String::new();
+    let mut warnings = String::new();
+    for (edition, files) in files_by_edition(target, files).await {
+        let mut cmd =
+            direct_rustfmt_command(&target.crate_root, rust_fmt_build_outputs, &edition, opts);
+        cmd.arg("--check").args(&files);
+        let out = run_rustfmt(&mut cmd, opts.timeout).await.into_result()?;
+        if let Some(diff) = out.diff {
+            diffs.push_str(&diff);
+        }
+        if let Some(edition_warnings) = out.warnings {
+            warnings.push_str(&edition_warnings);
+        }
+    }
+    Ok(CheckOutput {
+        diff: (!diffs.is_empty()).then_some(diffs),
+        warnings: (!warnings.is_empty()).then_some(warnings),
+    })
+}
+
+/// Groups files by the edition of the closest `Cargo.toml` above them, files without one
+/// get the published edition, and 2021 if that isn't known either since most code parses
+/// under it
+async fn files_by_edition(
+    target: &CrateReadyForAnalysis,
+    files: Vec<PathBuf>,
+) -> BTreeMap<String, Vec<PathBuf>> {
+    let fallback = target
+        .pruned_crate
+        .edition
+        .clone()
+        .unwrap_or_else(|| "2021".to_string());
+    let mut manifest_editions: HashMap<PathBuf, Option<Edition>> = HashMap::new();
+    let mut grouped: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let mut edition = None;
+        for dir in file.ancestors().skip(1) {
+            let root = target.crate_root.join(dir);
+            if let Some(known) = manifest_editions.get(&root) {
+                edition = *known;
+                break;
+            }
+            if !tokio::fs::try_exists(root.join("Cargo.toml"))
+                .await
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            let read = match read_root_edition(&root).await {
+                Ok(read) => read,
+                Err(e) => {
+                    tracing::debug!(
+                        "failed to read edition at {} in '{}': {}",
+                        root.display(),
+                        target.pruned_crate.crate_name,
+                        unpack(&*e)
+                    );
+                    None
+                }
+            };
+            manifest_editions.insert(root, read);
+            edition = read;
+            break;
+        }
+        let edition = edition.map_or_else(|| fallback.clone(), |e| e.to_string());
+        grouped.entry(edition).or_default().push(file);
+    }
+    grouped
+}
+
+/// `rustfmt` invoked without `cargo`, files to format are added by the caller
+pub(super) fn direct_rustfmt_command(
+    target_repo: &Path,
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    edition: &str,
+    opts: FmtOpts<'_>,
+) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(&rust_fmt_build_outputs.built_binary_path);
+    cmd.env(
+        "LD_LIBRARY_PATH",
+        rust_fmt_build_outputs.toolchain_lib_path.ld_library_path(),
+    )
+    .env_remove("RUSTUP_TOOLCHAIN")
+    .current_dir(target_repo)
+    .arg("--edition")
+    .arg(edition)
+    .args(rustfmt_args(opts));
+    cmd
+}
+
+/// Arguments passed on to `rustfmt` itself
+fn rustfmt_args(opts: FmtOpts<'_>) -> Vec<&str> {
+    let mut args = vec![];
+    if opts.unstable_features {
+        args.push("--unstable-features");
+    }
+    if let Some(cfg) = opts.config {
+        args.push("--config");
+        args.push(cfg);
     }
+    args
+}
 
-    match run_rustfmt(&mut cmd, timeout).await {
-        RustfmtOutput::Success => Ok(None),
-        RustfmtOutput::Diff(d) => Ok(Some(d)),
-        RustfmtOutput::Failure(e) => Err(e),
+/// Runs `rustfmt` on empty input with the run's options, so that a config it doesn't accept
+/// fails the run up front instead of failing every crate
+pub(crate) async fn preflight_config(
+    label: &str,
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<()> {
+    if opts.config.is_none() && !opts.unstable_features {
+        return Ok(());
+    }
+    let mut cmd = tokio::process::Command::new(&rust_fmt_build_outputs.built_binary_path);
+    cmd.env(
+        "LD_LIBRARY_PATH",
+        rust_fmt_build_outputs.toolchain_lib_path.ld_library_path(),
+    )
+    .env_remove("RUSTUP_TOOLCHAIN")
+    .arg("--emit")
+    .arg("stdout")
+    .args(rustfmt_args(opts))
+    .stdin(std::process::Stdio::null())
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::piped())
+    .kill_on_drop(true);
+    let out = tokio::time::timeout(opts.timeout, cmd.output())
+        .await
+        .with_context(|| format!("{label} rustfmt config check timed out"))?
+        .with_context(|| format!("failed to run {label} rustfmt config check"))?;
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    if !out.status.success() {
+        bail!("{label} rustfmt rejected the config: {stderr}");
+    }
+    // Unstable options are ignored with a warning, which would make the run meaningless
+    if !opts.unstable_features && stderr.contains("unstable features are only available") {
+        bail!(
+            "{label} rustfmt ignores unstable options in the config without `--unstable-features`: {stderr}"
+        );
     }
+    Ok(())
 }
 
 struct TimedOutput<T> {
@@ -175,3 +944,25 @@ async fn timed<F: Future<Output = T>, T>(fut: F) -> TimedOutput<T> {
         elapsed: start.elapsed(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_scales_with_crate_size_up_to_the_max() {
+        let scaling = TimeoutScaling {
+            per_mib: Duration::from_secs(10),
+            max: Duration::from_mins(2),
+        };
+        let base = Duration::from_secs(30);
+        let mib = 1024 * 1024;
+        assert_eq!(base, scaling.timeout_for(base, 0));
+        assert_eq!(Duration::from_secs(35), scaling.timeout_for(base, mib / 2));
+        assert_eq!(Duration::from_secs(40), scaling.timeout_for(base, mib));
+        assert_eq!(Duration::from_mins(2), scaling.timeout_for(base, 100 * mib));
+        // A base above the max is never cut down
+        let long = Duration::from_mins(5);
+        assert_eq!(long, scaling.timeout_for(long, 100 * mib));
+    }
+}
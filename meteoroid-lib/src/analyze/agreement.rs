@@ -0,0 +1,74 @@
+use crate::analyze::report::{FmtOutcome, RustfmtAnalysis};
+
+/// How the outcomes of all compared `rustfmt` builds on a crate relate to each other
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct BuildAgreement {
+    pub outcomes: Vec<LabeledOutcome>,
+    /// Labels of builds with identical outcomes (same diff, or all successes, or all failures),
+    /// in the order the builds were compared
+    pub classes: Vec<Vec<String>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct LabeledOutcome {
+    pub label: String,
+    pub outcome: FmtOutcome,
+}
+
+/// A build's result, reduced to what decides whether two builds agree
+#[derive(Eq, PartialEq)]
+pub(super) enum OutcomeKey {
+    Success,
+    Diff(String),
+    Failure,
+}
+
+impl OutcomeKey {
+    pub(super) fn from_analysis(analysis: &RustfmtAnalysis) -> Self {
+        if analysis.rustfmt_error.is_some() {
+            Self::Failure
+        } else if let Some(diff) = &analysis.diff_output {
+            Self::Diff(diff.clone())
+        } else {
+            Self::Success
+        }
+    }
+
+    pub(super) fn from_result(result: anyhow::Result<Option<String>>) -> Self {
+        match result {
+            Ok(None) => Self::Success,
+            Ok(Some(diff)) => Self::Diff(diff),
+            Err(_) => Self::Failure,
+        }
+    }
+
+    fn outcome(&self) -> FmtOutcome {
+        match self {
+            Self::Success => FmtOutcome::Success,
+            Self::Diff(_) => FmtOutcome::Diff,
+            Self::Failure => FmtOutcome::Failure,
+        }
+    }
+}
+
+pub(super) fn cluster(keyed: &[(String, OutcomeKey)]) -> BuildAgreement {
+    let mut classes: Vec<(&OutcomeKey, Vec<String>)> = vec![];
+    for (label, key) in keyed {
+        if let Some((_, labels)) = classes.iter_mut().find(|(k, _)| *k == key) {
+            labels.push(label.clone());
+        } else {
+            classes.push((key, vec![label.clone()]));
+        }
+    }
+    let classes = classes.into_iter().map(|(_, labels)| labels).collect();
+    BuildAgreement {
+        outcomes: keyed
+            .iter()
+            .map(|(label, key)| LabeledOutcome {
+                label: label.clone(),
+                outcome: key.outcome(),
+            })
+            .collect(),
+        classes,
+    }
+}
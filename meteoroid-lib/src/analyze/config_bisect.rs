@@ -0,0 +1,82 @@
+use crate::analyze::{FmtOpts, compare_rustfmt};
+use crate::cmd::RustFmtBuildOutputs;
+use crate::git::CrateReadyForAnalysis;
+use std::path::PathBuf;
+
+/// Finds a minimal subset of the comma-separated `config` options that still makes
+/// local and upstream `rustfmt` diverge on the target, using delta debugging (ddmin).
+/// Empty if the target diverges without any config
+pub(super) async fn minimize_config(
+    target: &CrateReadyForAnalysis,
+    virtual_members: Option<&[PathBuf]>,
+    rustfmt_build_outputs: &RustFmtBuildOutputs,
+    upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
+    config: &str,
+    opts: FmtOpts<'_>,
+) -> Vec<String> {
+    let diverges_with = async |options: &[String]| {
+        let config = options.join(",");
+        let (diverging_diff, _, _) = compare_rustfmt(
+            target,
+            virtual_members,
+            rustfmt_build_outputs,
+            upstream_rustfmt_build_outputs,
+            FmtOpts {
+                config: (!config.is_empty()).then_some(config.as_str()),
+                ..opts
+            },
+        )
+        .await;
+        diverging_diff.diverged()
+    };
+    let mut options = config
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if diverges_with(&[]).await {
+        return vec![];
+    }
+    let mut granularity = 2;
+    while options.len() >= 2 {
+        let chunks = split_chunks(&options, granularity);
+        let mut reduced = false;
+        for chunk in &chunks {
+            if diverges_with(chunk).await {
+                options.clone_from(chunk);
+                granularity = 2;
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced && chunks.len() > 2 {
+            for i in 0..chunks.len() {
+                let complement = chunks
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .flat_map(|(_, c)| c.iter().cloned())
+                    .collect::<Vec<_>>();
+                if diverges_with(&complement).await {
+                    options = complement;
+                    granularity = (granularity - 1).max(2);
+                    reduced = true;
+                    break;
+                }
+            }
+        }
+        if !reduced {
+            if granularity >= options.len() {
+                break;
+            }
+            granularity = (granularity * 2).min(options.len());
+        }
+    }
+    options
+}
+
+fn split_chunks(options: &[String], granularity: usize) -> Vec<Vec<String>> {
+    let chunk_size = options.len().div_ceil(granularity);
+    options.chunks(chunk_size).map(<[String]>::to_vec).collect()
+}
@@ -0,0 +1,94 @@
+use crate::analyze::{FmtOpts, direct_rustfmt_command};
+use crate::cmd::RustFmtBuildOutputs;
+use crate::fs::find_rs_files;
+use crate::git::CrateReadyForAnalysis;
+use crate::unpack;
+use anyhow::Context;
+use cargo_toml::Edition;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Formats every `.rs` file with both builds and returns the files where the outputs differ.
+/// Files that either build fails to format are skipped, those show up in the regular check.
+/// Files under a member in `member_editions` are formatted with that member's edition
+pub(super) async fn diverging_files(
+    target: &CrateReadyForAnalysis,
+    rustfmt_build_outputs: &RustFmtBuildOutputs,
+    upstream_rustfmt_build_outputs: &RustFmtBuildOutputs,
+    edition: Option<Edition>,
+    member_editions: &[(PathBuf, Edition)],
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let files = find_rs_files(&target.crate_root, opts.max_file_bytes)
+        .await?
+        .files;
+    let mut diverging = vec![];
+    for file in files {
+        let edition = member_editions
+            .iter()
+            .filter(|(member, _)| file.starts_with(member))
+            .max_by_key(|(member, _)| member.components().count())
+            .map(|(_, edition)| *edition)
+            .or(edition)
+            .map_or_else(|| "2021".to_string(), |e| e.to_string());
+        let (local, upstream) = tokio::join!(
+            format_file(
+                &target.crate_root,
+                &file,
+                rustfmt_build_outputs,
+                &edition,
+                opts
+            ),
+            format_file(
+                &target.crate_root,
+                &file,
+                upstream_rustfmt_build_outputs,
+                &edition,
+                opts
+            ),
+        );
+        match (local, upstream) {
+            (Ok(local), Ok(upstream)) => {
+                if local != upstream {
+                    diverging.push(file);
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::debug!(
+                    "skipping output comparison of {}: {}",
+                    file.display(),
+                    unpack(&*e)
+                );
+            }
+        }
+    }
+    Ok(diverging)
+}
+
+async fn format_file(
+    target_repo: &Path,
+    file: &Path,
+    rust_fmt_build_outputs: &RustFmtBuildOutputs,
+    edition: &str,
+    opts: FmtOpts<'_>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut cmd = direct_rustfmt_command(target_repo, rust_fmt_build_outputs, edition, opts);
+    cmd.arg("--emit")
+        .arg("stdout")
+        .arg(file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let out = tokio::time::timeout(opts.timeout, cmd.output())
+        .await
+        .with_context(|| format!("formatting {} timed out", file.display()))?
+        .with_context(|| format!("failed to run rustfmt on {}", file.display()))?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "rustfmt failed on {}: {}",
+            file.display(),
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    Ok(out.stdout)
+}
@@ -1,39 +1,218 @@
+mod checkpoint;
+mod default_config;
 mod html;
+mod render;
+mod repro;
+mod sarif;
 
-use crate::analyze::similarity::similarity;
-use crate::cmd::{DiffResult, try_diff};
-use crate::crates::crate_consumer::default::{CrateName, GitRepo, NormalPath};
+pub(crate) use checkpoint::CompletedCrates;
+pub use default_config::DefaultConfigChange;
+pub use render::{RenderOpts, render_report};
+pub(crate) use repro::diff_by_file;
+
+use crate::analyze::agreement::BuildAgreement;
+use crate::analyze::similarity::ErrorComparator;
+use crate::cmd::{DiffResult, EnvironmentalFailure, RustfmtPanic, RustfmtTimeout, try_diff};
+use crate::crates::crate_consumer::default::{CrateName, GitRepo};
+use crate::fs::{write_atomically, write_atomically_async};
+use crate::git::CrateOrigin;
+use crate::notify::RunSummary;
+use crate::timings::{Phase, Timings};
 use crate::unpack;
 use anyhow::Context;
+use cargo_toml::Edition;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
 
-#[derive(serde::Serialize)]
-pub(crate) struct AnalysisReport {
+/// The summary of a run, written as `report.json`
+#[derive(serde::Serialize, serde::Deserialize)]
+// The unsafe is in the expansion of `tokio::join!`
+#[allow(clippy::unsafe_derive_deserialize)]
+pub struct AnalysisReport {
     #[serde(skip)]
     output: OutputDirs,
-    num_diverging_diffs: usize,
-    num_upstream_failures: usize,
-    num_upstream_diffs: usize,
-    num_upstream_successes: usize,
-    num_local_failures: usize,
-    num_local_diffs: usize,
-    num_local_successes: usize,
-    crate_reports: Vec<CrateReport>,
+    #[serde(skip)]
+    repro: Option<ReproOpts>,
+    #[serde(skip)]
+    checkpoint: Option<checkpoint::Checkpoint>,
+    pub num_diverging_diffs: usize,
+    pub num_flaky_divergences: usize,
+    /// Divergences where the builds only disagree on line endings
+    #[serde(default)]
+    pub num_line_ending_divergences: usize,
+    pub num_upstream_failures: usize,
+    pub num_upstream_diffs: usize,
+    pub num_upstream_successes: usize,
+    pub num_local_failures: usize,
+    pub num_local_diffs: usize,
+    pub num_local_successes: usize,
+    pub num_local_only_failures: usize,
+    pub num_similar_errors: usize,
+    /// Crates where both sides failed with similar errors, likely not a regression,
+    /// unlike crates where only one side failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_failures: Vec<CrateName>,
+    /// Crates where local or upstream `rustfmt` didn't finish in time
+    pub num_timeouts: usize,
+    pub percentages: ReportPercentages,
+    /// How long each phase of the run took, set when finishing
+    pub phase_timings: PhaseTimings,
+    /// Time `rustfmt` took on each side, summed by how it went
+    #[serde(default)]
+    pub elapsed_by_outcome: ElapsedByOutcome,
+    /// Builds that failed without aborting the run, with `SetupFailurePolicy::KeepGoing`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setup_failures: Vec<String>,
+    /// Crates that weren't analyzed because clones from their host kept failing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_unreachable: Vec<CrateName>,
+    /// Options whose defaults differ between the builds, also written to `config-diff.txt`.
+    /// `None` if the defaults couldn't be compared
+    #[serde(default)]
+    pub default_config_changes: Option<Vec<DefaultConfigChange>>,
+    /// Keyed by edition, `unknown` if it couldn't be read
+    pub by_edition: BTreeMap<String, EditionCounters>,
+    pub crate_reports: Vec<CrateReport>,
+    /// When the report was written, RFC3339 in UTC
+    #[serde(default)]
+    pub generated_at: String,
+    #[serde(default)]
+    pub meteoroid_version: String,
+    /// Hostname of the machine the run was on, if it could be read
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Copy, Clone)]
+pub struct EditionCounters {
+    pub num_analyzed: usize,
+    pub num_diverging_diffs: usize,
+}
+
+/// Rates derived from the counters, as percentages of analyzed crates, set when finishing
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct ReportPercentages {
+    pub diverged: f64,
+    pub local_only_failures: f64,
+    pub similar_errors: f64,
+}
+
+impl ReportPercentages {
+    fn from_report(report: &AnalysisReport) -> Self {
+        let analyzed = report.num_analyzed();
+        #[allow(clippy::cast_precision_loss)]
+        let pct = |count: usize| {
+            if analyzed == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / analyzed as f64
+            }
+        };
+        Self {
+            diverged: pct(report.num_diverging_diffs),
+            local_only_failures: pct(report.num_local_only_failures),
+            similar_errors: pct(report.num_similar_errors),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct ElapsedByOutcome {
+    pub upstream: OutcomeElapsed,
+    pub local: OutcomeElapsed,
+}
+
+/// Summed seconds per outcome, panics and timeouts aren't counted as failures here
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct OutcomeElapsed {
+    pub success: f64,
+    pub diff: f64,
+    pub failure: f64,
+    pub panic: f64,
+    pub timeout: f64,
+}
+
+impl OutcomeElapsed {
+    fn add(&mut self, outcome: FmtOutcome, elapsed: Duration) {
+        let sum = match outcome {
+            FmtOutcome::Success => &mut self.success,
+            FmtOutcome::Diff => &mut self.diff,
+            FmtOutcome::Failure => &mut self.failure,
+            FmtOutcome::Panic => &mut self.panic,
+            FmtOutcome::Timeout => &mut self.timeout,
+        };
+        *sum += elapsed.as_secs_f64();
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+pub struct PhaseTimings {
+    pub fetch_index: String,
+    pub parse_index: String,
+    /// Summed over every clone and sync, they run concurrently
+    pub clone: String,
+    pub build: String,
+    pub analyze: String,
 }
 
+impl PhaseTimings {
+    fn recorded(timings: &Timings) -> Self {
+        Self {
+            fetch_index: fmt_elapsed(timings.recorded(Phase::FetchIndex)),
+            parse_index: fmt_elapsed(timings.recorded(Phase::ParseIndex)),
+            clone: fmt_elapsed(timings.recorded(Phase::Clone)),
+            build: fmt_elapsed(timings.recorded(Phase::Build)),
+            analyze: fmt_elapsed(timings.recorded(Phase::Analyze)),
+        }
+    }
+}
+
+#[derive(Default)]
 struct OutputDirs {
     base: PathBuf,
     diverged: PathBuf,
     nondiverged: PathBuf,
     errors: PathBuf,
+    repros: PathBuf,
+    layout: OutputLayout,
+}
+
+/// How diffs and errors are laid out in the output dir
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum OutputLayout {
+    /// Files named after their crate, under `diverged`, `nondiverged`, and `errors`
+    #[default]
+    Flat,
+    /// A directory per crate under `diverged` or `nondiverged`, holding all of its files
+    ByCrate,
+}
+
+/// A file written for a crate, `label` is `local` or `upstream`
+#[derive(Debug, Copy, Clone)]
+enum OutputFile {
+    Diff(&'static str),
+    Error(&'static str),
+    MetaDiff,
+}
+
+/// Set to copy the files that diverged for each crate into the output dir,
+/// so that they can be used to reproduce the divergence
+pub(crate) struct ReproOpts {
+    /// The extra `rustfmt` config used in the run, needed to reproduce
+    pub(crate) config: Option<String>,
 }
 
 impl Ord for CrateReport {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Diverged is top priority
+        // Panics are always bugs, above everything else
+        if self.has_panic() && !other.has_panic() {
+            return Ordering::Greater;
+        } else if !self.has_panic() && other.has_panic() {
+            return Ordering::Less;
+        }
+        // Then diverged
         if self.diverged && !other.diverged {
             return Ordering::Greater;
         } else if !self.diverged && other.diverged {
@@ -61,7 +240,11 @@ impl PartialOrd for CrateReport {
 }
 
 impl AnalysisReport {
-    pub(crate) async fn new(output_dir: Option<PathBuf>) -> anyhow::Result<Self> {
+    pub(crate) async fn new(
+        output_dir: Option<PathBuf>,
+        repro: Option<ReproOpts>,
+        layout: OutputLayout,
+    ) -> anyhow::Result<Self> {
         let output = if let Some(output_dir) = output_dir {
             output_dir
         } else {
@@ -72,6 +255,7 @@ impl AnalysisReport {
         let diverged = output.join("diverged");
         let nondiverged = output.join("nondiverged");
         let errors = output.join("errors");
+        let repros = output.join("repros");
         let (r1, r2, r3) = tokio::join!(
             tokio::fs::create_dir_all(&diverged),
             tokio::fs::create_dir_all(&nondiverged),
@@ -92,39 +276,53 @@ impl AnalysisReport {
                 diverged,
                 nondiverged,
                 errors,
+                repros,
+                layout,
             },
+            repro,
+            checkpoint: None,
             num_diverging_diffs: 0,
+            num_flaky_divergences: 0,
+            num_line_ending_divergences: 0,
             num_upstream_failures: 0,
             num_upstream_diffs: 0,
             num_upstream_successes: 0,
             num_local_failures: 0,
             num_local_diffs: 0,
             num_local_successes: 0,
+            num_local_only_failures: 0,
+            num_similar_errors: 0,
+            expected_failures: Vec::new(),
+            num_timeouts: 0,
+            percentages: ReportPercentages::default(),
+            phase_timings: PhaseTimings::default(),
+            elapsed_by_outcome: ElapsedByOutcome::default(),
+            setup_failures: Vec::new(),
+            skipped_unreachable: Vec::new(),
+            default_config_changes: None,
+            by_edition: BTreeMap::new(),
             crate_reports: vec![],
+            generated_at: String::new(),
+            meteoroid_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: hostname().await,
         })
     }
 
     pub(crate) async fn add_result(
         &mut self,
         diff_tool: Option<&Path>,
+        error_comparator: &dyn ErrorComparator,
         cr: CrateAnalysis,
         write_outputs: bool,
         skip_non_diverging_diffs: bool,
     ) {
         let pre_errors = self.num_local_failures + self.num_upstream_failures;
-        if cr.diverging_diff.diverged() {
-            self.num_diverging_diffs += 1;
-        }
-        let similar_errors = if let (Some(local_err), Some(upstream_err)) = (
-            cr.local_rustfmt_analysis.rustfmt_error.as_deref(),
-            cr.upstream_rustfmt_analysis.rustfmt_error.as_deref(),
-        ) {
-            let lerr = local_err.to_string();
-            let uerr = upstream_err.to_string();
-            similarity(&lerr, &uerr)
-        } else {
-            false
-        };
+        self.checkpoint_completed(&cr);
+        self.count_divergence(&cr);
+        let similar_errors = self.count_error_similarity(error_comparator, &cr);
+        let repro_dir = self.write_repro_if_enabled(&cr, write_outputs).await;
+        let upstream_elapsed = cr.upstream_rustfmt_analysis.elapsed;
+        let local_elapsed = cr.local_rustfmt_analysis.elapsed;
         let upstream_out = create_rustfmt_output(
             &cr.crate_name,
             &self.output,
@@ -149,8 +347,20 @@ impl AnalysisReport {
             &mut self.num_local_failures,
         )
         .await;
+        self.elapsed_by_outcome
+            .upstream
+            .add(upstream_out.outcome, upstream_elapsed);
+        self.elapsed_by_outcome
+            .local
+            .add(local_out.outcome, local_elapsed);
+        if local_out.outcome == FmtOutcome::Timeout || upstream_out.outcome == FmtOutcome::Timeout {
+            self.num_timeouts += 1;
+        }
         let meta_diff_file = match cr.diverging_diff {
-            DivergingDiff::LocalOnly | DivergingDiff::UpstreamOnly | DivergingDiff::None => None,
+            DivergingDiff::LocalOnly
+            | DivergingDiff::UpstreamOnly
+            | DivergingDiff::FormattedOutput
+            | DivergingDiff::None => None,
             DivergingDiff::DiffBetween => {
                 Self::write_meta_diff_if_present(
                     diff_tool,
@@ -171,14 +381,111 @@ impl AnalysisReport {
                 cr.crate_name.clone(),
                 cr.local_root.display().to_string(),
                 cr.crate_url,
+                cr.origin,
                 cr.head_branch,
-                cr.diverging_diff.diverged(),
+                cr.head_commit,
+                cr.version,
+                cr.description,
+                cr.diverging_diff,
+                cr.flaky,
+                cr.line_ending_divergence,
+                cr.triggering_config,
+                cr.used_crate_config,
+                cr.virtual_members,
+                cr.oversized_repo_bytes,
+                cr.build_agreement,
+                cr.output_diverged_files,
+                cr.skipped_large_files,
                 similar_errors,
                 meta_diff_file,
+                repro_dir,
                 upstream_out,
                 local_out,
             ));
         }
+        self.write_checkpoint_if_due().await;
+    }
+
+    /// Returns whether both sides failed with similar errors
+    fn count_error_similarity(
+        &mut self,
+        error_comparator: &dyn ErrorComparator,
+        cr: &CrateAnalysis,
+    ) -> bool {
+        let similar_errors = if let (Some(local_err), Some(upstream_err)) = (
+            cr.local_rustfmt_analysis.rustfmt_error.as_deref(),
+            cr.upstream_rustfmt_analysis.rustfmt_error.as_deref(),
+        ) {
+            let lerr = local_err.to_string();
+            let uerr = upstream_err.to_string();
+            error_comparator.similar(&lerr, &uerr)
+        } else {
+            false
+        };
+        if similar_errors {
+            self.num_similar_errors += 1;
+            self.expected_failures.push(cr.crate_name.clone());
+        }
+        if cr.local_rustfmt_analysis.rustfmt_error.is_some()
+            && cr.upstream_rustfmt_analysis.rustfmt_error.is_none()
+        {
+            self.num_local_only_failures += 1;
+        }
+        similar_errors
+    }
+
+    fn count_divergence(&mut self, cr: &CrateAnalysis) {
+        if cr.diverging_diff.diverged() {
+            self.num_diverging_diffs += 1;
+        }
+        if cr.flaky {
+            self.num_flaky_divergences += 1;
+        }
+        if cr.line_ending_divergence {
+            self.num_line_ending_divergences += 1;
+        }
+        let edition_counters = self
+            .by_edition
+            .entry(
+                cr.edition
+                    .map_or_else(|| "unknown".to_string(), |e| e.to_string()),
+            )
+            .or_default();
+        edition_counters.num_analyzed += 1;
+        if cr.diverging_diff.diverged() {
+            edition_counters.num_diverging_diffs += 1;
+        }
+    }
+
+    async fn write_repro_if_enabled(
+        &self,
+        cr: &CrateAnalysis,
+        write_outputs: bool,
+    ) -> Option<PathBuf> {
+        let repro = self.repro.as_ref()?;
+        if !write_outputs || !cr.diverging_diff.diverged() {
+            return None;
+        }
+        match repro::write_repro(
+            &self.output.repros,
+            &cr.crate_name,
+            &cr.local_root,
+            cr.upstream_rustfmt_analysis.diff_output.as_deref(),
+            cr.local_rustfmt_analysis.diff_output.as_deref(),
+            repro.config.as_deref(),
+        )
+        .await
+        {
+            Ok(dir) => dir,
+            Err(e) => {
+                tracing::error!(
+                    "failed to write reproduction for '{}': {}",
+                    cr.crate_name,
+                    unpack(&*e)
+                );
+                None
+            }
+        }
     }
 
     async fn write_meta_diff_if_present(
@@ -215,8 +522,8 @@ impl AnalysisReport {
                 return None;
             }
         };
-        let name = match crate_name.try_convert_to_diverge_file_name() {
-            Ok(n) => n,
+        let path = match place_file(output_dirs, crate_name, OutputFile::MetaDiff, true) {
+            Ok(path) => path,
             Err(e) => {
                 tracing::error!(
                     "failed to convert crate name to diverge file name: {}",
@@ -225,7 +532,6 @@ impl AnalysisReport {
                 return None;
             }
         };
-        let path = place_file(output_dirs, &name, true, false);
         if let Err(e) = dump_content(&path, &content).await {
             tracing::error!(
                 "failed to write diverge meta diff to path={}: {}",
@@ -237,42 +543,167 @@ impl AnalysisReport {
         Some(path)
     }
 
+    /// Every analyzed crate gets exactly one upstream outcome
+    fn num_analyzed(&self) -> usize {
+        self.num_upstream_successes + self.num_upstream_diffs + self.num_upstream_failures
+    }
+
     pub(crate) async fn finish_report(
         mut self,
         report_dest: Option<PathBuf>,
-    ) -> anyhow::Result<()> {
+        report_formats: &[ReportFormat],
+        report_filters: &[ReportFilter],
+        report_sort: ReportSort,
+        open_report: bool,
+        timings: &Timings,
+    ) -> anyhow::Result<RunSummary> {
+        self.retain_filtered(report_filters);
         self.crate_reports
             .sort_by(|a, b| a.crate_name.cmp(&b.crate_name));
+        self.percentages = ReportPercentages::from_report(&self);
+        self.phase_timings = PhaseTimings::recorded(timings);
+        self.generated_at = crate::crates::api::format_rfc3339(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        );
+        let summary = RunSummary {
+            num_analyzed: self.num_analyzed(),
+            num_diverging_diffs: self.num_diverging_diffs,
+            diverged_crates: self
+                .crate_reports
+                .iter()
+                .filter(|cr| cr.diverged)
+                .map(|cr| cr.crate_name.to_string())
+                .collect(),
+            #[cfg(feature = "s3")]
+            output_dir: self.output.base.clone(),
+        };
+        let report_formats = report_formats.to_vec();
         tokio::task::spawn_blocking(move || {
-            let path = if let Some(report_dest) = report_dest {
-                report_dest
-            } else {
-                self.output.base.join("report.json")
-            };
-            let mut writer = std::fs::OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(&path)
-                .with_context(|| {
-                    format!(
-                        "failed to open report file for writing at {}",
-                        path.display()
-                    )
-                })?;
-            serde_json::to_writer_pretty(&mut writer, &self)
-                .with_context(|| format!("failed to write report to {}", path.display()))?;
             if self.num_diverging_diffs > 0 {
                 tracing::info!("Found {} diverging diffs", self.num_diverging_diffs);
+                if self.num_flaky_divergences > 0 {
+                    tracing::info!(
+                        "{} of the diverging diffs were flaky",
+                        self.num_flaky_divergences
+                    );
+                }
             } else {
                 tracing::info!("Found no diverging diffs");
             }
-            tracing::info!("Wrote report to {}", path.display());
-            self.html_report()?;
-            Ok::<_, anyhow::Error>(())
+            tracing::info!(
+                "Of {} analyzed crates, {:.1}% diverged, {:.1}% failed on local only, {:.1}% had similar errors",
+                self.num_analyzed(),
+                self.percentages.diverged,
+                self.percentages.local_only_failures,
+                self.percentages.similar_errors
+            );
+            if report_formats.contains(&ReportFormat::Json) {
+                let path = if let Some(report_dest) = report_dest {
+                    report_dest
+                } else {
+                    self.output.base.join("report.json")
+                };
+                write_atomically(&path, |writer| {
+                    serde_json::to_writer_pretty(writer, &self)
+                        .with_context(|| format!("failed to write report to {}", path.display()))
+                })?;
+                tracing::info!("Wrote report to {}", path.display());
+            }
+            self.write_rendered_reports(&report_formats, report_sort, open_report)
         })
         .await
         .context("failed to join report writing task")??;
+        Ok(summary)
+    }
+
+    fn retain_filtered(&mut self, report_filters: &[ReportFilter]) {
+        if !report_filters.is_empty() {
+            self.crate_reports
+                .retain(|cr| report_filters.iter().any(|f| f.matches(cr)));
+        }
+    }
+
+    /// Counts and percentages again from the crate reports that are left after filtering,
+    /// so that the summary describes the crates shown. Crate reports don't carry the edition,
+    /// so the per-edition counters are left out, timings are kept as the run's
+    fn recount_retained(&mut self) {
+        self.num_diverging_diffs = 0;
+        self.num_flaky_divergences = 0;
+        self.num_line_ending_divergences = 0;
+        self.num_upstream_failures = 0;
+        self.num_upstream_diffs = 0;
+        self.num_upstream_successes = 0;
+        self.num_local_failures = 0;
+        self.num_local_diffs = 0;
+        self.num_local_successes = 0;
+        self.num_local_only_failures = 0;
+        self.num_similar_errors = 0;
+        self.num_timeouts = 0;
+        self.by_edition.clear();
+        for cr in &self.crate_reports {
+            self.num_diverging_diffs += usize::from(cr.diverged);
+            self.num_flaky_divergences += usize::from(cr.flaky);
+            self.num_line_ending_divergences += usize::from(cr.line_ending_divergence);
+            self.num_similar_errors += usize::from(cr.similar_errors);
+            let upstream = cr.upstream_rustfmt_output.outcome;
+            let local = cr.local_rustfmt_output.outcome;
+            for (outcome, successes, diffs, failures) in [
+                (
+                    upstream,
+                    &mut self.num_upstream_successes,
+                    &mut self.num_upstream_diffs,
+                    &mut self.num_upstream_failures,
+                ),
+                (
+                    local,
+                    &mut self.num_local_successes,
+                    &mut self.num_local_diffs,
+                    &mut self.num_local_failures,
+                ),
+            ] {
+                match outcome {
+                    FmtOutcome::Success => *successes += 1,
+                    FmtOutcome::Diff => *diffs += 1,
+                    FmtOutcome::Failure | FmtOutcome::Panic | FmtOutcome::Timeout => {
+                        *failures += 1;
+                    }
+                }
+            }
+            self.num_local_only_failures += usize::from(local.failed() && !upstream.failed());
+            self.num_timeouts +=
+                usize::from(local == FmtOutcome::Timeout || upstream == FmtOutcome::Timeout);
+        }
+        let retained = self
+            .crate_reports
+            .iter()
+            .map(|cr| &cr.crate_name)
+            .collect::<std::collections::BTreeSet<_>>();
+        self.expected_failures
+            .retain(|crate_name| retained.contains(crate_name));
+        self.percentages = ReportPercentages::from_report(self);
+    }
+
+    /// The reports rendered from the JSON report, `Json` in `report_formats` is left to the caller
+    fn write_rendered_reports(
+        self,
+        report_formats: &[ReportFormat],
+        report_sort: ReportSort,
+        open_report: bool,
+    ) -> anyhow::Result<()> {
+        if report_formats.contains(&ReportFormat::Sarif) {
+            self.sarif_report()?;
+        }
+        if report_formats.contains(&ReportFormat::Html) {
+            let diverged = self.crate_reports.iter().any(|cr| cr.diverged);
+            let html_path = self.html_report(report_sort)?;
+            if open_report {
+                html::open_in_browser(&html_path, diverged);
+            }
+        } else if open_report {
+            tracing::warn!("not opening the report, no html report was written");
+        }
         Ok(())
     }
 }
@@ -289,14 +720,31 @@ async fn create_rustfmt_output(
     diff_counter: &mut usize,
     failure_counter: &mut usize,
 ) -> FmtOutput {
-    if analysis.rustfmt_error.is_none() && analysis.diff_output.is_none() {
+    let panic_message = analysis
+        .rustfmt_error
+        .as_ref()
+        .and_then(|e| e.downcast_ref::<RustfmtPanic>())
+        .map(|panic| panic.0.clone());
+    let outcome = if panic_message.is_some() {
+        FmtOutcome::Panic
+    } else if analysis
+        .rustfmt_error
+        .as_ref()
+        .is_some_and(|e| e.downcast_ref::<RustfmtTimeout>().is_some())
+    {
+        FmtOutcome::Timeout
+    } else if analysis.rustfmt_error.is_some() {
+        FmtOutcome::Failure
+    } else if analysis.diff_output.is_some() {
+        FmtOutcome::Diff
+    } else {
         *success_counter += 1;
-    }
+        FmtOutcome::Success
+    };
     let diff_output_file = if let Some(diff) = analysis.diff_output {
         *diff_counter += 1;
-        let file_name = crate_name.try_convert_to_diff_file_name(label);
+        let file_name = place_file(output, crate_name, OutputFile::Diff(label), diverged);
         if write_outputs && let Ok(file_name) = file_name {
-            let file_name = place_file(output, &file_name, diverged, false);
             if let Err(e) = dump_content(&file_name, &diff).await {
                 tracing::error!("failed to dump diff output: {}", unpack(&*e));
                 None
@@ -311,9 +759,8 @@ async fn create_rustfmt_output(
     };
     let error_output_file = if let Some(e) = analysis.rustfmt_error {
         *failure_counter += 1;
-        let file_name = crate_name.try_convert_to_rustfmt_error_file_name(label);
+        let file_name = place_file(output, crate_name, OutputFile::Error(label), diverged);
         if write_outputs && let Ok(file_name) = file_name {
-            let file_name = place_file(output, &file_name, diverged, true);
             if let Err(e) = dump_content(&file_name, &unpack(&*e).to_string()).await {
                 tracing::error!("failed to dump error output: {}", unpack(&*e));
                 None
@@ -327,6 +774,9 @@ async fn create_rustfmt_output(
         None
     };
     FmtOutput {
+        outcome,
+        panic_message,
+        warnings: analysis.warnings,
         diff_output_file,
         error_output_file,
         elapsed: fmt_elapsed(analysis.elapsed),
@@ -335,55 +785,142 @@ async fn create_rustfmt_output(
 
 // Too many bools here
 async fn dump_content(dest: &Path, content: &str) -> anyhow::Result<()> {
-    let mut file = tokio::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&dest)
-        .await
-        .with_context(|| format!("failed to open {}", dest.display()))?;
-    file.write_all(content.as_bytes())
-        .await
-        .with_context(|| format!("failed to write to {}", dest.display()))
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    write_atomically_async(dest, content.as_bytes()).await
 }
 
-fn place_file(output: &OutputDirs, file_name: &NormalPath, diverged: bool, err: bool) -> PathBuf {
-    if err {
-        output.errors.as_path().join(file_name.0.as_path())
-    } else if diverged {
-        output.diverged.as_path().join(file_name.0.as_path())
+fn place_file(
+    output: &OutputDirs,
+    crate_name: &CrateName,
+    file: OutputFile,
+    diverged: bool,
+) -> anyhow::Result<PathBuf> {
+    let divergence_dir = if diverged {
+        &output.diverged
     } else {
-        output.nondiverged.as_path().join(file_name.0.as_path())
+        &output.nondiverged
+    };
+    Ok(match output.layout {
+        OutputLayout::Flat => {
+            let (dir, file_name) = match file {
+                OutputFile::Diff(label) => (
+                    divergence_dir,
+                    crate_name.try_convert_to_diff_file_name(label)?,
+                ),
+                OutputFile::Error(label) => (
+                    &output.errors,
+                    crate_name.try_convert_to_rustfmt_error_file_name(label)?,
+                ),
+                OutputFile::MetaDiff => (
+                    divergence_dir,
+                    crate_name.try_convert_to_diverge_file_name()?,
+                ),
+            };
+            dir.join(file_name.0)
+        }
+        OutputLayout::ByCrate => {
+            let file_name = match file {
+                OutputFile::Diff(label) => format!("{label}.diff"),
+                OutputFile::Error(label) => format!("{label}-error.txt"),
+                OutputFile::MetaDiff => "diverge.dif".to_string(),
+            };
+            divergence_dir
+                .join(crate_name.0.0.as_path())
+                .join(file_name)
+        }
+    })
+}
+
+async fn hostname() -> Option<String> {
+    if let Ok(host) = std::env::var("HOSTNAME")
+        && !host.trim().is_empty()
+    {
+        return Some(host.trim().to_string());
     }
+    tokio::fs::read_to_string("/proc/sys/kernel/hostname")
+        .await
+        .ok()
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
 }
 
 fn fmt_elapsed(elapsed: Duration) -> String {
     format!("{:.2}s", elapsed.as_secs_f64())
 }
 
-#[derive(serde::Serialize, Eq, PartialEq)]
-struct CrateReport {
-    crate_name: CrateName,
-    local_root: String,
-    repo_url: Option<GitRepo>,
-    head_branch: Option<String>,
-    diverged: bool,
-    similar_errors: bool,
-    meta_diff_file: Option<PathBuf>,
-    upstream_rustfmt_output: FmtOutput,
-    local_rustfmt_output: FmtOutput,
+#[derive(serde::Serialize, serde::Deserialize, Debug, Eq, PartialEq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CrateReport {
+    pub crate_name: CrateName,
+    pub local_root: String,
+    pub repo_url: Option<GitRepo>,
+    pub origin: CrateOrigin,
+    pub head_branch: Option<String>,
+    /// Checked out commit of local crates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_commit: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub diverged: bool,
+    pub divergence: DivergingDiff,
+    /// The divergence didn't reproduce consistently when rechecked
+    pub flaky: bool,
+    /// The only difference between the builds' diffs is CRLF against LF line endings
+    #[serde(default)]
+    pub line_ending_divergence: bool,
+    /// The smallest subset of the `config` options that still diverges, if searched for
+    pub triggering_config: Option<Vec<String>>,
+    /// `config` was left out since the crate has its own `rustfmt.toml`
+    pub used_crate_config: bool,
+    /// Number of members formatted one by one, if the root manifest is virtual
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_members: Option<usize>,
+    /// Size on disk of the checkout, if it was over `--warn-repo-size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oversized_repo_bytes: Option<u64>,
+    /// Which builds agree, when comparing more builds than local and upstream
+    pub build_agreement: Option<BuildAgreement>,
+    /// Files where formatting with each build produced different output, relative to the repo
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_diverged_files: Vec<PathBuf>,
+    /// Files left out of formatting for being larger than `max_file_bytes`, relative to the repo
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_large_files: Vec<PathBuf>,
+    pub similar_errors: bool,
+    pub meta_diff_file: Option<PathBuf>,
+    pub repro_dir: Option<PathBuf>,
+    pub upstream_rustfmt_output: FmtOutput,
+    pub local_rustfmt_output: FmtOutput,
 }
 
 impl CrateReport {
-    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
     fn new(
         crate_name: CrateName,
         local_root: String,
         repo_url: Option<GitRepo>,
+        origin: CrateOrigin,
         head_branch: Option<String>,
-        diverged: bool,
+        head_commit: Option<String>,
+        version: Option<String>,
+        description: Option<String>,
+        divergence: DivergingDiff,
+        flaky: bool,
+        line_ending_divergence: bool,
+        triggering_config: Option<Vec<String>>,
+        used_crate_config: bool,
+        virtual_members: Option<usize>,
+        oversized_repo_bytes: Option<u64>,
+        build_agreement: Option<BuildAgreement>,
+        output_diverged_files: Vec<PathBuf>,
+        skipped_large_files: Vec<PathBuf>,
         similar_errors: bool,
         meta_diff_file: Option<PathBuf>,
+        repro_dir: Option<PathBuf>,
         upstream_rustfmt_output: FmtOutput,
         local_rustfmt_output: FmtOutput,
     ) -> Self {
@@ -391,15 +928,35 @@ impl CrateReport {
             crate_name,
             local_root,
             repo_url,
+            origin,
             head_branch,
-            diverged,
+            head_commit,
+            version,
+            description,
+            diverged: divergence.diverged(),
+            divergence,
+            flaky,
+            line_ending_divergence,
+            triggering_config,
+            used_crate_config,
+            virtual_members,
+            oversized_repo_bytes,
+            build_agreement,
+            output_diverged_files,
+            skipped_large_files,
             similar_errors,
             meta_diff_file,
+            repro_dir,
             upstream_rustfmt_output,
             local_rustfmt_output,
         }
     }
 
+    fn has_panic(&self) -> bool {
+        self.upstream_rustfmt_output.outcome == FmtOutcome::Panic
+            || self.local_rustfmt_output.outcome == FmtOutcome::Panic
+    }
+
     fn has_error(&self) -> bool {
         self.upstream_rustfmt_output.error_output_file.is_some()
             || self.local_rustfmt_output.error_output_file.is_some()
@@ -409,30 +966,205 @@ impl CrateReport {
         self.upstream_rustfmt_output.diff_output_file.is_some()
             || self.local_rustfmt_output.diff_output_file.is_some()
     }
+
+    /// Every way this crate is notable, in order of severity, empty if nothing stood out
+    fn failure_kinds(&self) -> Vec<FailureKind> {
+        let mut kinds = vec![];
+        if self.local_rustfmt_output.outcome == FmtOutcome::Panic {
+            kinds.push(FailureKind::LocalPanic);
+        }
+        if self.upstream_rustfmt_output.outcome == FmtOutcome::Panic {
+            kinds.push(FailureKind::UpstreamPanic);
+        }
+        if matches!(
+            self.local_rustfmt_output.outcome,
+            FmtOutcome::Failure | FmtOutcome::Timeout
+        ) {
+            kinds.push(FailureKind::LocalFailure);
+        }
+        if matches!(
+            self.upstream_rustfmt_output.outcome,
+            FmtOutcome::Failure | FmtOutcome::Timeout
+        ) {
+            kinds.push(FailureKind::UpstreamFailure);
+        }
+        match self.divergence {
+            DivergingDiff::LocalOnly => kinds.push(FailureKind::LocalOnlyDiff),
+            DivergingDiff::UpstreamOnly => kinds.push(FailureKind::UpstreamOnlyDiff),
+            DivergingDiff::DiffBetween => kinds.push(FailureKind::DiffBetween),
+            DivergingDiff::FormattedOutput => kinds.push(FailureKind::FormattedOutput),
+            DivergingDiff::None => {}
+        }
+        kinds
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum FailureKind {
+    LocalPanic,
+    UpstreamPanic,
+    LocalFailure,
+    UpstreamFailure,
+    LocalOnlyDiff,
+    UpstreamOnlyDiff,
+    DiffBetween,
+    FormattedOutput,
+}
+
+impl FailureKind {
+    const ALL: [Self; 8] = [
+        Self::LocalPanic,
+        Self::UpstreamPanic,
+        Self::LocalFailure,
+        Self::UpstreamFailure,
+        Self::LocalOnlyDiff,
+        Self::UpstreamOnlyDiff,
+        Self::DiffBetween,
+        Self::FormattedOutput,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Self::LocalPanic => "local-panic",
+            Self::UpstreamPanic => "upstream-panic",
+            Self::LocalFailure => "local-failure",
+            Self::UpstreamFailure => "upstream-failure",
+            Self::LocalOnlyDiff => "local-only-diff",
+            Self::UpstreamOnlyDiff => "upstream-only-diff",
+            Self::DiffBetween => "diff-between",
+            Self::FormattedOutput => "formatted-output",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::LocalPanic => "Local rustfmt panicked",
+            Self::UpstreamPanic => "Upstream rustfmt panicked",
+            Self::LocalFailure => "Local rustfmt failed",
+            Self::UpstreamFailure => "Upstream rustfmt failed",
+            Self::LocalOnlyDiff => "Local rustfmt produced a diff while upstream did not",
+            Self::UpstreamOnlyDiff => "Upstream rustfmt produced a diff while local did not",
+            Self::DiffBetween => "Local and upstream rustfmt produced different diffs",
+            Self::FormattedOutput => {
+                "Local and upstream rustfmt produced the same diffs, but different formatted output"
+            }
+        }
+    }
+}
+
+/// How crates are ordered in the HTML report
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ReportSort {
+    /// Panics first, then divergences, errors, and diffs, by name within each
+    #[default]
+    Severity,
+    /// By name only
+    Name,
+}
+
+/// Restricts which crates are included in the written reports, the counters are unaffected
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReportFilter {
+    /// Local `rustfmt` failed, upstream didn't
+    LocalOnlyFailure,
+    /// Upstream `rustfmt` failed, local didn't
+    UpstreamOnlyFailure,
+    /// Only local `rustfmt` produced a diff
+    LocalOnlyDiff,
+    /// Only upstream `rustfmt` produced a diff
+    UpstreamOnlyDiff,
+    /// Both produced diffs, but they differ
+    DiffBetween,
+    /// The diffs matched, but the formatted output differed
+    FormattedOutput,
+}
+
+impl ReportFilter {
+    fn matches(self, report: &CrateReport) -> bool {
+        let local_failed = report.local_rustfmt_output.outcome.failed();
+        let upstream_failed = report.upstream_rustfmt_output.outcome.failed();
+        match self {
+            Self::LocalOnlyFailure => local_failed && !upstream_failed,
+            Self::UpstreamOnlyFailure => upstream_failed && !local_failed,
+            Self::LocalOnlyDiff => report.divergence == DivergingDiff::LocalOnly,
+            Self::UpstreamOnlyDiff => report.divergence == DivergingDiff::UpstreamOnly,
+            Self::DiffBetween => report.divergence == DivergingDiff::DiffBetween,
+            Self::FormattedOutput => report.divergence == DivergingDiff::FormattedOutput,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Html,
+    Sarif,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FmtOutcome {
+    Success,
+    Diff,
+    Failure,
+    /// A failure where `rustfmt` panicked
+    Panic,
+    /// A failure where `rustfmt` didn't finish in time
+    Timeout,
+}
+
+impl FmtOutcome {
+    #[inline]
+    fn failed(self) -> bool {
+        matches!(self, Self::Failure | Self::Panic | Self::Timeout)
+    }
 }
 
-#[derive(serde::Serialize, Eq, PartialEq)]
-struct FmtOutput {
-    diff_output_file: Option<PathBuf>,
-    error_output_file: Option<PathBuf>,
-    elapsed: String,
+#[derive(serde::Serialize, serde::Deserialize, Debug, Eq, PartialEq)]
+pub struct FmtOutput {
+    pub outcome: FmtOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panic_message: Option<String>,
+    /// Stderr output of a run that didn't fail, such as ignored config options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<String>,
+    pub diff_output_file: Option<PathBuf>,
+    pub error_output_file: Option<PathBuf>,
+    pub elapsed: String,
 }
 
 pub(crate) struct CrateAnalysis {
     pub(super) crate_name: CrateName,
     pub(super) local_root: PathBuf,
     pub(super) crate_url: Option<GitRepo>,
+    pub(super) origin: CrateOrigin,
     pub(super) head_branch: Option<String>,
+    pub(super) head_commit: Option<String>,
+    pub(super) version: Option<String>,
+    pub(super) description: Option<String>,
+    pub(super) edition: Option<Edition>,
     pub(super) diverging_diff: DivergingDiff,
+    pub(super) flaky: bool,
+    pub(super) line_ending_divergence: bool,
+    pub(super) triggering_config: Option<Vec<String>>,
+    pub(super) used_crate_config: bool,
+    pub(super) virtual_members: Option<usize>,
+    pub(super) oversized_repo_bytes: Option<u64>,
+    pub(super) build_agreement: Option<BuildAgreement>,
+    pub(super) output_diverged_files: Vec<PathBuf>,
+    pub(super) skipped_large_files: Vec<PathBuf>,
     pub(super) upstream_rustfmt_analysis: RustfmtAnalysis,
     pub(super) local_rustfmt_analysis: RustfmtAnalysis,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub(crate) enum DivergingDiff {
+#[derive(serde::Serialize, serde::Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergingDiff {
     LocalOnly,
     UpstreamOnly,
     DiffBetween,
+    /// The `--check` diffs matched, but formatting produced different output
+    FormattedOutput,
     None,
 }
 
@@ -444,12 +1176,46 @@ impl DivergingDiff {
 }
 
 impl CrateAnalysis {
+    pub(crate) fn diverged(&self) -> bool {
+        self.diverging_diff.diverged()
+    }
+
+    /// Whether `rustfmt` failed on either side for reasons that aren't down to the crate
+    pub(crate) fn failed_environmentally(&self) -> bool {
+        [
+            &self.upstream_rustfmt_analysis,
+            &self.local_rustfmt_analysis,
+        ]
+        .into_iter()
+        .any(|analysis| {
+            analysis
+                .rustfmt_error
+                .as_ref()
+                .is_some_and(anyhow::Error::is::<EnvironmentalFailure>)
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         crate_name: CrateName,
         local_root: PathBuf,
         crate_url: Option<GitRepo>,
+        origin: CrateOrigin,
         head_branch: Option<String>,
+        head_commit: Option<String>,
+        version: Option<String>,
+        description: Option<String>,
+        edition: Option<Edition>,
         diverging_diff: DivergingDiff,
+        flaky: bool,
+        line_ending_divergence: bool,
+        triggering_config: Option<Vec<String>>,
+        used_crate_config: bool,
+        virtual_members: Option<usize>,
+        oversized_repo_bytes: Option<u64>,
+        build_agreement: Option<BuildAgreement>,
+        output_diverged_files: Vec<PathBuf>,
+        skipped_large_files: Vec<PathBuf>,
         upstream_rustfmt_analysis: RustfmtAnalysis,
         local_rustfmt_analysis: RustfmtAnalysis,
     ) -> Self {
@@ -457,16 +1223,82 @@ impl CrateAnalysis {
             crate_name,
             local_root,
             crate_url,
+            origin,
             head_branch,
+            head_commit,
+            version,
+            description,
+            edition,
             diverging_diff,
+            flaky,
+            line_ending_divergence,
+            triggering_config,
+            used_crate_config,
+            virtual_members,
+            oversized_repo_bytes,
+            build_agreement,
+            output_diverged_files,
+            skipped_large_files,
             upstream_rustfmt_analysis,
             local_rustfmt_analysis,
         }
     }
 }
 
+/// A finished crate analysis, handed to the sink of `meteoroid_with_sink` as soon as
+/// the crate is done
+#[derive(Debug, Clone)]
+pub struct PublicCrateAnalysis {
+    pub crate_name: String,
+    pub local_root: PathBuf,
+    pub repository: Option<String>,
+    pub version: Option<String>,
+    pub diverged: bool,
+    /// The divergence didn't reproduce consistently when rechecked
+    pub flaky: bool,
+    pub upstream: PublicRustfmtAnalysis,
+    pub local: PublicRustfmtAnalysis,
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicRustfmtAnalysis {
+    pub diff: Option<String>,
+    pub error: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl From<&CrateAnalysis> for PublicCrateAnalysis {
+    fn from(cr: &CrateAnalysis) -> Self {
+        Self {
+            crate_name: cr.crate_name.to_string(),
+            local_root: cr.local_root.clone(),
+            repository: cr.crate_url.as_ref().map(ToString::to_string),
+            version: cr.version.clone(),
+            diverged: cr.diverged(),
+            flaky: cr.flaky,
+            upstream: PublicRustfmtAnalysis::from(&cr.upstream_rustfmt_analysis),
+            local: PublicRustfmtAnalysis::from(&cr.local_rustfmt_analysis),
+        }
+    }
+}
+
+impl From<&RustfmtAnalysis> for PublicRustfmtAnalysis {
+    fn from(analysis: &RustfmtAnalysis) -> Self {
+        Self {
+            diff: analysis.diff_output.clone(),
+            error: analysis
+                .rustfmt_error
+                .as_ref()
+                .map(|e| unpack(&**e).to_string()),
+            elapsed: analysis.elapsed,
+        }
+    }
+}
+
 pub(super) struct RustfmtAnalysis {
     pub(super) diff_output: Option<String>,
     pub(super) rustfmt_error: Option<anyhow::Error>,
+    /// What `rustfmt` printed to stderr when it didn't fail
+    pub(super) warnings: Option<String>,
     pub(super) elapsed: Duration,
 }
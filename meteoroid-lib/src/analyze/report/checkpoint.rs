@@ -0,0 +1,163 @@
+//! Progress of a run, written to the output dir as it goes, so that a run that dies (or is
+//! stopped) can be restarted without redoing the crates it already analyzed
+use crate::analyze::report::{AnalysisReport, CrateAnalysis};
+use crate::crates::crate_consumer::default::{CrateName, GitRepo};
+use crate::fs::write_atomically_async;
+use crate::unpack;
+use anyhow::Context;
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+const WRITE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct CompletedCrate {
+    crate_name: CrateName,
+    repository: Option<GitRepo>,
+    local_root: PathBuf,
+    /// Only known for local crates, if it changed the crate is analyzed again
+    head_commit: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct CheckpointRef<'a> {
+    completed: &'a [CompletedCrate],
+    report: &'a AnalysisReport,
+}
+
+#[derive(serde::Deserialize)]
+struct CheckpointFile {
+    completed: Vec<CompletedCrate>,
+    report: AnalysisReport,
+}
+
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    completed: Vec<CompletedCrate>,
+    last_written: Instant,
+}
+
+/// Crates analyzed before the checkpoint, by where they were analyzed, with their commit
+pub(crate) type CompletedCrates = FxHashMap<PathBuf, Option<String>>;
+
+impl AnalysisReport {
+    /// Picks up the counters and crate reports of a previous run with the same output dir,
+    /// if it left a checkpoint, and starts checkpointing this run.
+    /// Returns the crates that don't need to be analyzed again
+    pub(crate) async fn resume_from_checkpoint(&mut self) -> anyhow::Result<CompletedCrates> {
+        let path = self.output.base.join(CHECKPOINT_FILE);
+        let mut checkpoint = Checkpoint {
+            path,
+            completed: Vec::new(),
+            last_written: Instant::now(),
+        };
+        let previous = match tokio::fs::read(&checkpoint.path).await {
+            Ok(content) => {
+                serde_json::from_slice::<CheckpointFile>(&content).with_context(|| {
+                    format!(
+                        "failed to parse checkpoint at {}",
+                        checkpoint.path.display()
+                    )
+                })?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.checkpoint = Some(checkpoint);
+                return Ok(CompletedCrates::default());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("failed to read checkpoint at {}", checkpoint.path.display())
+                });
+            }
+        };
+        tracing::info!(
+            "resuming from checkpoint at {}, skipping {} already analyzed crates",
+            checkpoint.path.display(),
+            previous.completed.len()
+        );
+        let completed = previous
+            .completed
+            .iter()
+            .map(|cr| (cr.local_root.clone(), cr.head_commit.clone()))
+            .collect();
+        checkpoint.completed = previous.completed;
+        *self = AnalysisReport {
+            output: std::mem::take(&mut self.output),
+            repro: self.repro.take(),
+            checkpoint: Some(checkpoint),
+            meteoroid_version: std::mem::take(&mut self.meteoroid_version),
+            host: self.host.take(),
+            ..previous.report
+        };
+        Ok(completed)
+    }
+
+    pub(super) fn checkpoint_completed(&mut self, cr: &CrateAnalysis) {
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            checkpoint.completed.push(CompletedCrate {
+                crate_name: cr.crate_name.clone(),
+                repository: cr.crate_url.clone(),
+                local_root: cr.local_root.clone(),
+                head_commit: cr.head_commit.clone(),
+            });
+        }
+    }
+
+    pub(super) async fn write_checkpoint_if_due(&mut self) {
+        if self
+            .checkpoint
+            .as_ref()
+            .is_some_and(|cp| cp.last_written.elapsed() >= WRITE_INTERVAL)
+        {
+            self.write_checkpoint().await;
+        }
+    }
+
+    /// Writes the checkpoint now, ex: when the run is stopped before it's done
+    pub(crate) async fn write_checkpoint(&mut self) {
+        let Some(mut checkpoint) = self.checkpoint.take() else {
+            return;
+        };
+        if let Err(e) = write_checkpoint_file(&checkpoint.path, &checkpoint.completed, self).await {
+            tracing::warn!("failed to write checkpoint: {}", unpack(&*e));
+        }
+        checkpoint.last_written = Instant::now();
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// The run finished, a restart should start over
+    pub(crate) async fn remove_checkpoint(&mut self) {
+        let Some(checkpoint) = self.checkpoint.take() else {
+            return;
+        };
+        if let Err(e) = tokio::fs::remove_file(&checkpoint.path).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!(
+                "failed to remove checkpoint at {}: {}",
+                checkpoint.path.display(),
+                unpack(&e)
+            );
+        }
+    }
+}
+
+async fn write_checkpoint_file(
+    path: &Path,
+    completed: &[CompletedCrate],
+    report: &AnalysisReport,
+) -> anyhow::Result<()> {
+    let content = serde_json::to_vec(&CheckpointRef { completed, report })
+        .context("failed to serialize checkpoint")?;
+    write_atomically_async(path, &content)
+        .await
+        .context("failed to write checkpoint")?;
+    tracing::debug!(
+        "wrote checkpoint with {} crates to {}",
+        completed.len(),
+        path.display()
+    );
+    Ok(())
+}
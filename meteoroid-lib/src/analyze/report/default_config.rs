@@ -0,0 +1,115 @@
+//! How the default configs of the two `rustfmt` builds differ, a changed default can explain
+//! divergences that aren't down to formatting logic
+use crate::analyze::report::AnalysisReport;
+use crate::cmd::{RustFmtBuildOutputs, output_string};
+use crate::unpack;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tokio::process::Command;
+
+const CONFIG_DIFF_FILE: &str = "config-diff.txt";
+
+/// An option with a different default in each build, `None` where a build doesn't have it
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DefaultConfigChange {
+    pub option: String,
+    pub upstream: Option<String>,
+    pub local: Option<String>,
+}
+
+impl AnalysisReport {
+    /// Writes the changed defaults to `config-diff.txt` and the report,
+    /// left out if either build can't print its default config
+    pub(crate) async fn record_default_config_diff(
+        &mut self,
+        local: &RustFmtBuildOutputs,
+        upstream: &RustFmtBuildOutputs,
+    ) {
+        let (local_config, upstream_config) =
+            tokio::join!(print_default_config(local), print_default_config(upstream));
+        let (local_config, upstream_config) = match (local_config, upstream_config) {
+            (Ok(local), Ok(upstream)) => (local, upstream),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!(
+                    "failed to print the default rustfmt config, not comparing them: {}",
+                    unpack(&*e)
+                );
+                return;
+            }
+        };
+        let changes = diff_configs(&upstream_config, &local_config);
+        if !changes.is_empty() {
+            tracing::info!(
+                "{} options have different defaults in local and upstream rustfmt",
+                changes.len()
+            );
+        }
+        let path = self.output.base.join(CONFIG_DIFF_FILE);
+        if let Err(e) = tokio::fs::write(&path, render(&changes)).await {
+            tracing::warn!(
+                "failed to write default config diff to {}: {}",
+                path.display(),
+                unpack(&e)
+            );
+        }
+        self.default_config_changes = Some(changes);
+    }
+}
+
+async fn print_default_config(build: &RustFmtBuildOutputs) -> anyhow::Result<String> {
+    output_string(
+        Command::new(&build.built_binary_path)
+            .env(
+                "LD_LIBRARY_PATH",
+                build.toolchain_lib_path.ld_library_path(),
+            )
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .arg("--print-config")
+            .arg("default"),
+    )
+    .await
+}
+
+/// Configs are printed as `option = value` lines
+fn parse_config(config: &str) -> BTreeMap<&str, &str> {
+    config
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(option, value)| (option.trim(), value.trim()))
+        .collect()
+}
+
+fn diff_configs(upstream: &str, local: &str) -> Vec<DefaultConfigChange> {
+    let upstream = parse_config(upstream);
+    let local = parse_config(local);
+    let mut options = upstream.keys().chain(local.keys()).collect::<Vec<_>>();
+    options.sort_unstable();
+    options.dedup();
+    options
+        .into_iter()
+        .filter(|option| upstream.get(*option) != local.get(*option))
+        .map(|option| DefaultConfigChange {
+            option: (*option).to_string(),
+            upstream: upstream.get(option).map(ToString::to_string),
+            local: local.get(option).map(ToString::to_string),
+        })
+        .collect()
+}
+
+fn render(changes: &[DefaultConfigChange]) -> String {
+    if changes.is_empty() {
+        return "local and upstream rustfmt have the same default config\n".to_string();
+    }
+    let mut out = String::from("--- upstream\n+++ local\n");
+    for change in changes {
+        if let Some(upstream) = &change.upstream {
+            let _ = writeln!(out, "-{} = {upstream}", change.option);
+        }
+        if let Some(local) = &change.local {
+            let _ = writeln!(out, "+{} = {local}", change.option);
+        }
+    }
+    out
+}
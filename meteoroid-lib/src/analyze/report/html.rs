@@ -1,26 +1,90 @@
 //! Code in this file (mainly HTML) is generated by AI
-use crate::analyze::report::{AnalysisReport, CrateReport, FmtOutput};
+use crate::analyze::report::{
+    AnalysisReport, CrateReport, ElapsedByOutcome, FmtOutcome, FmtOutput, ReportSort,
+};
+use crate::fs::write_atomically;
+use crate::git::CrateOrigin;
 use crate::unpack;
 use anyhow::Context;
-use std::path::Path;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Browsers struggle with reports of thousands of crates, the rest go on further pages
+const CRATES_PER_PAGE: usize = 250;
 
 impl AnalysisReport {
-    pub(crate) fn html_report(mut self) -> anyhow::Result<()> {
-        // Generate HTML report
-        let html_path = self.output.base.join("report.html");
-        self.crate_reports.sort_by(|a, b| b.cmp(a));
-        let html_content = self.generate_html();
-        std::fs::write(&html_path, html_content)
-            .with_context(|| format!("failed to write HTML report to {}", html_path.display()))?;
-        tracing::info!("Wrote HTML report to {}", html_path.display());
-        Ok(())
+    /// Returns the path to the first page
+    pub(crate) fn html_report(mut self, sort: ReportSort) -> anyhow::Result<PathBuf> {
+        match sort {
+            ReportSort::Severity => self.crate_reports.sort_by(|a, b| b.cmp(a)),
+            ReportSort::Name => self
+                .crate_reports
+                .sort_by(|a, b| a.crate_name.cmp(&b.crate_name)),
+        }
+        let pages = self.page_ranges();
+        let mut first_page = None;
+        for (page, range) in pages.iter().enumerate() {
+            let html_path = self.output.base.join(page_file_name(page));
+            let html_content = self.generate_html(&self.crate_reports[range.clone()], page, &pages);
+            write_atomically(&html_path, |writer| {
+                writer.write_all(html_content.as_bytes()).with_context(|| {
+                    format!("failed to write HTML report to {}", html_path.display())
+                })
+            })?;
+            first_page.get_or_insert(html_path);
+        }
+        let html_path = first_page.unwrap_or_else(|| self.output.base.join(page_file_name(0)));
+        if pages.len() > 1 {
+            tracing::info!(
+                "Wrote HTML report to {} ({} pages)",
+                html_path.display(),
+                pages.len()
+            );
+        } else {
+            tracing::info!("Wrote HTML report to {}", html_path.display());
+        }
+        Ok(html_path)
+    }
+
+    /// Panicked and diverged crates all go on the first page, however many there are,
+    /// since they're sorted first they're a prefix of the crate reports
+    fn page_ranges(&self) -> Vec<std::ops::Range<usize>> {
+        let total = self.crate_reports.len();
+        let first_len = self
+            .crate_reports
+            .iter()
+            .take_while(|cr| cr.has_panic() || cr.diverged)
+            .count()
+            .max(CRATES_PER_PAGE)
+            .min(total);
+        let mut pages = Vec::with_capacity(1 + (total - first_len).div_ceil(CRATES_PER_PAGE));
+        pages.push(0..first_len);
+        let mut start = first_len;
+        while start < total {
+            let end = (start + CRATES_PER_PAGE).min(total);
+            pages.push(start..end);
+            start = end;
+        }
+        pages
     }
 
     #[allow(clippy::too_many_lines)]
-    fn generate_html(&self) -> String {
+    fn generate_html(
+        &self,
+        crate_reports: &[CrateReport],
+        page: usize,
+        pages: &[std::ops::Range<usize>],
+    ) -> String {
         let total_reports = self.crate_reports.len();
-        let total_upstream =
-            self.num_upstream_successes + self.num_upstream_diffs + self.num_upstream_failures;
+        let total_upstream = self.num_analyzed();
+        let shown = pages.get(page).map_or_else(String::new, |range| {
+            if pages.len() > 1 {
+                format!(", showing {}-{}", range.start + 1, range.end)
+            } else {
+                String::new()
+            }
+        });
+        let page_nav = page_nav_html(page, pages.len());
 
         format!(
             r#"<!DOCTYPE html>
@@ -30,19 +94,74 @@ impl AnalysisReport {
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
     <title>Meoteoroid report</title>
     <style>
+        :root {{
+            --bg: #f5f5f5;
+            --card-bg: white;
+            --panel-bg: #f8f9fa;
+            --text: #333;
+            --muted: #666;
+            --label: #495057;
+            --faint: #6c757d;
+            --border: #e9ecef;
+            --link: #007bff;
+            --code-text: #212529;
+            --error-text: #dc3545;
+            --shared-diff-bg: #e7f3ff;
+            --shared-diff-title: #0056b3;
+            --shared-error-bg: #ffe7e7;
+            --shared-error-title: #a71d2a;
+        }}
+        :root[data-theme="dark"] {{
+            --bg: #1e1f22;
+            --card-bg: #2b2d31;
+            --panel-bg: #232428;
+            --text: #e3e5e8;
+            --muted: #a0a4aa;
+            --label: #b5bac1;
+            --faint: #8e9297;
+            --border: #3f4147;
+            --link: #5ea3ff;
+            --code-text: #e3e5e8;
+            --error-text: #ff7b85;
+            --shared-diff-bg: #1d2a3a;
+            --shared-diff-title: #8cc2ff;
+            --shared-error-bg: #3a1f22;
+            --shared-error-title: #ff9aa2;
+        }}
         body {{
             font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, Oxygen, Ubuntu, Cantarell, sans-serif;
             line-height: 1.6;
             max-width: 1400px;
             margin: 0 auto;
             padding: 20px;
-            background: #f5f5f5;
+            background: var(--bg);
+            color: var(--text);
         }}
         h1, h2, h3 {{
-            color: #333;
+            color: var(--text);
+        }}
+        a {{
+            color: var(--link);
+        }}
+        .theme-toggle {{
+            position: fixed;
+            top: 15px;
+            right: 15px;
+            padding: 6px 12px;
+            border: 1px solid var(--border);
+            border-radius: 4px;
+            background: var(--card-bg);
+            color: var(--text);
+            cursor: pointer;
+        }}
+        .page-nav {{
+            margin: 15px 0;
+        }}
+        .page-nav a, .page-nav strong {{
+            padding: 2px 6px;
         }}
         .summary {{
-            background: white;
+            background: var(--card-bg);
             padding: 20px;
             border-radius: 8px;
             box-shadow: 0 2px 4px rgba(0,0,0,0.1);
@@ -55,7 +174,7 @@ impl AnalysisReport {
             margin-top: 20px;
         }}
         .stat-box {{
-            background: #f8f9fa;
+            background: var(--panel-bg);
             padding: 15px;
             border-radius: 6px;
             border-left: 4px solid #007bff;
@@ -71,16 +190,16 @@ impl AnalysisReport {
         }}
         .stat-label {{
             font-size: 0.9em;
-            color: #666;
+            color: var(--muted);
             margin-bottom: 5px;
         }}
         .stat-value {{
             font-size: 2em;
             font-weight: bold;
-            color: #333;
+            color: var(--text);
         }}
         .crate-report {{
-            background: white;
+            background: var(--card-bg);
             padding: 20px;
             border-radius: 8px;
             box-shadow: 0 2px 4px rgba(0,0,0,0.1);
@@ -90,14 +209,14 @@ impl AnalysisReport {
             display: flex;
             justify-content: space-between;
             align-items: center;
-            border-bottom: 2px solid #e9ecef;
+            border-bottom: 2px solid var(--border);
             padding-bottom: 15px;
             margin-bottom: 15px;
         }}
         .crate-name {{
             font-size: 1.3em;
             font-weight: bold;
-            color: #007bff;
+            color: var(--link);
         }}
         .diverged-badge {{
             background: #dc3545;
@@ -107,13 +226,21 @@ impl AnalysisReport {
             font-size: 0.85em;
             font-weight: bold;
         }}
+        .flaky-badge {{
+            background: #ffc107;
+            color: #212529;
+            padding: 5px 15px;
+            border-radius: 20px;
+            font-size: 0.85em;
+            font-weight: bold;
+        }}
         .crate-info {{
             display: grid;
             grid-template-columns: repeat(auto-fit, minmax(300px, 1fr));
             gap: 10px;
             margin-bottom: 15px;
             font-size: 0.9em;
-            color: #666;
+            color: var(--muted);
         }}
         .info-item {{
             display: flex;
@@ -121,7 +248,7 @@ impl AnalysisReport {
         }}
         .info-label {{
             font-weight: 600;
-            color: #495057;
+            color: var(--label);
         }}
         .fmt-outputs {{
             display: grid;
@@ -130,7 +257,7 @@ impl AnalysisReport {
             overflow: hidden;
         }}
         .fmt-section {{
-            background: #f8f9fa;
+            background: var(--panel-bg);
             padding: 15px;
             border-radius: 6px;
             min-width: 0;
@@ -138,7 +265,7 @@ impl AnalysisReport {
         }}
         .fmt-section h4 {{
             margin-top: 0;
-            color: #495057;
+            color: var(--label);
         }}
         .output-item {{
             margin: 10px 0;
@@ -146,10 +273,10 @@ impl AnalysisReport {
         }}
         .output-label {{
             font-weight: 600;
-            color: #495057;
+            color: var(--label);
         }}
         .file-link {{
-            color: #007bff;
+            color: var(--link);
             text-decoration: none;
             word-break: break-all;
         }}
@@ -169,7 +296,7 @@ impl AnalysisReport {
             font-weight: bold;
         }}
         .elapsed {{
-            color: #6c757d;
+            color: var(--faint);
             font-size: 0.85em;
         }}
         .collapsible {{
@@ -219,7 +346,7 @@ impl AnalysisReport {
             max-height: 0;
             overflow: hidden;
             transition: max-height 0.3s ease-out;
-            background-color: #fff;
+            background-color: var(--card-bg);
             border: 1px solid #dc3545;
             border-top: none;
             border-radius: 0 0 4px 4px;
@@ -230,19 +357,19 @@ impl AnalysisReport {
         .error-content pre {{
             margin: 0;
             padding: 10px;
-            background-color: #f8f9fa;
+            background-color: var(--panel-bg);
             border-radius: 4px;
             overflow-x: auto;
             font-family: 'Courier New', Consolas, monospace;
             font-size: 0.85em;
             line-height: 1.4;
-            color: #dc3545;
+            color: var(--error-text);
         }}
         .diff-content {{
             max-height: 0;
             overflow: hidden;
             transition: max-height 0.3s ease-out;
-            background-color: #fff;
+            background-color: var(--card-bg);
             border: 1px solid #ffc107;
             border-top: none;
             border-radius: 0 0 4px 4px;
@@ -253,19 +380,19 @@ impl AnalysisReport {
         .diff-content pre {{
             margin: 0;
             padding: 10px;
-            background-color: #f8f9fa;
+            background-color: var(--panel-bg);
             border-radius: 4px;
             overflow-x: auto;
             font-family: 'Courier New', Consolas, monospace;
             font-size: 0.85em;
             line-height: 1.4;
-            color: #212529;
+            color: var(--code-text);
         }}
         .meta-diff-content {{
             max-height: 0;
             overflow: hidden;
             transition: max-height 0.3s ease-out;
-            background-color: #fff;
+            background-color: var(--card-bg);
             border: 1px solid #6f42c1;
             border-top: none;
             border-radius: 0 0 4px 4px;
@@ -276,13 +403,13 @@ impl AnalysisReport {
         .meta-diff-content pre {{
             margin: 0;
             padding: 10px;
-            background-color: #f8f9fa;
+            background-color: var(--panel-bg);
             border-radius: 4px;
             overflow-x: auto;
             font-family: 'Courier New', Consolas, monospace;
             font-size: 0.85em;
             line-height: 1.4;
-            color: #212529;
+            color: var(--code-text);
         }}
         @media (max-width: 768px) {{
             .fmt-outputs {{
@@ -291,6 +418,20 @@ impl AnalysisReport {
         }}
     </style>
     <script>
+        // Applied before the body renders, so there's no flash of the wrong theme
+        (function () {{
+            var theme = localStorage.getItem('meteoroid-theme');
+            if (theme) {{
+                document.documentElement.dataset.theme = theme;
+            }}
+        }})();
+
+        function toggleTheme() {{
+            var theme = document.documentElement.dataset.theme === 'dark' ? 'light' : 'dark';
+            document.documentElement.dataset.theme = theme;
+            localStorage.setItem('meteoroid-theme', theme);
+        }}
+
         function toggleError(button) {{
             button.classList.toggle('active');
             var content = button.nextElementSibling;
@@ -323,7 +464,9 @@ impl AnalysisReport {
     </script>
 </head>
 <body>
+    <button class="theme-toggle" onclick="toggleTheme()">Toggle dark mode</button>
     <h1>🦀 Rustfmt Analysis Report</h1>
+    <p>Generated {} by meteoroid {} on {}</p>
 
     <div class="summary">
         <h2>Summary</h2>
@@ -332,10 +475,32 @@ impl AnalysisReport {
                 <div class="stat-label">Diverging diffs</div>
                 <div class="stat-value">{}</div>
             </div>
+            <div class="stat-box warning">
+                <div class="stat-label">Flaky divergences</div>
+                <div class="stat-value">{}</div>
+            </div>
             <div class="stat-box">
                 <div class="stat-label">Total crates analyzed</div>
                 <div class="stat-value">{}</div>
             </div>
+            <div class="stat-box warning">
+                <div class="stat-label">Timed out</div>
+                <div class="stat-value">{}</div>
+            </div>
+        </div>
+        <div class="stats-grid">
+            <div class="stat-box danger">
+                <div class="stat-label">Diverged</div>
+                <div class="stat-value">{:.1}%</div>
+            </div>
+            <div class="stat-box danger">
+                <div class="stat-label">Failed on local only</div>
+                <div class="stat-value">{:.1}%</div>
+            </div>
+            <div class="stat-box warning">
+                <div class="stat-label">Similar errors</div>
+                <div class="stat-value">{:.1}%</div>
+            </div>
         </div>
 
         <h3 style="margin-top: 30px;">Local results</h3>
@@ -370,35 +535,235 @@ impl AnalysisReport {
             </div>
         </div>
 
+        <h3 style="margin-top: 30px;">Phase timings</h3>
+        <div class="stats-grid">
+            {}
+        </div>
+
+        <h3 style="margin-top: 30px;">Rustfmt time by outcome (upstream / local)</h3>
+        <div class="stats-grid">
+            {}
+        </div>
+
+        <h3 style="margin-top: 30px;">Diverging diffs by edition</h3>
+        <div class="stats-grid">
+            {}
+        </div>
+        {}
+        {}
+        {}
+        {}
+
     </div>
 
-    <h2>Crate Reports ({})</h2>
+    <h2>Crate Reports ({}{})</h2>
+    {}
+    {}
     {}
 </body>
 </html>"#,
+            html_escape(&self.generated_at),
+            html_escape(&self.meteoroid_version),
+            html_escape(self.host.as_deref().unwrap_or("unknown host")),
             self.num_diverging_diffs,
+            self.num_flaky_divergences,
             total_upstream,
+            self.num_timeouts,
+            self.percentages.diverged,
+            self.percentages.local_only_failures,
+            self.percentages.similar_errors,
             self.num_local_successes,
             self.num_local_diffs,
             self.num_local_failures,
             self.num_upstream_successes,
             self.num_upstream_diffs,
             self.num_upstream_failures,
+            self.generate_phase_timings_html(),
+            self.generate_elapsed_by_outcome_html(),
+            self.generate_by_edition_html(),
+            self.generate_setup_failures_html(),
+            self.generate_skipped_unreachable_html(),
+            self.generate_default_config_changes_html(),
+            self.generate_expected_failures_html(),
             total_reports,
-            self.generate_crate_reports_html()
+            shown,
+            page_nav,
+            Self::generate_crate_reports_html(crate_reports, page == 0),
+            page_nav,
         )
     }
 
-    fn generate_crate_reports_html(&self) -> String {
-        self.crate_reports
+    fn generate_phase_timings_html(&self) -> String {
+        let timings = &self.phase_timings;
+        [
+            ("Fetch index", &timings.fetch_index),
+            ("Parse index", &timings.parse_index),
+            ("Clone (summed)", &timings.clone),
+            ("Build", &timings.build),
+            ("Analyze", &timings.analyze),
+        ]
+        .iter()
+        .map(|(label, elapsed)| {
+            format!(
+                r#"<div class="stat-box">
+                <div class="stat-label">{label}</div>
+                <div class="stat-value">{elapsed}</div>
+            </div>"#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+
+    fn generate_elapsed_by_outcome_html(&self) -> String {
+        let ElapsedByOutcome { upstream, local } = &self.elapsed_by_outcome;
+        [
+            ("Successes", upstream.success, local.success),
+            ("Diffs", upstream.diff, local.diff),
+            ("Failures", upstream.failure, local.failure),
+            ("Panics", upstream.panic, local.panic),
+            ("Timeouts", upstream.timeout, local.timeout),
+        ]
+        .iter()
+        .map(|(label, upstream, local)| {
+            format!(
+                r#"<div class="stat-box">
+                <div class="stat-label">{label}</div>
+                <div class="stat-value">{upstream:.2}s / {local:.2}s</div>
+            </div>"#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+
+    fn generate_expected_failures_html(&self) -> String {
+        if self.expected_failures.is_empty() {
+            return String::new();
+        }
+        let items = self
+            .expected_failures
+            .iter()
+            .map(|crate_name| format!("<li>{}</li>", html_escape(&crate_name.to_string())))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            r#"<h3 style="margin-top: 30px;">Expected failures ({})</h3>
+        <p>Both local and upstream failed with similar errors, likely not a regression</p>
+        <ul>
+            {items}
+        </ul>"#,
+            self.expected_failures.len()
+        )
+    }
+
+    fn generate_default_config_changes_html(&self) -> String {
+        let Some(changes) = self
+            .default_config_changes
+            .as_ref()
+            .filter(|changes| !changes.is_empty())
+        else {
+            return String::new();
+        };
+        let missing = "<em>missing</em>".to_string();
+        let items = changes
             .iter()
-            .map(Self::generate_crate_report_html)
+            .map(|change| {
+                format!(
+                    "<li><code>{}</code>: {} upstream, {} local</li>",
+                    html_escape(&change.option),
+                    change
+                        .upstream
+                        .as_deref()
+                        .map_or_else(|| missing.clone(), html_escape),
+                    change
+                        .local
+                        .as_deref()
+                        .map_or_else(|| missing.clone(), html_escape),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            r#"<h3 style="margin-top: 30px;">Changed default config ({})</h3>
+        <p>Divergences could come from these changed defaults rather than formatting logic</p>
+        <ul>
+            {items}
+        </ul>"#,
+            changes.len()
+        )
+    }
+
+    fn generate_setup_failures_html(&self) -> String {
+        if self.setup_failures.is_empty() {
+            return String::new();
+        }
+        let items = self
+            .setup_failures
+            .iter()
+            .map(|failure| format!("<li>{}</li>", html_escape(failure)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            r#"<h3 style="margin-top: 30px;">Setup failures</h3>
+        <ul>
+            {items}
+        </ul>"#
+        )
+    }
+
+    fn generate_skipped_unreachable_html(&self) -> String {
+        if self.skipped_unreachable.is_empty() {
+            return String::new();
+        }
+        let items = self
+            .skipped_unreachable
+            .iter()
+            .map(|crate_name| format!("<li>{}</li>", html_escape(&crate_name.to_string())))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            r#"<h3 style="margin-top: 30px;">Skipped, host unreachable ({})</h3>
+        <ul>
+            {items}
+        </ul>"#,
+            self.skipped_unreachable.len()
+        )
+    }
+
+    fn generate_by_edition_html(&self) -> String {
+        self.by_edition
+            .iter()
+            .map(|(edition, counters)| {
+                format!(
+                    r#"<div class="stat-box">
+                <div class="stat-label">Edition {}</div>
+                <div class="stat-value">{} / {}</div>
+            </div>"#,
+                    html_escape(edition),
+                    counters.num_diverging_diffs,
+                    counters.num_analyzed
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn generate_crate_reports_html(crate_reports: &[CrateReport], first_page: bool) -> String {
+        // Diverged crates are all on the first page
+        let first_divergence = first_page
+            .then(|| crate_reports.iter().position(|cr| cr.diverged))
+            .flatten();
+        crate_reports
+            .iter()
+            .enumerate()
+            .map(|(i, cr)| Self::generate_crate_report_html(cr, Some(i) == first_divergence))
             .collect::<Vec<_>>()
             .join("\n")
     }
 
     #[allow(clippy::too_many_lines)]
-    fn generate_crate_report_html(report: &CrateReport) -> String {
+    fn generate_crate_report_html(report: &CrateReport, first_divergence: bool) -> String {
         let mut has_identical_diffs = false;
         let shared_diff_section = if !report.diverged
             && !report.has_error()
@@ -457,8 +822,151 @@ impl AnalysisReport {
             String::new()
         };
 
+        let mut extra_items = String::new();
+        if report.origin == CrateOrigin::Local {
+            extra_items.push_str(
+                r#"<div class="info-item">
+                <span class="info-label">Origin:</span>
+                <span>Local directory</span>
+            </div>"#,
+            );
+        }
+        if let Some(version) = &report.version {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Version:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(version)
+            );
+        }
+        if let Some(description) = &report.description {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Description:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(description)
+            );
+        }
+        if let Some(triggering_config) = &report.triggering_config {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Triggering config:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(&triggering_config.join(","))
+            );
+        }
+        if report.line_ending_divergence {
+            extra_items.push_str(
+                r#"<div class="info-item">
+                <span class="info-label">Divergence:</span>
+                <span>Only line endings, CRLF against LF</span>
+            </div>"#,
+            );
+        }
+        if report.used_crate_config {
+            extra_items.push_str(
+                r#"<div class="info-item">
+                <span class="info-label">Config:</span>
+                <span>The crate's own rustfmt.toml, `--config` left out</span>
+            </div>"#,
+            );
+        }
+        if let Some(members) = report.virtual_members {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Workspace:</span>
+                <span>Virtual, {members} members formatted individually</span>
+            </div>"#
+            );
+        }
+        if let Some(commit) = &report.head_commit {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Commit:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(commit)
+            );
+        }
+        if let Some(bytes) = report.oversized_repo_bytes {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Repo size:</span>
+                <span>{} MiB, over the warning threshold</span>
+            </div>"#,
+                bytes / (1024 * 1024)
+            );
+        }
+        if !report.output_diverged_files.is_empty() {
+            let files = report
+                .output_diverged_files
+                .iter()
+                .map(|f| f.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Formatted output differs in:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(&files)
+            );
+        }
+        if !report.skipped_large_files.is_empty() {
+            let files = report
+                .skipped_large_files
+                .iter()
+                .map(|f| f.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Skipped large files:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(&files)
+            );
+        }
+        if let Some(agreement) = &report.build_agreement {
+            let classes = agreement
+                .classes
+                .iter()
+                .map(|class| class.join(", "))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Agreeing builds:</span>
+                <span>{}</span>
+            </div>"#,
+                html_escape(&classes)
+            );
+        }
+        if let Some(repro_dir) = &report.repro_dir {
+            let _ = write!(
+                extra_items,
+                r#"<div class="info-item">
+                <span class="info-label">Reproduction:</span>
+                <a href="{0}" class="file-link">{0}</a>
+            </div>"#,
+                html_escape(&repro_dir.display().to_string())
+            );
+        }
+
         format!(
-            r#"<div class="crate-report">
+            r#"<div class="crate-report"{}>
         <div class="crate-header">
             <div class="crate-name">{}</div>
             {}
@@ -476,6 +984,7 @@ impl AnalysisReport {
                 <span class="info-label">Local path:</span>
                 <span>{}</span>
             </div>
+            {}
         </div>
         <div class="fmt-outputs">
             {}
@@ -485,11 +994,17 @@ impl AnalysisReport {
         {}
         {}
     </div>"#,
-            report.crate_name,
-            if report.diverged {
-                r#"<span class="diverged-badge">DIVERGED</span>"#
+            if first_divergence {
+                format!(r#" id="{FIRST_DIVERGENCE_ANCHOR}""#)
             } else {
-                ""
+                String::new()
+            },
+            report.crate_name,
+            match (report.diverged, report.flaky) {
+                (true, true) =>
+                    r#"<span class="flaky-badge">FLAKY</span> <span class="diverged-badge">DIVERGED</span>"#,
+                (true, false) => r#"<span class="diverged-badge">DIVERGED</span>"#,
+                _ => "",
             },
             report
                 .repo_url
@@ -501,6 +1016,7 @@ impl AnalysisReport {
                 .map_or_else(|| "local".to_string(), std::string::ToString::to_string),
             report.head_branch.as_deref().unwrap_or("local"),
             report.local_root,
+            extra_items,
             Self::generate_fmt_output_html(
                 "Local rustfmt",
                 &report.local_rustfmt_output,
@@ -523,8 +1039,8 @@ impl AnalysisReport {
         let escaped_content = html_escape(diff_content);
         format!(
             r#"<div style="margin-top: 20px; grid-column: 1 / -1;">
-            <div style="background: #e7f3ff; padding: 15px; border-radius: 6px; border-left: 4px solid #007bff;">
-                <h4 style="margin-top: 0; color: #0056b3;">📝 Shared Diff (identical for both local and upstream)</h4>
+            <div style="background: var(--shared-diff-bg); padding: 15px; border-radius: 6px; border-left: 4px solid #007bff;">
+                <h4 style="margin-top: 0; color: var(--shared-diff-title);">📝 Shared Diff (identical for both local and upstream)</h4>
                 <button class="collapsible diff" onclick="toggleDiff(this)" style="margin-top: 10px;">Show diff</button>
                 <div class="diff-content">
                     <div class="diff-content-inner">
@@ -540,8 +1056,8 @@ impl AnalysisReport {
         let escaped_content = html_escape(error_content);
         format!(
             r#"<div style="margin-top: 20px; grid-column: 1 / -1;">
-            <div style="background: #ffe7e7; padding: 15px; border-radius: 6px; border-left: 4px solid #dc3545;">
-                <h4 style="margin-top: 0; color: #a71d2a;">❌ Shared Error (similar for both local and upstream)</h4>
+            <div style="background: var(--shared-error-bg); padding: 15px; border-radius: 6px; border-left: 4px solid #dc3545;">
+                <h4 style="margin-top: 0; color: var(--shared-error-title);">❌ Shared Error (similar for both local and upstream)</h4>
                 <button class="collapsible" onclick="toggleError(this)" style="margin-top: 10px;">Show error details</button>
                 <div class="error-content">
                     <div class="error-content-inner">
@@ -604,11 +1120,12 @@ impl AnalysisReport {
                     }
                 }
             };
-            (
-                r#"<span class="status-error">❌ Error</span>"#,
-                content,
-                None,
-            )
+            let status = match output.outcome {
+                FmtOutcome::Panic => r#"<span class="status-error">💥 Panic</span>"#,
+                FmtOutcome::Timeout => r#"<span class="status-error">⏱️ Timeout</span>"#,
+                _ => r#"<span class="status-error">❌ Error</span>"#,
+            };
+            (status, content, None)
         } else if let Some(diff_file) = output.diff_output_file.as_ref() {
             // Only read diff content if we're not skipping it (i.e., not showing shared diff)
             let content = if skip_diff_content {
@@ -663,7 +1180,7 @@ impl AnalysisReport {
         };
 
         let diff_link_note = if skip_diff_content && output.diff_output_file.is_some() {
-            r#"<div class="output-item" style="font-style: italic; color: #6c757d;">
+            r#"<div class="output-item" style="font-style: italic; color: var(--faint);">
                 (Diff content shown in shared section below)
             </div>"#
         } else {
@@ -671,7 +1188,7 @@ impl AnalysisReport {
         };
 
         let error_link_note = if skip_error_content && output.error_output_file.is_some() {
-            r#"<div class="output-item" style="font-style: italic; color: #6c757d;">
+            r#"<div class="output-item" style="font-style: italic; color: var(--faint);">
                 (Error content shown in shared section below)
             </div>"#
         } else {
@@ -693,8 +1210,20 @@ impl AnalysisReport {
                 {}
                 {}
                 {}
+                {}
+                {}
             </div>"#,
                 output.elapsed,
+                output.panic_message.as_ref().map(|message| format!(
+                    r#"<div class="output-item">
+                    <span class="output-label">Panic:</span> <pre>{}</pre>
+                </div>"#, html_escape(message)
+                )).unwrap_or_default(),
+                output.warnings.as_ref().map(|warnings| format!(
+                    r#"<div class="output-item">
+                    <span class="output-label">Warnings:</span> <pre>{}</pre>
+                </div>"#, html_escape(warnings)
+                )).unwrap_or_default(),
                 output.diff_output_file.as_ref().map(|f| format!(
                     r#"<div class="output-item">
                     <span class="output-label">Diff:</span> <a href="{FILE}" class="file-link">{FILE}</a>
@@ -713,6 +1242,31 @@ impl AnalysisReport {
     }
 }
 
+fn page_file_name(page: usize) -> String {
+    if page == 0 {
+        "report.html".to_string()
+    } else {
+        format!("report-{}.html", page + 1)
+    }
+}
+
+fn page_nav_html(page: usize, num_pages: usize) -> String {
+    if num_pages <= 1 {
+        return String::new();
+    }
+    let links = (0..num_pages)
+        .map(|p| {
+            if p == page {
+                format!("<strong>{}</strong>", p + 1)
+            } else {
+                format!(r#"<a href="{}">{}</a>"#, page_file_name(p), p + 1)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(r#"<nav class="page-nav">Pages: {links}</nav>"#)
+}
+
 /// This was written by AI, I'm keeping it but it shouldn't be used for anything
 /// non-trivial without actually looking into proper html escapes.
 fn html_escape(s: &str) -> String {
@@ -722,3 +1276,161 @@ fn html_escape(s: &str) -> String {
         .replace('"', "&quot;")
         .replace('\'', "&#39;")
 }
+
+/// Set on the first diverging crate, so that the report can be opened straight at it
+pub(crate) const FIRST_DIVERGENCE_ANCHOR: &str = "first-divergence";
+
+pub(crate) fn report_url(html_path: &Path, jump_to_divergence: bool) -> anyhow::Result<url::Url> {
+    let abs = std::path::absolute(html_path)
+        .with_context(|| format!("failed to make {} absolute", html_path.display()))?;
+    let mut url = url::Url::from_file_path(&abs)
+        .map_err(|()| anyhow::anyhow!("failed to convert {} to a url", abs.display()))?;
+    if jump_to_divergence {
+        url.set_fragment(Some(FIRST_DIVERGENCE_ANCHOR));
+    }
+    Ok(url)
+}
+
+pub(crate) fn open_in_browser(html_path: &Path, jump_to_divergence: bool) {
+    if !has_display() {
+        tracing::info!(
+            "no display found, not opening the report at {}",
+            html_path.display()
+        );
+        return;
+    }
+    let url = match report_url(html_path, jump_to_divergence) {
+        Ok(url) => url,
+        Err(e) => {
+            tracing::error!("failed to open report: {}", unpack(&*e));
+            return;
+        }
+    };
+    if let Err(e) = open::that_detached(url.as_str()) {
+        tracing::error!("failed to open {url} in a browser: {}", unpack(&e));
+    }
+}
+
+fn has_display() -> bool {
+    if cfg!(target_os = "linux") {
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::report::OutputLayout;
+
+    fn crate_report(name: &str, divergence: &str) -> CrateReport {
+        let output = serde_json::json!({
+            "outcome": "success",
+            "diff_output_file": null,
+            "error_output_file": null,
+            "elapsed": "0.01s",
+        });
+        serde_json::from_value(serde_json::json!({
+            "crate_name": name,
+            "local_root": format!("/crates/{name}"),
+            "repo_url": null,
+            "origin": "local",
+            "head_branch": null,
+            "version": null,
+            "description": null,
+            "diverged": divergence != "none",
+            "divergence": divergence,
+            "flaky": false,
+            "triggering_config": null,
+            "used_crate_config": false,
+            "build_agreement": null,
+            "similar_errors": false,
+            "meta_diff_file": null,
+            "repro_dir": null,
+            "upstream_rustfmt_output": output,
+            "local_rustfmt_output": output,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn report_url_points_at_the_html_file() {
+        let url = report_url(Path::new("/out/report dir/report.html"), false).unwrap();
+        assert_eq!("file:///out/report%20dir/report.html", url.as_str());
+        let url = report_url(Path::new("/out/report.html"), true).unwrap();
+        assert_eq!("file:///out/report.html#first-divergence", url.as_str());
+    }
+
+    #[test]
+    fn relative_report_path_is_made_absolute() {
+        let url = report_url(Path::new("report.html"), false).unwrap();
+        let expected = std::env::current_dir().unwrap().join("report.html");
+        assert_eq!(expected, url.to_file_path().unwrap());
+    }
+
+    #[tokio::test]
+    async fn large_reports_are_split_into_linked_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report =
+            AnalysisReport::new(Some(dir.path().to_path_buf()), None, OutputLayout::Flat)
+                .await
+                .unwrap();
+        report.crate_reports = (0..600)
+            .map(|i| {
+                // Named to sort last, but diverged crates still go first
+                if i % 200 == 0 {
+                    crate_report(&format!("z-diverged-{i}"), "local_only")
+                } else {
+                    crate_report(&format!("a-clean-{i:03}"), "none")
+                }
+            })
+            .collect();
+        let first = report.html_report(ReportSort::Severity).unwrap();
+        assert_eq!(dir.path().join("report.html"), first);
+        let pages = ["report.html", "report-2.html", "report-3.html"]
+            .map(|page| std::fs::read_to_string(dir.path().join(page)).unwrap());
+        assert!(!dir.path().join("report-4.html").exists());
+        for diverged in ["z-diverged-0", "z-diverged-200", "z-diverged-400"] {
+            assert!(
+                pages[0].contains(diverged),
+                "{diverged} not on the first page"
+            );
+        }
+        assert!(pages[0].contains("showing 1-250"));
+        assert!(pages[2].contains("showing 501-600"));
+        for (page, html) in pages.iter().enumerate() {
+            for (other, file) in ["report.html", "report-2.html", "report-3.html"]
+                .iter()
+                .enumerate()
+            {
+                let link = format!(r#"<a href="{file}">{}</a>"#, other + 1);
+                assert_eq!(page != other, html.contains(&link), "page {page}: {link}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rendered_report_has_both_themes_and_a_toggle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report =
+            AnalysisReport::new(Some(dir.path().to_path_buf()), None, OutputLayout::Flat)
+                .await
+                .unwrap();
+        report.crate_reports = vec![crate_report("themed", "local_only")];
+        let html = std::fs::read_to_string(report.html_report(ReportSort::Name).unwrap()).unwrap();
+        assert!(html.contains(":root {"));
+        assert!(html.contains(r#":root[data-theme="dark"] {"#));
+        // Every color the diffs use is defined for both themes
+        for var in [
+            "--shared-diff-bg",
+            "--shared-error-bg",
+            "--error-text",
+            "--code-text",
+        ] {
+            assert_eq!(2, html.matches(&format!("{var}:")).count(), "{var}");
+        }
+        assert!(html.contains(r#"<button class="theme-toggle" onclick="toggleTheme()">"#));
+        assert!(html.contains("localStorage.setItem('meteoroid-theme'"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Renders the reports of a finished run again from its `report.json`, ex: with other filters
+//! or another sort, without analyzing anything. The diff and error files the report points to
+//! are read from where the run wrote them
+use crate::MeteroidError;
+use crate::analyze::report::{AnalysisReport, ReportFilter, ReportFormat, ReportSort};
+use anyhow::Context;
+use std::path::PathBuf;
+
+pub struct RenderOpts {
+    /// The `report.json` of a previous run
+    pub report: PathBuf,
+    /// Where the rendered reports are written, the directory of `report` if not set
+    pub output_dir: Option<PathBuf>,
+    pub formats: Vec<ReportFormat>,
+    pub filters: Vec<ReportFilter>,
+    pub sort: ReportSort,
+    pub open: bool,
+}
+
+/// Reads the report at `opts.report` and writes the html and sarif reports from it again,
+/// a report that can't be read is a setup error and failing to write them an analysis error
+pub async fn render_report(opts: RenderOpts) -> Result<(), MeteroidError> {
+    let content = tokio::fs::read(&opts.report)
+        .await
+        .with_context(|| format!("failed to read report at {}", opts.report.display()))
+        .map_err(MeteroidError::setup)?;
+    let report = serde_json::from_slice::<AnalysisReport>(&content)
+        .with_context(|| format!("failed to parse report at {}", opts.report.display()))
+        .map_err(MeteroidError::setup)?;
+    write_rendered(report, opts)
+        .await
+        .map_err(MeteroidError::analysis)
+}
+
+async fn write_rendered(mut report: AnalysisReport, opts: RenderOpts) -> anyhow::Result<()> {
+    let base = match opts.output_dir {
+        Some(dir) => dir,
+        None => opts.report.parent().map(PathBuf::from).unwrap_or_default(),
+    };
+    tokio::fs::create_dir_all(&base)
+        .await
+        .with_context(|| format!("failed to create output dir at {}", base.display()))?;
+    if opts.formats.contains(&ReportFormat::Json) {
+        tracing::info!("not rendering json, it's what the other reports are rendered from");
+    }
+    report.output.base = base;
+    if !opts.filters.is_empty() {
+        report.retain_filtered(&opts.filters);
+        report.recount_retained();
+    }
+    tokio::task::spawn_blocking(move || {
+        report.write_rendered_reports(&opts.formats, opts.sort, opts.open)
+    })
+    .await
+    .context("failed to join report rendering task")?
+}
@@ -0,0 +1,160 @@
+use crate::crates::crate_consumer::default::CrateName;
+use anyhow::Context;
+use rustc_hash::FxHashMap;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Copies the files whose diffs differ between local and upstream `rustfmt`
+/// into `repro_base`, along with any `rustfmt` config at the repo root and a
+/// README with the command to reproduce.
+/// Returns the created directory, or `None` if no diverging files could be identified
+pub(super) async fn write_repro(
+    repro_base: &Path,
+    crate_name: &CrateName,
+    local_root: &Path,
+    upstream_diff: Option<&str>,
+    local_diff: Option<&str>,
+    config: Option<&str>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let files = diverging_files(upstream_diff, local_diff);
+    let relative_files = files
+        .iter()
+        .filter_map(|file| {
+            let rel = file
+                .strip_prefix(local_root)
+                .ok()
+                .filter(|rel| rel.components().all(|c| matches!(c, Component::Normal(_))));
+            if rel.is_none() {
+                tracing::warn!(
+                    "diverging file {} for '{crate_name}' is not under {}, skipping it",
+                    file.display(),
+                    local_root.display()
+                );
+            }
+            rel
+        })
+        .collect::<Vec<_>>();
+    if relative_files.is_empty() {
+        return Ok(None);
+    }
+    let name = crate_name.try_convert_to_repro_dir_name()?;
+    let repro_dir = repro_base.join(name.0);
+    for rel in &relative_files {
+        copy_into(local_root, rel, &repro_dir).await?;
+    }
+    let mut config_files = vec![];
+    for cfg_name in ["rustfmt.toml", ".rustfmt.toml"] {
+        let cfg = Path::new(cfg_name);
+        if tokio::fs::try_exists(local_root.join(cfg))
+            .await
+            .with_context(|| {
+                format!("failed to check for {cfg_name} in {}", local_root.display())
+            })?
+        {
+            copy_into(local_root, cfg, &repro_dir).await?;
+            config_files.push(cfg_name);
+        }
+    }
+    let readme = repro_readme(crate_name, &relative_files, &config_files, config);
+    let readme_path = repro_dir.join("README.md");
+    tokio::fs::write(&readme_path, readme)
+        .await
+        .with_context(|| format!("failed to write {}", readme_path.display()))?;
+    Ok(Some(repro_dir))
+}
+
+async fn copy_into(src_root: &Path, rel: &Path, dest_root: &Path) -> anyhow::Result<()> {
+    let src = src_root.join(rel);
+    let dest = dest_root.join(rel);
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("failed to create repro dir at {}", parent.display()))?;
+    }
+    tokio::fs::copy(&src, &dest)
+        .await
+        .with_context(|| format!("failed to copy {} to {}", src.display(), dest.display()))?;
+    Ok(())
+}
+
+fn repro_readme(
+    crate_name: &CrateName,
+    files: &[&Path],
+    config_files: &[&str],
+    config: Option<&str>,
+) -> String {
+    let mut readme = format!(
+        "# Diverging files for {crate_name}\n\n\
+        Local and upstream rustfmt formatted these files differently.\n\
+        Reproduce from this directory with each rustfmt build:\n\n```shell\nrustfmt --check"
+    );
+    if let Some(cfg) = config {
+        let _ = write!(readme, " --config '{cfg}'");
+    }
+    for file in files {
+        let _ = write!(readme, " {}", file.display());
+    }
+    readme.push_str("\n```\n");
+    if !config_files.is_empty() {
+        let _ = write!(
+            readme,
+            "\nThe crate's rustfmt config ({}) is included, rustfmt picks it up automatically.\n",
+            config_files.join(", ")
+        );
+    }
+    readme.push_str(
+        "\nThe edition is normally supplied by cargo, pass `--edition` if the crate's edition is needed.\n",
+    );
+    readme
+}
+
+/// Files that only one side has a diff for, or that both have different diffs for, sorted
+fn diverging_files(upstream_diff: Option<&str>, local_diff: Option<&str>) -> Vec<PathBuf> {
+    let upstream = upstream_diff.map(diff_by_file).unwrap_or_default();
+    let local = local_diff.map(diff_by_file).unwrap_or_default();
+    let mut files = upstream
+        .iter()
+        .filter(|(file, diff)| local.get(*file) != Some(*diff))
+        .map(|(file, _)| file.clone())
+        .chain(
+            local
+                .keys()
+                .filter(|file| !upstream.contains_key(*file))
+                .cloned(),
+        )
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Splits `rustfmt --check` output into the diff for each file
+pub(crate) fn diff_by_file(diff: &str) -> FxHashMap<PathBuf, String> {
+    let mut by_file: FxHashMap<PathBuf, String> = FxHashMap::default();
+    let mut current: Option<PathBuf> = None;
+    for line in diff.lines() {
+        if let Some(file) = parse_diff_header(line) {
+            current = Some(file);
+            continue;
+        }
+        if let Some(file) = &current {
+            let entry = by_file.entry(file.clone()).or_default();
+            entry.push_str(line);
+            entry.push('\n');
+        }
+    }
+    by_file
+}
+
+// Looks like `Diff in /path/to/file.rs:12:`, or in older versions
+// `Diff in /path/to/file.rs at line 12:`
+fn parse_diff_header(line: &str) -> Option<PathBuf> {
+    let rest = line.strip_prefix("Diff in ")?.strip_suffix(':')?;
+    if let Some((file, _line)) = rest.rsplit_once(" at line ") {
+        return Some(PathBuf::from(file));
+    }
+    let (file, line_no) = rest.rsplit_once(':')?;
+    line_no
+        .chars()
+        .all(|c| c.is_ascii_digit())
+        .then(|| PathBuf::from(file))
+}
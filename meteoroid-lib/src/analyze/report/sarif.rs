@@ -0,0 +1,152 @@
+use crate::analyze::report::{AnalysisReport, CrateReport, FailureKind};
+use crate::fs::write_atomically;
+use anyhow::Context;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+
+impl AnalysisReport {
+    pub(crate) fn sarif_report(&self) -> anyhow::Result<()> {
+        let sarif_path = self.output.base.join("report.sarif");
+        let log = SarifLog::from_reports(&self.crate_reports);
+        write_atomically(&sarif_path, |writer| {
+            serde_json::to_writer_pretty(writer, &log).with_context(|| {
+                format!("failed to write SARIF report to {}", sarif_path.display())
+            })
+        })?;
+        tracing::info!("Wrote SARIF report to {}", sarif_path.display());
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SarifLog {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: [SarifRun; 1],
+}
+
+impl SarifLog {
+    fn from_reports(reports: &[CrateReport]) -> Self {
+        let rules = FailureKind::ALL
+            .into_iter()
+            .map(|kind| SarifRule {
+                id: kind.id(),
+                short_description: SarifMessage {
+                    text: kind.description().to_string(),
+                },
+            })
+            .collect();
+        let results = reports
+            .iter()
+            .flat_map(|report| {
+                report
+                    .failure_kinds()
+                    .into_iter()
+                    .map(|kind| SarifResult::new(report, kind))
+            })
+            .collect();
+        Self {
+            schema: SARIF_SCHEMA,
+            version: SARIF_VERSION,
+            runs: [SarifRun {
+                tool: SarifTool {
+                    driver: SarifDriver {
+                        name: "meteoroid",
+                        version: env!("CARGO_PKG_VERSION"),
+                        rules,
+                    },
+                },
+                results,
+            }],
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SarifRun {
+    tool: SarifTool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(serde::Serialize)]
+struct SarifTool {
+    driver: SarifDriver,
+}
+
+#[derive(serde::Serialize)]
+struct SarifDriver {
+    name: &'static str,
+    version: &'static str,
+    rules: Vec<SarifRule>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifRule {
+    id: &'static str,
+    short_description: SarifMessage,
+}
+
+#[derive(serde::Serialize)]
+struct SarifMessage {
+    text: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: &'static str,
+    level: &'static str,
+    message: SarifMessage,
+    locations: [SarifLocation; 1],
+}
+
+impl SarifResult {
+    fn new(report: &CrateReport, kind: FailureKind) -> Self {
+        let uri = report
+            .repo_url
+            .as_ref()
+            .map_or_else(|| report.local_root.clone(), ToString::to_string);
+        let level = match kind {
+            FailureKind::LocalPanic
+            | FailureKind::UpstreamPanic
+            | FailureKind::LocalFailure
+            | FailureKind::UpstreamFailure => "error",
+            FailureKind::LocalOnlyDiff
+            | FailureKind::UpstreamOnlyDiff
+            | FailureKind::DiffBetween
+            | FailureKind::FormattedOutput => "warning",
+        };
+        Self {
+            rule_id: kind.id(),
+            level,
+            message: SarifMessage {
+                text: format!("{}: {}", report.crate_name, kind.description()),
+            },
+            locations: [SarifLocation {
+                physical_location: SarifPhysicalLocation {
+                    artifact_location: SarifArtifactLocation { uri },
+                },
+            }],
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifLocation {
+    physical_location: SarifPhysicalLocation,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifPhysicalLocation {
+    artifact_location: SarifArtifactLocation,
+}
+
+#[derive(serde::Serialize)]
+struct SarifArtifactLocation {
+    uri: String,
+}
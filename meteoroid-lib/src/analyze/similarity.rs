@@ -1,5 +1,17 @@
-pub(super) fn similarity(a: &str, b: &str) -> bool {
-    // Seems to get pretty good results on normalized levenshtein
-    let similarity = strsim::normalized_levenshtein(a, b);
-    similarity > 0.9
+/// Decides whether the errors local and upstream `rustfmt` failed with on a crate are
+/// similar enough that the failure likely isn't a regression
+pub trait ErrorComparator: Send + Sync {
+    fn similar(&self, a: &str, b: &str) -> bool;
+}
+
+/// The default, errors are similar when their normalized levenshtein similarity is over `0.9`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LevenshteinComparator;
+
+impl ErrorComparator for LevenshteinComparator {
+    fn similar(&self, a: &str, b: &str) -> bool {
+        // Seems to get pretty good results on normalized levenshtein
+        let similarity = strsim::normalized_levenshtein(a, b);
+        similarity > 0.9
+    }
 }
@@ -0,0 +1,114 @@
+//! Telling apart divergences that are only trailing whitespace, newline, or line ending noise
+
+/// Whether the `--check` diffs are the same once trailing whitespace and hunks that only add or
+/// remove blank lines are left out, no diff counts as an empty one.
+/// Spacing and indentation within a line still count
+pub(super) fn same_ignoring_whitespace(upstream: Option<&str>, local: Option<&str>) -> bool {
+    changes_ignoring(upstream.unwrap_or_default(), Noise::TrailingWhitespace)
+        == changes_ignoring(local.unwrap_or_default(), Noise::TrailingWhitespace)
+}
+
+/// Like `same_ignoring_whitespace`, but only a trailing `\r` is left out, ex: one build writing
+/// CRLF where the other writes LF
+pub(super) fn same_ignoring_line_endings(upstream: Option<&str>, local: Option<&str>) -> bool {
+    changes_ignoring(upstream.unwrap_or_default(), Noise::LineEndings)
+        == changes_ignoring(local.unwrap_or_default(), Noise::LineEndings)
+}
+
+#[derive(Copy, Clone)]
+enum Noise {
+    /// Trailing whitespace, including `\r`, and blank lines
+    TrailingWhitespace,
+    /// A trailing `\r`
+    LineEndings,
+}
+
+impl Noise {
+    fn trim(self, line: &str) -> &str {
+        match self {
+            Noise::TrailingWhitespace => line.trim_end(),
+            Noise::LineEndings => line.strip_suffix('\r').unwrap_or(line),
+        }
+    }
+
+    fn ignores(self, removed: &[&str], added: &[&str]) -> bool {
+        removed == added
+            || matches!(self, Noise::TrailingWhitespace)
+                && removed.iter().chain(added).all(|line| line.is_empty())
+    }
+}
+
+/// The changed lines of each `Diff in <file> at line <n>:` hunk with `noise` trimmed off,
+/// hunks that only differ in `noise` are left out.
+/// Headers are left out too, an added blank line shifts the line numbers of later hunks
+fn changes_ignoring(diff: &str, noise: Noise) -> Vec<String> {
+    let mut changes = vec![];
+    let mut removed = vec![];
+    let mut added = vec![];
+    let mut hunk = vec![];
+    let mut flush = |removed: &mut Vec<&str>, added: &mut Vec<&str>, hunk: &mut Vec<String>| {
+        if !noise.ignores(removed, added) {
+            changes.append(hunk);
+        }
+        hunk.clear();
+        removed.clear();
+        added.clear();
+    };
+    // Not `lines`, it would drop the `\r` of CRLF line endings
+    for line in diff.split('\n') {
+        if line.starts_with("Diff in ") {
+            flush(&mut removed, &mut added, &mut hunk);
+            continue;
+        }
+        let (side, content) = match line.split_at_checked(1) {
+            Some(("-", content)) => (&mut removed, content),
+            Some(("+", content)) => (&mut added, content),
+            _ => continue,
+        };
+        let trimmed = noise.trim(content);
+        side.push(trimmed);
+        hunk.push(format!("{}{trimmed}", &line[..1]));
+    }
+    flush(&mut removed, &mut added, &mut hunk);
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACING: &str = "Diff in src/lib.rs at line 1:\n-fn  fixture() {}\n+fn fixture() {}\n";
+
+    #[test]
+    fn trailing_whitespace_and_blank_lines_are_ignored() {
+        let trailing = "Diff in src/lib.rs at line 1:\n-fn fixture() {}  \t\n+fn fixture() {}\n";
+        let crlf = "Diff in src/lib.rs at line 1:\n-fn fixture() {}\r\n+fn fixture() {}\n";
+        let blank = "Diff in src/lib.rs at line 3:\n+\n+\nDiff in src/lib.rs at line 9:\n-\n";
+        for diff in [trailing, crlf, blank] {
+            assert!(same_ignoring_whitespace(Some(diff), None), "{diff:?}");
+        }
+        // Line numbers shifted by an added blank line
+        let shifted = format!("{blank}{}", SPACING.replace("line 1", "line 12"));
+        assert!(same_ignoring_whitespace(Some(&shifted), Some(SPACING)));
+    }
+
+    #[test]
+    fn spacing_and_indentation_still_diverge() {
+        let indent = "Diff in src/lib.rs at line 2:\n-    let a = 1;\n+  let a = 1;\n";
+        let joined = "Diff in src/lib.rs at line 2:\n-call(a,\n-b);\n+call(a,b);\n";
+        let blank_and_code = "Diff in src/lib.rs at line 2:\n+\n+fn added() {}\n";
+        for diff in [SPACING, indent, joined, blank_and_code] {
+            assert!(!same_ignoring_whitespace(Some(diff), None), "{diff:?}");
+        }
+    }
+
+    #[test]
+    fn only_carriage_returns_are_line_endings() {
+        let crlf = SPACING.replace('\n', "\r\n");
+        assert!(same_ignoring_line_endings(Some(&crlf), Some(SPACING)));
+        let trailing = "Diff in src/lib.rs at line 1:\n-fn fixture() {}  \n+fn fixture() {}\n";
+        assert!(!same_ignoring_line_endings(Some(trailing), None));
+        let blank = "Diff in src/lib.rs at line 3:\n+\n";
+        assert!(!same_ignoring_line_endings(Some(blank), None));
+    }
+}
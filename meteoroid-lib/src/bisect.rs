@@ -0,0 +1,272 @@
+use crate::MeteroidError;
+use crate::analyze::{FmtOpts, regresses, virtual_workspace_members};
+use crate::cmd::{CargoCommand, RustFmtBuildOutputs, build_rustfmt, output_string};
+use crate::fs::Workdir;
+use crate::git::CrateReadyForAnalysis;
+use crate::local_crates::verify_crate_in;
+use crate::unpack;
+use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::Instrument;
+
+// A bisect over more commits than this would need more than 2^64 commits
+const MAX_BISECT_STEPS: usize = 64;
+
+pub struct BisectConfig {
+    pub workdir: PathBuf,
+    pub rustfmt_repo: PathBuf,
+    pub rustfmt_upstream_repo: PathBuf,
+    /// The crate that diverges
+    pub crate_dir: PathBuf,
+    /// A commit in `rustfmt_repo` where the crate doesn't diverge,
+    /// defaults to where `rustfmt_repo` branched off of upstream's `HEAD`
+    pub good: Option<String>,
+    /// A commit in `rustfmt_repo` where the crate diverges, defaults to `HEAD`
+    pub bad: Option<String>,
+    pub config: Option<String>,
+    pub unstable_features: bool,
+    pub timeout: Duration,
+    pub cargo: CargoCommand,
+}
+
+pub struct BisectOutcome {
+    pub culprit: String,
+    /// One line summary of the culprit commit
+    pub summary: String,
+}
+
+/// Finds the first commit in the local `rustfmt` repo that makes the crate diverge from upstream.
+/// The bisect runs in a separate worktree under the workdir, so the local repo's checkout
+/// isn't touched, the worktree is kept between runs so that builds are incremental
+pub async fn bisect(config: BisectConfig) -> Result<BisectOutcome, MeteroidError> {
+    let prepared = prepare_bisect(&config)
+        .await
+        .map_err(MeteroidError::setup)?;
+    run_bisect(&config, &prepared)
+        .await
+        .map_err(MeteroidError::analysis)
+}
+
+/// What the bisect runs against, set up before any commit is checked
+struct PreparedBisect {
+    target: CrateReadyForAnalysis,
+    virtual_members: Option<Vec<PathBuf>>,
+    good: String,
+    bad: String,
+    upstream_build: RustFmtBuildOutputs,
+    worktree: PathBuf,
+}
+
+async fn prepare_bisect(config: &BisectConfig) -> anyhow::Result<PreparedBisect> {
+    let wd = Workdir::new(config.workdir.clone());
+    wd.ensure_workdir().await?;
+    let target = verify_crate_in(config.crate_dir.clone())
+        .await
+        .with_context(|| format!("invalid crate at {}", config.crate_dir.display()))?;
+    let bad = rev_parse(
+        &config.rustfmt_repo,
+        config.bad.as_deref().unwrap_or("HEAD"),
+    )
+    .await?;
+    let good = if let Some(good) = config.good.as_deref() {
+        rev_parse(&config.rustfmt_repo, good).await?
+    } else {
+        let upstream_head = rev_parse(&config.rustfmt_upstream_repo, "HEAD").await?;
+        git(&config.rustfmt_repo, &["merge-base", &bad, &upstream_head])
+            .await
+            .context("failed to find where the local rustfmt repo branched off upstream, supply a good commit explicitly")?
+            .trim()
+            .to_string()
+    };
+    tracing::info!(
+        "bisecting {} between good={good} and bad={bad}",
+        target.pruned_crate.crate_name
+    );
+    let upstream_build = build_rustfmt(&config.rustfmt_upstream_repo, &config.cargo)
+        .instrument(tracing::info_span!("build_rustfmt", side = "upstream"))
+        .await?;
+    let worktree = wd.base.join("rustfmt-bisect");
+    prepare_worktree(&config.rustfmt_repo, &worktree, &bad).await?;
+    let virtual_members = virtual_workspace_members(&target).await;
+    Ok(PreparedBisect {
+        target,
+        virtual_members,
+        good,
+        bad,
+        upstream_build,
+        worktree,
+    })
+}
+
+async fn run_bisect(
+    config: &BisectConfig,
+    prepared: &PreparedBisect,
+) -> anyhow::Result<BisectOutcome> {
+    let PreparedBisect {
+        target,
+        virtual_members,
+        good,
+        bad,
+        upstream_build,
+        worktree,
+    } = prepared;
+    let checker = Checker {
+        worktree,
+        upstream_build,
+        target,
+        virtual_members: virtual_members.as_deref(),
+        opts: FmtOpts {
+            config: config.config.as_deref(),
+            timeout: config.timeout,
+            // The crate has a manifest, so files are never passed to `rustfmt` directly
+            max_file_bytes: None,
+            unstable_features: config.unstable_features,
+            cargo: &config.cargo,
+        },
+    };
+    if checker.check(bad).await? != Some(true) {
+        bail!("the crate doesn't diverge at bad={bad}, nothing to bisect");
+    }
+    if checker.check(good).await? != Some(false) {
+        bail!("the crate already diverges at good={good}, supply an earlier good commit");
+    }
+    git(worktree, &["bisect", "start", bad, good]).await?;
+    let res = checker.run_bisect().await;
+    if let Err(e) = git(worktree, &["bisect", "reset"]).await {
+        tracing::warn!(
+            "failed to reset bisect in {}: {}",
+            worktree.display(),
+            unpack(&*e)
+        );
+    }
+    let culprit = res?;
+    let summary = git(worktree, &["show", "--no-patch", "--oneline", &culprit])
+        .await?
+        .trim()
+        .to_string();
+    Ok(BisectOutcome { culprit, summary })
+}
+
+struct Checker<'a> {
+    worktree: &'a Path,
+    upstream_build: &'a RustFmtBuildOutputs,
+    target: &'a CrateReadyForAnalysis,
+    /// Read once, the crate's checkout doesn't change during the bisect
+    virtual_members: Option<&'a [PathBuf]>,
+    opts: FmtOpts<'a>,
+}
+
+impl Checker<'_> {
+    async fn run_bisect(&self) -> anyhow::Result<String> {
+        for _ in 0..MAX_BISECT_STEPS {
+            let head = rev_parse(self.worktree, "HEAD").await?;
+            let verdict = match self.check_checked_out(&head).await? {
+                Some(true) => "bad",
+                Some(false) => "good",
+                None => "skip",
+            };
+            let output = git(self.worktree, &["bisect", verdict]).await?;
+            if let Some(culprit) = parse_first_bad_commit(&output) {
+                return Ok(culprit);
+            }
+            if output.contains("only skipped commits left to test") {
+                bail!(
+                    "couldn't narrow down the culprit, remaining commits failed to build:\n{output}"
+                );
+            }
+        }
+        bail!("bisect didn't converge after {MAX_BISECT_STEPS} steps")
+    }
+
+    async fn check(&self, commit: &str) -> anyhow::Result<Option<bool>> {
+        git(self.worktree, &["checkout", "--detach", commit]).await?;
+        self.check_checked_out(commit).await
+    }
+
+    /// `Some(true)` if the crate diverges at the checked out commit,
+    /// `None` if `rustfmt` couldn't be built there
+    async fn check_checked_out(&self, commit: &str) -> anyhow::Result<Option<bool>> {
+        let build = match build_rustfmt(self.worktree, self.opts.cargo)
+            .instrument(tracing::info_span!(
+                "build_rustfmt",
+                side = "bisect",
+                commit
+            ))
+            .await
+        {
+            Ok(build) => build,
+            Err(e) => {
+                tracing::warn!(
+                    "failed to build rustfmt at {commit}, skipping: {}",
+                    unpack(&*e)
+                );
+                return Ok(None);
+            }
+        };
+        let diverges = regresses(
+            self.target,
+            self.virtual_members,
+            &build,
+            self.upstream_build,
+            self.opts,
+        )
+        .await;
+        tracing::info!("{commit} is {}", if diverges { "bad" } else { "good" });
+        Ok(Some(diverges))
+    }
+}
+
+async fn prepare_worktree(
+    rustfmt_repo: &Path,
+    worktree: &Path,
+    commit: &str,
+) -> anyhow::Result<()> {
+    if tokio::fs::try_exists(worktree)
+        .await
+        .with_context(|| format!("failed to check if {} exists", worktree.display()))?
+    {
+        // Left over from an interrupted run
+        let _ = git(worktree, &["bisect", "reset"]).await;
+        git(worktree, &["checkout", "--detach", commit]).await?;
+        return Ok(());
+    }
+    let worktree_str = worktree
+        .to_str()
+        .with_context(|| format!("worktree path {} is not valid utf8", worktree.display()))?;
+    git(
+        rustfmt_repo,
+        &["worktree", "add", "--detach", worktree_str, commit],
+    )
+    .await?;
+    Ok(())
+}
+
+async fn rev_parse(repo: &Path, rev: &str) -> anyhow::Result<String> {
+    let out = git(
+        repo,
+        &["rev-parse", "--verify", &format!("{rev}^{{commit}}")],
+    )
+    .await
+    .with_context(|| format!("failed to resolve '{rev}' in {}", repo.display()))?;
+    Ok(out.trim().to_string())
+}
+
+async fn git(cwd: &Path, args: &[&str]) -> anyhow::Result<String> {
+    output_string(
+        Command::new("git")
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .current_dir(cwd),
+    )
+    .await
+}
+
+// Looks like `<sha> is the first bad commit`
+fn parse_first_bad_commit(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        line.strip_suffix(" is the first bad commit")
+            .map(|sha| sha.trim().to_string())
+    })
+}
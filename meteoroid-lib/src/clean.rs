@@ -0,0 +1,113 @@
+use crate::MeteroidError;
+use crate::fs::Workdir;
+use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+
+/// Which parts of the workdir to remove
+#[derive(Debug, Copy, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CleanOpts {
+    /// Cloned repositories, the directories directly under the workdir
+    pub clones: bool,
+    /// The downloaded crates index (`crates.csv` and `versions.csv`)
+    pub index: bool,
+    /// Everything in the workdir
+    pub all: bool,
+    /// Only collect what would be removed, don't remove anything
+    pub dry_run: bool,
+}
+
+/// Removes the parts of the workdir selected by `opts`,
+/// returns the removed paths (or the ones that would be removed on a dry run)
+pub async fn clean(workdir: PathBuf, opts: CleanOpts) -> Result<Vec<PathBuf>, MeteroidError> {
+    clean_workdir(workdir, opts)
+        .await
+        .map_err(MeteroidError::setup)
+}
+
+async fn clean_workdir(workdir: PathBuf, opts: CleanOpts) -> anyhow::Result<Vec<PathBuf>> {
+    let wd = Workdir::new(workdir);
+    if !tokio::fs::try_exists(&wd.base)
+        .await
+        .with_context(|| format!("failed to check if workdir exists at {}", wd.base.display()))?
+    {
+        tracing::info!("no workdir at {}, nothing to clean", wd.base.display());
+        return Ok(vec![]);
+    }
+    let base = tokio::fs::canonicalize(&wd.base)
+        .await
+        .with_context(|| format!("failed to canonicalize workdir {}", wd.base.display()))?;
+    let mut targets = vec![];
+    if opts.all || opts.clones {
+        let mut rd = tokio::fs::read_dir(&base)
+            .await
+            .with_context(|| format!("failed to read workdir {}", base.display()))?;
+        while let Some(ent) = rd
+            .next_entry()
+            .await
+            .with_context(|| format!("failed to read entry in workdir {}", base.display()))?
+        {
+            let ft = ent
+                .file_type()
+                .await
+                .with_context(|| format!("failed to get file type of {}", ent.path().display()))?;
+            if opts.all || ft.is_dir() {
+                targets.push(ent.path());
+            }
+        }
+    }
+    if opts.index && !opts.all {
+        for csv in [
+            &wd.crates_csv,
+            &wd.versions_csv,
+            &wd.prev_versions_csv,
+            &wd.selection_cache,
+        ] {
+            if tokio::fs::try_exists(csv)
+                .await
+                .with_context(|| format!("failed to check if {} exists", csv.display()))?
+            {
+                targets.push(base.join(csv.file_name().unwrap_or_default()));
+            }
+        }
+    }
+    targets.sort();
+    for target in &targets {
+        ensure_inside(&base, target)?;
+        if opts.dry_run {
+            continue;
+        }
+        remove(target).await?;
+        tracing::debug!("removed {}", target.display());
+    }
+    Ok(targets)
+}
+
+fn ensure_inside(base: &Path, target: &Path) -> anyhow::Result<()> {
+    // Targets are direct children of the canonical workdir, anything else is a bug
+    // and shouldn't be deleted
+    if target.parent() != Some(base) || target.file_name().is_none_or(|n| n == "..") {
+        bail!(
+            "refusing to remove {}, it's not inside the workdir at {}",
+            target.display(),
+            base.display()
+        );
+    }
+    Ok(())
+}
+
+async fn remove(target: &Path) -> anyhow::Result<()> {
+    // Don't follow symlinks, remove the link itself
+    let md = tokio::fs::symlink_metadata(target)
+        .await
+        .with_context(|| format!("failed to read metadata of {}", target.display()))?;
+    if md.is_dir() {
+        tokio::fs::remove_dir_all(target)
+            .await
+            .with_context(|| format!("failed to remove directory {}", target.display()))
+    } else {
+        tokio::fs::remove_file(target)
+            .await
+            .with_context(|| format!("failed to remove file {}", target.display()))
+    }
+}
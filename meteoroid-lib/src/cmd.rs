@@ -1,35 +1,217 @@
 use crate::unpack;
 use anyhow::{Context, bail};
+use std::fmt::{Display, Formatter};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Command;
 
 pub(crate) async fn output_string(cmd: &mut Command) -> anyhow::Result<String> {
+    output_string_redacted(cmd, None).await
+}
+
+/// Like `output_string`, but with `secret` left out of errors, they include the command's env
+pub(crate) async fn output_string_redacted(
+    cmd: &mut Command,
+    secret: Option<&str>,
+) -> anyhow::Result<String> {
+    let redact = |msg: String| match secret {
+        Some(secret) => msg.replace(secret, "<redacted>"),
+        None => msg,
+    };
     let output = cmd
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .output()
         .await
-        .with_context(|| format!("failed to run command: {cmd:?}"))?;
+        .with_context(|| redact(format!("failed to run command: {cmd:?}")))?;
     if !output.status.success() {
         let stdout = String::from_utf8_lossy(output.stdout.as_slice());
         let stderr = String::from_utf8_lossy(output.stderr.as_slice());
-        anyhow::bail!("command failed: {cmd:?}\nstdout: {stdout:?}\nstderr: {stderr:?}");
+        anyhow::bail!(
+            "{}",
+            redact(format!(
+                "command failed: {cmd:?}\nstdout: {stdout:?}\nstderr: {stderr:?}"
+            ))
+        );
     }
     Ok(String::from_utf8_lossy(output.stdout.as_slice()).to_string())
 }
 
+/// Installs the toolchain that `rustfmt_source_dir` uses (from its `rust-toolchain` file),
+/// does nothing if it's already installed.
+/// Returns the toolchain name
+pub(crate) async fn install_rustfmt_toolchain(rustfmt_source_dir: &Path) -> anyhow::Result<String> {
+    output_string(
+        Command::new("rustup")
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .arg("toolchain")
+            .arg("install")
+            .arg("--no-self-update")
+            .arg("--no-update")
+            .current_dir(rustfmt_source_dir),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "failed to install toolchain for {}",
+            rustfmt_source_dir.display()
+        )
+    })?;
+    let active = output_string(
+        Command::new("rustup")
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .arg("show")
+            .arg("active-toolchain")
+            .current_dir(rustfmt_source_dir),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "failed to check active toolchain in {}",
+            rustfmt_source_dir.display()
+        )
+    })?;
+    Ok(active
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
 pub(crate) enum RustfmtOutput {
-    Success,
-    Diff(String),
+    Success(CheckOutput),
+    Diff(CheckOutput),
+    /// `rustfmt` panicked, carries the panic message
+    Panic(String),
+    /// `rustfmt` didn't finish within the timeout, carries the timeout
+    Timeout(Duration),
     Failure(anyhow::Error),
 }
 
+/// What a `--check` run that didn't fail printed
+#[derive(Debug, Default)]
+pub(crate) struct CheckOutput {
+    pub(crate) diff: Option<String>,
+    /// Anything written to stderr, `rustfmt` warns there about ignored or unstable options
+    /// even when it succeeds
+    pub(crate) warnings: Option<String>,
+}
+
+impl RustfmtOutput {
+    /// Panics become a [`RustfmtPanic`] error, timeouts a [`RustfmtTimeout`]
+    pub(crate) fn into_result(self) -> anyhow::Result<CheckOutput> {
+        match self {
+            Self::Success(out) | Self::Diff(out) => Ok(out),
+            Self::Panic(message) => Err(RustfmtPanic(message).into()),
+            Self::Timeout(timeout) => Err(RustfmtTimeout(timeout).into()),
+            Self::Failure(e) => Err(e),
+        }
+    }
+}
+
+/// Kept apart from other failures since a panic in `rustfmt` is always a bug,
+/// can be found with `downcast_ref` on the failure
+#[derive(Debug)]
+pub(crate) struct RustfmtPanic(pub(crate) String);
+
+impl Display for RustfmtPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rustfmt panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for RustfmtPanic {}
+
+/// Hangs often point at pathological input, so they're kept apart from other failures,
+/// can be found with `downcast_ref` on the failure
+#[derive(Debug)]
+pub(crate) struct RustfmtTimeout(pub(crate) Duration);
+
+impl Display for RustfmtTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rustfmt timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RustfmtTimeout {}
+
+/// A failure that's likely down to the machine rather than the crate or `rustfmt`,
+/// ex: a full disk or a held lock, worth trying again.
+/// Can be found with `downcast_ref` on the failure
+#[derive(Debug)]
+pub(crate) struct EnvironmentalFailure(pub(crate) String);
+
+impl Display for EnvironmentalFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for EnvironmentalFailure {}
+
+// What `cargo`, `rustup` and the OS print when they can't get what they need,
+// none of these come from `rustfmt` failing to format
+const ENVIRONMENTAL_ERRORS: &[&str] = &[
+    "Blocking waiting for file lock",
+    "No space left on device",
+    "Too many open files",
+    "Resource temporarily unavailable",
+    "Cannot allocate memory",
+    "Text file busy",
+    "Stale file handle",
+];
+
+/// `cargo`'s `could not lock ... file lock` when another process holds its lock, a bare
+/// `could not lock` could as well be `rustfmt` quoting the source it failed on
+fn is_cargo_lock_failure(line: &str) -> bool {
+    line.split_once("could not lock")
+        .is_some_and(|(_, rest)| rest.contains("file lock"))
+}
+
+fn command_failure(message: String, stderr: &str) -> anyhow::Error {
+    if stderr.lines().any(|line| {
+        is_cargo_lock_failure(line) || ENVIRONMENTAL_ERRORS.iter().any(|err| line.contains(err))
+    }) {
+        EnvironmentalFailure(message).into()
+    } else {
+        anyhow::anyhow!(message)
+    }
+}
+
+/// How `cargo` is invoked to build `rustfmt` and to run `cargo fmt`, ex: a wrapper script
+/// in place of `cargo`, or extra flags such as `--offline`
+#[derive(Debug, Clone)]
+pub struct CargoCommand {
+    pub bin: PathBuf,
+    /// Passed before the subcommand
+    pub args: Vec<String>,
+}
+
+impl Default for CargoCommand {
+    fn default() -> Self {
+        Self {
+            bin: PathBuf::from("cargo"),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl CargoCommand {
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.bin);
+        cmd.args(&self.args);
+        cmd
+    }
+}
+
 pub(crate) async fn build_rustfmt(
     rustfmt_source_dir: &Path,
+    cargo: &CargoCommand,
 ) -> anyhow::Result<RustFmtBuildOutputs> {
-    let output = Command::new("cargo")
+    let output = cargo
+        .command()
         .env_remove("RUSTUP_TOOLCHAIN")
         .arg("build")
         .arg("--release")
@@ -54,6 +236,47 @@ pub(crate) async fn build_rustfmt(
             rustfmt_source_dir.display()
         );
     }
+    let built = locate_built_rustfmt(rustfmt_source_dir).await?;
+    tracing::info!(
+        "built rustfmt binary at {} with LD_LIBRARY_PATH at {}",
+        built.built_binary_path.display(),
+        built.toolchain_lib_path.0.display()
+    );
+    Ok(built)
+}
+
+/// A `rustfmt` that was built elsewhere, run with the `LD_LIBRARY_PATH` meteoroid was started with
+pub(crate) async fn prebuilt_rustfmt(binary: &Path) -> anyhow::Result<RustFmtBuildOutputs> {
+    if !tokio::fs::try_exists(binary)
+        .await
+        .with_context(|| format!("failed to check if {} exists", binary.display()))?
+    {
+        bail!(
+            "prebuilt rustfmt binary at {} does not exist",
+            binary.display()
+        );
+    }
+    Ok(RustFmtBuildOutputs {
+        built_binary_path: binary.to_path_buf(),
+        toolchain_lib_path: ToolchainLibPath(
+            std::env::var_os("LD_LIBRARY_PATH").map_or_else(PathBuf::new, PathBuf::from),
+        ),
+        build_error: None,
+    })
+}
+
+/// The binary left by an earlier build in `rustfmt_source_dir`, used when building fails
+/// and setup failures shouldn't abort the run
+pub(crate) async fn previous_rustfmt_build(
+    rustfmt_source_dir: &Path,
+    build_error: String,
+) -> anyhow::Result<RustFmtBuildOutputs> {
+    let mut previous = locate_built_rustfmt(rustfmt_source_dir).await?;
+    previous.build_error = Some(build_error);
+    Ok(previous)
+}
+
+async fn locate_built_rustfmt(rustfmt_source_dir: &Path) -> anyhow::Result<RustFmtBuildOutputs> {
     let expected_built_binary = rustfmt_source_dir
         .join("target")
         .join("release")
@@ -75,14 +298,10 @@ pub(crate) async fn build_rustfmt(
     let toolchain_lib_path = locate_rustfmt_toolchain(rustfmt_source_dir)
         .await
         .context("failed to locate toolchain lib path")?;
-    tracing::info!(
-        "built rustfmt binary at {} with LD_LIBRARY_PATH at {}",
-        expected_built_binary.display(),
-        toolchain_lib_path.0.display()
-    );
     Ok(RustFmtBuildOutputs {
         built_binary_path: expected_built_binary,
         toolchain_lib_path,
+        build_error: None,
     })
 }
 
@@ -90,6 +309,8 @@ pub(crate) async fn build_rustfmt(
 pub struct RustFmtBuildOutputs {
     pub built_binary_path: PathBuf,
     pub toolchain_lib_path: ToolchainLibPath,
+    /// Why building failed, if this is a previous build that was fallen back to
+    pub(crate) build_error: Option<String>,
 }
 
 #[derive(Clone)]
@@ -187,6 +408,16 @@ async fn try_find_toolchain_lib_dir(toolchain: &str) -> anyhow::Result<PathBuf>
     );
 }
 
+/// Too many open files, `ErrorKind` has no stable variant for it
+const EMFILE: i32 = 24;
+
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::StorageFull
+    ) || e.raw_os_error() == Some(EMFILE)
+}
+
 pub(crate) async fn run_rustfmt(cmd: &mut Command, timeout: Duration) -> RustfmtOutput {
     let out = match tokio::time::timeout(
         timeout,
@@ -199,32 +430,54 @@ pub(crate) async fn run_rustfmt(cmd: &mut Command, timeout: Duration) -> Rustfmt
     {
         Ok(Ok(out)) => out,
         Ok(Err(e)) => {
-            return RustfmtOutput::Failure(anyhow::anyhow!(
-                "command failed to finish: {}, cmd={cmd:?}",
-                unpack(&e)
-            ));
+            // Never got to run `rustfmt`, only worth retrying if the machine was out of
+            // something for a moment, a missing or non-executable binary stays that way
+            let message = format!("command failed to finish: {}, cmd={cmd:?}", unpack(&e));
+            return RustfmtOutput::Failure(if is_transient(&e) {
+                EnvironmentalFailure(message).into()
+            } else {
+                anyhow::anyhow!(message)
+            });
         }
         Err(_e) => {
-            return RustfmtOutput::Failure(anyhow::anyhow!("command timed out, cmd={cmd:?}"));
+            tracing::debug!("command timed out after {timeout:?}, cmd={cmd:?}");
+            return RustfmtOutput::Timeout(timeout);
         }
     };
+    let warnings = Some(
+        String::from_utf8_lossy(out.stderr.as_slice())
+            .trim()
+            .to_string(),
+    )
+    .filter(|stderr| !stderr.is_empty());
     if out.status.success() {
-        return RustfmtOutput::Success;
+        return RustfmtOutput::Success(CheckOutput {
+            diff: None,
+            warnings,
+        });
+    }
+    if let Some(message) = panic_message(&String::from_utf8_lossy(out.stderr.as_slice())) {
+        return RustfmtOutput::Panic(message);
     }
     if let Some(1) = out.status.code() {
         if out.stdout.is_empty() {
-            return RustfmtOutput::Failure(anyhow::anyhow!(
-                "command failed: {cmd:?}\nstderr: {}",
-                String::from_utf8_lossy(out.stderr.as_slice())
+            let stderr = String::from_utf8_lossy(out.stderr.as_slice());
+            return RustfmtOutput::Failure(command_failure(
+                format!("command failed: {cmd:?}\nstderr: {stderr}"),
+                &stderr,
             ));
         }
         let stdout = String::from_utf8_lossy(out.stdout.as_slice()).to_string();
-        return RustfmtOutput::Diff(stdout);
+        return RustfmtOutput::Diff(CheckOutput {
+            diff: Some(stdout),
+            warnings,
+        });
     }
     let stdout = String::from_utf8_lossy(out.stdout.as_slice());
     let stderr = String::from_utf8_lossy(out.stderr.as_slice());
-    RustfmtOutput::Failure(anyhow::anyhow!(
-        "command failed: {cmd:?}\nstdout: {stdout:?}\nstderr: {stderr:?}"
+    RustfmtOutput::Failure(command_failure(
+        format!("command failed: {cmd:?}\nstdout: {stdout:?}\nstderr: {stderr:?}"),
+        &stderr,
     ))
 }
 
@@ -279,3 +532,110 @@ pub(crate) async fn try_diff(
         ))
     }
 }
+
+/// The `thread '...' panicked at` line, and the message on the line after it
+/// on toolchains that put it there
+fn panic_message(stderr: &str) -> Option<String> {
+    let mut lines = stderr.lines();
+    let panicked =
+        lines.find(|line| line.starts_with("thread '") && line.contains("' panicked at "))?;
+    let message = lines.next().filter(|line| {
+        !line.starts_with("note:") && !line.starts_with("stack backtrace:") && !line.is_empty()
+    });
+    Some(match message {
+        Some(message) => format!("{panicked}\n{message}"),
+        None => panicked.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in for `rustfmt` that runs `script`
+    async fn run_script(script: &str) -> RustfmtOutput {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        run_rustfmt(&mut cmd, Duration::from_mins(1)).await
+    }
+
+    #[tokio::test]
+    async fn panic_stderr_is_a_panic() {
+        let out = run_script(
+            "echo \"thread 'main' panicked at src/lib.rs:1:1:\" >&2\n\
+             echo 'index out of bounds' >&2\n\
+             echo 'note: run with `RUST_BACKTRACE=1`' >&2\n\
+             exit 101",
+        )
+        .await;
+        let RustfmtOutput::Panic(message) = out else {
+            panic!("expected a panic");
+        };
+        assert_eq!(
+            "thread 'main' panicked at src/lib.rs:1:1:\nindex out of bounds",
+            message
+        );
+        let out = run_script("echo 'error: expected item' >&2\nexit 2").await;
+        assert!(matches!(out, RustfmtOutput::Failure(_)));
+    }
+
+    #[tokio::test]
+    async fn warnings_on_success_are_captured() {
+        let out = run_script("echo 'Warning: option `foo` is unstable' >&2").await;
+        let RustfmtOutput::Success(out) = out else {
+            panic!("expected a success");
+        };
+        assert_eq!(None, out.diff);
+        assert_eq!(
+            Some("Warning: option `foo` is unstable".to_string()),
+            out.warnings
+        );
+        let RustfmtOutput::Success(out) = run_script("true").await else {
+            panic!("expected a success");
+        };
+        assert_eq!(None, out.warnings);
+    }
+
+    #[tokio::test]
+    async fn hang_is_a_timeout() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let timeout = Duration::from_millis(100);
+        let out = run_rustfmt(&mut cmd, timeout).await;
+        assert!(matches!(out, RustfmtOutput::Timeout(after) if after == timeout));
+        let err = out.into_result().unwrap_err();
+        assert!(err.downcast_ref::<RustfmtTimeout>().is_some(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn missing_binary_is_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cmd = Command::new(dir.path().join("rustfmt"));
+        let RustfmtOutput::Failure(err) = run_rustfmt(&mut cmd, Duration::from_mins(1)).await
+        else {
+            panic!("expected a failure");
+        };
+        assert!(!err.is::<EnvironmentalFailure>(), "{err}");
+        assert!(is_transient(&std::io::Error::from_raw_os_error(EMFILE)));
+        assert!(is_transient(&std::io::ErrorKind::WouldBlock.into()));
+        assert!(!is_transient(&std::io::ErrorKind::PermissionDenied.into()));
+    }
+
+    #[tokio::test]
+    async fn only_cargos_lock_message_is_environmental() {
+        let out = run_script(
+            "echo 'error: could not lock the package cache, waiting for file lock' >&2\nexit 1",
+        )
+        .await;
+        let RustfmtOutput::Failure(err) = out else {
+            panic!("expected a failure");
+        };
+        assert!(err.is::<EnvironmentalFailure>(), "{err}");
+        let out =
+            run_script("echo 'error: could not lock the mutex in src/lib.rs' >&2\nexit 1").await;
+        let RustfmtOutput::Failure(err) = out else {
+            panic!("expected a failure");
+        };
+        assert!(!err.is::<EnvironmentalFailure>(), "{err}");
+    }
+}
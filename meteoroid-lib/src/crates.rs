@@ -1,21 +1,27 @@
 pub(crate) mod api;
+pub(crate) mod builtin_corpus;
 pub(crate) mod crate_consumer;
 pub(crate) mod csv_parse;
+pub(crate) mod index_diff;
+pub(crate) mod selection_cache;
 
-use crate::error::unpack;
 use anyhow::Context;
 use futures::StreamExt;
 use reqwest::Response;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::TrySendError;
+use tokio::io::AsyncWriteExt;
 
-pub(crate) async fn update_index_to(path: &Path) -> anyhow::Result<()> {
-    const TAR_URL: &str = "https://static.crates.io/db-dump.tar.gz";
-    let client = reqwest::Client::builder()
+pub(crate) fn http_client() -> anyhow::Result<reqwest::Client> {
+    reqwest::Client::builder()
         .user_agent("meteoroid-marcus.grass@protonmail.com")
         .use_rustls_tls()
         .build()
-        .context("failed to build reqwest client")?;
+        .context("failed to build reqwest client")
+}
+
+pub(crate) async fn update_index_to(path: &Path) -> anyhow::Result<()> {
+    const TAR_URL: &str = "https://static.crates.io/db-dump.tar.gz";
+    let client = http_client()?;
     tracing::debug!("fetching crates index tar from {}", TAR_URL);
     let resp = client
         .get(TAR_URL)
@@ -29,82 +35,44 @@ pub(crate) async fn update_index_to(path: &Path) -> anyhow::Result<()> {
         "got success response from {}, starting stream decode",
         TAR_URL
     );
-    let reader = response_reader(resp);
-    untar_gzipped(reader, path.to_path_buf()).await?;
+    // Spooled to disk so that memory use doesn't depend on the size of the dump
+    let spool = tempfile::NamedTempFile::new_in(path)
+        .with_context(|| format!("failed to create a temp file in {}", path.display()))?;
+    download_to(resp, &spool).await?;
+    untar_gzipped(spooled_reader(&spool, IO_BUF_BYTES)?, path.to_path_buf()).await?;
     Ok(())
 }
 
-fn response_reader(response: Response) -> AsyncReadShim {
-    let (send, recv) = std::sync::mpsc::sync_channel(32);
-    tokio::task::spawn(async move {
-        let mut stream = response.bytes_stream();
-        while let Some(next) = stream.next().await {
-            let data = match next {
-                Ok(d) => d,
-                Err(e) => {
-                    tracing::error!("failed to read from response stream: {}", unpack(&e));
-                    break;
-                }
-            };
-            // This construction is not ideal, timed poll ready on a sync channel
-            loop {
-                match send.try_send(data.to_vec()) {
-                    Ok(()) => {
-                        break;
-                    }
-                    Err(TrySendError::Disconnected(_)) => {
-                        tracing::debug!(
-                            "tar response sender closed, aborting read (this could happen because it finished early)"
-                        );
-                        return;
-                    }
-                    Err(TrySendError::Full(_)) => {
-                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
-                    }
-                }
-            }
-        }
-    });
-    AsyncReadShim {
-        recv,
-        overflow: vec![],
-    }
-}
+const IO_BUF_BYTES: usize = 64 * 1024;
 
-struct AsyncReadShim {
-    recv: std::sync::mpsc::Receiver<Vec<u8>>,
-    overflow: Vec<u8>,
+/// Reads the spooled tar back `capacity` bytes at a time
+fn spooled_reader(
+    spool: &tempfile::NamedTempFile,
+    capacity: usize,
+) -> anyhow::Result<std::io::BufReader<std::fs::File>> {
+    let file = spool
+        .reopen()
+        .context("failed to reopen the downloaded crates index tar")?;
+    Ok(std::io::BufReader::with_capacity(capacity, file))
 }
 
-impl std::io::Read for AsyncReadShim {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let overflow_len = self.overflow.len();
-        let buf_len = buf.len();
-        if overflow_len > 0 {
-            return if buf_len >= overflow_len {
-                buf[..overflow_len].copy_from_slice(&self.overflow);
-                self.overflow = vec![];
-                Ok(overflow_len)
-            } else {
-                let rem = overflow_len - buf_len;
-                buf.copy_from_slice(&self.overflow[..buf_len]);
-                self.overflow.copy_within(buf_len.., 0);
-                self.overflow.truncate(rem);
-                Ok(buf_len)
-            };
-        }
-        let data = self
-            .recv
-            .recv()
-            .map_err(|_| std::io::Error::other("input channel closed"))?;
-        if buf.len() >= data.len() {
-            buf[..data.len()].copy_from_slice(&data);
-            return Ok(data.len());
-        }
-        buf.copy_from_slice(&data[..buf_len]);
-        self.overflow = data[buf_len..].to_vec();
-        Ok(buf_len)
+async fn download_to(response: Response, spool: &tempfile::NamedTempFile) -> anyhow::Result<()> {
+    let file = spool
+        .reopen()
+        .context("failed to open temp file for the crates index tar")?;
+    let mut out =
+        tokio::io::BufWriter::with_capacity(IO_BUF_BYTES, tokio::fs::File::from_std(file));
+    let mut stream = response.bytes_stream();
+    while let Some(next) = stream.next().await {
+        let data = next.context("failed to read from response stream")?;
+        out.write_all(&data)
+            .await
+            .context("failed to write crates index tar to temp file")?;
     }
+    out.flush()
+        .await
+        .context("failed to flush crates index tar to temp file")?;
+    Ok(())
 }
 
 async fn untar_gzipped<R: std::io::Read + Send + 'static>(
@@ -120,20 +88,39 @@ async fn untar_gzipped<R: std::io::Read + Send + 'static>(
         for ent_res in entries {
             let mut ent = ent_res.context("failed to read tar entry")?;
             let ent_path = ent.path().context("failed to get tar entry path")?;
-            if ent_path.ends_with("versions.csv") {
-                let versions_dest = dest.join("versions.csv");
-                ent.unpack(&versions_dest).with_context(|| {
+            let Some(file_name) = ent_path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            // Older dumps have shipped the csvs compressed individually
+            let (csv_name, gzipped) = match file_name {
+                "versions.csv" => ("versions.csv", false),
+                "versions.csv.gz" => ("versions.csv", true),
+                "crates.csv" => ("crates.csv", false),
+                "crates.csv.gz" => ("crates.csv", true),
+                _ => continue,
+            };
+            let csv_dest = dest.join(csv_name);
+            if gzipped {
+                let mut out = std::fs::File::create(&csv_dest)
+                    .with_context(|| format!("failed to create {}", csv_dest.display()))?;
+                std::io::copy(&mut flate2::read::GzDecoder::new(&mut ent), &mut out).with_context(
+                    || {
+                        format!(
+                            "failed to decompress {csv_name}.gz to {}",
+                            csv_dest.display()
+                        )
+                    },
+                )?;
+            } else {
+                ent.unpack(&csv_dest).with_context(|| {
                     format!("failed to unpack crates index tar at {}", dest.display())
                 })?;
-                tracing::debug!("unpacked versions.csv to {}", versions_dest.display());
+            }
+            tracing::debug!("unpacked {csv_name} to {}", csv_dest.display());
+            if csv_name == "versions.csv" {
                 versions_unpacked = true;
-            } else if ent_path.ends_with("crates.csv") {
-                let crates_dest = dest.join("crates.csv");
-                ent.unpack(&crates_dest).with_context(|| {
-                    format!("failed to unpack crates index tar at {}", dest.display())
-                })?;
+            } else {
                 crates_unpacked = true;
-                tracing::debug!("unpacked crates.csv to {}", crates_dest.display());
             }
             if versions_unpacked && crates_unpacked {
                 tracing::debug!(
@@ -149,3 +136,84 @@ async fn untar_gzipped<R: std::io::Read + Send + 'static>(
     .context("failed to unpack crates index tar")??;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn append(tar: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, path, data).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gzipped_csv_entries_are_decompressed_on_extraction() {
+        let mut tar = tar::Builder::new(Vec::new());
+        append(&mut tar, "2024-01-01/README.md", b"readme");
+        append(
+            &mut tar,
+            "2024-01-01/data/versions.csv.gz",
+            &gzip(b"crate_id\n1\n"),
+        );
+        // Plain and gzipped entries can be mixed
+        append(&mut tar, "2024-01-01/data/crates.csv", b"id\n1\n");
+        let dump = gzip(&tar.into_inner().unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        untar_gzipped(std::io::Cursor::new(dump), dir.path().to_path_buf())
+            .await
+            .unwrap();
+        assert_eq!(
+            "crate_id\n1\n",
+            std::fs::read_to_string(dir.path().join("versions.csv")).unwrap()
+        );
+        assert_eq!(
+            "id\n1\n",
+            std::fs::read_to_string(dir.path().join("crates.csv")).unwrap()
+        );
+        assert!(!dir.path().join("README.md").exists());
+    }
+
+    #[tokio::test]
+    async fn spooled_dump_is_extracted_through_a_small_buffer() {
+        // Much larger than the buffer, so it's read in many small pieces
+        let versions = (0..10_000).fold(String::from("crate_id\n"), |mut csv, id| {
+            csv.push_str(&id.to_string());
+            csv.push('\n');
+            csv
+        });
+        let mut tar = tar::Builder::new(Vec::new());
+        append(
+            &mut tar,
+            "2024-01-01/data/versions.csv",
+            versions.as_bytes(),
+        );
+        append(&mut tar, "2024-01-01/data/crates.csv", b"id\n1\n");
+        let mut spool = tempfile::NamedTempFile::new().unwrap();
+        spool.write_all(&gzip(&tar.into_inner().unwrap())).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        untar_gzipped(
+            spooled_reader(&spool, 16).unwrap(),
+            dir.path().to_path_buf(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            versions,
+            std::fs::read_to_string(dir.path().join("versions.csv")).unwrap()
+        );
+        assert_eq!(
+            "id\n1\n",
+            std::fs::read_to_string(dir.path().join("crates.csv")).unwrap()
+        );
+    }
+}
@@ -28,6 +28,71 @@ pub(crate) struct VersionsEntry<'a> {
     pub(crate) yanked: bool,
 }
 
+impl VersionsEntry<'_> {
+    /// `created_at` is formatted like `2017-02-13 03:45:32.123456`, in UTC.
+    /// Sub-second precision and any trailing timezone offset is ignored
+    pub(crate) fn created_at_unix_seconds(&self) -> Option<u64> {
+        parse_db_timestamp(self.created_at)
+    }
+}
+
+pub(crate) fn parse_db_timestamp(value: &str) -> Option<u64> {
+    let (date, time) = value.trim().split_once([' ', 'T'])?;
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    let mut time_parts = time.splitn(3, ':');
+    let hour: u64 = time_parts.next()?.parse().ok()?;
+    let minute: u64 = time_parts.next()?.parse().ok()?;
+    let second: u64 = time_parts.next()?.get(..2)?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01, from <http://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ`
+pub(crate) fn format_rfc3339(unix_seconds: u64) -> String {
+    let days = i64::try_from(unix_seconds / 86_400).unwrap_or(i64::MAX);
+    let (year, month, day) = civil_from_days(days);
+    let secs_of_day = unix_seconds % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = u32::try_from(day_of_year - (153 * mp + 2) / 5 + 1).unwrap_or(1);
+    let month = u32::try_from(if mp < 10 { mp + 3 } else { mp - 9 }).unwrap_or(1);
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
 #[derive(Default)]
 pub(crate) struct VersionsEntryBuilder<'a> {
     inner: VersionsEntry<'a>,
@@ -41,16 +106,11 @@ impl<'a> VersionsEntryBuilder<'a> {
             1 => self.inner.categories = value,
             2 => self.inner.checksum = value,
             3 => self.inner.crate_id = value.parse().context("failed to parse crate id as u64")?,
-            4 => {
-                self.inner.crate_size =
-                    value.parse().context("failed to parse crate size as u64")?;
-            }
+            4 => self.inner.crate_size = parse_count("crate_size", value),
             5 => self.inner.created_at = value,
             6 => self.inner.description = value,
             7 => self.inner.documentation = value,
-            8 => {
-                self.inner.downloads = value.parse().context("failed to parse downloads as u64")?;
-            }
+            8 => self.inner.downloads = parse_count("downloads", value),
             9 => self.inner.edition = value,
             10 => self.inner.features = value,
             11 => self.inner.has_lib = value,
@@ -66,16 +126,21 @@ impl<'a> VersionsEntryBuilder<'a> {
             21 => self.inner.rust_version = value,
             22 => self.inner.updated_at = value,
             23 => self.inner.yanked = parse_yanked_bool(value)?,
-            overflow => {
-                bail!("too many fields entered in version entry builder: {overflow}");
-            }
+            // Columns appended to the dump after the ones we know of are ignored,
+            // so that schema additions don't break parsing
+            _ => {}
         }
         self.next_field += 1;
-        Ok(self.next_field == 24)
+        Ok(self.next_field >= 24)
+    }
+
+    /// Fields entered beyond the ones that are parsed
+    pub(crate) fn num_extra_fields(&self) -> usize {
+        self.next_field.saturating_sub(24)
     }
 
     pub(crate) fn consume(self) -> anyhow::Result<VersionsEntry<'a>> {
-        if self.next_field == 24 {
+        if self.next_field >= 24 {
             Ok(self.inner)
         } else {
             bail!(
@@ -86,6 +151,17 @@ impl<'a> VersionsEntryBuilder<'a> {
     }
 }
 
+/// Empty or malformed counts are taken as 0, the size and download filters deal with those
+fn parse_count(field: &str, value: &str) -> u64 {
+    if value.is_empty() {
+        return 0;
+    }
+    value.parse().unwrap_or_else(|_| {
+        tracing::trace!("malformed {field} '{value}' in versions csv, using 0");
+        0
+    })
+}
+
 fn parse_yanked_bool(value: &str) -> anyhow::Result<bool> {
     if value == "f" {
         Ok(false)
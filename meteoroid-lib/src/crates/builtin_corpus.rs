@@ -0,0 +1,54 @@
+use crate::crates::crate_consumer::default::{PrunedCrate, pruned_crate_from_repository};
+use anyhow::Context;
+
+const BUILTIN_CORPUS: &str = include_str!("builtin_corpus.txt");
+
+/// Crates from the curated list shipped with meteoroid, no index needed
+pub(crate) fn builtin_corpus() -> anyhow::Result<Vec<PrunedCrate>> {
+    parse_corpus(BUILTIN_CORPUS)
+}
+
+/// One `<crate-name> <repository>` per line, empty lines and lines starting with `#` are ignored
+fn parse_corpus(corpus: &str) -> anyhow::Result<Vec<PrunedCrate>> {
+    let mut crates = vec![];
+    for (line_num, line) in (1..).zip(corpus.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (crate_name, repository) = line
+            .split_once(char::is_whitespace)
+            .with_context(|| format!("expected '<crate-name> <repository>' on line {line_num}"))?;
+        let pruned = pruned_crate_from_repository(crate_name, repository.trim())
+            .with_context(|| format!("invalid corpus entry on line {line_num}: '{line}'"))?;
+        crates.push(pruned);
+    }
+    Ok(crates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_corpus_parses_into_repos() {
+        let corpus = builtin_corpus().unwrap();
+        assert!(corpus.len() >= 40, "only {} crates", corpus.len());
+        for cr in &corpus {
+            let repo = cr.repository.as_ref().unwrap();
+            assert_eq!(Some("github.com"), repo.as_url().host_str());
+        }
+        let dirs = corpus
+            .iter()
+            .map(|cr| cr.repo_dir_name.0.0.as_path())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(corpus.len(), dirs.len(), "repos cloned to the same dir");
+    }
+
+    #[test]
+    fn invalid_entry_names_its_line() {
+        let err = parse_corpus("# comment\nserde https://github.com/serde-rs/serde\nbroken\n")
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("line 3"), "{err:#}");
+    }
+}
@@ -1,18 +1,139 @@
+mod reservoir;
+
 use crate::crates::api::VersionsEntry;
 use crate::crates::crate_consumer::CrateConsumer;
+use crate::crates::crate_consumer::default::reservoir::Reservoir;
 use crate::unpack;
 use anyhow::{Context, bail};
-use rustc_hash::FxHashSet;
-use std::collections::{BinaryHeap, HashSet};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::{BTreeSet, BinaryHeap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
-use std::path::{Component, PathBuf};
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf};
 use url::Url;
 
+#[derive(Debug, Clone)]
 pub struct ConsumerOpts {
     pub max_crates: usize,
     pub min_size: u64,
+    /// If not empty, only crates with exactly these names are selected
+    pub only_crate_names: BTreeSet<String>,
     pub exclude_crate_name_contains: Vec<String>,
     pub exclude_repository_contains: Vec<String>,
+    /// Exclude crates whose license expression contains any of these
+    pub exclude_license_contains: Vec<String>,
+    /// If not empty, only keep crates whose license expression contains one of these
+    pub include_license_contains: Vec<String>,
+    /// If this or `include_category_contains` is not empty, only keep crates where
+    /// one of these is contained in a keyword, or one of the category terms in a category
+    pub include_keyword_contains: Vec<String>,
+    pub include_category_contains: Vec<String>,
+    /// Only keep crates that have a library target, or crates that have binaries
+    pub only_kind: Option<CrateKind>,
+    /// Skip crates declaring a `rust-version` below this
+    pub min_rust_version: Option<semver::Version>,
+    /// Skip crates that don't declare a (parseable) `rust-version`
+    pub require_rust_version: bool,
+    /// Keep the highest ranked crates per category instead of globally,
+    /// then pick from each category in turn until `max_crates` is reached
+    pub stratify_by_category: bool,
+    /// Maximum number of selected crates whose repository belongs to the same org
+    pub max_per_org: Option<usize>,
+    /// Select a seeded random sample of the crates that pass the filters,
+    /// instead of the most popular ones
+    pub sample: Option<SampleOpts>,
+    /// What to rank crates by when selecting the top `max_crates`
+    pub select_by: SelectBy,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SelectBy {
+    /// Most downloaded crates
+    #[default]
+    Downloads,
+    /// Most recently created crates
+    Newest,
+    /// Most downloads per day since being created, favors actively growing crates
+    Velocity,
+}
+
+/// Reads a denylist with one crate name (or substring of one) per line,
+/// empty lines and anything after a `#` are ignored
+pub fn read_denylist(path: &Path) -> anyhow::Result<Vec<String>> {
+    read_name_list(path, "denylist")
+}
+
+/// Reads an allowlist with one exact crate name per line, in the same format as the denylist
+pub fn read_allowlist(path: &Path) -> anyhow::Result<BTreeSet<String>> {
+    read_name_list(path, "allowlist")
+}
+
+fn read_name_list<C: FromIterator<String>>(path: &Path, what: &str) -> anyhow::Result<C> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {what} at {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Repos and crates to analyze, rather than selecting them from the index
+#[derive(Debug, Clone, Default)]
+pub struct RepoList {
+    pub urls: Vec<Url>,
+    /// Looked up in the index
+    pub crate_names: BTreeSet<String>,
+}
+
+/// Reads one repo url or crate name per line, anything with a `://` is taken as a url.
+/// Empty lines and lines starting with `#` are ignored
+pub fn read_repo_list(reader: impl BufRead) -> anyhow::Result<RepoList> {
+    let mut list = RepoList::default();
+    for (line_num, line) in (1..).zip(reader.lines()) {
+        let line = line.context("failed to read repo list")?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.contains("://") {
+            validate_repo(line).with_context(|| format!("invalid repo url on line {line_num}"))?;
+            list.urls
+                .push(Url::parse(line).context("failed to parse repository url")?);
+        } else {
+            best_attempt_validate_path(line)
+                .with_context(|| format!("invalid crate name on line {line_num}"))?;
+            list.crate_names.insert(line.to_string());
+        }
+    }
+    Ok(list)
+}
+
+/// Parses a `rust-version` as cargo accepts it, ex: `1.70` or `1.70.0`
+pub fn parse_rust_version(rust_version: &str) -> anyhow::Result<semver::Version> {
+    let rust_version = rust_version.trim();
+    let padded = match rust_version.matches('.').count() {
+        0 => format!("{rust_version}.0.0"),
+        1 => format!("{rust_version}.0"),
+        _ => rust_version.to_string(),
+    };
+    semver::Version::parse(&padded)
+        .with_context(|| format!("failed to parse rust version '{rust_version}'"))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrateKind {
+    /// Crates with a library target
+    Lib,
+    /// Crates with at least one binary
+    Bin,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SampleOpts {
+    pub seed: u64,
+    pub size: usize,
 }
 
 impl Default for ConsumerOpts {
@@ -21,13 +142,130 @@ impl Default for ConsumerOpts {
             max_crates: 100,
             // Last time I checked, average was 177K
             min_size: 20_000,
+            only_crate_names: BTreeSet::new(),
             exclude_crate_name_contains: vec![],
             exclude_repository_contains: vec![],
+            exclude_license_contains: vec![],
+            include_license_contains: vec![],
+            include_keyword_contains: vec![],
+            include_category_contains: vec![],
+            only_kind: None,
+            min_rust_version: None,
+            require_rust_version: false,
+            stratify_by_category: false,
+            max_per_org: None,
+            sample: None,
+            select_by: SelectBy::Downloads,
         }
     }
 }
 
 impl ConsumerOpts {
+    /// Catches combinations that would select nothing, rather than running and finding nothing
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_crates == 0 {
+            bail!("max crates is 0, no crates would be selected");
+        }
+        if self.max_per_org == Some(0) {
+            bail!("max crates per org is 0, no crates would be selected");
+        }
+        if let Some(sample) = self.sample
+            && sample.size == 0
+        {
+            bail!("sample size is 0, no crates would be selected");
+        }
+        Ok(())
+    }
+
+    /// The options that narrow down the selection, to explain why nothing was selected.
+    /// Destructured so that a new option can't be left out of the explanation
+    pub(crate) fn active_filters(&self) -> Vec<String> {
+        let Self {
+            max_crates,
+            min_size,
+            only_crate_names,
+            exclude_crate_name_contains,
+            exclude_repository_contains,
+            exclude_license_contains,
+            include_license_contains,
+            include_keyword_contains,
+            include_category_contains,
+            only_kind,
+            min_rust_version,
+            require_rust_version,
+            stratify_by_category,
+            max_per_org,
+            sample,
+            select_by,
+        } = self;
+        let mut filters = vec![
+            format!("max crates: {max_crates}"),
+            format!("min size: {min_size}"),
+        ];
+        let lists = [
+            ("allowlist", only_crate_names.iter().collect::<Vec<_>>()),
+            (
+                "excluded names containing",
+                exclude_crate_name_contains.iter().collect(),
+            ),
+            (
+                "excluded repositories containing",
+                exclude_repository_contains.iter().collect(),
+            ),
+            (
+                "excluded licenses containing",
+                exclude_license_contains.iter().collect(),
+            ),
+            (
+                "licenses containing",
+                include_license_contains.iter().collect(),
+            ),
+            (
+                "keywords containing",
+                include_keyword_contains.iter().collect(),
+            ),
+            (
+                "categories containing",
+                include_category_contains.iter().collect(),
+            ),
+        ];
+        for (label, values) in lists {
+            if !values.is_empty() {
+                let values = values
+                    .into_iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                filters.push(format!("{label}: {values}"));
+            }
+        }
+        if let Some(kind) = only_kind {
+            filters.push(format!("only kind: {kind:?}"));
+        }
+        if let Some(version) = min_rust_version {
+            filters.push(format!("min rust version: {version}"));
+        }
+        if *require_rust_version {
+            filters.push("requires a rust version".to_string());
+        }
+        if *stratify_by_category {
+            filters.push("stratified by category".to_string());
+        }
+        if let Some(max) = max_per_org {
+            filters.push(format!("max per org: {max}"));
+        }
+        if let Some(sample) = sample {
+            filters.push(format!(
+                "sample size: {} with seed {}",
+                sample.size, sample.seed
+            ));
+        }
+        if *select_by != SelectBy::default() {
+            filters.push(format!("selected by: {select_by:?}"));
+        }
+        filters
+    }
+
     #[must_use]
     pub fn add_excluded_crate_name_contains(mut self, crate_name_contains: String) -> Self {
         self.exclude_crate_name_contains.push(crate_name_contains);
@@ -38,32 +276,124 @@ impl ConsumerOpts {
         self.exclude_repository_contains.push(repository_contains);
         self
     }
+
+    /// Checks the cheap, user-configured filters, true if the crate should be skipped
+    fn filters_out(&self, crate_name: &str, versions_entry: &VersionsEntry) -> bool {
+        if !self.only_crate_names.is_empty() && !self.only_crate_names.contains(crate_name) {
+            return true;
+        }
+        if self.min_size > versions_entry.crate_size {
+            return true;
+        }
+        for excl in &self.exclude_crate_name_contains {
+            if crate_name.contains(excl) {
+                return true;
+            }
+        }
+        for excl in &self.exclude_repository_contains {
+            if versions_entry.repository.contains(excl) {
+                return true;
+            }
+        }
+        // License is an SPDX expression like `MIT OR Apache-2.0`, substring matching is good enough
+        for excl in &self.exclude_license_contains {
+            if versions_entry.license.contains(excl) {
+                return true;
+            }
+        }
+        if !self.include_license_contains.is_empty()
+            && !self
+                .include_license_contains
+                .iter()
+                .any(|incl| versions_entry.license.contains(incl))
+        {
+            return true;
+        }
+        if !self.matches_kind(versions_entry) || !self.matches_rust_version(versions_entry) {
+            return true;
+        }
+        !self.matches_keyword_or_category(versions_entry)
+    }
+
+    fn matches_kind(&self, versions_entry: &VersionsEntry) -> bool {
+        match self.only_kind {
+            None => true,
+            // Postgres boolean
+            Some(CrateKind::Lib) => versions_entry.has_lib == "t",
+            // Postgres array literal, `{}` when there are no binaries
+            Some(CrateKind::Bin) => {
+                let bins = versions_entry.bin_names.trim();
+                !bins.is_empty() && bins != "{}"
+            }
+        }
+    }
+
+    fn matches_rust_version(&self, versions_entry: &VersionsEntry) -> bool {
+        if self.min_rust_version.is_none() && !self.require_rust_version {
+            return true;
+        }
+        let declared = versions_entry.rust_version.trim();
+        if declared.is_empty() {
+            return !self.require_rust_version;
+        }
+        let declared = match parse_rust_version(declared) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::trace!(
+                    "unparseable rust_version '{declared}', treating as unknown: {}",
+                    unpack(&*e)
+                );
+                return !self.require_rust_version;
+            }
+        };
+        self.min_rust_version
+            .as_ref()
+            .is_none_or(|min| declared >= *min)
+    }
+
+    /// Keywords and categories are postgres array literals, ex: `{parser,"no-std"}`,
+    /// matched case-insensitively on the raw field
+    fn matches_keyword_or_category(&self, versions_entry: &VersionsEntry) -> bool {
+        if self.include_keyword_contains.is_empty() && self.include_category_contains.is_empty() {
+            return true;
+        }
+        let keywords = versions_entry.keywords.to_ascii_lowercase();
+        let categories = versions_entry.categories.to_ascii_lowercase();
+        self.include_keyword_contains
+            .iter()
+            .any(|incl| keywords.contains(&incl.to_ascii_lowercase()))
+            || self
+                .include_category_contains
+                .iter()
+                .any(|incl| categories.contains(&incl.to_ascii_lowercase()))
+    }
 }
 
 #[derive(Debug)]
-pub(crate) struct CrateByPopularity {
-    downloads: u64,
+pub(crate) struct CrateBySortKey {
+    /// Higher is preferred
+    sort_key: u64,
     rt: RetainCrate,
 }
 
-impl PartialEq for CrateByPopularity {
+impl PartialEq for CrateBySortKey {
     fn eq(&self, other: &Self) -> bool {
-        self.downloads == other.downloads
+        self.sort_key == other.sort_key
     }
 }
 
-impl Eq for CrateByPopularity {}
+impl Eq for CrateBySortKey {}
 
 #[allow(clippy::non_canonical_partial_ord_impl)]
-impl PartialOrd for CrateByPopularity {
+impl PartialOrd for CrateBySortKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(other.downloads.cmp(&self.downloads))
+        Some(other.sort_key.cmp(&self.sort_key))
     }
 }
 
-impl Ord for CrateByPopularity {
+impl Ord for CrateBySortKey {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.downloads.cmp(&self.downloads)
+        other.sort_key.cmp(&self.sort_key)
     }
 }
 
@@ -73,42 +403,88 @@ pub(crate) struct RetainCrate {
     crate_id: u64,
     repository: GitRepo,
     repo_dir_name: RepoName,
+    subdir: Option<PathBuf>,
+    org: String,
+    crate_size: u64,
+    downloads: u64,
+    version: Option<String>,
+    description: Option<String>,
+    edition: Option<String>,
 }
 
 #[derive(Default)]
 pub(crate) struct Consumer {
     consumer_opts: ConsumerOpts,
-    crates: BinaryHeap<CrateByPopularity>,
+    crates: BinaryHeap<CrateBySortKey>,
+    crates_by_category: FxHashMap<String, BinaryHeap<CrateBySortKey>>,
     contained_crate_ids: FxHashSet<u64>,
+    retained_per_org: FxHashMap<String, usize>,
+    reservoir: Option<Reservoir<CrateBySortKey>>,
+    /// If set, only crates with these ids are considered
+    restrict_to: Option<FxHashSet<u64>>,
+    /// For calculating age when selecting by velocity
+    now_unix_seconds: u64,
 }
 
 impl Consumer {
     pub fn new(consumer_opts: ConsumerOpts) -> Self {
+        let reservoir = consumer_opts
+            .sample
+            .map(|sample| Reservoir::new(sample.seed, sample.size));
         Self {
             consumer_opts,
             crates: BinaryHeap::new(),
+            crates_by_category: FxHashMap::default(),
             contained_crate_ids: HashSet::default(),
+            retained_per_org: FxHashMap::default(),
+            reservoir,
+            restrict_to: None,
+            now_unix_seconds: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
         }
     }
+
+    /// `None` if the entry can't be ranked by `select_by`
+    fn sort_key(&self, crate_name: &NormalPath, versions_entry: &VersionsEntry) -> Option<u64> {
+        match self.consumer_opts.select_by {
+            SelectBy::Downloads => Some(versions_entry.downloads),
+            SelectBy::Newest | SelectBy::Velocity => {
+                let Some(created_at) = versions_entry.created_at_unix_seconds() else {
+                    tracing::trace!(
+                        "rejected crate {crate_name:?} with unparseable created_at: '{}'",
+                        versions_entry.created_at
+                    );
+                    return None;
+                };
+                Some(if self.consumer_opts.select_by == SelectBy::Newest {
+                    created_at
+                } else {
+                    downloads_per_day(versions_entry.downloads, created_at, self.now_unix_seconds)
+                })
+            }
+        }
+    }
+
+    pub fn restrict_to(&mut self, crate_ids: FxHashSet<u64>) {
+        self.restrict_to = Some(crate_ids);
+    }
 }
 
 impl CrateConsumer for Consumer {
     fn consume(&mut self, crate_name: &str, versions_entry: VersionsEntry) -> anyhow::Result<bool> {
-        if self.consumer_opts.min_size > versions_entry.crate_size {
+        if self
+            .restrict_to
+            .as_ref()
+            .is_some_and(|ids| !ids.contains(&versions_entry.crate_id))
+        {
             return Ok(true);
         }
-        for excl in &self.consumer_opts.exclude_crate_name_contains {
-            if crate_name.contains(excl) {
-                return Ok(true);
-            }
-        }
-        for excl in &self.consumer_opts.exclude_repository_contains {
-            if versions_entry.repository.contains(excl) {
-                return Ok(true);
-            }
+        if self.consumer_opts.filters_out(crate_name, &versions_entry) {
+            return Ok(true);
         }
-        let (git_repo, repo_name) = match validate_repo(versions_entry.repository) {
-            Ok((g, r)) => (g, r),
+        let validated = match validate_repo(versions_entry.repository) {
+            Ok(v) => v,
             Err(e) => {
                 tracing::trace!(
                     "Rejected repository: '{}': {}",
@@ -131,57 +507,193 @@ impl CrateConsumer for Consumer {
                 return Ok(true);
             }
         };
-        if self.crates.len() >= self.consumer_opts.max_crates {
-            let Some(cr) = self.crates.peek() else {
-                bail!("crate length too long, but nothing to peek (this is a bug)");
-            };
-            if versions_entry.downloads > cr.downloads {
-                let Some(cr) = self.crates.pop() else {
-                    bail!("crate length too long, but nothing to pop (this is a bug)");
-                };
-                self.contained_crate_ids.remove(&cr.rt.crate_id);
-                self.contained_crate_ids.insert(versions_entry.crate_id);
-                self.crates.push(CrateByPopularity {
-                    downloads: versions_entry.downloads,
-                    rt: RetainCrate {
-                        crate_name: CrateName(crate_name),
-                        crate_id: versions_entry.crate_id,
-                        repository: git_repo,
-                        repo_dir_name: repo_name,
-                    },
-                });
-            }
-            Ok(true)
-        } else {
-            self.crates.push(CrateByPopularity {
+        let Some(sort_key) = self.sort_key(&crate_name, &versions_entry) else {
+            return Ok(true);
+        };
+        let candidate = CrateBySortKey {
+            sort_key,
+            rt: RetainCrate {
+                crate_name: CrateName(crate_name),
+                crate_id: versions_entry.crate_id,
+                repository: validated.repository,
+                repo_dir_name: validated.repo_name,
+                subdir: validated.subdir,
+                org: validated.org,
+                crate_size: versions_entry.crate_size,
                 downloads: versions_entry.downloads,
-                rt: RetainCrate {
-                    crate_name: CrateName(crate_name),
-                    crate_id: versions_entry.crate_id,
-                    repository: git_repo,
-                    repo_dir_name: repo_name,
-                },
-            });
+                version: non_empty(versions_entry.num),
+                description: non_empty(versions_entry.description),
+                edition: non_empty(versions_entry.edition),
+            },
+        };
+        if let Some(reservoir) = self.reservoir.as_mut() {
+            reservoir.offer(candidate.rt.crate_id, candidate);
+            return Ok(true);
+        }
+        let org = candidate.rt.org.clone();
+        let crate_id = candidate.rt.crate_id;
+        let category = self
+            .consumer_opts
+            .stratify_by_category
+            .then(|| first_category(versions_entry.categories));
+        let max_crates = self.consumer_opts.max_crates;
+        let RetainOutcome::Retained { evicted } =
+            retain_highest_ranked(self.heap_for(category), max_crates, candidate)?
+        else {
+            return Ok(true);
+        };
+        self.contained_crate_ids.insert(crate_id);
+        if let Some(evicted) = &evicted {
+            self.contained_crate_ids.remove(&evicted.rt.crate_id);
+            self.release_org(&evicted.rt.org);
+        }
+        if self.retain_org(&org)
+            && let Some(evicted) = evicted
+        {
+            // Trimming the org made room again, and the evicted crate ranks highest
+            // of the ones left out
+            self.put_back(category, evicted);
+        }
+        Ok(true)
+    }
+}
 
-            Ok(true)
+impl Consumer {
+    /// The heap of `category`, or the global one when not stratifying
+    fn heap_for(&mut self, category: Option<&str>) -> &mut BinaryHeap<CrateBySortKey> {
+        let Some(category) = category else {
+            return &mut self.crates;
+        };
+        if !self.crates_by_category.contains_key(category) {
+            self.crates_by_category
+                .insert(category.to_string(), BinaryHeap::new());
         }
+        self.crates_by_category
+            .get_mut(category)
+            .expect("bucket was inserted above")
+    }
+
+    fn put_back(&mut self, category: Option<&str>, evicted: Box<CrateBySortKey>) {
+        let max_crates = self.consumer_opts.max_crates;
+        let heap = self.heap_for(category);
+        // The trimmed crate may have been from another category's bucket
+        if heap.len() >= max_crates {
+            return;
+        }
+        let crate_id = evicted.rt.crate_id;
+        let org = evicted.rt.org.clone();
+        heap.push(*evicted);
+        self.contained_crate_ids.insert(crate_id);
+        *self.retained_per_org.entry(org).or_default() += 1;
+    }
+
+    /// Counts a retained crate of `org`, true if that put the org over the cap and its lowest
+    /// ranked crate was dropped
+    fn retain_org(&mut self, org: &str) -> bool {
+        let Some(max_per_org) = self.consumer_opts.max_per_org else {
+            return false;
+        };
+        let count = self.retained_per_org.entry(org.to_string()).or_default();
+        *count += 1;
+        if *count <= max_per_org {
+            return false;
+        }
+        // Over the cap, drop the lowest ranked crate from that org, which may be the one just added
+        let lowest_ranked = self
+            .crates
+            .iter()
+            .chain(self.crates_by_category.values().flatten())
+            .filter(|c| c.rt.org == org)
+            .min_by_key(|c| c.sort_key)
+            .map(|c| c.rt.crate_id);
+        if let Some(crate_id) = lowest_ranked {
+            self.crates.retain(|c| c.rt.crate_id != crate_id);
+            for bucket in self.crates_by_category.values_mut() {
+                bucket.retain(|c| c.rt.crate_id != crate_id);
+            }
+            self.contained_crate_ids.remove(&crate_id);
+            self.release_org(org);
+            return true;
+        }
+        false
+    }
+
+    fn release_org(&mut self, org: &str) {
+        if let Some(count) = self.retained_per_org.get_mut(org) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+enum RetainOutcome {
+    Rejected,
+    /// `evicted` is the crate that was evicted to make room, if any
+    Retained {
+        evicted: Option<Box<CrateBySortKey>>,
+    },
+}
+
+fn retain_highest_ranked(
+    crates: &mut BinaryHeap<CrateBySortKey>,
+    max_crates: usize,
+    candidate: CrateBySortKey,
+) -> anyhow::Result<RetainOutcome> {
+    let mut evicted = None;
+    if crates.len() >= max_crates {
+        let Some(cr) = crates.peek() else {
+            bail!("crate length too long, but nothing to peek (this is a bug)");
+        };
+        if candidate.sort_key <= cr.sort_key {
+            return Ok(RetainOutcome::Rejected);
+        }
+        let Some(cr) = crates.pop() else {
+            bail!("crate length too long, but nothing to pop (this is a bug)");
+        };
+        evicted = Some(Box::new(cr));
+    }
+    crates.push(candidate);
+    Ok(RetainOutcome::Retained { evicted })
+}
+
+/// Age is floored to a day so that crates published moments ago don't dominate
+fn downloads_per_day(downloads: u64, created_at: u64, now: u64) -> u64 {
+    const DAY: u64 = 86_400;
+    let age = now.saturating_sub(created_at).max(DAY);
+    downloads.saturating_mul(DAY) / age
+}
+
+/// Categories come as a postgres array literal, ex: `{"Command line utilities",Encoding}`,
+/// only the first one is used for bucketing
+fn first_category(categories: &str) -> &str {
+    const UNCATEGORIZED: &str = "uncategorized";
+    let inner = categories.trim_start_matches('{').trim_end_matches('}');
+    let first = inner.split(',').next().unwrap_or_default();
+    let first = first.trim().trim_matches('"');
+    if first.is_empty() {
+        UNCATEGORIZED
+    } else {
+        first
     }
 }
 
 /// Should be considered and treated as untrusted user input
-#[derive(Debug, Clone, serde::Serialize, Eq, PartialEq, PartialOrd, Ord)]
-pub(crate) struct CrateName(pub(crate) NormalPath);
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Eq, PartialEq, PartialOrd, Ord)]
+pub struct CrateName(pub(crate) NormalPath);
 
 impl CrateName {
-    pub fn try_convert_to_diff_file_name(&self, label: &str) -> anyhow::Result<NormalPath> {
+    pub(crate) fn try_convert_to_diff_file_name(&self, label: &str) -> anyhow::Result<NormalPath> {
         let raw = format!("{}-{label}.diff", self.0.0.display());
         best_attempt_validate_path(&raw)
     }
-    pub fn try_convert_to_diverge_file_name(&self) -> anyhow::Result<NormalPath> {
+    pub(crate) fn try_convert_to_diverge_file_name(&self) -> anyhow::Result<NormalPath> {
         let raw = format!("{}-diverge.dif", self.0.0.display());
         best_attempt_validate_path(&raw)
     }
-    pub fn try_convert_to_rustfmt_error_file_name(
+    pub(crate) fn try_convert_to_repro_dir_name(&self) -> anyhow::Result<NormalPath> {
+        let raw = format!("{}-repro", self.0.0.display());
+        best_attempt_validate_path(&raw)
+    }
+    pub(crate) fn try_convert_to_rustfmt_error_file_name(
         &self,
         label: &str,
     ) -> anyhow::Result<NormalPath> {
@@ -198,16 +710,26 @@ impl Display for CrateName {
 }
 
 /// Should be considered and treated as untrusted user input
-#[derive(Debug, Clone, serde::Serialize, Eq, PartialEq)]
-pub(crate) struct GitRepo(pub(crate) Url);
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Eq, PartialEq)]
+#[serde(try_from = "Url")]
+pub struct GitRepo(pub(crate) Url);
 
 impl GitRepo {
     #[inline]
-    pub fn as_url(&self) -> &Url {
+    pub(crate) fn as_url(&self) -> &Url {
         &self.0
     }
 }
 
+/// Cached selections are read back from disk, so they go through the same validation
+impl TryFrom<Url> for GitRepo {
+    type Error = anyhow::Error;
+
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        Ok(validate_repo(url.as_str())?.repository)
+    }
+}
+
 impl Display for GitRepo {
     #[inline]
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -216,7 +738,7 @@ impl Display for GitRepo {
 }
 
 /// Should be considered and treated as untrusted user input
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct RepoName(pub(crate) NormalPath);
 
 impl RepoName {
@@ -237,7 +759,7 @@ impl Display for RepoName {
 /// can be turned into a path that **should** be valid.
 /// Since `repository` is just metadata that's not validated, it is a potential attack
 /// vector. This is a best-effort sanitation of what should be considered unsafe user input.
-fn validate_repo(repo: &str) -> anyhow::Result<(GitRepo, RepoName)> {
+fn validate_repo(repo: &str) -> anyhow::Result<ValidatedRepo> {
     let url = Url::parse(repo).context("failed to parse repository url")?;
     if !url.scheme().starts_with("https") {
         bail!("url must be https");
@@ -247,24 +769,85 @@ fn validate_repo(repo: &str) -> anyhow::Result<(GitRepo, RepoName)> {
         // Todo: Add more forges
         bail!("not a recognized forge: {host}");
     }
+    // Empty segments come from trailing (or doubled) slashes
     let mut ps = url
         .path_segments()
-        .context("failed to get path segments from repository url")?;
-    let _org = ps.next().context("failed to get org from repository url")?;
+        .context("failed to get path segments from repository url")?
+        .filter(|s| !s.is_empty());
+    let org = ps.next().context("failed to get org from repository url")?;
     let repo_name = ps
         .next()
         .context("failed to get repo name from repository url")?;
-    // Perhaps overly strict, but generally repos are <org>/<repo> in paths,
-    if ps.next().is_some() {
-        bail!("repository url has too many path segments");
+    // `<repo>` and `<repo>.git` are the same repo, and shouldn't be cloned twice
+    let repo_name = repo_name.strip_suffix(".git").unwrap_or(repo_name);
+    if repo_name.is_empty() {
+        bail!("repository url has an empty repo name");
     }
+    // Perhaps overly strict, but generally repos are <org>/<repo> in paths,
+    // except for links into the repo's tree, `<org>/<repo>/tree/<ref>/<subdir>`
+    let subdir = match ps.next() {
+        None => None,
+        Some("tree") => {
+            ps.next()
+                .filter(|git_ref| !git_ref.is_empty())
+                .context("repository url has a tree without a ref")?;
+            validate_subdir(ps)?
+        }
+        Some(_) => bail!("repository url has too many path segments"),
+    };
     let pb = best_attempt_validate_path(repo_name).context("failed to validate repository path")?;
-    Ok((GitRepo(url), RepoName(pb)))
+    // Rebuilt so that every form of the same repo's url ends up the same
+    let mut clone_url = url.clone();
+    clone_url.set_path(&format!("{org}/{repo_name}"));
+    clone_url.set_query(None);
+    clone_url.set_fragment(None);
+    // Forges treat orgs case-insensitively
+    let org = org.to_lowercase();
+    Ok(ValidatedRepo {
+        repository: GitRepo(clone_url),
+        repo_name: RepoName(pb),
+        subdir,
+        org,
+    })
+}
+
+/// The path after `tree/<ref>`, only plain directory names are accepted
+fn validate_subdir<'a>(segments: impl Iterator<Item = &'a str>) -> anyhow::Result<Option<PathBuf>> {
+    let mut subdir = PathBuf::new();
+    for segment in segments.filter(|s| !s.is_empty()) {
+        let segment = best_attempt_validate_path(segment)
+            .context("failed to validate repository subdirectory")?;
+        if segment.0.components().count() != 1 {
+            bail!(
+                "unexpected repository subdirectory segment: {}",
+                segment.0.display()
+            );
+        }
+        subdir.push(segment.0);
+    }
+    Ok((!subdir.as_os_str().is_empty()).then_some(subdir))
 }
 
-#[derive(Debug, Clone, serde::Serialize, Eq, PartialEq, PartialOrd, Ord)]
+struct ValidatedRepo {
+    repository: GitRepo,
+    repo_name: RepoName,
+    /// The crate's directory within the repository, if the url pointed into it
+    subdir: Option<PathBuf>,
+    org: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Eq, PartialEq, PartialOrd, Ord)]
+#[serde(try_from = "PathBuf")]
 pub(crate) struct NormalPath(pub(crate) PathBuf);
 
+impl TryFrom<PathBuf> for NormalPath {
+    type Error = anyhow::Error;
+
+    fn try_from(path_buf: PathBuf) -> Result<Self, Self::Error> {
+        normalized_single(path_buf)
+    }
+}
+
 impl NormalPath {
     #[inline]
     pub(crate) fn from_checked_path(path_buf: PathBuf) -> Self {
@@ -291,22 +874,673 @@ fn normalized_single(path_buf: PathBuf) -> anyhow::Result<NormalPath> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// For crates that are explicitly asked for, rather than selected from the index
+pub(crate) fn pruned_crate_from_repository(
+    crate_name: &str,
+    repository: &str,
+) -> anyhow::Result<PrunedCrate> {
+    let validated =
+        validate_repo(repository).with_context(|| format!("invalid repository '{repository}'"))?;
+    let crate_name = best_attempt_validate_path(crate_name)
+        .with_context(|| format!("invalid crate name '{crate_name}'"))?;
+    Ok(PrunedCrate {
+        crate_name: CrateName(crate_name),
+        repository: Some(validated.repository),
+        repo_dir_name: validated.repo_name,
+        subdir: validated.subdir,
+        crate_size: None,
+        downloads: None,
+        version: None,
+        description: None,
+        edition: None,
+    })
+}
+
+/// A repo given by url alone, named after the repo
+pub(crate) fn pruned_crate_from_url(url: &Url) -> anyhow::Result<PrunedCrate> {
+    let validated =
+        validate_repo(url.as_str()).with_context(|| format!("invalid repository '{url}'"))?;
+    Ok(PrunedCrate {
+        crate_name: CrateName(validated.repo_name.0.clone()),
+        repository: Some(validated.repository),
+        repo_dir_name: validated.repo_name,
+        subdir: validated.subdir,
+        crate_size: None,
+        downloads: None,
+        version: None,
+        description: None,
+        edition: None,
+    })
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PrunedCrate {
     pub(crate) crate_name: CrateName,
     pub(crate) repository: Option<GitRepo>,
     pub(crate) repo_dir_name: RepoName,
+    /// The crate's directory within the repository, from repository urls like
+    /// `https://github.com/<org>/<repo>/tree/<ref>/<subdir>`
+    #[serde(default)]
+    pub(crate) subdir: Option<PathBuf>,
+    /// Size in bytes of the published `.crate`, for crates selected from the index
+    #[serde(default)]
+    pub(crate) crate_size: Option<u64>,
+    /// All-time downloads, for crates selected from the index
+    #[serde(default)]
+    pub(crate) downloads: Option<u64>,
+    /// The published version, or the manifest version for local crates
+    pub(crate) version: Option<String>,
+    pub(crate) description: Option<String>,
+    /// The published edition, or the manifest edition for local crates
+    #[serde(default)]
+    pub(crate) edition: Option<String>,
+}
+
+/// A crate picked for analysis, as returned from `select_crates`.
+/// Serializes to what `--list-selected` prints, so the field names are kept stable
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PublicPrunedCrate {
+    #[serde(rename = "name")]
+    pub crate_name: String,
+    #[serde(rename = "repo")]
+    pub repository: Option<String>,
+    /// The crate's directory within the repository, if the repository url pointed into it
+    pub subdir: Option<PathBuf>,
+    /// All-time downloads, only known for crates selected from the index
+    pub downloads: Option<u64>,
+    /// Size in bytes of the published `.crate`, only known for crates selected from the index
+    #[serde(rename = "size")]
+    pub crate_size: Option<u64>,
+    pub version: Option<String>,
+    pub edition: Option<String>,
+}
+
+impl From<PrunedCrate> for PublicPrunedCrate {
+    fn from(pc: PrunedCrate) -> Self {
+        Self {
+            crate_name: pc.crate_name.to_string(),
+            repository: pc.repository.as_ref().map(ToString::to_string),
+            subdir: pc.subdir,
+            downloads: pc.downloads,
+            crate_size: pc.crate_size,
+            version: pc.version,
+            edition: pc.edition,
+        }
+    }
 }
 
 impl Consumer {
     pub(crate) fn get_crates(self) -> Vec<PrunedCrate> {
-        self.crates
+        let selected = if let Some(reservoir) = self.reservoir {
+            reservoir.into_sample()
+        } else if self.consumer_opts.stratify_by_category {
+            tracing::debug!(
+                "selecting crates from {} categories",
+                self.crates_by_category.len()
+            );
+            round_robin_categories(self.crates_by_category, self.consumer_opts.max_crates)
+        } else {
+            self.crates.into_vec()
+        };
+        selected
             .into_iter()
             .map(|c| PrunedCrate {
                 crate_name: c.rt.crate_name,
                 repository: Some(c.rt.repository),
                 repo_dir_name: c.rt.repo_dir_name,
+                subdir: c.rt.subdir,
+                crate_size: Some(c.rt.crate_size),
+                downloads: Some(c.rt.downloads),
+                version: c.rt.version,
+                description: c.rt.description,
+                edition: c.rt.edition,
             })
             .collect()
     }
 }
+
+/// Takes the highest ranked remaining crate from each category in turn
+fn round_robin_categories(
+    crates_by_category: FxHashMap<String, BinaryHeap<CrateBySortKey>>,
+    max_crates: usize,
+) -> Vec<CrateBySortKey> {
+    let mut buckets = crates_by_category.into_iter().collect::<Vec<_>>();
+    // Deterministic order between runs
+    buckets.sort_by(|a, b| a.0.cmp(&b.0));
+    // Ord is reversed on `CrateBySortKey`, so this is highest ranked first
+    let mut buckets = buckets
+        .into_iter()
+        .map(|(_category, crates)| crates.into_sorted_vec().into_iter())
+        .collect::<Vec<_>>();
+    let mut selected = Vec::with_capacity(max_crates);
+    while selected.len() < max_crates && !buckets.is_empty() {
+        buckets.retain_mut(|bucket| {
+            if selected.len() >= max_crates {
+                return true;
+            }
+            if let Some(next) = bucket.next() {
+                selected.push(next);
+                true
+            } else {
+                false
+            }
+        });
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(max_crates: usize) -> ConsumerOpts {
+        ConsumerOpts {
+            max_crates,
+            min_size: 0,
+            ..ConsumerOpts::default()
+        }
+    }
+
+    fn entry(crate_id: u64, downloads: u64, repository: &str) -> VersionsEntry<'_> {
+        VersionsEntry {
+            crate_id,
+            downloads,
+            repository,
+            created_at: "2024-01-01 00:00:00",
+            ..VersionsEntry::default()
+        }
+    }
+
+    fn selected_names(consumer: Consumer) -> Vec<String> {
+        let mut names = consumer
+            .get_crates()
+            .into_iter()
+            .map(|c| c.crate_name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn stratified_selection_spans_categories() {
+        let mut consumer = Consumer::new(ConsumerOpts {
+            stratify_by_category: true,
+            ..opts(4)
+        });
+        for id in 0..6 {
+            // The web crates are all more popular than the parsing ones
+            let (categories, downloads) = if id < 4 {
+                ("{web}", 1000 + id)
+            } else {
+                ("{parsing}", id)
+            };
+            let repository = format!("https://github.com/org-{id}/repo-{id}");
+            let entry = VersionsEntry {
+                categories,
+                ..entry(id, downloads, &repository)
+            };
+            consumer.consume(&format!("crate-{id}"), entry).unwrap();
+        }
+        assert_eq!(
+            ["crate-2", "crate-3", "crate-4", "crate-5"],
+            selected_names(consumer).as_slice()
+        );
+    }
+
+    #[test]
+    fn crates_over_the_org_cap_are_trimmed() {
+        let mut consumer = Consumer::new(ConsumerOpts {
+            max_per_org: Some(2),
+            ..opts(10)
+        });
+        for (id, repository) in [
+            "https://github.com/big-org/a",
+            "https://github.com/Big-Org/b",
+            "https://github.com/big-org/c",
+            "https://github.com/other/d",
+            "https://github.com/another/e",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = id as u64;
+            consumer
+                .consume(&format!("crate-{id}"), entry(id, 100 + id, repository))
+                .unwrap();
+        }
+        // The lowest ranked crate of the org is the one trimmed
+        assert_eq!(
+            ["crate-1", "crate-2", "crate-3", "crate-4"],
+            selected_names(consumer).as_slice()
+        );
+    }
+
+    #[test]
+    fn evicted_crate_is_kept_when_the_candidate_is_over_the_org_cap() {
+        let mut consumer = Consumer::new(ConsumerOpts {
+            max_per_org: Some(1),
+            ..opts(3)
+        });
+        let crates = [
+            (10, "https://github.com/x/low"),
+            (20, "https://github.com/y/mid"),
+            (30, "https://github.com/z/high"),
+            // Evicts the lowest ranked crate, then is trimmed for being the lower of org `y`
+            (15, "https://github.com/y/over-cap"),
+        ];
+        for (id, (downloads, repository)) in crates.into_iter().enumerate() {
+            let id = id as u64;
+            consumer
+                .consume(&format!("crate-{id}"), entry(id, downloads, repository))
+                .unwrap();
+        }
+        assert_eq!(
+            ["crate-0", "crate-1", "crate-2"],
+            selected_names(consumer).as_slice()
+        );
+    }
+
+    fn sampled(seed: u64) -> Vec<String> {
+        let mut consumer = Consumer::new(ConsumerOpts {
+            sample: Some(SampleOpts { seed, size: 10 }),
+            ..opts(100)
+        });
+        for id in 0..200 {
+            let repository = format!("https://github.com/org/repo-{id}");
+            consumer
+                .consume(&format!("crate-{id}"), entry(id, id, &repository))
+                .unwrap();
+        }
+        selected_names(consumer)
+    }
+
+    #[test]
+    fn same_seed_samples_the_same_crates() {
+        let sample = sampled(7);
+        assert_eq!(10, sample.len());
+        assert_eq!(sample, sampled(7));
+        assert_ne!(sample, sampled(8));
+    }
+
+    #[test]
+    fn newest_crates_are_selected() {
+        let mut consumer = Consumer::new(ConsumerOpts {
+            select_by: SelectBy::Newest,
+            ..opts(2)
+        });
+        let crates = [
+            ("2020-05-01 12:00:00", 3000),
+            ("2024-02-01 00:00:00.123456", 10),
+            ("2022-11-30 23:59:59", 20),
+            // Unparseable dates can't be ranked
+            ("yesterday", 1),
+        ];
+        for (id, (created_at, downloads)) in crates.into_iter().enumerate() {
+            let id = id as u64;
+            let repository = format!("https://github.com/org/repo-{id}");
+            let entry = VersionsEntry {
+                created_at,
+                ..entry(id, downloads, &repository)
+            };
+            consumer.consume(&format!("crate-{id}"), entry).unwrap();
+        }
+        assert_eq!(["crate-1", "crate-2"], selected_names(consumer).as_slice());
+    }
+
+    const REPOS: [&str; 4] = [
+        "https://github.com/a/a",
+        "https://github.com/b/b",
+        "https://github.com/c/c",
+        "https://github.com/d/d",
+    ];
+
+    /// Names of the crates selected from `entries`, each named after its id
+    fn select_from<'a>(
+        opts: ConsumerOpts,
+        entries: impl IntoIterator<Item = VersionsEntry<'a>>,
+    ) -> Vec<String> {
+        let mut consumer = Consumer::new(opts);
+        for entry in entries {
+            consumer
+                .consume(&format!("crate-{}", entry.crate_id), entry)
+                .unwrap();
+        }
+        selected_names(consumer)
+    }
+
+    #[test]
+    fn license_filters_match_substrings() {
+        let licenses = [
+            "MIT OR Apache-2.0",
+            "GPL-3.0-only",
+            "Apache-2.0",
+            "MIT/GPL-2.0",
+        ];
+        let entries = || {
+            licenses
+                .iter()
+                .enumerate()
+                .map(|(id, license)| VersionsEntry {
+                    license,
+                    ..entry(id as u64, 1, REPOS[id])
+                })
+        };
+        let permissive = ConsumerOpts {
+            include_license_contains: vec!["MIT".to_string(), "Apache".to_string()],
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-0", "crate-2", "crate-3"],
+            select_from(permissive, entries()).as_slice()
+        );
+        let no_gpl = ConsumerOpts {
+            exclude_license_contains: vec!["GPL".to_string()],
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-0", "crate-2"],
+            select_from(no_gpl, entries()).as_slice()
+        );
+    }
+
+    #[test]
+    fn keyword_or_category_terms_select_a_subset() {
+        let tagged = [
+            ("{parser,\"no-std\"}", "{}"),
+            ("{web}", "{Parser-implementations}"),
+            ("{cli}", "{command-line-utilities}"),
+            ("{}", "{}"),
+        ];
+        let entries = || {
+            tagged
+                .iter()
+                .enumerate()
+                .map(|(id, (keywords, categories))| VersionsEntry {
+                    categories,
+                    keywords,
+                    ..entry(id as u64, 1, REPOS[id])
+                })
+        };
+        let parsers = ConsumerOpts {
+            include_keyword_contains: vec!["parser".to_string()],
+            include_category_contains: vec!["parser".to_string()],
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-0", "crate-1"],
+            select_from(parsers, entries()).as_slice()
+        );
+        let cli = ConsumerOpts {
+            include_category_contains: vec!["command-line".to_string()],
+            ..opts(10)
+        };
+        assert_eq!(["crate-2"], select_from(cli, entries()).as_slice());
+        assert_eq!(4, select_from(opts(10), entries()).len());
+    }
+
+    #[test]
+    fn only_kind_checks_the_lib_and_bin_targets() {
+        let targets = [
+            ("t", "{}"),
+            ("t", "{tool}"),
+            ("f", "{tool,other}"),
+            // Neither
+            ("f", "{}"),
+        ];
+        let entries = || {
+            targets
+                .iter()
+                .enumerate()
+                .map(|(id, (has_lib, bin_names))| VersionsEntry {
+                    bin_names,
+                    has_lib,
+                    ..entry(id as u64, 1, REPOS[id])
+                })
+        };
+        let lib = ConsumerOpts {
+            only_kind: Some(CrateKind::Lib),
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-0", "crate-1"],
+            select_from(lib, entries()).as_slice()
+        );
+        let bin = ConsumerOpts {
+            only_kind: Some(CrateKind::Bin),
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-1", "crate-2"],
+            select_from(bin, entries()).as_slice()
+        );
+    }
+
+    #[test]
+    fn min_rust_version_keeps_unknown_unless_required() {
+        let declared = ["1.60", "1.85.0", "", "not-a-version"];
+        let entries = || {
+            declared
+                .iter()
+                .enumerate()
+                .map(|(id, rust_version)| VersionsEntry {
+                    rust_version,
+                    ..entry(id as u64, 1, REPOS[id])
+                })
+        };
+        let min = || Some(semver::Version::new(1, 70, 0));
+        let recent = ConsumerOpts {
+            min_rust_version: min(),
+            ..opts(10)
+        };
+        assert_eq!(
+            ["crate-1", "crate-2", "crate-3"],
+            select_from(recent, entries()).as_slice()
+        );
+        let required = ConsumerOpts {
+            min_rust_version: min(),
+            require_rust_version: true,
+            ..opts(10)
+        };
+        assert_eq!(["crate-1"], select_from(required, entries()).as_slice());
+    }
+
+    #[test]
+    fn allowlist_alone_can_empty_the_selection() {
+        let allowlisted = ConsumerOpts {
+            only_crate_names: BTreeSet::from(["missing".to_string()]),
+            ..opts(10)
+        };
+        let entries = REPOS
+            .iter()
+            .enumerate()
+            .map(|(id, repo)| entry(id as u64, 1, repo));
+        assert!(select_from(allowlisted.clone(), entries).is_empty());
+        assert_eq!(
+            vec!["max crates: 10", "min size: 0", "allowlist: missing"],
+            allowlisted.active_filters()
+        );
+        let sampled = ConsumerOpts {
+            max_per_org: Some(1),
+            sample: Some(SampleOpts { seed: 7, size: 2 }),
+            ..allowlisted
+        };
+        let filters = sampled.active_filters();
+        assert!(
+            filters.contains(&"max per org: 1".to_string()),
+            "{filters:?}"
+        );
+        assert!(
+            filters.contains(&"sample size: 2 with seed 7".to_string()),
+            "{filters:?}"
+        );
+    }
+
+    #[test]
+    fn excluded_name_substrings_are_not_selected() {
+        let names = ["serde", "libz-sys", "test-helpers", "tokio"];
+        let mut consumer = Consumer::new(ConsumerOpts {
+            exclude_crate_name_contains: vec!["test".to_string(), "sys".to_string()],
+            ..opts(10)
+        });
+        for (id, name) in names.into_iter().enumerate() {
+            consumer
+                .consume(name, entry(id as u64, 1, REPOS[id]))
+                .unwrap();
+        }
+        assert_eq!(["serde", "tokio"], selected_names(consumer).as_slice());
+    }
+
+    #[test]
+    fn restricted_selection_only_has_the_changed_ids() {
+        let mut consumer = Consumer::new(opts(10));
+        consumer.restrict_to([1, 3].into_iter().collect());
+        for (id, repository) in REPOS.into_iter().enumerate() {
+            let id = id as u64;
+            consumer
+                .consume(&format!("crate-{id}"), entry(id, 1, repository))
+                .unwrap();
+        }
+        assert_eq!(["crate-1", "crate-3"], selected_names(consumer).as_slice());
+    }
+
+    #[test]
+    fn newer_fast_growing_crate_outranks_an_older_popular_one() {
+        let crates = [
+            // More downloads in total, spread over years
+            ("2015-01-01 00:00:00", 1_000_000),
+            ("2025-01-01 00:00:00", 100_000),
+        ];
+        let select = |select_by| {
+            let mut consumer = Consumer::new(ConsumerOpts {
+                select_by,
+                ..opts(1)
+            });
+            // Fixed, so that the ages don't depend on when the test runs
+            consumer.now_unix_seconds =
+                crate::crates::api::parse_db_timestamp("2025-04-11 00:00:00").unwrap();
+            for (id, (created_at, downloads)) in crates.into_iter().enumerate() {
+                let entry = VersionsEntry {
+                    created_at,
+                    ..entry(id as u64, downloads, REPOS[id])
+                };
+                consumer.consume(&format!("crate-{id}"), entry).unwrap();
+            }
+            selected_names(consumer)
+        };
+        assert_eq!(["crate-0"], select(SelectBy::Downloads).as_slice());
+        assert_eq!(["crate-1"], select(SelectBy::Velocity).as_slice());
+    }
+
+    #[test]
+    fn selecting_nothing_is_invalid() {
+        opts(1).validate().unwrap();
+        let invalid = [
+            (opts(0), "max crates is 0"),
+            (
+                ConsumerOpts {
+                    max_per_org: Some(0),
+                    ..opts(1)
+                },
+                "max crates per org is 0",
+            ),
+            (
+                ConsumerOpts {
+                    sample: Some(SampleOpts { seed: 1, size: 0 }),
+                    ..opts(1)
+                },
+                "sample size is 0",
+            ),
+        ];
+        for (opts, expected) in invalid {
+            let message = opts.validate().unwrap_err().to_string();
+            assert!(message.contains(expected), "{message}");
+        }
+    }
+
+    #[test]
+    fn tree_urls_are_reduced_to_the_repo_and_subdir() {
+        let shapes = [
+            (
+                "https://github.com/org/mono/tree/main/crates/foo",
+                Some("crates/foo"),
+            ),
+            ("https://github.com/org/mono/tree/v1.2.3/foo/", Some("foo")),
+            ("https://github.com/org/mono/tree/main", None),
+            ("https://github.com/org/mono/tree/main/", None),
+        ];
+        for (url, subdir) in shapes {
+            let validated = validate_repo(url).unwrap();
+            assert_eq!(
+                "https://github.com/org/mono",
+                validated.repository.0.as_str()
+            );
+            assert_eq!(Path::new("mono"), validated.repo_name.as_path());
+            assert_eq!(subdir.map(PathBuf::from), validated.subdir, "{url}");
+        }
+        for url in [
+            "https://github.com/org/mono/tree",
+            "https://github.com/org/mono/tree/",
+            "https://github.com/org/mono/blob/main/foo",
+        ] {
+            assert!(validate_repo(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn repo_url_forms_normalize_to_one_repo() {
+        let forms = [
+            "https://github.com/org/repo",
+            "https://github.com/org/repo.git",
+            "https://github.com/org/repo/",
+        ]
+        .map(|url| validate_repo(url).unwrap());
+        for validated in &forms {
+            assert_eq!(forms[0].repository, validated.repository);
+            assert_eq!(Path::new("repo"), validated.repo_name.as_path());
+        }
+        assert_eq!(
+            "https://github.com/org/repo",
+            forms[0].repository.0.as_str()
+        );
+    }
+
+    #[test]
+    fn repo_list_mixes_urls_and_crate_names() {
+        let list = read_repo_list(
+            "# repos to check\n\
+             https://github.com/fixture/first\n\
+             \n\
+             serde\n  \
+             https://github.com/fixture/second  \n\
+             # tokio\n\
+             anyhow\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let urls = list.urls.iter().map(Url::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "https://github.com/fixture/first",
+                "https://github.com/fixture/second"
+            ],
+            urls
+        );
+        assert_eq!(
+            BTreeSet::from(["anyhow".to_string(), "serde".to_string()]),
+            list.crate_names
+        );
+    }
+
+    #[test]
+    fn repo_list_errors_name_the_line() {
+        let err = read_repo_list("serde\n\nhttp://github.com/fixture/insecure\n".as_bytes())
+            .unwrap_err()
+            .to_string();
+        assert_eq!("invalid repo url on line 3", err);
+    }
+}
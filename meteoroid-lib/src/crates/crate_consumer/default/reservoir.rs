@@ -0,0 +1,62 @@
+use rustc_hash::FxHashSet;
+
+/// Uniform sample of a stream of unknown length (algorithm R), deterministic for a given seed
+/// and input order
+pub(super) struct Reservoir<T> {
+    size: usize,
+    rng: SplitMix64,
+    offered: FxHashSet<u64>,
+    sample: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub(super) fn new(seed: u64, size: usize) -> Self {
+        Self {
+            size,
+            rng: SplitMix64(seed),
+            offered: FxHashSet::default(),
+            sample: Vec::with_capacity(size),
+        }
+    }
+
+    /// Items are deduplicated by `id`, only the first offer of an id is considered
+    pub(super) fn offer(&mut self, id: u64, item: T) {
+        if !self.offered.insert(id) {
+            return;
+        }
+        if self.sample.len() < self.size {
+            self.sample.push(item);
+            return;
+        }
+        let seen = self.offered.len() as u64;
+        let replace = self.rng.next_below(seen);
+        if let Ok(replace) = usize::try_from(replace)
+            && replace < self.size
+        {
+            self.sample[replace] = item;
+        }
+    }
+
+    pub(super) fn into_sample(self) -> Vec<T> {
+        self.sample
+    }
+}
+
+/// Small, well known, PRNG. Used instead of a crate since the output needs to stay stable
+/// across dependency upgrades for samples to be reproducible
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// In `0..upper`, negligible bias for the ranges used here
+    fn next_below(&mut self, upper: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(upper)) >> 64) as u64
+    }
+}
@@ -1,54 +1,153 @@
 use crate::crates::api::VersionsEntryBuilder;
 use crate::crates::crate_consumer::CrateConsumer;
+use crate::error::unpack;
 use crate::fs::Workdir;
 use anyhow::Context;
+use csv::StringRecord;
 use rustc_hash::FxHashMap;
 use std::path::Path;
 
-pub(crate) fn consume_crates_data(
-    workdir: &Workdir,
-    consumer: &mut impl CrateConsumer,
-) -> anyhow::Result<()> {
-    let name_id_mapping = parse_id_name_mapping(&workdir.crates_csv)?;
-    parse_versions_xml(&workdir.versions_csv, &name_id_mapping, consumer)?;
-    Ok(())
+/// How the csvs are read, through a memory map by default with the `mmap` feature
+#[derive(Debug, Copy, Clone, Default)]
+enum ReadMode {
+    // Only compared against in tests, with the `mmap` feature
+    #[cfg_attr(feature = "mmap", allow(dead_code))]
+    #[cfg_attr(not(feature = "mmap"), default)]
+    Buffered,
+    #[cfg(feature = "mmap")]
+    #[default]
+    Mapped,
 }
 
-fn parse_versions_xml(
-    path: &Path,
-    name_id_mapping: &FxHashMap<u64, String>,
-    consumer: &mut impl CrateConsumer,
-) -> anyhow::Result<()> {
-    tracing::debug!("parsing versions data from {}", path.display());
+enum CsvSource {
+    Buffered(std::fs::File),
+    #[cfg(feature = "mmap")]
+    Mapped(std::io::Cursor<memmap2::Mmap>),
+}
+
+impl std::io::Read for CsvSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Buffered(file) => file.read(buf),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map.read(buf),
+        }
+    }
+}
+
+fn csv_reader(path: &Path, mode: ReadMode) -> anyhow::Result<csv::Reader<CsvSource>> {
     let file = std::fs::OpenOptions::new()
         .read(true)
         .create(false)
         .open(path)
         .with_context(|| format!("failed to open file at {}", path.display()))?;
-    let mut rdr = csv::ReaderBuilder::new()
+    let source = match mode {
+        ReadMode::Buffered => CsvSource::Buffered(file),
+        #[cfg(feature = "mmap")]
+        // Safety: the csvs are only written when fetching the index, which is done before
+        // parsing. Another run sharing the workdir could still rewrite them under the map, the
+        // same caveat as running two fetches into one workdir at once
+        ReadMode::Mapped => CsvSource::Mapped(std::io::Cursor::new(
+            unsafe { memmap2::Mmap::map(&file) }
+                .with_context(|| format!("failed to mmap file at {}", path.display()))?,
+        )),
+    };
+    Ok(csv::ReaderBuilder::new()
         .has_headers(true)
-        .from_reader(file);
+        .from_reader(source))
+}
+
+type IdNameMapping = FxHashMap<u64, String>;
+
+type MappingTask = tokio::task::JoinHandle<anyhow::Result<IdNameMapping>>;
+
+/// Versions records held while the id to name mapping is being built, past this reading
+/// waits for the mapping, so that memory use doesn't grow with the size of the dump
+const MAX_READ_AHEAD: usize = 16 * 1024;
+
+/// Parses the dumps on blocking threads, the id to name mapping is built on its own
+/// while versions are read. Returns the consumer once it's done
+pub(crate) async fn consume_crates_data<C: CrateConsumer + Send + 'static>(
+    workdir: &Workdir,
+    mut consumer: C,
+) -> anyhow::Result<C> {
+    let span = tracing::Span::current();
+    let mapping_span = span.clone();
+    let crates_csv = workdir.crates_csv.clone();
+    let mapping = tokio::task::spawn_blocking(move || {
+        mapping_span.in_scope(|| parse_id_name_mapping(&crates_csv, ReadMode::default()))
+    });
+    let versions_csv = workdir.versions_csv.clone();
+    tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            parse_versions_xml(
+                &versions_csv,
+                mapping,
+                MAX_READ_AHEAD,
+                ReadMode::default(),
+                &mut consumer,
+            )
+        })?;
+        Ok(consumer)
+    })
+    .await
+    .context("versions parsing task panicked")?
+}
+
+/// Only called from blocking threads
+fn join_mapping(mapping: MappingTask) -> anyhow::Result<IdNameMapping> {
+    tokio::runtime::Handle::current()
+        .block_on(mapping)
+        .context("crate id to name mapping task panicked")?
+}
+
+fn parse_versions_xml(
+    path: &Path,
+    mapping: MappingTask,
+    max_read_ahead: usize,
+    mode: ReadMode,
+    consumer: &mut impl CrateConsumer,
+) -> anyhow::Result<()> {
+    tracing::debug!("parsing versions data from {}", path.display());
+    let mut rdr = csv_reader(path, mode)?;
     let records = rdr.records();
     let mut records_read = 0;
+    let mut versions = VersionsConsumer {
+        path,
+        name_id_mapping: IdNameMapping::default(),
+        warned_extra_fields: false,
+        consumer,
+    };
+    // Records read before the mapping is done are held until it is, up to `max_read_ahead`
+    let mut pending = Some((mapping, Vec::new()));
     for rec_res in records {
         records_read += 1;
         let record = rec_res
             .with_context(|| format!("failed to read csv record from: {}", path.display()))?;
-        let mut bldr = VersionsEntryBuilder::default();
-        for val in &record {
-            bldr.enter_next(val).with_context(|| {
-                format!("failed to parse version entry from {}", path.display())
-            })?;
+        if let Some((handle, read_ahead)) = pending.as_mut()
+            && !handle.is_finished()
+            && read_ahead.len() < max_read_ahead
+        {
+            read_ahead.push((records_read, record));
+            continue;
         }
-        let val = bldr.consume()?;
-        let crate_name = name_id_mapping
-            .get(&val.crate_id)
-            .context("failed to find crate name for id")?;
-        if !consumer.consume(crate_name, val)? {
+        if let Some((handle, read_ahead)) = pending.take()
+            && !versions.catch_up(handle, read_ahead)?
+        {
+            tracing::info!("consumer finished early, after {records_read} csv records read");
+            return Ok(());
+        }
+        if !versions.consume(records_read, &record)? {
             tracing::info!("consumer finished early, after {records_read} csv records read");
-            break;
+            return Ok(());
         }
     }
+    if let Some((handle, read_ahead)) = pending.take()
+        && !versions.catch_up(handle, read_ahead)?
+    {
+        tracing::info!("consumer finished early, after {records_read} csv records read");
+        return Ok(());
+    }
     tracing::debug!(
         "consumed {records_read} csv records from {}",
         path.display()
@@ -56,16 +155,69 @@ fn parse_versions_xml(
     Ok(())
 }
 
-fn parse_id_name_mapping(path: &Path) -> anyhow::Result<FxHashMap<u64, String>> {
+struct VersionsConsumer<'a, C> {
+    path: &'a Path,
+    name_id_mapping: IdNameMapping,
+    warned_extra_fields: bool,
+    consumer: &'a mut C,
+}
+
+impl<C: CrateConsumer> VersionsConsumer<'_, C> {
+    /// Waits for the mapping and consumes the records read meanwhile,
+    /// returns false if the consumer finished early
+    fn catch_up(
+        &mut self,
+        mapping: MappingTask,
+        read_ahead: Vec<(usize, StringRecord)>,
+    ) -> anyhow::Result<bool> {
+        self.name_id_mapping = join_mapping(mapping)?;
+        tracing::debug!(
+            "id to name mapping ready after {} versions records read ahead",
+            read_ahead.len()
+        );
+        for (record_num, record) in read_ahead {
+            if !self.consume(record_num, &record)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns false if the consumer finished early
+    fn consume(&mut self, record_num: usize, record: &StringRecord) -> anyhow::Result<bool> {
+        let mut bldr = VersionsEntryBuilder::default();
+        // A single malformed row shouldn't abort parsing the whole dump
+        if let Err(e) = record
+            .iter()
+            .try_for_each(|val| bldr.enter_next(val).map(drop))
+        {
+            tracing::trace!(
+                "skipping malformed version entry {record_num} in {}: {}",
+                self.path.display(),
+                unpack(&*e)
+            );
+            return Ok(true);
+        }
+        if !self.warned_extra_fields && bldr.num_extra_fields() > 0 {
+            tracing::warn!(
+                "versions csv at {} has {} more columns than expected, ignoring them",
+                self.path.display(),
+                bldr.num_extra_fields()
+            );
+            self.warned_extra_fields = true;
+        }
+        let val = bldr.consume()?;
+        let crate_name = self
+            .name_id_mapping
+            .get(&val.crate_id)
+            .context("failed to find crate name for id")?;
+        self.consumer.consume(crate_name, val)
+    }
+}
+
+fn parse_id_name_mapping(path: &Path, mode: ReadMode) -> anyhow::Result<IdNameMapping> {
     tracing::debug!("parsing crate id to name mapping from {}", path.display());
-    let file = std::fs::OpenOptions::new()
-        .read(true)
-        .create(false)
-        .open(path)
-        .with_context(|| format!("failed to open file at {}", path.display()))?;
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(file);
+    let mut rdr = csv_reader(path, mode)?;
     let records = rdr.records();
     let mut approx_size = 0;
     let mut map = FxHashMap::default();
@@ -91,3 +243,204 @@ fn parse_id_name_mapping(path: &Path) -> anyhow::Result<FxHashMap<u64, String>>
     );
     Ok(map)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crates::api::VersionsEntry;
+    use std::fmt::Write;
+
+    const VERSIONS_HEADER: &str = "bin_names,categories,checksum,crate_id,crate_size,created_at,\
+        description,documentation,downloads,edition,features,has_lib,homepage,id,keywords,\
+        license,links,num,num_no_build,published_by,repository,rust_version,updated_at,yanked";
+
+    #[derive(Debug, PartialEq)]
+    struct Parsed {
+        name: String,
+        description: String,
+        features: String,
+        downloads: u64,
+        crate_size: u64,
+        repository: String,
+    }
+
+    #[derive(Default)]
+    struct Collect(Vec<Parsed>);
+
+    impl CrateConsumer for Collect {
+        fn consume(&mut self, crate_name: &str, entry: VersionsEntry) -> anyhow::Result<bool> {
+            self.0.push(Parsed {
+                name: crate_name.to_string(),
+                description: entry.description.to_string(),
+                features: entry.features.to_string(),
+                downloads: entry.downloads,
+                crate_size: entry.crate_size,
+                repository: entry.repository.to_string(),
+            });
+            Ok(true)
+        }
+    }
+
+    /// Writes `crates.csv` with crate `n` named `crate-n`, and `versions.csv` with `header`
+    /// and `rows`
+    fn write_dumps(dir: &Path, crates: u64, header: &str, rows: &[String]) -> Workdir {
+        let mut crates_csv =
+            "created_at,description,documentation,homepage,id,max_upload_size,readme,name\n"
+                .to_string();
+        for id in 1..=crates {
+            writeln!(crates_csv, ",,,,{id},,,crate-{id}").unwrap();
+        }
+        std::fs::write(dir.join("crates.csv"), crates_csv).unwrap();
+        let mut versions_csv = format!("{header}\n");
+        for row in rows {
+            versions_csv.push_str(row);
+            versions_csv.push('\n');
+        }
+        std::fs::write(dir.join("versions.csv"), versions_csv).unwrap();
+        Workdir::new(dir.to_path_buf())
+    }
+
+    /// Parses the dumps written by `write_dumps`
+    async fn parse(crates: u64, header: &str, rows: &[String]) -> Vec<Parsed> {
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = write_dumps(dir.path(), crates, header, rows);
+        consume_crates_data(&workdir, Collect::default())
+            .await
+            .unwrap()
+            .0
+    }
+
+    /// A versions row with the given description, features, size and downloads cells
+    fn row(id: u64, description: &str, features: &str, size: &str, downloads: &str) -> String {
+        format!(
+            "{{}},{{}},,{id},{size},2024-01-01 00:00:00,{description},,{downloads},2021,\
+             {features},t,,{id},{{}},MIT,,0.1.0,0.1.0,,https://github.com/o/r{id},,\
+             2024-01-01 00:00:00,f"
+        )
+    }
+
+    #[tokio::test]
+    async fn extra_column_and_embedded_commas_are_parsed() {
+        let header = format!("{VERSIONS_HEADER},added_later");
+        let rows = [format!(
+            "{},\"with, a comma\"",
+            row(
+                1,
+                "\"Fast, \"\"safe\"\", small\"",
+                "\"{\"\"default\"\":[\"\"std\"\",\"\"alloc\"\"]}\"",
+                "100",
+                "5"
+            )
+        )];
+        assert_eq!(
+            vec![Parsed {
+                name: "crate-1".to_string(),
+                description: "Fast, \"safe\", small".to_string(),
+                features: "{\"default\":[\"std\",\"alloc\"]}".to_string(),
+                downloads: 5,
+                crate_size: 100,
+                repository: "https://github.com/o/r1".to_string(),
+            }],
+            parse(1, &header, &rows).await
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_and_garbage_counts_are_zero() {
+        let rows = [row(1, "", "{}", "", ""), row(2, "", "{}", "big", "-3")];
+        let counts = parse(2, VERSIONS_HEADER, &rows)
+            .await
+            .into_iter()
+            .map(|p| (p.name, p.crate_size, p.downloads))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("crate-1".to_string(), 0, 0), ("crate-2".to_string(), 0, 0),],
+            counts
+        );
+    }
+
+    #[tokio::test]
+    async fn garbage_crate_id_only_skips_its_record() {
+        let rows = [
+            row(1, "", "{}", "1", "1").replacen(",1,1,", ",x,1,", 1),
+            row(2, "", "{}", "1", "1"),
+            row(3, "", "{}", "1", "1").replacen(",3,1,", ",,1,", 1),
+        ];
+        let names = parse(3, VERSIONS_HEADER, &rows)
+            .await
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["crate-2".to_string()], names);
+    }
+
+    #[tokio::test]
+    async fn versions_read_ahead_of_the_mapping_are_all_consumed() {
+        let rows = (1..=50)
+            .map(|id| row(id, "", "{}", "1", "1"))
+            .collect::<Vec<_>>();
+        let expected = (1..=50).map(|id| format!("crate-{id}")).collect::<Vec<_>>();
+        // Unbounded, bounded below the number of rows, and reading one at a time
+        for max_read_ahead in [usize::MAX, 8, 0] {
+            let dir = tempfile::tempdir().unwrap();
+            let workdir = write_dumps(dir.path(), 50, VERSIONS_HEADER, &rows);
+            let crates_csv = workdir.crates_csv.clone();
+            // Slow enough that versions are read ahead of it
+            let mapping = tokio::task::spawn_blocking(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                parse_id_name_mapping(&crates_csv, ReadMode::default())
+            });
+            let collect = tokio::task::spawn_blocking(move || {
+                let mut collect = Collect::default();
+                parse_versions_xml(
+                    &workdir.versions_csv,
+                    mapping,
+                    max_read_ahead,
+                    ReadMode::default(),
+                    &mut collect,
+                )
+                .map(|()| collect)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+            let names = collect.0.into_iter().map(|p| p.name).collect::<Vec<_>>();
+            assert_eq!(expected, names, "max_read_ahead: {max_read_ahead}");
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[tokio::test]
+    async fn mapped_and_buffered_reads_parse_the_same() {
+        let header = format!("{VERSIONS_HEADER},added_later");
+        let rows = (1..=20)
+            .map(|id| {
+                let description = format!("\"Crate {id}, \"\"quoted\"\"\"");
+                format!(
+                    "{},{id}",
+                    row(id, &description, "{}", "100", &id.to_string())
+                )
+            })
+            .collect::<Vec<_>>();
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = write_dumps(dir.path(), 20, &header, &rows);
+        let mut parsed = Vec::new();
+        for mode in [ReadMode::Buffered, ReadMode::Mapped] {
+            let crates_csv = workdir.crates_csv.clone();
+            let versions_csv = workdir.versions_csv.clone();
+            let mapping =
+                tokio::task::spawn_blocking(move || parse_id_name_mapping(&crates_csv, mode));
+            let collect = tokio::task::spawn_blocking(move || {
+                let mut collect = Collect::default();
+                parse_versions_xml(&versions_csv, mapping, MAX_READ_AHEAD, mode, &mut collect)
+                    .map(|()| collect)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+            parsed.push(collect.0);
+        }
+        assert_eq!(20, parsed[0].len());
+        assert_eq!(parsed[0], parsed[1]);
+    }
+}
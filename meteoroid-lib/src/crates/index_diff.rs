@@ -0,0 +1,128 @@
+use crate::crates::api::VersionsEntryBuilder;
+use crate::error::unpack;
+use anyhow::Context;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+/// Crate ids that have a version which is new in `versions_csv`, or whose `num`/`updated_at`
+/// changed since `prev_versions_csv`.
+/// Crate ids are stable across dumps, so the versions csvs are enough to find changed crates
+pub(crate) fn changed_crate_ids(
+    prev_versions_csv: &Path,
+    versions_csv: &Path,
+) -> anyhow::Result<FxHashSet<u64>> {
+    let mut prev = FxHashMap::default();
+    for_each_version(prev_versions_csv, |version_id, _crate_id, fingerprint| {
+        prev.insert(version_id, fingerprint);
+    })?;
+    let mut changed = FxHashSet::default();
+    for_each_version(versions_csv, |version_id, crate_id, fingerprint| {
+        if prev.get(&version_id) != Some(&fingerprint) {
+            changed.insert(crate_id);
+        }
+    })?;
+    tracing::debug!(
+        "found {} new or changed crates comparing {} to {}",
+        changed.len(),
+        versions_csv.display(),
+        prev_versions_csv.display()
+    );
+    Ok(changed)
+}
+
+/// Calls `f` with `(version_id, crate_id, fingerprint)` for each parseable row,
+/// the fingerprint being a hash of the version's `num` and `updated_at`
+fn for_each_version(path: &Path, mut f: impl FnMut(u64, u64, u64)) -> anyhow::Result<()> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open file at {}", path.display()))?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(file);
+    for rec_res in rdr.records() {
+        let record = rec_res
+            .with_context(|| format!("failed to read csv record from: {}", path.display()))?;
+        let mut bldr = VersionsEntryBuilder::default();
+        if let Err(e) = record
+            .iter()
+            .try_for_each(|val| bldr.enter_next(val).map(drop))
+        {
+            tracing::trace!(
+                "skipping malformed version entry in {}: {}",
+                path.display(),
+                unpack(&*e)
+            );
+            continue;
+        }
+        let entry = bldr.consume()?;
+        let Ok(version_id) = entry.id.parse::<u64>() else {
+            tracing::trace!(
+                "skipping version entry with malformed id '{}' in {}",
+                entry.id,
+                path.display()
+            );
+            continue;
+        };
+        let mut hasher = rustc_hash::FxHasher::default();
+        entry.num.hash(&mut hasher);
+        entry.updated_at.hash(&mut hasher);
+        f(version_id, entry.crate_id, hasher.finish());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    fn versions_csv(path: &Path, versions: &[(u64, u64, &str, &str)]) {
+        let mut csv = String::from(
+            "bin_names,categories,checksum,crate_id,crate_size,created_at,description,\
+             documentation,downloads,edition,features,has_lib,homepage,id,keywords,license,links,\
+             num,num_no_build,published_by,repository,rust_version,updated_at,yanked\n",
+        );
+        for (version_id, crate_id, num, updated_at) in versions {
+            writeln!(
+                csv,
+                "{{}},{{}},,{crate_id},1,2024-01-01 00:00:00,,,1,2021,{{}},t,,{version_id},{{}},\
+                 MIT,,{num},{num},,,,{updated_at},f"
+            )
+            .unwrap();
+        }
+        std::fs::write(path, csv).unwrap();
+    }
+
+    #[test]
+    fn only_new_and_changed_crates_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let prev = dir.path().join("versions.prev.csv");
+        let current = dir.path().join("versions.csv");
+        versions_csv(
+            &prev,
+            &[
+                (10, 1, "0.1.0", "2024-01-01 00:00:00"),
+                (20, 2, "1.0.0", "2024-01-01 00:00:00"),
+                (30, 3, "2.0.0", "2024-01-01 00:00:00"),
+            ],
+        );
+        versions_csv(
+            &current,
+            &[
+                (10, 1, "0.1.0", "2024-01-01 00:00:00"),
+                // Yanked or otherwise updated
+                (20, 2, "1.0.0", "2024-03-01 00:00:00"),
+                (30, 3, "2.0.0", "2024-01-01 00:00:00"),
+                // A new version of an existing crate, and a new crate
+                (31, 3, "2.1.0", "2024-03-01 00:00:00"),
+                (40, 4, "0.1.0", "2024-03-01 00:00:00"),
+            ],
+        );
+        let mut changed = changed_crate_ids(&prev, &current)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+        assert_eq!(vec![2, 3, 4], changed);
+    }
+}
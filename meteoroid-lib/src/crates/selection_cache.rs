@@ -0,0 +1,72 @@
+use crate::crates::crate_consumer::default::PrunedCrate;
+use crate::error::unpack;
+use anyhow::Context;
+use std::path::Path;
+
+/// The crates selected on a previous run, reused as long as the index and
+/// the selection options are unchanged
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedSelection {
+    opts_fingerprint: String,
+    index_modified_unix_seconds: u64,
+    crates: Vec<PrunedCrate>,
+}
+
+pub(crate) async fn load(
+    path: &Path,
+    opts_fingerprint: &str,
+    index_modified_unix_seconds: u64,
+) -> Option<Vec<PrunedCrate>> {
+    let content = match tokio::fs::read(path).await {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::warn!(
+                "failed to read cached selection at {}: {}",
+                path.display(),
+                unpack(&e)
+            );
+            return None;
+        }
+    };
+    let cached: CachedSelection = match serde_json::from_slice(&content) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!(
+                "failed to parse cached selection at {}, ignoring it: {}",
+                path.display(),
+                unpack(&e)
+            );
+            return None;
+        }
+    };
+    if cached.opts_fingerprint != opts_fingerprint {
+        tracing::debug!("selection options changed since cached selection, re-parsing index");
+        return None;
+    }
+    if cached.index_modified_unix_seconds != index_modified_unix_seconds {
+        tracing::debug!("index changed since cached selection, re-parsing index");
+        return None;
+    }
+    Some(cached.crates)
+}
+
+pub(crate) async fn store(
+    path: &Path,
+    opts_fingerprint: String,
+    index_modified_unix_seconds: u64,
+    crates: Vec<PrunedCrate>,
+) -> anyhow::Result<Vec<PrunedCrate>> {
+    let cached = CachedSelection {
+        opts_fingerprint,
+        index_modified_unix_seconds,
+        crates,
+    };
+    let content =
+        serde_json::to_vec_pretty(&cached).context("failed to serialize crate selection")?;
+    tokio::fs::write(path, content)
+        .await
+        .with_context(|| format!("failed to write crate selection to {}", path.display()))?;
+    tracing::debug!("cached crate selection at {}", path.display());
+    Ok(cached.crates)
+}
@@ -0,0 +1,175 @@
+use crate::cmd::{CargoCommand, output_string};
+use crate::fs::Workdir;
+use crate::unpack;
+use anyhow::Context;
+use std::path::Path;
+use tokio::process::Command;
+
+/// The outcome of a single environment check, `outcome` contains details either way
+pub struct DoctorCheck {
+    pub name: String,
+    pub outcome: Result<String, String>,
+}
+
+impl DoctorCheck {
+    fn new(name: String, outcome: anyhow::Result<String>) -> Self {
+        Self {
+            name,
+            outcome: outcome.map_err(|e| unpack(&*e).to_string()),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// Checks that everything meteoroid needs to run is in place:
+/// required tools are on path, the `rustfmt` repos are git repos, and the workdir is writable.
+/// `cargo` is probed through `cargo`, with the wrapper and arguments it will be run with
+pub async fn doctor(
+    workdir: &Path,
+    cargo: &CargoCommand,
+    rustfmt_local_repo: Option<&Path>,
+    rustfmt_upstream_repo: Option<&Path>,
+) -> Vec<DoctorCheck> {
+    let mut checks = vec![];
+    checks.push(DoctorCheck::new(
+        "git is installed".to_string(),
+        check_tool_version(&mut Command::new("git")).await,
+    ));
+    checks.push(DoctorCheck::new(
+        format!("{} is installed", cargo.bin.display()),
+        check_tool_version(&mut cargo.command()).await,
+    ));
+    checks.push(DoctorCheck::new(
+        "rustup is installed".to_string(),
+        check_tool_version(&mut Command::new("rustup")).await,
+    ));
+    for (side, repo) in [
+        ("local", rustfmt_local_repo),
+        ("upstream", rustfmt_upstream_repo),
+    ] {
+        let check = if let Some(repo) = repo {
+            DoctorCheck::new(
+                format!("{side} rustfmt repo at {} is a git repo", repo.display()),
+                check_git_repo(repo).await,
+            )
+        } else {
+            DoctorCheck::new(
+                format!("{side} rustfmt repo is a git repo"),
+                Err(anyhow::anyhow!("no {side} rustfmt repo configured")),
+            )
+        };
+        checks.push(check);
+    }
+    checks.push(DoctorCheck::new(
+        format!("workdir at {} is writable", workdir.display()),
+        check_workdir_writable(workdir).await,
+    ));
+    checks
+}
+
+async fn check_tool_version(cmd: &mut Command) -> anyhow::Result<String> {
+    let tool = cmd.as_std().get_program().to_string_lossy().to_string();
+    let version = output_string(cmd.arg("--version"))
+        .await
+        .with_context(|| format!("failed to run '{tool} --version', is it on path?"))?;
+    Ok(version
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+async fn check_git_repo(path: &Path) -> anyhow::Result<String> {
+    let top_level = output_string(
+        Command::new("git")
+            .arg("rev-parse")
+            .arg("--show-toplevel")
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .current_dir(path),
+    )
+    .await
+    .with_context(|| format!("{} is not a git repo", path.display()))?;
+    Ok(format!("git repo rooted at {}", top_level.trim()))
+}
+
+async fn check_workdir_writable(workdir: &Path) -> anyhow::Result<String> {
+    let wd = Workdir::new(workdir.to_path_buf());
+    wd.ensure_workdir().await?;
+    let base = wd.base.clone();
+    tokio::task::spawn_blocking(move || {
+        // Removed on drop
+        tempfile::tempfile_in(&base)
+            .with_context(|| format!("failed to create a file in {}", base.display()))
+    })
+    .await
+    .context("failed to join workdir write check")??;
+    Ok("writable".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tool_check_reports_the_version() {
+        let version = check_tool_version(&mut Command::new("git")).await.unwrap();
+        assert!(version.starts_with("git version"), "{version}");
+        assert!(
+            check_tool_version(&mut Command::new("meteoroid-no-such-tool"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn cargo_is_probed_through_the_cargo_command() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("cargo-wrapper");
+        std::fs::write(&bin, "#!/bin/sh\necho \"cargo-wrapper $*\"\n").unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let cargo = CargoCommand {
+            bin: bin.clone(),
+            args: vec!["--offline".to_string()],
+        };
+        let checks = doctor(dir.path(), &cargo, None, None).await;
+        let check = checks
+            .iter()
+            .find(|check| check.name == format!("{} is installed", bin.display()))
+            .unwrap();
+        assert_eq!(
+            Ok("cargo-wrapper --offline --version".to_string()),
+            check.outcome
+        );
+    }
+
+    #[tokio::test]
+    async fn git_repo_check_needs_a_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_git_repo(dir.path()).await.is_err());
+        let status = std::process::Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        check_git_repo(dir.path()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn workdir_check_creates_the_workdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = dir.path().join("workdir");
+        check_workdir_writable(&workdir).await.unwrap();
+        assert!(workdir.is_dir());
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(check_workdir_writable(&file.join("workdir")).await.is_err());
+    }
+}
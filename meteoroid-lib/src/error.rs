@@ -1,5 +1,34 @@
 use std::fmt::{Display, Formatter};
 
+/// Why a run failed, by which part of it failed
+#[derive(Debug, thiserror::Error)]
+pub enum MeteroidError {
+    /// Invalid options, missing tools, an unusable workdir,
+    /// or `rustfmt` repos that couldn't be built
+    #[error("failed to set up the run")]
+    Setup(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Fetching or reading the crates index failed
+    #[error("failed to fetch crates")]
+    Fetch(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Analyzing crates or writing the report failed
+    #[error("failed to analyze crates")]
+    Analysis(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl MeteroidError {
+    pub(crate) fn setup(e: anyhow::Error) -> Self {
+        Self::Setup(e.into())
+    }
+
+    pub(crate) fn fetch(e: anyhow::Error) -> Self {
+        Self::Fetch(e.into())
+    }
+
+    pub(crate) fn analysis(e: anyhow::Error) -> Self {
+        Self::Analysis(e.into())
+    }
+}
+
 pub struct ErrFmt<'a>(&'a (dyn std::error::Error + Send + Sync));
 
 #[inline]
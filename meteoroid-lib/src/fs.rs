@@ -1,8 +1,9 @@
 use crate::error::unpack;
 use anyhow::{Context, bail};
+use cargo_toml::{Edition, Inheritable};
 use std::fs::Metadata;
 use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone)]
@@ -10,6 +11,11 @@ pub(crate) struct Workdir {
     pub(crate) base: PathBuf,
     pub(crate) versions_csv: PathBuf,
     pub(crate) crates_csv: PathBuf,
+    /// `versions.csv` from before the last index refetch, kept for diffing when
+    /// only analyzing changed crates
+    pub(crate) prev_versions_csv: PathBuf,
+    pub(crate) selection_cache: PathBuf,
+    pub(crate) toolchain_lock: PathBuf,
 }
 
 impl Workdir {
@@ -17,6 +23,9 @@ impl Workdir {
         Self {
             versions_csv: base.join("versions.csv"),
             crates_csv: base.join("crates.csv"),
+            prev_versions_csv: base.join("versions.prev.csv"),
+            selection_cache: base.join("selection.json"),
+            toolchain_lock: base.join(".toolchain.lock"),
             base,
         }
     }
@@ -45,6 +54,89 @@ impl Workdir {
         Ok(needs_refetch(&self.crates_csv, staleness_limit_days).await?
             || needs_refetch(&self.versions_csv, staleness_limit_days).await?)
     }
+
+    /// When the index was last written, as the latest of both csvs' modification times
+    pub(crate) async fn index_modified_unix_seconds(&self) -> anyhow::Result<u64> {
+        let mut latest = 0;
+        for csv in [&self.crates_csv, &self.versions_csv] {
+            let md = tokio::fs::metadata(csv)
+                .await
+                .with_context(|| format!("failed to read metadata of {}", csv.display()))?;
+            let modified = last_updated(&md)
+                .and_then(|lu| lu.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .with_context(|| format!("failed to read last updated on {}", csv.display()))?;
+            latest = latest.max(modified.as_secs());
+        }
+        Ok(latest)
+    }
+}
+
+/// Takes an exclusive lock on the file at `path`, creating it if necessary,
+/// waits until any other process holding it releases it.
+/// The lock is released when the returned file is dropped
+pub(crate) async fn lock_exclusive(path: &Path) -> anyhow::Result<std::fs::File> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open lock file at {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("failed to lock {}", path.display()))?;
+        Ok(file)
+    })
+    .await
+    .context("failed to join lock task")?
+}
+
+/// Where `dest` is written before it's moved into place, in the same directory so that the
+/// move is a rename on the same filesystem
+fn temp_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    dest.with_file_name(name)
+}
+
+/// Writes to a temporary file next to `dest` and renames it to `dest` when `write` succeeds,
+/// so that dying mid-write never leaves a truncated file at `dest`. If `write` fails, what was
+/// written is left at `<dest>.tmp`. The file is synced before the rename, or a crash could
+/// leave the rename on disk without the content
+pub(crate) fn write_atomically(
+    dest: &Path,
+    write: impl FnOnce(&mut dyn std::io::Write) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let tmp = temp_path(dest);
+    let file = std::fs::File::create(&tmp)
+        .with_context(|| format!("failed to create {}", tmp.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+    write(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(std::io::IntoInnerError::into_error)
+        .with_context(|| format!("failed to write to {}", tmp.display()))?
+        .sync_all()
+        .with_context(|| format!("failed to sync {}", tmp.display()))?;
+    std::fs::rename(&tmp, dest)
+        .with_context(|| format!("failed to move {} to {}", tmp.display(), dest.display()))
+}
+
+/// Async `write_atomically` for content that's already in memory
+pub(crate) async fn write_atomically_async(dest: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let tmp = temp_path(dest);
+    let mut file = tokio::fs::File::create(&tmp)
+        .await
+        .with_context(|| format!("failed to create {}", tmp.display()))?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, content)
+        .await
+        .with_context(|| format!("failed to write to {}", tmp.display()))?;
+    file.sync_all()
+        .await
+        .with_context(|| format!("failed to sync {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, dest)
+        .await
+        .with_context(|| format!("failed to move {} to {}", tmp.display(), dest.display()))
 }
 
 async fn needs_refetch(path: &PathBuf, staleness_limit_days: u8) -> anyhow::Result<bool> {
@@ -104,6 +196,202 @@ pub(crate) async fn has_top_level_cargo_toml(repo_root: &Path) -> anyhow::Result
         .with_context(|| format!("failed to check for Cargo.toml at {}", path.display()))
 }
 
+/// Bytes taken up by every file under `root`, symlinks aren't followed
+pub(crate) async fn dir_size(root: &Path) -> anyhow::Result<u64> {
+    let root = root.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut size = 0;
+        let mut dirs = vec![root];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| format!("failed to read dir at {}", dir.display()))?
+            {
+                let entry =
+                    entry.with_context(|| format!("failed to read entry in {}", dir.display()))?;
+                let path = entry.path();
+                let metadata = entry
+                    .metadata()
+                    .with_context(|| format!("failed to get metadata of {}", path.display()))?;
+                if metadata.is_dir() {
+                    dirs.push(path);
+                } else if metadata.is_file() {
+                    size += metadata.len();
+                }
+            }
+        }
+        Ok(size)
+    })
+    .await
+    .context("failed to join dir size measurement")?
+}
+
+pub(crate) struct RsFiles {
+    /// Relative to the searched root
+    pub(crate) files: Vec<PathBuf>,
+    /// Files left out because they were larger than the size limit, relative to the root
+    pub(crate) oversized: Vec<PathBuf>,
+}
+
+/// Every `.rs` file under `root`, skipping `target` and hidden directories,
+/// files larger than `max_bytes` are put in `oversized` instead
+pub(crate) async fn find_rs_files(root: &Path, max_bytes: Option<u64>) -> anyhow::Result<RsFiles> {
+    let root = root.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut found = RsFiles {
+            files: vec![],
+            oversized: vec![],
+        };
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| format!("failed to read dir at {}", dir.display()))?
+            {
+                let entry =
+                    entry.with_context(|| format!("failed to read entry in {}", dir.display()))?;
+                let path = entry.path();
+                let file_type = entry
+                    .file_type()
+                    .with_context(|| format!("failed to get file type of {}", path.display()))?;
+                let name = entry.file_name();
+                if file_type.is_dir() {
+                    if name != "target" && !name.to_string_lossy().starts_with('.') {
+                        dirs.push(path);
+                    }
+                } else if file_type.is_file()
+                    && path.extension().is_some_and(|ext| ext == "rs")
+                    && let Ok(rel) = path.strip_prefix(&root)
+                {
+                    let too_large = if let Some(max) = max_bytes {
+                        entry
+                            .metadata()
+                            .with_context(|| {
+                                format!("failed to get metadata of {}", path.display())
+                            })?
+                            .len()
+                            > max
+                    } else {
+                        false
+                    };
+                    if too_large {
+                        found.oversized.push(rel.to_path_buf());
+                    } else {
+                        found.files.push(rel.to_path_buf());
+                    }
+                }
+            }
+        }
+        found.files.sort();
+        found.oversized.sort();
+        Ok(found)
+    })
+    .await
+    .context("failed to join rs file search")?
+}
+
+/// The edition of the package at the repo root, or the workspace's default edition
+/// if the package inherits it or the manifest is virtual
+pub(crate) async fn read_root_edition(repo_root: &Path) -> anyhow::Result<Option<Edition>> {
+    let path = repo_root.join("Cargo.toml");
+    let content = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("failed to read Cargo.toml at {}", path.display()))?;
+    let manifest = cargo_toml::Manifest::from_slice(&content)
+        .with_context(|| format!("failed to parse Cargo.toml at {}", path.display()))?;
+    let workspace_edition = manifest
+        .workspace
+        .as_ref()
+        .and_then(|ws| ws.package.as_ref())
+        .and_then(|pkg| pkg.edition);
+    Ok(match manifest.package.as_ref().map(|pkg| &pkg.edition) {
+        Some(Inheritable::Set(edition)) => Some(*edition),
+        Some(Inheritable::Inherited) | None => workspace_edition,
+    })
+}
+
+/// The edition of a workspace member, falling back to the workspace's default edition
+/// if the member inherits it or doesn't set one
+pub(crate) async fn read_member_edition(
+    repo_root: &Path,
+    member: &Path,
+) -> anyhow::Result<Option<Edition>> {
+    let path = repo_root.join(member).join("Cargo.toml");
+    let content = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("failed to read Cargo.toml at {}", path.display()))?;
+    let manifest = cargo_toml::Manifest::from_slice(&content)
+        .with_context(|| format!("failed to parse Cargo.toml at {}", path.display()))?;
+    match manifest.package.as_ref().map(|pkg| &pkg.edition) {
+        Some(Inheritable::Set(edition)) => Ok(Some(*edition)),
+        Some(Inheritable::Inherited) | None => read_root_edition(repo_root).await,
+    }
+}
+
+/// Member roots relative to `repo_root` if its `Cargo.toml` is a virtual manifest,
+/// `None` if it has a `[package]`. Only trailing `/*` globs are expanded,
+/// and members outside the repo or without a `Cargo.toml` are left out
+pub(crate) async fn read_virtual_members(repo_root: &Path) -> anyhow::Result<Option<Vec<PathBuf>>> {
+    let path = repo_root.join("Cargo.toml");
+    let content = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("failed to read Cargo.toml at {}", path.display()))?;
+    let manifest = cargo_toml::Manifest::from_slice(&content)
+        .with_context(|| format!("failed to parse Cargo.toml at {}", path.display()))?;
+    let Some(workspace) = manifest.workspace.filter(|_| manifest.package.is_none()) else {
+        return Ok(None);
+    };
+    let mut members = vec![];
+    for member in &workspace.members {
+        if let Some(parent) = member.strip_suffix("/*") {
+            let dir = repo_root.join(parent);
+            let mut entries = tokio::fs::read_dir(&dir)
+                .await
+                .with_context(|| format!("failed to read dir at {}", dir.display()))?;
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .with_context(|| format!("failed to read entry in {}", dir.display()))?
+            {
+                members.push(Path::new(parent).join(entry.file_name()));
+            }
+        } else if !member.contains(['*', '?', '[']) {
+            members.push(PathBuf::from(member));
+        }
+    }
+    let mut found = Vec::with_capacity(members.len());
+    for member in members {
+        // Members come from untrusted manifests, don't follow them out of the repo
+        if !member
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            || workspace
+                .exclude
+                .iter()
+                .any(|excluded| member == Path::new(excluded))
+        {
+            continue;
+        }
+        if has_top_level_cargo_toml(&repo_root.join(&member)).await? {
+            found.push(member);
+        }
+    }
+    found.sort();
+    Ok(Some(found))
+}
+
+/// A `rustfmt.toml` or `.rustfmt.toml` at the repo root
+pub(crate) async fn has_rustfmt_config(repo_root: &Path) -> anyhow::Result<bool> {
+    for name in ["rustfmt.toml", ".rustfmt.toml"] {
+        let path = repo_root.join(name);
+        if tokio::fs::try_exists(&path)
+            .await
+            .with_context(|| format!("failed to check for rustfmt config at {}", path.display()))?
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 pub(crate) async fn has_rust_toolchain(repo_root: &Path) -> anyhow::Result<bool> {
     let rust_toolchain_classic = repo_root.join("rust-toolchain");
     if tokio::fs::try_exists(&rust_toolchain_classic)
@@ -127,3 +415,50 @@ pub(crate) async fn has_rust_toolchain(repo_root: &Path) -> anyhow::Result<bool>
             )
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn dir_size_sums_nested_files_without_following_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join("top"), [0; 100]).unwrap();
+        std::fs::write(nested.join("deep"), [0; 23]).unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("big"), [0; 4096]).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+        assert_eq!(123, dir_size(dir.path()).await.unwrap());
+        assert!(dir_size(&dir.path().join("missing")).await.is_err());
+    }
+
+    #[test]
+    fn interrupted_write_leaves_no_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("report.json");
+        let res = write_atomically(&dest, |writer| {
+            writer.write_all(b"{\"num_diverging_diffs\":")?;
+            anyhow::bail!("interrupted")
+        });
+        assert!(res.is_err());
+        assert!(!dest.exists());
+        let partial = std::fs::read_to_string(dir.path().join("report.json.tmp")).unwrap();
+        assert_eq!("{\"num_diverging_diffs\":", partial);
+    }
+
+    #[test]
+    fn interrupted_write_keeps_the_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("report.json");
+        write_atomically(&dest, |writer| Ok(writer.write_all(b"previous")?)).unwrap();
+        assert!(!dir.path().join("report.json.tmp").exists());
+        let res = write_atomically(&dest, |writer| {
+            writer.write_all(b"partial")?;
+            anyhow::bail!("interrupted")
+        });
+        assert!(res.is_err());
+        assert_eq!("previous", std::fs::read_to_string(&dest).unwrap());
+    }
+}
@@ -1,31 +1,146 @@
 use crate::StopReceiver;
-use crate::cmd::output_string;
-use crate::crates::crate_consumer::default::{GitRepo, PrunedCrate};
+use crate::cmd::{output_string, output_string_redacted};
+use crate::crates::crate_consumer::default::{CrateName, GitRepo, PrunedCrate};
 use crate::error::unpack;
-use crate::fs::{Workdir, has_rust_toolchain, has_top_level_cargo_toml};
+use crate::fs::{Workdir, dir_size, has_rust_toolchain, has_top_level_cargo_toml};
+use crate::git::unreachable::HostBreaker;
+use crate::metrics;
+use crate::sync::Tally;
+use crate::timings::{Phase, Timings};
 use anyhow::{Context, bail};
+use base64::Engine;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::process::Command;
+use tracing::Instrument;
 use url::Url;
 
+mod unreachable;
+
+pub use unreachable::UnreachableHostPolicy;
+
 pub(crate) struct CrateReadyForAnalysis {
     pub(crate) repo_root: PathBuf,
+    /// Where `rustfmt` is run, the crate's subdirectory in the repo if it has one with a
+    /// `Cargo.toml`, otherwise `repo_root`
+    pub(crate) crate_root: PathBuf,
     pub(crate) head_branch: Option<String>,
+    /// Checked out commit, only looked up for local crates
+    pub(crate) head_commit: Option<String>,
     pub(crate) pruned_crate: PrunedCrate,
+    /// Without a top-level `Cargo.toml`, `rustfmt` is run directly on the repo's `.rs` files
+    pub(crate) has_manifest: bool,
+    pub(crate) origin: CrateOrigin,
+    /// Size on disk of the checkout, only set if it was over `SyncOpts::warn_repo_size`
+    pub(crate) oversized_repo_bytes: Option<u64>,
+}
+
+/// How cloned repos are prepared for analysis
+#[derive(Debug, Clone)]
+pub(crate) struct SyncOpts {
+    /// Fetch and reset repos that were already cloned
+    pub(crate) should_sync: bool,
+    /// Keep repos without a top-level `Cargo.toml`, `rustfmt` is run directly on them
+    pub(crate) direct_fallback: bool,
+    /// Warn about checkouts taking up more than this many bytes on disk
+    pub(crate) warn_repo_size: Option<u64>,
+    /// Credentials for https repos on private hosts
+    pub(crate) git_token: Option<GitToken>,
+    /// Skip crates on hosts that clones keep failing from
+    pub(crate) unreachable_host: Option<UnreachableHostPolicy>,
+}
+
+impl SyncOpts {
+    fn auth_header_for(&self, url: &Url) -> Option<String> {
+        self.git_token
+            .as_ref()
+            .and_then(|token| token.auth_header_for(url))
+    }
+}
+
+/// A token sent along with https `git` requests to `hosts`, for cloning private repos
+#[derive(Clone)]
+pub struct GitToken {
+    pub token: String,
+    /// Host names the token is sent to, requests to other hosts don't get it
+    pub hosts: Vec<String>,
+}
+
+impl std::fmt::Debug for GitToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitToken")
+            .field("token", &"<redacted>")
+            .field("hosts", &self.hosts)
+            .finish()
+    }
 }
 
+impl GitToken {
+    /// `http.extraHeader` value to authenticate to `url`, if it's an https url on one of `hosts`
+    fn auth_header_for(&self, url: &Url) -> Option<String> {
+        if url.scheme() != "https" {
+            return None;
+        }
+        let host = url.host_str()?;
+        if !self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+            return None;
+        }
+        // Basic auth with the token as password works for both Github and Gitlab
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("oauth2:{}", self.token));
+        Some(format!("Authorization: Basic {credentials}"))
+    }
+}
+
+/// Passes `auth_header` through the env rather than args or the remote url, so that it
+/// doesn't end up in the repo's config, errors need to redact it with `output_string_redacted`
+fn authenticate<'a>(cmd: &'a mut Command, auth_header: Option<&str>) -> &'a mut Command {
+    if let Some(header) = auth_header {
+        cmd.env("GIT_CONFIG_COUNT", "1")
+            .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+            .env("GIT_CONFIG_VALUE_0", header);
+    }
+    cmd
+}
+
+/// Where a crate was found, shown in the report
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrateOrigin {
+    /// Cloned with `git`
+    Git,
+    /// Found in a local directory
+    Local,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn run_sync_task(
     workdir: Workdir,
-    should_sync: bool,
+    opts: SyncOpts,
     crates: Vec<PrunedCrate>,
     max_concurrent: NonZeroUsize,
+    prefetch_depth: NonZeroUsize,
+    tally: Arc<Tally>,
+    timings: Arc<Timings>,
     mut stop_receiver: StopReceiver,
 ) -> tokio::sync::mpsc::Receiver<CrateReadyForAnalysis> {
-    let (send, recv) = tokio::sync::mpsc::channel(max_concurrent.get());
+    // Cloned crates waiting for analysis, the clones in flight come on top of these
+    let (send, recv) = tokio::sync::mpsc::channel(prefetch_depth.get());
+    tally.selected(crates.len());
     tokio::task::spawn(async move {
         match stop_receiver
-            .with_stop(sync_task(workdir, should_sync, crates, send))
+            .with_stop(sync_task(
+                workdir,
+                opts,
+                crates,
+                max_concurrent,
+                &tally,
+                &timings,
+                send,
+            ))
             .await
         {
             None => {
@@ -44,88 +159,261 @@ pub(crate) fn run_sync_task(
 
 async fn sync_task(
     workdir: Workdir,
-    should_sync: bool,
+    opts: SyncOpts,
     crates: Vec<PrunedCrate>,
+    max_concurrent: NonZeroUsize,
+    tally: &Tally,
+    timings: &Timings,
     sender: tokio::sync::mpsc::Sender<CrateReadyForAnalysis>,
 ) -> anyhow::Result<()> {
-    for cr in crates {
-        let Some(repo) = cr.repository.as_ref() else {
-            continue;
-        };
-        let dir = workdir.base.join(cr.repo_dir_name.as_path());
-        tracing::trace!(
-            "ensuring crate '{}' exists at {} with source {}",
-            cr.crate_name,
-            dir.display(),
-            repo,
-        );
-        match ensure_at(&dir, repo.as_url()).await {
-            Ok(()) => {}
-            Err(e) => {
-                tracing::error!(
-                    "failed to ensure crate '{}' at {} with source {}: {}",
-                    cr.crate_name,
-                    dir.display(),
-                    repo,
-                    unpack(&*e)
+    let breaker = opts.unreachable_host.map(HostBreaker::new);
+    let mut crates = crates.into_iter();
+    let mut in_flight = FuturesUnordered::new();
+    loop {
+        while in_flight.len() < max_concurrent.get()
+            && let Some(cr) = crates.next()
+        {
+            // Queued crates of a host that's been found unreachable don't take a clone slot
+            if let Some(breaker) = &breaker
+                && let Some(host) = repo_host(&cr)
+                && breaker.is_unreachable(&host)
+            {
+                tracing::warn!(
+                    "skipping '{}', clones from {host} keep failing",
+                    cr.crate_name
                 );
+                tally.unreachable(cr.crate_name);
                 continue;
             }
+            in_flight.push(prepare_reachable_crate(
+                &workdir,
+                &opts,
+                breaker.as_ref(),
+                timings,
+                cr,
+            ));
         }
-        let (head_branch, top_level_cargo_toml, rust_toolchain_toml) = tokio::join!(
-            find_remote_head_branch(&dir, "origin"),
-            has_top_level_cargo_toml(&dir),
-            has_rust_toolchain(&dir)
-        );
-        let head_branch = match head_branch {
-            Ok(h) => h,
-            Err(e) => {
-                tracing::error!(
-                    "failed to find remote head branch for crate '{}' at {} with source {}: {}",
-                    cr.crate_name,
-                    dir.display(),
-                    repo,
-                    unpack(&*e)
-                );
+        let Some(prepared) = in_flight.next().await else {
+            break;
+        };
+        let ready = match prepared? {
+            Prepared::Ready(ready) => ready,
+            Prepared::Skipped => {
+                tally.skipped();
+                continue;
+            }
+            Prepared::CloneFailed => {
+                tally.clone_failed();
+                continue;
+            }
+            Prepared::Unreachable(crate_name) => {
+                tally.unreachable(crate_name);
                 continue;
             }
         };
-        if !top_level_cargo_toml? {
-            tracing::warn!("skipping {}, no Cargo.toml at top-level", cr.crate_name);
-            continue;
+        if sender.send(*ready).await.is_err() {
+            bail!("failed to send git synced crate")
         }
-        if rust_toolchain_toml? {
-            tracing::warn!(
-                "skipping {}, has rust-toolchain specified (causes issues)",
-                cr.crate_name
+    }
+    Ok(())
+}
+
+enum Prepared {
+    Ready(Box<CrateReadyForAnalysis>),
+    Skipped,
+    CloneFailed,
+    /// Not cloned, or failed to clone after other clones made the host unreachable
+    Unreachable(CrateName),
+}
+
+fn repo_host(cr: &PrunedCrate) -> Option<String> {
+    cr.repository
+        .as_ref()
+        .and_then(|repo| repo.as_url().host_str())
+        .map(str::to_string)
+}
+
+/// `prepare_crate`, recording how the clone went with the crate's host
+async fn prepare_reachable_crate(
+    workdir: &Workdir,
+    opts: &SyncOpts,
+    breaker: Option<&HostBreaker>,
+    timings: &Timings,
+    cr: PrunedCrate,
+) -> anyhow::Result<Prepared> {
+    let Some((breaker, host)) = breaker.zip(repo_host(&cr)) else {
+        return prepare_crate(workdir, opts, timings, cr).await;
+    };
+    let crate_name = cr.crate_name.clone();
+    let prepared = prepare_crate(workdir, opts, timings, cr).await?;
+    match prepared {
+        // Other clones from the host made it unreachable while this one was running
+        Prepared::CloneFailed if breaker.is_unreachable(&host) => {
+            tracing::warn!("skipping '{crate_name}', clones from {host} keep failing");
+            return Ok(Prepared::Unreachable(crate_name));
+        }
+        Prepared::CloneFailed => breaker.record_failure(&host),
+        Prepared::Ready(_) | Prepared::Skipped => breaker.record_success(&host),
+        Prepared::Unreachable(_) => {}
+    }
+    Ok(prepared)
+}
+
+/// Clones (or syncs) the crate's repo, if it can be analyzed
+#[allow(clippy::too_many_lines)]
+async fn prepare_crate(
+    workdir: &Workdir,
+    opts: &SyncOpts,
+    timings: &Timings,
+    cr: PrunedCrate,
+) -> anyhow::Result<Prepared> {
+    let Some(repo) = cr.repository.as_ref() else {
+        return Ok(Prepared::Skipped);
+    };
+    let dir = workdir.base.join(cr.repo_dir_name.as_path());
+    tracing::trace!(
+        "ensuring crate '{}' exists at {} with source {}",
+        cr.crate_name,
+        dir.display(),
+        repo,
+    );
+    let auth_header = opts.auth_header_for(repo.as_url());
+    let auth_header = auth_header.as_deref();
+    metrics::clone_started();
+    let ensured = timings
+        .timed(
+            Phase::Clone,
+            ensure_at(&dir, repo.as_url(), auth_header).instrument(tracing::info_span!(
+                "clone",
+                crate_name = %cr.crate_name,
+                repository = %repo,
+            )),
+        )
+        .await;
+    metrics::clone_finished();
+    match ensured {
+        Ok(()) => {}
+        Err(e) => {
+            tracing::error!(
+                "failed to ensure crate '{}' at {} with source {}: {}",
+                cr.crate_name,
+                dir.display(),
+                repo,
+                unpack(&*e)
             );
-            continue;
+            return Ok(Prepared::CloneFailed);
         }
-        if should_sync && let Err(e) = sync_existing(&dir, &head_branch).await {
+    }
+    let (head_branch, crate_root, rust_toolchain_toml) = tokio::join!(
+        find_remote_head_branch(&dir, "origin", auth_header),
+        find_crate_root(&dir, &cr),
+        has_rust_toolchain(&dir)
+    );
+    let head_branch = match head_branch {
+        Ok(h) => h,
+        Err(e) => {
             tracing::error!(
-                "failed to sync crate '{}' at {} with source {}: {}",
+                "failed to find remote head branch for crate '{}' at {} with source {}: {}",
                 cr.crate_name,
                 dir.display(),
                 repo,
                 unpack(&*e)
             );
+            return Ok(Prepared::CloneFailed);
         }
-        if sender
-            .send(CrateReadyForAnalysis {
-                repo_root: dir,
-                head_branch: Some(head_branch),
-                pruned_crate: cr,
-            })
-            .await
-            .is_err()
-        {
-            bail!("failed to send git synced crate")
+    };
+    let (crate_root, has_manifest) = crate_root?;
+    if !has_manifest {
+        if !opts.direct_fallback {
+            tracing::warn!("skipping {}, no Cargo.toml at top-level", cr.crate_name);
+            return Ok(Prepared::Skipped);
         }
+        tracing::debug!(
+            "no Cargo.toml at top-level for {}, running rustfmt directly",
+            cr.crate_name
+        );
     }
-    Ok(())
+    if rust_toolchain_toml? {
+        tracing::warn!(
+            "skipping {}, has rust-toolchain specified (causes issues)",
+            cr.crate_name
+        );
+        return Ok(Prepared::Skipped);
+    }
+    if opts.should_sync
+        && let Err(e) = sync_existing(&dir, &head_branch, auth_header).await
+    {
+        tracing::error!(
+            "failed to sync crate '{}' at {} with source {}: {}",
+            cr.crate_name,
+            dir.display(),
+            repo,
+            unpack(&*e)
+        );
+    }
+    let oversized_repo_bytes = if let Some(max) = opts.warn_repo_size {
+        oversized_repo(&cr, &dir, max).await
+    } else {
+        None
+    };
+    Ok(Prepared::Ready(Box::new(CrateReadyForAnalysis {
+        repo_root: dir,
+        crate_root,
+        head_branch: Some(head_branch),
+        head_commit: None,
+        pruned_crate: cr,
+        has_manifest,
+        origin: CrateOrigin::Git,
+        oversized_repo_bytes,
+    })))
+}
+
+/// The crate's subdirectory if it was given one that has a `Cargo.toml`, otherwise the repo root,
+/// with whether there's a `Cargo.toml` where the crate is
+async fn find_crate_root(dir: &Path, cr: &PrunedCrate) -> anyhow::Result<(PathBuf, bool)> {
+    if let Some(subdir) = &cr.subdir {
+        let root = dir.join(subdir);
+        if has_top_level_cargo_toml(&root).await? {
+            return Ok((root, true));
+        }
+        tracing::warn!(
+            "no Cargo.toml in {} for '{}', analyzing from the repo root instead",
+            subdir.display(),
+            cr.crate_name
+        );
+    }
+    Ok((dir.to_path_buf(), has_top_level_cargo_toml(dir).await?))
 }
 
-pub(crate) async fn ensure_at(path: &Path, repo_url: &Url) -> anyhow::Result<()> {
+/// Huge checkouts tend to be the ones that make runs slow, the size is kept to correlate them
+async fn oversized_repo(cr: &PrunedCrate, dir: &Path, max: u64) -> Option<u64> {
+    match dir_size(dir).await {
+        Ok(size) if size > max => {
+            tracing::warn!(
+                "crate '{}' at {} takes up {} MiB on disk",
+                cr.crate_name,
+                dir.display(),
+                size / (1024 * 1024)
+            );
+            Some(size)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            tracing::debug!(
+                "failed to measure the size of {}: {}",
+                dir.display(),
+                unpack(&*e)
+            );
+            None
+        }
+    }
+}
+
+pub(crate) async fn ensure_at(
+    path: &Path,
+    repo_url: &Url,
+    auth_header: Option<&str>,
+) -> anyhow::Result<()> {
     if tokio::fs::try_exists(path)
         .await
         .with_context(|| format!("failed to check if '{}' exists", path.display()))?
@@ -140,14 +428,9 @@ pub(crate) async fn ensure_at(path: &Path, repo_url: &Url) -> anyhow::Result<()>
             path.display(),
             repo_url
         );
-        output_string(
-            Command::new("git")
-                .arg("clone")
-                .arg("--depth")
-                .arg("1")
-                .arg(repo_url.as_str())
-                .arg(path)
-                .env("GIT_TERMINAL_PROMPT", "0"),
+        output_string_redacted(
+            authenticate(&mut clone_command(repo_url, path), auth_header),
+            auth_header,
         )
         .await
         .with_context(|| {
@@ -156,11 +439,40 @@ pub(crate) async fn ensure_at(path: &Path, repo_url: &Url) -> anyhow::Result<()>
                 path.display()
             )
         })?;
+        if uses_lfs(path).await {
+            tracing::warn!(
+                "{repo_url} uses git-lfs, LFS tracked files were left as pointers in {}",
+                path.display()
+            );
+        }
     }
     Ok(())
 }
 
-async fn sync_existing(repo_root: &Path, head_branch: &str) -> anyhow::Result<()> {
+fn clone_command(repo_url: &Url, path: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("clone")
+        .arg("--depth")
+        .arg("1")
+        .arg(repo_url.as_str())
+        .arg(path)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        // LFS files are assets, not code, keep the pointers instead of downloading them
+        .env("GIT_LFS_SKIP_SMUDGE", "1");
+    cmd
+}
+
+async fn uses_lfs(repo_root: &Path) -> bool {
+    tokio::fs::read_to_string(repo_root.join(".gitattributes"))
+        .await
+        .is_ok_and(|attributes| attributes.contains("filter=lfs"))
+}
+
+async fn sync_existing(
+    repo_root: &Path,
+    head_branch: &str,
+    auth_header: Option<&str>,
+) -> anyhow::Result<()> {
     let git_dir = repo_root.join(".git");
     if !tokio::fs::try_exists(&git_dir).await.with_context(|| {
         format!(
@@ -177,12 +489,16 @@ async fn sync_existing(repo_root: &Path, head_branch: &str) -> anyhow::Result<()
         "found existing git repo at {}, syncing",
         repo_root.display()
     );
-    output_string(
-        Command::new("git")
-            .arg("fetch")
-            .arg("origin")
-            .env("GIT_TERMINAL_PROMPT", "0")
-            .current_dir(repo_root),
+    output_string_redacted(
+        authenticate(
+            Command::new("git")
+                .arg("fetch")
+                .arg("origin")
+                .env("GIT_TERMINAL_PROMPT", "0")
+                .current_dir(repo_root),
+            auth_header,
+        ),
+        auth_header,
     )
     .await
     .with_context(|| {
@@ -197,6 +513,7 @@ async fn sync_existing(repo_root: &Path, head_branch: &str) -> anyhow::Result<()
             .arg("--hard")
             .arg(format!("origin/{head_branch}"))
             .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
             .current_dir(repo_root),
     )
     .await?;
@@ -204,21 +521,33 @@ async fn sync_existing(repo_root: &Path, head_branch: &str) -> anyhow::Result<()
     Ok(())
 }
 
-async fn git_remote_show(cwd: &Path, remote: &str) -> anyhow::Result<String> {
-    output_string(
-        Command::new("git")
-            .arg("remote")
-            .arg("show")
-            .arg(remote)
-            .env("GIT_TERMINAL_PROMPT", "0")
-            .current_dir(cwd),
+async fn git_remote_show(
+    cwd: &Path,
+    remote: &str,
+    auth_header: Option<&str>,
+) -> anyhow::Result<String> {
+    output_string_redacted(
+        authenticate(
+            Command::new("git")
+                .arg("remote")
+                .arg("show")
+                .arg(remote)
+                .env("GIT_TERMINAL_PROMPT", "0")
+                .current_dir(cwd),
+            auth_header,
+        ),
+        auth_header,
     )
     .await
     .with_context(|| format!("failed to run git remote show at '{}'", cwd.display()))
 }
 
-async fn find_remote_head_branch(cwd: &Path, remote: &str) -> anyhow::Result<String> {
-    let output = git_remote_show(cwd, remote).await?;
+async fn find_remote_head_branch(
+    cwd: &Path,
+    remote: &str,
+    auth_header: Option<&str>,
+) -> anyhow::Result<String> {
+    let output = git_remote_show(cwd, remote, auth_header).await?;
     parse_head_branch(&output)
 }
 
@@ -263,29 +592,64 @@ fn parse_remote_output(output: &str) -> anyhow::Result<RemoteOutput> {
     })
 }
 
-pub(crate) async fn scan_git_repo(repo_root: &Path) -> anyhow::Result<(GitRepo, String)> {
-    let output = output_string(
+/// What could be found out about a local checkout, a detached HEAD or a repo without
+/// remotes still has a commit
+pub(crate) struct GitScan {
+    pub(crate) repository: Option<GitRepo>,
+    pub(crate) head_branch: Option<String>,
+    pub(crate) head_commit: String,
+}
+
+/// Fails only if `repo_root` isn't a git repo with at least one commit
+pub(crate) async fn scan_git_repo(repo_root: &Path) -> anyhow::Result<GitScan> {
+    let head_commit = output_string(
         Command::new("git")
-            .arg("remote")
-            .arg("show")
-            .env("GIT_TERMINAL_PROMPT", "0")
+            .arg("rev-parse")
+            .arg("HEAD")
             .current_dir(repo_root),
     )
     .await
     .with_context(|| {
         format!(
-            "failed to run 'git remote show' at '{}'",
-            repo_root.display()
-        )
-    })?;
-    // 128 is 'no git repo' could check for that instead of always returning an error (turn into optional instead)
-    let remote = guess_remote_from_show_output(&output).with_context(|| {
-        format!(
-            "failed to guess remote from 'git remote show' output at '{}'",
+            "failed to run 'git rev-parse HEAD' at '{}'",
             repo_root.display()
         )
-    })?;
-    let remote_output = git_remote_show(repo_root, &remote).await?;
+    })?
+    .trim()
+    .to_string();
+    match scan_git_remote(repo_root).await {
+        Ok((repository, head_branch)) => Ok(GitScan {
+            repository: Some(repository),
+            head_branch: Some(head_branch),
+            head_commit,
+        }),
+        Err(e) => {
+            tracing::debug!(
+                "no usable remote at '{}', keeping only the commit: {}",
+                repo_root.display(),
+                unpack(&*e)
+            );
+            Ok(GitScan {
+                repository: None,
+                head_branch: None,
+                head_commit,
+            })
+        }
+    }
+}
+
+async fn scan_git_remote(repo_root: &Path) -> anyhow::Result<(GitRepo, String)> {
+    let output = output_string(
+        Command::new("git")
+            .arg("remote")
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .current_dir(repo_root),
+    )
+    .await
+    .with_context(|| format!("failed to run 'git remote' at '{}'", repo_root.display()))?;
+    let remote = pick_remote(&output)
+        .with_context(|| format!("failed to pick a remote at '{}'", repo_root.display()))?;
+    let remote_output = git_remote_show(repo_root, &remote, None).await?;
     let remote_output = parse_remote_output(&remote_output).with_context(|| {
         format!(
             "failed to parse remote output from 'git remote show' output at '{}'",
@@ -295,16 +659,222 @@ pub(crate) async fn scan_git_repo(repo_root: &Path) -> anyhow::Result<(GitRepo,
     Ok((GitRepo(remote_output.fetch_url), remote_output.head_branch))
 }
 
-fn guess_remote_from_show_output(output: &str) -> Option<String> {
-    let mut last_seen_remote = None;
-    // Hoping to find `origin`, apart from that, hoping only a single remote exists
-    // if neither of those is true, this will be weird, since it's just grabbing the
-    // last seen remote. (sorted alphabetically by `git` I think).
-    for line in output.lines() {
-        if line.trim() == "origin" {
-            return Some("origin".to_string());
+/// Picks `origin`, or the only remote, from `git remote` output (one remote name per line)
+fn pick_remote(output: &str) -> anyhow::Result<String> {
+    let remotes = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    if remotes.contains(&"origin") {
+        return Ok("origin".to_string());
+    }
+    match remotes.as_slice() {
+        [] => bail!("no remotes configured"),
+        [only] => Ok((*only).to_string()),
+        many => bail!(
+            "multiple remotes and none named 'origin', can't tell which to use: {}",
+            many.join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crates::crate_consumer::default::pruned_crate_from_url;
+    use crate::stop_channel;
+    use std::time::Duration;
+
+    fn git(cwd: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=fixture",
+                "-c",
+                "user.email=fixture@example.com",
+            ])
+            .args(args)
+            .current_dir(cwd)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed in {}", cwd.display());
+    }
+
+    /// A repo at `path` with a single commit, returns the commit
+    fn committed_repo(path: &Path) -> String {
+        std::fs::create_dir_all(path).unwrap();
+        std::fs::write(
+            path.join("Cargo.toml"),
+            "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        git(path, &["init", "--quiet", "--initial-branch=main"]);
+        git(path, &["add", "--all"]);
+        git(path, &["commit", "--quiet", "--message", "fixture"]);
+        let out = std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(path)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    }
+
+    async fn wait_for_buffered(
+        recv: &tokio::sync::mpsc::Receiver<CrateReadyForAnalysis>,
+        n: usize,
+    ) {
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while recv.len() < n {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn prefetch_buffer_is_kept_filled_to_its_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        committed_repo(&origin);
+        let workdir = Workdir::new(dir.path().join("workdir"));
+        let crates = (0..6)
+            .map(|i| {
+                let url = format!("https://github.com/fixture/crate{i}")
+                    .parse()
+                    .unwrap();
+                let cr = pruned_crate_from_url(&url).unwrap();
+                // Already cloned, so that nothing is fetched over the network
+                let clone = workdir.base.join(cr.repo_dir_name.as_path());
+                git(
+                    dir.path(),
+                    &[
+                        "clone",
+                        "--quiet",
+                        origin.to_str().unwrap(),
+                        clone.to_str().unwrap(),
+                    ],
+                );
+                cr
+            })
+            .collect::<Vec<_>>();
+        let opts = SyncOpts {
+            should_sync: false,
+            direct_fallback: false,
+            warn_repo_size: None,
+            git_token: None,
+            unreachable_host: None,
+        };
+        let (_stop_send, stop_receiver) = stop_channel();
+        let mut recv = run_sync_task(
+            workdir,
+            opts,
+            crates,
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+            Arc::new(Tally::default()),
+            Arc::new(Timings::default()),
+            stop_receiver,
+        );
+        let mut analyzed = Vec::new();
+        for _ in 0..4 {
+            wait_for_buffered(&recv, 2).await;
+            analyzed.push(
+                recv.recv()
+                    .await
+                    .unwrap()
+                    .pruned_crate
+                    .crate_name
+                    .to_string(),
+            );
+        }
+        while let Some(ready) = recv.recv().await {
+            analyzed.push(ready.pruned_crate.crate_name.to_string());
         }
-        last_seen_remote = Some(line.trim().to_string());
+        assert_eq!(6, analyzed.len());
+    }
+
+    #[test]
+    fn clone_skips_the_lfs_smudge() {
+        let url = "https://github.com/fixture/assets".parse().unwrap();
+        let cmd = clone_command(&url, Path::new("assets"));
+        let smudge = cmd
+            .as_std()
+            .get_envs()
+            .find(|(key, _)| *key == "GIT_LFS_SKIP_SMUDGE")
+            .and_then(|(_, value)| value);
+        assert_eq!(Some(std::ffi::OsStr::new("1")), smudge);
+    }
+
+    #[tokio::test]
+    async fn repo_without_remotes_keeps_its_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let commit = committed_repo(dir.path());
+        let scan = scan_git_repo(dir.path()).await.unwrap();
+        assert_eq!(commit, scan.head_commit);
+        assert!(scan.repository.is_none());
+        assert!(scan.head_branch.is_none());
+    }
+
+    #[tokio::test]
+    async fn detached_head_keeps_its_commit_and_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        let commit = committed_repo(&origin);
+        let origin_url = Url::from_directory_path(&origin).unwrap();
+        let clone = dir.path().join("clone");
+        git(
+            dir.path(),
+            &[
+                "clone",
+                "--quiet",
+                origin_url.as_str(),
+                clone.to_str().unwrap(),
+            ],
+        );
+        git(&clone, &["checkout", "--quiet", "--detach", "HEAD"]);
+        let scan = scan_git_repo(&clone).await.unwrap();
+        assert_eq!(commit, scan.head_commit);
+        assert_eq!(Some("main"), scan.head_branch.as_deref());
+        assert!(scan.repository.is_some());
+    }
+
+    #[tokio::test]
+    async fn token_is_sent_to_its_hosts_and_redacted_from_errors() {
+        let token = GitToken {
+            token: "fixture-secret".to_string(),
+            hosts: vec!["127.0.0.1".to_string()],
+        };
+        // Nothing listens on port 1, so the clone fails after the header is set
+        let allowed = "https://127.0.0.1:1/fixture/private".parse().unwrap();
+        let header = token.auth_header_for(&allowed).unwrap();
+        let other_host = "https://github.com/fixture/private".parse().unwrap();
+        assert!(token.auth_header_for(&other_host).is_none());
+        let not_https = "http://127.0.0.1:1/fixture/private".parse().unwrap();
+        assert!(token.auth_header_for(&not_https).is_none());
+        let mut cmd = clone_command(&allowed, Path::new("private"));
+        let sent = authenticate(&mut cmd, Some(&header))
+            .as_std()
+            .get_envs()
+            .find(|(key, _)| *key == "GIT_CONFIG_VALUE_0")
+            .and_then(|(_, value)| value);
+        assert_eq!(Some(std::ffi::OsStr::new(&header)), sent);
+        let dir = tempfile::tempdir().unwrap();
+        let err = ensure_at(&dir.path().join("private"), &allowed, Some(&header))
+            .await
+            .unwrap_err();
+        let err = format!("{err:?}");
+        assert!(!err.contains(&header), "{err}");
+        assert!(err.contains("<redacted>"), "{err}");
+    }
+
+    #[test]
+    fn origin_or_the_only_remote_is_picked() {
+        assert!(pick_remote("").is_err());
+        assert_eq!("upstream", pick_remote("upstream\n").unwrap());
+        assert_eq!("origin", pick_remote("fork\norigin\nupstream\n").unwrap());
+        let err = pick_remote("fork\nupstream\n").unwrap_err().to_string();
+        assert!(err.contains("fork, upstream"), "{err}");
     }
-    last_seen_remote
 }
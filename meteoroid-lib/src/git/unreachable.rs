@@ -0,0 +1,78 @@
+//! Holds back clones to hosts that keep failing, so that a host being down doesn't eat the
+//! run with clones that won't work
+use rustc_hash::FxHashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// When to stop cloning from a host
+#[derive(Debug, Copy, Clone)]
+pub struct UnreachableHostPolicy {
+    /// Clones from the same host failing in a row before it's considered unreachable
+    pub failure_threshold: NonZeroUsize,
+    /// How long clones from an unreachable host are skipped, after that the next clone
+    /// is let through to check if it's back
+    pub cooldown: Duration,
+}
+
+#[derive(Default)]
+struct HostState {
+    consecutive_failures: usize,
+    unreachable_until: Option<Instant>,
+}
+
+pub(crate) struct HostBreaker {
+    policy: UnreachableHostPolicy,
+    hosts: Mutex<FxHashMap<String, HostState>>,
+}
+
+impl HostBreaker {
+    pub(crate) fn new(policy: UnreachableHostPolicy) -> Self {
+        Self {
+            policy,
+            hosts: Mutex::new(FxHashMap::default()),
+        }
+    }
+
+    /// Whether clones from `host` should be skipped right now
+    pub(crate) fn is_unreachable(&self, host: &str) -> bool {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(state) = hosts.get_mut(host) else {
+            return false;
+        };
+        match state.unreachable_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // Cooled down, one more failure and it's unreachable again
+                tracing::info!("retrying clones from {host} after cooldown");
+                state.unreachable_until = None;
+                state.consecutive_failures = self.policy.failure_threshold.get() - 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn record_success(&self, host: &str) {
+        self.hosts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(host);
+    }
+
+    pub(crate) fn record_failure(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = hosts.entry(host.to_string()).or_default();
+        state.consecutive_failures += 1;
+        if state.unreachable_until.is_none()
+            && state.consecutive_failures >= self.policy.failure_threshold.get()
+        {
+            tracing::warn!(
+                "{} clones from {host} failed in a row, skipping its crates for {}s",
+                state.consecutive_failures,
+                self.policy.cooldown.as_secs()
+            );
+            state.unreachable_until = Some(Instant::now() + self.policy.cooldown);
+        }
+    }
+}
@@ -1,29 +1,67 @@
 use crate::fs::Workdir;
+use anyhow::Context;
 use dashmap::DashSet;
 use futures::StreamExt;
 use futures::stream::FuturesUnordered;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 mod analyze;
+mod bisect;
+mod clean;
 pub(crate) mod cmd;
 mod crates;
+mod doctor;
 pub(crate) mod error;
 mod fs;
 mod git;
 mod local_crates;
+mod metrics;
+mod notify;
 mod sync;
+mod timings;
+#[cfg(feature = "s3")]
+mod upload;
+mod warmup;
 
-pub use crate::analyze::AnalyzeArgs;
-use crate::analyze::report::{AnalysisReport, CrateAnalysis};
-use crate::cmd::{RustFmtBuildOutputs, build_rustfmt};
+pub use crate::analyze::agreement::{BuildAgreement, LabeledOutcome};
+pub use crate::analyze::report::{
+    AnalysisReport, CrateReport, DefaultConfigChange, DivergingDiff, EditionCounters,
+    ElapsedByOutcome, FmtOutcome, FmtOutput, OutcomeElapsed, OutputLayout, PhaseTimings,
+    PublicCrateAnalysis, PublicRustfmtAnalysis, RenderOpts, ReportFilter, ReportFormat,
+    ReportPercentages, ReportSort, render_report,
+};
+use crate::analyze::report::{CompletedCrates, CrateAnalysis, ReproOpts};
+pub use crate::analyze::similarity::{ErrorComparator, LevenshteinComparator};
+use crate::analyze::{AnalysisSettings, LabeledBuild};
+pub use crate::analyze::{AnalyzeArgs, LabeledRustfmtRepo, PrebuiltRustfmt, TimeoutScaling};
+pub use crate::bisect::{BisectConfig, BisectOutcome, bisect};
+pub use crate::clean::{CleanOpts, clean};
+pub use crate::cmd::CargoCommand;
+use crate::cmd::{
+    RustFmtBuildOutputs, build_rustfmt, install_rustfmt_toolchain, prebuilt_rustfmt,
+    previous_rustfmt_build,
+};
 use crate::crates::crate_consumer::default::PrunedCrate;
-use crate::git::CrateReadyForAnalysis;
-pub use crate::sync::{StopReceiver, stop_channel};
-pub use crates::crate_consumer::default::ConsumerOpts;
-pub use error::unpack;
+pub use crate::doctor::{DoctorCheck, doctor};
+use crate::fs::lock_exclusive;
+pub use crate::git::{CrateOrigin, GitToken, UnreachableHostPolicy};
+use crate::git::{CrateReadyForAnalysis, SyncOpts};
+pub use crate::notify::NotifyOpts;
+use crate::sync::{ReorderBuffer, Tally};
+pub use crate::sync::{StopReceiver, StopSender, stop_channel};
+use crate::timings::{Phase, Timings};
+#[cfg(feature = "s3")]
+pub use crate::upload::S3UploadOpts;
+pub use crate::warmup::warmup;
+pub use crates::crate_consumer::default::{
+    ConsumerOpts, CrateKind, CrateName, GitRepo, PublicPrunedCrate, RepoList, SampleOpts, SelectBy,
+    parse_rust_version, read_allowlist, read_denylist, read_repo_list,
+};
+pub use error::{MeteroidError, unpack};
 
 pub struct MeteroidConfig {
     pub workdir: PathBuf,
@@ -31,85 +69,597 @@ pub struct MeteroidConfig {
     pub consumer_opts: ConsumerOpts,
     pub crate_source: CrateSource,
     pub analyze_args: AnalyzeArgs,
+    /// Crates analyzed at once, independent of how many are cloned at once
     pub analysis_max_concurrent: NonZeroUsize,
+    /// Finished analyses that can wait to be added to the report before analysis is held back,
+    /// defaults to twice `analysis_max_concurrent`
+    pub result_buffer: Option<NonZeroUsize>,
     pub analysis_timeout: Duration,
+    /// Scale `analysis_timeout` up with the size of each crate
+    pub analysis_timeout_scaling: Option<TimeoutScaling>,
+    /// Hand analyzed crates on in the order they were queued for analysis,
+    /// rather than as they finish
+    pub ordered_output: bool,
+    /// Where to post a summary when the run finishes
+    pub notify: Option<NotifyOpts>,
+    /// Whether failing to build `rustfmt` aborts the run
+    pub setup_failure_policy: SetupFailurePolicy,
+    #[cfg(feature = "s3")]
+    pub s3_upload: Option<S3UploadOpts>,
+    /// Serve live metrics on `/metrics` at this address while running
+    #[cfg(feature = "metrics")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
     pub stop_receiver: StopReceiver,
 }
 
+/// What to do when a `rustfmt` build fails
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SetupFailurePolicy {
+    /// Abort the run
+    #[default]
+    FailFast,
+    /// Fall back to the previous build of local or upstream `rustfmt` if there is one,
+    /// and leave out extra builds that fail, noting it in the report
+    KeepGoing,
+}
+
 pub enum CrateSource {
     GitSync(GitSyncConfig),
+    /// Like `GitSync`, but uses a curated list of crates shipped with meteoroid
+    /// instead of selecting from the crates index
+    BuiltinCorpus(GitSyncConfig),
+    /// Like `GitSync`, but only analyzes the listed repos and crates, crate selection options
+    /// are ignored. The index is only fetched if crates are listed by name
+    GitUrls {
+        list: RepoList,
+        git: GitSyncConfig,
+    },
     LocalCrates(LocalCratesConfig),
+    /// Local crates and crates selected from the index, analyzed in the same run
+    Combined {
+        local: LocalCratesConfig,
+        git: GitSyncConfig,
+        /// Apply `max_crates` to both sources together, instead of to each of them
+        max_crates_global: bool,
+    },
 }
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct GitSyncConfig {
     pub crates_index_max_age_days: u8,
     pub git_resync_before: bool,
+    /// Clones (or syncs) running at once, independent of how many crates are analyzed at once
     pub git_clone_max_concurrent: NonZeroUsize,
+    /// How many cloned crates to keep ready ahead of the analysis,
+    /// defaults to `git_clone_max_concurrent`
+    pub prefetch_depth: Option<NonZeroUsize>,
+    /// Run `rustfmt` directly on `.rs` files in repos without a top-level `Cargo.toml`,
+    /// instead of skipping them
+    pub rustfmt_direct_fallback: bool,
+    /// Keep the previous `versions.csv` when the index is refetched, and only select crates
+    /// that are new or have changed versions compared to it
+    pub only_changed_crates: bool,
+    /// Re-parse the crates index even if a cached selection from a previous run
+    /// with the same options and index is available
+    pub refresh_selection: bool,
+    /// Warn about, and note in the report, repos taking up more than this many bytes on disk
+    pub warn_repo_size: Option<u64>,
+    /// Authenticate https clones of private repos
+    pub git_token: Option<GitToken>,
+    /// Only warn when no crates are selected from the index, instead of failing
+    pub allow_empty_selection: bool,
+    /// Skip the crates of hosts that clones keep failing from, for a while
+    pub unreachable_host: Option<UnreachableHostPolicy>,
 }
 
+impl GitSyncConfig {
+    fn sync_opts(&self) -> SyncOpts {
+        SyncOpts {
+            should_sync: self.git_resync_before,
+            direct_fallback: self.rustfmt_direct_fallback,
+            warn_repo_size: self.warn_repo_size,
+            git_token: self.git_token.clone(),
+            unreachable_host: self.unreachable_host,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct LocalCratesConfig {
     pub crate_dir: PathBuf,
+    /// Whether symlinked directories under `crate_dir` should be analyzed,
+    /// if not, they are skipped
+    pub follow_symlinks: bool,
+    /// Directory names under `crate_dir` that are never considered crates
+    pub skip_dirs: Vec<String>,
+}
+
+/// Build outputs, vcs metadata, and other things that won't be crates
+pub const DEFAULT_LOCAL_SKIP_DIRS: &[&str] = &["target", ".git", "node_modules"];
+
+#[inline]
+pub async fn meteoroid(config: MeteroidConfig) -> Result<(), MeteroidError> {
+    Box::pin(exec_parallel(config, None)).await
+}
+
+/// Like [`meteoroid`], but also hands each crate to `sink` as soon as its analysis is done,
+/// the report is still written at the end
+#[inline]
+pub async fn meteoroid_with_sink<F: FnMut(&PublicCrateAnalysis) + Send>(
+    config: MeteroidConfig,
+    mut sink: F,
+) -> Result<(), MeteroidError> {
+    Box::pin(exec_parallel(config, Some(&mut sink))).await
 }
 
+/// Select crates the same way as [`meteoroid`] would, without cloning, building, or analyzing
+/// anything, crates from the index are still fetched (if stale) and parsed
 #[inline]
-pub async fn meteoroid(config: MeteroidConfig) -> anyhow::Result<()> {
-    exec_parallel(config).await
+pub async fn select_crates(
+    config: &MeteroidConfig,
+) -> Result<Vec<PublicPrunedCrate>, MeteroidError> {
+    select_crates_in(&config.workdir, &config.crate_source, &config.consumer_opts).await
 }
 
-async fn exec_parallel(mut config: MeteroidConfig) -> anyhow::Result<()> {
-    let wd = Workdir::new(config.workdir);
+/// Like [`select_crates`], without needing a full config
+pub async fn select_crates_in(
+    workdir: &Path,
+    crate_source: &CrateSource,
+    consumer_opts: &ConsumerOpts,
+) -> Result<Vec<PublicPrunedCrate>, MeteroidError> {
+    consumer_opts
+        .validate()
+        .context("invalid crate selection options")
+        .map_err(MeteroidError::setup)?;
+    let wd = Workdir::new(workdir.to_path_buf());
+    // Nothing is reported, so the timings are left unused
+    let timings = Timings::default();
+    let selected = match crate_source {
+        CrateSource::GitSync(gs) => select_from_index(&wd, gs, consumer_opts.clone(), &timings)
+            .await
+            .map_err(MeteroidError::fetch)?,
+        CrateSource::BuiltinCorpus(_) => crates::builtin_corpus::builtin_corpus()
+            .context("failed to parse builtin corpus")
+            .map_err(MeteroidError::setup)?,
+        CrateSource::GitUrls { list, git: gs } => listed_crates(&wd, list, gs, &timings)
+            .await
+            .map_err(MeteroidError::fetch)?,
+        CrateSource::LocalCrates(lc) => {
+            local_crates::collect_local_crates(lc, consumer_opts.clone())
+                .await
+                .map_err(MeteroidError::fetch)?
+        }
+        CrateSource::Combined {
+            local,
+            git: gs,
+            max_crates_global,
+        } => {
+            let mut selected = select_from_index(&wd, gs, consumer_opts.clone(), &timings)
+                .await
+                .map_err(MeteroidError::fetch)?;
+            selected.extend(
+                local_crates::collect_local_crates(local, consumer_opts.clone())
+                    .await
+                    .map_err(MeteroidError::fetch)?,
+            );
+            if *max_crates_global {
+                selected.truncate(consumer_opts.max_crates);
+            }
+            selected
+        }
+    };
+    Ok(selected.into_iter().map(PublicPrunedCrate::from).collect())
+}
+
+/// Fetches the crates index if it's stale and parses it into the cached selection, which runs
+/// with the same selection options then reuse, without cloning or analyzing anything.
+/// Returns how many crates were selected
+pub async fn fetch_index(
+    workdir: &Path,
+    git_sync_config: &GitSyncConfig,
+    consumer_opts: &ConsumerOpts,
+) -> Result<usize, MeteroidError> {
+    consumer_opts
+        .validate()
+        .context("invalid crate selection options")
+        .map_err(MeteroidError::setup)?;
+    let wd = Workdir::new(workdir.to_path_buf());
+    let selected = select_from_index(
+        &wd,
+        git_sync_config,
+        consumer_opts.clone(),
+        &Timings::default(),
+    )
+    .await
+    .map_err(MeteroidError::fetch)?;
+    Ok(selected.len())
+}
+
+type AnalysisSink<'a> = &'a mut (dyn FnMut(&PublicCrateAnalysis) + Send);
+
+async fn exec_parallel(
+    mut config: MeteroidConfig,
+    sink: Option<AnalysisSink<'_>>,
+) -> Result<(), MeteroidError> {
+    config
+        .consumer_opts
+        .validate()
+        .context("invalid crate selection options")
+        .map_err(MeteroidError::setup)?;
+    let wd = Workdir::new(config.workdir.clone());
+    let timings = Arc::new(Timings::default());
+    #[cfg(feature = "metrics")]
+    let metrics_stop = if let Some(addr) = config.metrics_addr {
+        let (send, recv) = stop_channel();
+        metrics::serve(addr, recv)
+            .await
+            .map_err(MeteroidError::setup)?;
+        Some(send)
+    } else {
+        None
+    };
+    let Some(prepared) = config
+        .stop_receiver
+        .with_stop(prepare_run(
+            &wd,
+            &config.analyze_args,
+            config.setup_failure_policy,
+            &config.crate_source,
+            &config.consumer_opts,
+            &timings,
+        ))
+        .await
+        .transpose()?
+    else {
+        tracing::info!("stopped before starting analysis, exiting");
+        return Ok(());
+    };
+    let tally = Arc::new(Tally::default());
+    let reconcile = prepared.source.accounts_for_every_crate();
     let (sync_stop_send, sync_stop_recv) = stop_channel();
-    let (sync, local_build_outputs, upstream_build_outputs) = match config.crate_source {
+    let sync = start_sync(
+        wd,
+        prepared.source,
+        &config,
+        &tally,
+        &timings,
+        sync_stop_recv,
+    );
+    let settings = analysis_settings(&config, prepared.extra_builds);
+    if let Err(e) = preflight_config(
+        &settings,
+        &prepared.local_build_outputs,
+        &prepared.upstream_build_outputs,
+    )
+    .await
+    {
+        sync_stop_send.stop().await;
+        return Err(MeteroidError::setup(e));
+    }
+    let (mut report, completed) = start_report(
+        &config,
+        prepared.setup_failures,
+        &prepared.local_build_outputs,
+        &prepared.upstream_build_outputs,
+    )
+    .await?;
+    let (analysis_out_recv, analysis_stop_send) = spawn_analysis(
+        sync,
+        prepared.local_build_outputs,
+        prepared.upstream_build_outputs,
+        settings,
+        completed,
+        &tally,
+        &config,
+    );
+    let drained = config
+        .stop_receiver
+        .with_stop(timings.timed(
+            Phase::Analyze,
+            drain_analyses(analysis_out_recv, &mut report, sink, &config.analyze_args),
+        ))
+        .await
+        .is_some();
+    if drained {
+        tracing::debug!("analysis drain finished");
+        if reconcile {
+            tally.reconcile();
+        }
+        report.skipped_unreachable = tally.unreachable_crates();
+        report.remove_checkpoint().await;
+    } else {
+        tracing::info!("analysis task was stopped before finishing, gracefully exiting");
+        report.write_checkpoint().await;
+    }
+    publish_report(&config, report, &timings).await?;
+    sync_stop_send.stop().await;
+    analysis_stop_send.stop().await;
+    #[cfg(feature = "metrics")]
+    if let Some(stop) = metrics_stop {
+        stop.stop().await;
+    }
+    Ok(())
+}
+
+/// Everything built and selected before analysis can start
+struct PreparedRun {
+    local_build_outputs: RustFmtBuildOutputs,
+    upstream_build_outputs: RustFmtBuildOutputs,
+    extra_builds: Vec<LabeledBuild>,
+    setup_failures: Vec<String>,
+    source: SyncSource,
+}
+
+/// Where the crates of a run come from, once they're selected
+enum SyncSource {
+    Git(GitSync),
+    Local(LocalCratesConfig),
+    Combined {
+        git: GitSync,
+        local: LocalCratesConfig,
+        max_crates_global: bool,
+    },
+}
+
+impl SyncSource {
+    // Crates past the global cap are left in the sources, never accounted for
+    fn accounts_for_every_crate(&self) -> bool {
+        !matches!(
+            self,
+            Self::Combined {
+                max_crates_global: true,
+                ..
+            }
+        )
+    }
+}
+
+/// Selected crates, and how to clone them
+struct GitSync {
+    opts: SyncOpts,
+    max_concurrent: NonZeroUsize,
+    prefetch_depth: NonZeroUsize,
+    targets: Vec<PrunedCrate>,
+}
+
+impl GitSync {
+    fn new(config: &GitSyncConfig, targets: Vec<PrunedCrate>) -> Self {
+        Self {
+            opts: config.sync_opts(),
+            max_concurrent: config.git_clone_max_concurrent,
+            prefetch_depth: config
+                .prefetch_depth
+                .unwrap_or(config.git_clone_max_concurrent),
+            targets,
+        }
+    }
+
+    fn start(
+        self,
+        wd: Workdir,
+        tally: &Arc<Tally>,
+        timings: &Arc<Timings>,
+        stop_receiver: StopReceiver,
+    ) -> tokio::sync::mpsc::Receiver<CrateReadyForAnalysis> {
+        git::run_sync_task(
+            wd,
+            self.opts,
+            self.targets,
+            self.max_concurrent,
+            self.prefetch_depth,
+            Arc::clone(tally),
+            Arc::clone(timings),
+            stop_receiver,
+        )
+    }
+}
+
+/// The repos (or prebuilt binaries) of local and upstream `rustfmt`
+#[derive(Copy, Clone)]
+struct RustfmtBuilds<'a> {
+    rustfmt_repo: &'a Path,
+    rustfmt_upstream_repo: &'a Path,
+    prebuilt: Option<&'a PrebuiltRustfmt>,
+    policy: SetupFailurePolicy,
+    cargo: &'a CargoCommand,
+}
+
+async fn prepare_run(
+    wd: &Workdir,
+    args: &AnalyzeArgs,
+    policy: SetupFailurePolicy,
+    crate_source: &CrateSource,
+    consumer_opts: &ConsumerOpts,
+    timings: &Timings,
+) -> Result<PreparedRun, MeteroidError> {
+    let (extra_builds, mut setup_failures) =
+        build_extra_rustfmts(wd, &args.extra_rustfmt_repos, policy, &args.cargo)
+            .await
+            .map_err(MeteroidError::setup)?;
+    let builds = RustfmtBuilds {
+        rustfmt_repo: &args.rustfmt_repo,
+        rustfmt_upstream_repo: &args.rustfmt_upstream_repo,
+        prebuilt: args.prebuilt_rustfmt.as_ref(),
+        policy,
+        cargo: &args.cargo,
+    };
+    let ((local_build_outputs, upstream_build_outputs), source) = match crate_source {
         CrateSource::GitSync(gs) => {
-            let Some((local_build_outputs, upstream_build_outputs, targets)) = config
-                .stop_receiver
-                .with_stop(prepare_rustfmt_and_fetched_crates(
-                    &wd,
-                    config.analyze_args.rustfmt_repo,
-                    config.analyze_args.rustfmt_upstream_repo,
-                    gs.crates_index_max_age_days,
-                    config.consumer_opts,
-                ))
+            let (built, targets) =
+                build_while_selecting(wd, builds, gs, consumer_opts.clone(), timings).await?;
+            (built, SyncSource::Git(GitSync::new(gs, targets)))
+        }
+        CrateSource::BuiltinCorpus(gs) => {
+            let targets = crates::builtin_corpus::builtin_corpus()
+                .context("failed to parse builtin corpus")
+                .map_err(MeteroidError::setup)?;
+            wd.ensure_workdir().await.map_err(MeteroidError::setup)?;
+            let built = build_after_warmup(wd, builds, timings)
                 .await
-                .transpose()?
-            else {
-                tracing::info!("stopped before starting analysis, exiting");
-                return Ok(());
-            };
-            let sync = git::run_sync_task(
-                wd,
-                gs.git_resync_before,
-                targets,
-                gs.git_clone_max_concurrent,
-                sync_stop_recv,
-            );
-            (sync, local_build_outputs, upstream_build_outputs)
+                .map_err(MeteroidError::setup)?;
+            (built, SyncSource::Git(GitSync::new(gs, targets)))
+        }
+        CrateSource::GitUrls { list, git: gs } => {
+            wd.ensure_workdir().await.map_err(MeteroidError::setup)?;
+            let targets = listed_crates(wd, list, gs, timings)
+                .await
+                .map_err(MeteroidError::fetch)?;
+            let built = build_after_warmup(wd, builds, timings)
+                .await
+                .map_err(MeteroidError::setup)?;
+            (built, SyncSource::Git(GitSync::new(gs, targets)))
         }
         CrateSource::LocalCrates(lc) => {
-            let Some((local_build_outputs, upstream_build_outputs)) = config
-                .stop_receiver
-                .with_stop(prepare_rustfmt(
-                    config.analyze_args.rustfmt_repo,
-                    config.analyze_args.rustfmt_upstream_repo,
-                ))
+            let built = build_after_warmup(wd, builds, timings)
                 .await
-                .transpose()?
-            else {
-                tracing::info!("stopped before starting analysis, exiting");
-                return Ok(());
+                .map_err(MeteroidError::setup)?;
+            (built, SyncSource::Local(lc.clone()))
+        }
+        CrateSource::Combined {
+            local,
+            git: gs,
+            max_crates_global,
+        } => {
+            let (built, targets) =
+                build_while_selecting(wd, builds, gs, consumer_opts.clone(), timings).await?;
+            let source = SyncSource::Combined {
+                git: GitSync::new(gs, targets),
+                local: local.clone(),
+                max_crates_global: *max_crates_global,
             };
-            let sync = local_crates::local_crate_find_task(
-                lc.crate_dir,
+            (built, source)
+        }
+    };
+    for (side, build) in [
+        ("local", &local_build_outputs),
+        ("upstream", &upstream_build_outputs),
+    ] {
+        if let Some(build_error) = &build.build_error {
+            setup_failures.push(format!(
+                "{side} rustfmt failed to build, used the previous build at {}: {build_error}",
+                build.built_binary_path.display()
+            ));
+        }
+    }
+    Ok(PreparedRun {
+        local_build_outputs,
+        upstream_build_outputs,
+        extra_builds,
+        setup_failures,
+        source,
+    })
+}
+
+fn start_sync(
+    wd: Workdir,
+    source: SyncSource,
+    config: &MeteroidConfig,
+    tally: &Arc<Tally>,
+    timings: &Arc<Timings>,
+    stop_receiver: StopReceiver,
+) -> tokio::sync::mpsc::Receiver<CrateReadyForAnalysis> {
+    match source {
+        SyncSource::Git(git) => git.start(wd, tally, timings, stop_receiver),
+        SyncSource::Local(local) => local_crates::local_crate_find_task(
+            local,
+            config.analysis_max_concurrent,
+            config.consumer_opts.clone(),
+            Arc::clone(tally),
+            stop_receiver,
+        ),
+        SyncSource::Combined {
+            git,
+            local,
+            max_crates_global,
+        } => {
+            let max = max_crates_global.then_some(config.consumer_opts.max_crates);
+            let (git_stop_send, git_stop_recv) = stop_channel();
+            let (local_stop_send, local_stop_recv) = stop_channel();
+            let git_sync = git.start(wd, tally, timings, git_stop_recv);
+            let local_sync = local_crates::local_crate_find_task(
+                local,
                 config.analysis_max_concurrent,
-                config.consumer_opts,
-                sync_stop_recv,
+                config.consumer_opts.clone(),
+                Arc::clone(tally),
+                local_stop_recv,
             );
-            (sync, local_build_outputs, upstream_build_outputs)
+            sync::merge_receivers(
+                git_sync,
+                local_sync,
+                [git_stop_send, local_stop_send],
+                max,
+                config.analysis_max_concurrent,
+                stop_receiver,
+            )
         }
+    }
+}
+
+fn analysis_settings(config: &MeteroidConfig, extra_builds: Vec<LabeledBuild>) -> AnalysisSettings {
+    let args = &config.analyze_args;
+    AnalysisSettings {
+        config: args.config.clone(),
+        timeout: config.analysis_timeout,
+        timeout_scaling: config.analysis_timeout_scaling,
+        recheck_divergences: args.recheck_divergences,
+        bisect_config: args.bisect_config,
+        compare_formatted_output: args.compare_formatted_output,
+        max_file_bytes: args.max_file_bytes,
+        unstable_features: args.unstable_features,
+        respect_crate_config: args.respect_crate_config,
+        ignore_whitespace_diffs: args.ignore_whitespace_diffs,
+        analysis_retries: args.analysis_retries,
+        cargo: args.cargo.clone(),
+        extra_builds,
+    }
+}
+
+/// Creates the report, and picks up where a checkpointed run left off if asked to
+async fn start_report(
+    config: &MeteroidConfig,
+    setup_failures: Vec<String>,
+    local_build_outputs: &RustFmtBuildOutputs,
+    upstream_build_outputs: &RustFmtBuildOutputs,
+) -> Result<(AnalysisReport, CompletedCrates), MeteroidError> {
+    let args = &config.analyze_args;
+    let repro = args.write_repros.then(|| ReproOpts {
+        config: args.config.clone(),
+    });
+    let mut report = AnalysisReport::new(config.output_dir.clone(), repro, args.output_layout)
+        .await
+        .map_err(MeteroidError::analysis)?;
+    let completed = if args.checkpoint {
+        report
+            .resume_from_checkpoint()
+            .await
+            .map_err(MeteroidError::analysis)?
+    } else {
+        CompletedCrates::default()
     };
-    let (analysis_out_send, analysis_out_recv) = tokio::sync::mpsc::channel(32);
+    report.setup_failures = setup_failures;
+    report
+        .record_default_config_diff(local_build_outputs, upstream_build_outputs)
+        .await;
+    Ok((report, completed))
+}
 
+/// Analyzes crates as they're synced on a separate task, returns the finished analyses
+/// and what stops the task
+fn spawn_analysis(
+    sync: tokio::sync::mpsc::Receiver<CrateReadyForAnalysis>,
+    local_build_outputs: RustFmtBuildOutputs,
+    upstream_build_outputs: RustFmtBuildOutputs,
+    settings: AnalysisSettings,
+    completed: CompletedCrates,
+    tally: &Arc<Tally>,
+    config: &MeteroidConfig,
+) -> (tokio::sync::mpsc::Receiver<CrateAnalysis>, StopSender) {
+    let (analysis_out_send, analysis_out_recv) =
+        result_channel(config.result_buffer, config.analysis_max_concurrent);
     let (analysis_stop_send, mut analysis_stop_recv) = stop_channel();
+    let analysis_tally = Arc::clone(tally);
+    let max_concurrent = config.analysis_max_concurrent;
+    let ordered = config.ordered_output;
     tokio::task::spawn(async move {
         match analysis_stop_recv
             .with_stop(analysis_task(
@@ -117,9 +667,11 @@ async fn exec_parallel(mut config: MeteroidConfig) -> anyhow::Result<()> {
                 analysis_out_send,
                 local_build_outputs,
                 upstream_build_outputs,
-                config.analyze_args.config,
-                config.analysis_max_concurrent,
-                config.analysis_timeout,
+                settings,
+                completed,
+                &analysis_tally,
+                max_concurrent,
+                ordered,
             ))
             .await
         {
@@ -131,94 +683,390 @@ async fn exec_parallel(mut config: MeteroidConfig) -> anyhow::Result<()> {
             }
         }
     });
+    (analysis_out_recv, analysis_stop_send)
+}
 
-    let mut report = AnalysisReport::new(config.output_dir).await?;
-
-    match config
-        .stop_receiver
-        .with_stop(drain_analyses(
-            analysis_out_recv,
-            &mut report,
-            config.analyze_args.write_outputs,
-            config.analyze_args.skip_non_diverging_diffs,
-            config.analyze_args.diff_tool.as_deref(),
-        ))
+/// Writes the final report, then notifies and uploads it if configured to
+async fn publish_report(
+    config: &MeteroidConfig,
+    report: AnalysisReport,
+    timings: &Timings,
+) -> Result<(), MeteroidError> {
+    let args = &config.analyze_args;
+    let summary = report
+        .finish_report(
+            args.report_dest.clone(),
+            &args.report_formats,
+            &args.report_filters,
+            args.report_sort,
+            args.open_report,
+            timings,
+        )
         .await
-    {
-        None => {
-            tracing::info!("analysis task was stopped before finishing, gracefully exiting");
-        }
-        Some(()) => {
-            tracing::debug!("analysis drain finished");
-        }
+        .map_err(MeteroidError::analysis)?;
+    if let Some(notify_opts) = &config.notify {
+        notify::notify(notify_opts, &summary).await;
+    }
+    #[cfg(feature = "s3")]
+    if let Some(s3_opts) = &config.s3_upload {
+        let extra_files = args.report_dest.iter().cloned().collect::<Vec<_>>();
+        upload::upload_outputs(s3_opts, &summary.output_dir, &extra_files)
+            .instrument(tracing::info_span!("s3_upload"))
+            .await
+            .map_err(MeteroidError::analysis)?;
     }
-    report
-        .finish_report(config.analyze_args.report_dest)
-        .await?;
-    sync_stop_send.stop().await;
-    analysis_stop_send.stop().await;
     Ok(())
 }
 
 async fn drain_analyses(
     mut analysis_out_recv: tokio::sync::mpsc::Receiver<CrateAnalysis>,
     report: &mut AnalysisReport,
-    write_outputs: bool,
-    skip_non_diverging_diffs: bool,
-    diff_tool: Option<&Path>,
+    mut sink: Option<AnalysisSink<'_>>,
+    args: &AnalyzeArgs,
 ) {
     while let Some(next) = analysis_out_recv.recv().await {
+        if let Some(sink) = sink.as_mut() {
+            sink(&PublicCrateAnalysis::from(&next));
+        }
         report
-            .add_result(diff_tool, next, write_outputs, skip_non_diverging_diffs)
+            .add_result(
+                args.diff_tool.as_deref(),
+                &*args.error_comparator,
+                next,
+                args.write_outputs,
+                args.skip_non_diverging_diffs,
+            )
             .await;
     }
 }
 
-async fn prepare_rustfmt_and_fetched_crates(
+async fn listed_crates(
+    wd: &Workdir,
+    list: &RepoList,
+    git_sync_config: &GitSyncConfig,
+    timings: &Timings,
+) -> anyhow::Result<Vec<PrunedCrate>> {
+    let mut crates = list
+        .urls
+        .iter()
+        .map(crates::crate_consumer::default::pruned_crate_from_url)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if !list.crate_names.is_empty() {
+        let opts = ConsumerOpts {
+            max_crates: list.crate_names.len(),
+            min_size: 0,
+            only_crate_names: list.crate_names.clone(),
+            ..ConsumerOpts::default()
+        };
+        let found = fetch_and_process_crates(wd, git_sync_config, opts, timings).await?;
+        for name in &list.crate_names {
+            if !found.iter().any(|cr| cr.crate_name.to_string() == *name) {
+                tracing::warn!("crate '{name}' wasn't found in the index, or has no usable repo");
+            }
+        }
+        crates.extend(found);
+    }
+    let mut names = rustc_hash::FxHashSet::default();
+    if let Some(dup) = crates
+        .iter()
+        .find(|cr| !names.insert(cr.repo_dir_name.0.0.as_path()))
+    {
+        anyhow::bail!(
+            "more than one repo url would be cloned to '{}'",
+            dup.repo_dir_name.0.0.display()
+        );
+    }
+    Ok(crates)
+}
+
+async fn build_while_selecting(
     workdir: &Workdir,
-    rustfmt_repo: PathBuf,
-    rustfmt_upstream_repo: PathBuf,
-    crates_index_max_age_days: u8,
+    builds: RustfmtBuilds<'_>,
+    git_sync_config: &GitSyncConfig,
     consumer_opts: ConsumerOpts,
-) -> anyhow::Result<(RustFmtBuildOutputs, RustFmtBuildOutputs, Vec<PrunedCrate>)> {
-    let build_task = build_sequential(rustfmt_repo, rustfmt_upstream_repo);
-    let ((local_build_outputs, upstream_build_outputs), targets) = tokio::try_join!(
-        build_task,
-        fetch_and_process_crates(workdir, crates_index_max_age_days, consumer_opts)
-    )?;
-    Ok((local_build_outputs, upstream_build_outputs, targets))
-}
-
-async fn prepare_rustfmt(
-    rustfmt_repo: PathBuf,
-    rustfmt_upstream_repo: PathBuf,
-) -> anyhow::Result<(RustFmtBuildOutputs, RustFmtBuildOutputs)> {
-    let build_task = build_sequential(rustfmt_repo, rustfmt_upstream_repo).await?;
-    Ok((build_task.0, build_task.1))
+    timings: &Timings,
+) -> Result<((RustFmtBuildOutputs, RustFmtBuildOutputs), Vec<PrunedCrate>), MeteroidError> {
+    let build_task = async {
+        build_after_warmup(workdir, builds, timings)
+            .await
+            .map_err(MeteroidError::setup)
+    };
+    let fetch_task = async {
+        select_from_index(workdir, git_sync_config, consumer_opts, timings)
+            .await
+            .map_err(MeteroidError::fetch)
+    };
+    tokio::try_join!(build_task, fetch_task)
 }
 
-// If not built sequentially, there can be toolchain download raciness
-async fn build_sequential(
-    rustfmt_repo: PathBuf,
-    rustfmt_upstream_repo: PathBuf,
+// Toolchains are installed up front, otherwise concurrent builds race on installing them
+async fn build_after_warmup(
+    workdir: &Workdir,
+    builds: RustfmtBuilds<'_>,
+    timings: &Timings,
 ) -> anyhow::Result<(RustFmtBuildOutputs, RustFmtBuildOutputs)> {
-    let local_build_outputs = build_rustfmt(&rustfmt_repo).await?;
-    let upstream_build_outputs = build_rustfmt(&rustfmt_upstream_repo).await?;
-    Ok((local_build_outputs, upstream_build_outputs))
+    if let Some(prebuilt) = builds.prebuilt {
+        return tokio::try_join!(
+            prebuilt_rustfmt(&prebuilt.local),
+            prebuilt_rustfmt(&prebuilt.upstream)
+        );
+    }
+    let start = Instant::now();
+    warmup::warmup_toolchains(workdir, builds.rustfmt_repo, builds.rustfmt_upstream_repo).await?;
+    let built = tokio::try_join!(
+        build_or_previous(builds.rustfmt_repo, builds.policy, builds.cargo)
+            .instrument(tracing::info_span!("build_rustfmt", side = "local")),
+        build_or_previous(builds.rustfmt_upstream_repo, builds.policy, builds.cargo)
+            .instrument(tracing::info_span!("build_rustfmt", side = "upstream"))
+    );
+    timings.record(Phase::Build, start.elapsed());
+    built
+}
+
+async fn build_or_previous(
+    rustfmt_repo: &Path,
+    policy: SetupFailurePolicy,
+    cargo: &CargoCommand,
+) -> anyhow::Result<RustFmtBuildOutputs> {
+    match build_rustfmt(rustfmt_repo, cargo).await {
+        Ok(build) => Ok(build),
+        Err(e) if policy == SetupFailurePolicy::KeepGoing => {
+            let build_error = unpack(&*e).to_string();
+            let previous = previous_rustfmt_build(rustfmt_repo, build_error)
+                .await
+                .with_context(|| {
+                    format!(
+                        "no previous build to fall back to after failing to build rustfmt: {}",
+                        unpack(&*e)
+                    )
+                })?;
+            tracing::warn!(
+                "failed to build rustfmt in {}, using the previous build at {}: {}",
+                rustfmt_repo.display(),
+                previous.built_binary_path.display(),
+                unpack(&*e)
+            );
+            Ok(previous)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn preflight_config(
+    settings: &AnalysisSettings,
+    local_build_outputs: &RustFmtBuildOutputs,
+    upstream_build_outputs: &RustFmtBuildOutputs,
+) -> anyhow::Result<()> {
+    let opts = settings.fmt_opts();
+    analyze::preflight_config("local", local_build_outputs, opts).await?;
+    analyze::preflight_config("upstream", upstream_build_outputs, opts).await?;
+    for extra in &settings.extra_builds {
+        analyze::preflight_config(&extra.label, &extra.build, opts).await?;
+    }
+    Ok(())
+}
+
+// Extra builds are only compared against, built one at a time to not starve the main builds
+async fn build_extra_rustfmts(
+    workdir: &Workdir,
+    repos: &[LabeledRustfmtRepo],
+    policy: SetupFailurePolicy,
+    cargo: &CargoCommand,
+) -> anyhow::Result<(Vec<LabeledBuild>, Vec<String>)> {
+    let mut builds = Vec::with_capacity(repos.len());
+    let mut failures = Vec::new();
+    if !repos.is_empty() {
+        // Holds the toolchain lock, which may be the first thing in the workdir
+        workdir.ensure_workdir().await?;
+    }
+    for extra in repos {
+        {
+            let _lock = lock_exclusive(&workdir.toolchain_lock).await?;
+            install_rustfmt_toolchain(&extra.repo)
+                .instrument(tracing::info_span!("warmup", side = %extra.label))
+                .await?;
+        }
+        let build = match build_rustfmt(&extra.repo, cargo)
+            .instrument(tracing::info_span!("build_rustfmt", side = %extra.label))
+            .await
+        {
+            Ok(build) => build,
+            Err(e) if policy == SetupFailurePolicy::KeepGoing => {
+                tracing::warn!(
+                    "failed to build extra rustfmt '{}', leaving it out: {}",
+                    extra.label,
+                    unpack(&*e)
+                );
+                failures.push(format!(
+                    "{} rustfmt failed to build and was left out: {}",
+                    extra.label,
+                    unpack(&*e)
+                ));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        builds.push(LabeledBuild {
+            label: extra.label.clone(),
+            build,
+        });
+    }
+    Ok((builds, failures))
+}
+
+/// `fetch_and_process_crates`, where selecting nothing is an error unless it's allowed,
+/// since it's usually a misconfigured filter rather than what was wanted
+async fn select_from_index(
+    wd: &Workdir,
+    git_sync_config: &GitSyncConfig,
+    consumer_opts: ConsumerOpts,
+    timings: &Timings,
+) -> anyhow::Result<Vec<PrunedCrate>> {
+    let mut filters = consumer_opts.active_filters();
+    if git_sync_config.only_changed_crates {
+        filters.push("only new or changed crates".to_string());
+    }
+    let selected = fetch_and_process_crates(wd, git_sync_config, consumer_opts, timings).await?;
+    if selected.is_empty() {
+        let filters = filters.join("; ");
+        if !git_sync_config.allow_empty_selection {
+            anyhow::bail!("no crates were selected from the index with the filters: {filters}");
+        }
+        tracing::warn!("no crates were selected from the index with the filters: {filters}");
+    }
+    Ok(selected)
 }
 
 async fn fetch_and_process_crates(
     wd: &Workdir,
-    crates_index_max_age_days: u8,
+    git_sync_config: &GitSyncConfig,
     consumer_opts: ConsumerOpts,
+    timings: &Timings,
 ) -> anyhow::Result<Vec<PrunedCrate>> {
     wd.ensure_workdir().await?;
-    if wd.needs_crates_refetch(crates_index_max_age_days).await? {
-        crates::update_index_to(&wd.base).await?;
+    if wd
+        .needs_crates_refetch(git_sync_config.crates_index_max_age_days)
+        .await?
+    {
+        if git_sync_config.only_changed_crates {
+            keep_previous_versions(wd).await?;
+        }
+        timings
+            .timed(
+                Phase::FetchIndex,
+                crates::update_index_to(&wd.base).instrument(tracing::info_span!("fetch_index")),
+            )
+            .await?;
+    }
+    let opts_fingerprint = format!(
+        "{consumer_opts:?}, only_changed_crates: {}",
+        git_sync_config.only_changed_crates
+    );
+    let index_modified = wd.index_modified_unix_seconds().await?;
+    if !git_sync_config.refresh_selection
+        && let Some(cached) =
+            crates::selection_cache::load(&wd.selection_cache, &opts_fingerprint, index_modified)
+                .await
+    {
+        tracing::info!(
+            "reusing {} crates selected on a previous run from {}",
+            cached.len(),
+            wd.selection_cache.display()
+        );
+        return Ok(cached);
     }
     let mut consumer = crates::crate_consumer::default::Consumer::new(consumer_opts);
-    crates::csv_parse::consume_crates_data(wd, &mut consumer)?;
-    Ok(consumer.get_crates())
+    if git_sync_config.only_changed_crates
+        && let Some(changed) = changed_since_previous_index(wd).await?
+    {
+        tracing::info!(
+            "only selecting from {} new or changed crates",
+            changed.len()
+        );
+        consumer.restrict_to(changed);
+    }
+    let parse_start = Instant::now();
+    let consumer = crates::csv_parse::consume_crates_data(wd, consumer)
+        .instrument(tracing::info_span!("parse_index"))
+        .await?;
+    timings.record(Phase::ParseIndex, parse_start.elapsed());
+    crates::selection_cache::store(
+        &wd.selection_cache,
+        opts_fingerprint,
+        index_modified,
+        consumer.get_crates(),
+    )
+    .await
+}
+
+async fn changed_since_previous_index(
+    wd: &Workdir,
+) -> anyhow::Result<Option<rustc_hash::FxHashSet<u64>>> {
+    if !tokio::fs::try_exists(&wd.prev_versions_csv)
+        .await
+        .with_context(|| {
+            format!(
+                "failed to check if {} exists",
+                wd.prev_versions_csv.display()
+            )
+        })?
+    {
+        tracing::warn!(
+            "no previous index at {} to diff against, selecting from all crates",
+            wd.prev_versions_csv.display()
+        );
+        return Ok(None);
+    }
+    let changed = tracing::info_span!("diff_index").in_scope(|| {
+        crates::index_diff::changed_crate_ids(&wd.prev_versions_csv, &wd.versions_csv)
+    })?;
+    Ok(Some(changed))
+}
+
+async fn keep_previous_versions(wd: &Workdir) -> anyhow::Result<()> {
+    match tokio::fs::rename(&wd.versions_csv, &wd.prev_versions_csv).await {
+        Ok(()) => {
+            tracing::debug!("kept previous index at {}", wd.prev_versions_csv.display());
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| {
+            format!(
+                "failed to move {} to {}",
+                wd.versions_csv.display(),
+                wd.prev_versions_csv.display()
+            )
+        }),
+    }
+}
+
+/// Holds `result_buffer` finished analyses, or twice as many as run at once if not set
+fn result_channel(
+    result_buffer: Option<NonZeroUsize>,
+    analysis_max_concurrent: NonZeroUsize,
+) -> (
+    tokio::sync::mpsc::Sender<CrateAnalysis>,
+    tokio::sync::mpsc::Receiver<CrateAnalysis>,
+) {
+    tokio::sync::mpsc::channel(
+        result_buffer.map_or(analysis_max_concurrent.get() * 2, NonZeroUsize::get),
+    )
+}
+
+/// The `rustfmt` builds every analysis runs with, cloning only bumps the refcounts
+#[derive(Clone)]
+struct SharedBuilds {
+    local: Arc<RustFmtBuildOutputs>,
+    upstream: Arc<RustFmtBuildOutputs>,
+}
+
+impl SharedBuilds {
+    fn new(local: RustFmtBuildOutputs, upstream: RustFmtBuildOutputs) -> Self {
+        Self {
+            local: Arc::new(local),
+            upstream: Arc::new(upstream),
+        }
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -227,50 +1075,152 @@ async fn analysis_task(
     send: tokio::sync::mpsc::Sender<CrateAnalysis>,
     local_build_outputs: RustFmtBuildOutputs,
     upstream_build_outputs: RustFmtBuildOutputs,
-    config: Option<String>,
+    settings: AnalysisSettings,
+    completed: CompletedCrates,
+    tally: &Tally,
     max_concurrent: NonZeroUsize,
-    timeout: Duration,
+    ordered: bool,
 ) {
+    let settings = Arc::new(settings);
+    let builds = SharedBuilds::new(local_build_outputs, upstream_build_outputs);
     let mut unordered = FuturesUnordered::new();
+    let mut reorder = ordered.then(ReorderBuffer::new);
+    let mut seq = 0;
     let seen = Arc::new(DashSet::default());
     while let Some(next) = recv.recv().await {
-        let rr = local_build_outputs.clone();
-        let upstream_rr = upstream_build_outputs.clone();
+        metrics::set_analysis_queue_depth(recv.len());
+        if completed
+            .get(&next.crate_root)
+            .is_some_and(|commit| *commit == next.head_commit)
+        {
+            tracing::debug!(
+                "skipping '{}', analyzed before the checkpoint",
+                next.pruned_crate.crate_name
+            );
+            tally.skipped();
+            continue;
+        }
+        let builds_c = builds.clone();
         let seen_c = seen.clone();
-        let cfg_c = config.clone();
-        unordered.push(tokio::task::spawn(async move {
-            analyze::analyze_crate(&next, &rr, &upstream_rr, cfg_c.as_deref(), seen_c, timeout)
+        let settings_c = settings.clone();
+        let span = tracing::info_span!(
+            "analyze_crate",
+            crate_name = %next.pruned_crate.crate_name,
+            repository = next.pruned_crate.repository.as_ref().map(tracing::field::display),
+        );
+        let handle = tokio::task::spawn(
+            async move {
+                analyze::analyze_crate(
+                    &next,
+                    &builds_c.local,
+                    &builds_c.upstream,
+                    &settings_c,
+                    seen_c,
+                )
                 .await
-        }));
+            }
+            .instrument(span),
+        );
+        let this_seq = seq;
+        seq += 1;
+        unordered.push(async move { (this_seq, handle.await) });
         if unordered.len() >= max_concurrent.get() {
-            let Some(next) = unordered.next().await else {
+            let Some((done_seq, next)) = unordered.next().await else {
                 tracing::error!("analysis task was empty, this should never happen");
                 continue;
             };
-            on_analysis(next, &send).await;
+            on_analysis_in_order(reorder.as_mut(), done_seq, next, tally, &send).await;
         }
     }
-    while let Some(res) = unordered.next().await {
-        on_analysis(res, &send).await;
+    while let Some((done_seq, res)) = unordered.next().await {
+        on_analysis_in_order(reorder.as_mut(), done_seq, res, tally, &send).await;
+    }
+}
+
+type AnalysisResult = Result<anyhow::Result<Option<CrateAnalysis>>, tokio::task::JoinError>;
+
+/// Without a reorder buffer results are passed on as they finish
+async fn on_analysis_in_order(
+    reorder: Option<&mut ReorderBuffer<AnalysisResult>>,
+    seq: u64,
+    value: AnalysisResult,
+    tally: &Tally,
+    send: &tokio::sync::mpsc::Sender<CrateAnalysis>,
+) {
+    let Some(reorder) = reorder else {
+        on_analysis(value, tally, send).await;
+        return;
+    };
+    reorder.push(seq, value);
+    while let Some(ready) = reorder.pop_ready() {
+        on_analysis(ready, tally, send).await;
     }
 }
 
 async fn on_analysis(
-    value: Result<anyhow::Result<Option<CrateAnalysis>>, tokio::task::JoinError>,
+    value: AnalysisResult,
+    tally: &Tally,
     send: &tokio::sync::mpsc::Sender<CrateAnalysis>,
 ) {
     match value {
         Ok(Ok(Some(res))) => {
+            metrics::crate_analyzed(res.diverged());
+            tally.analyzed();
             if send.send(res).await.is_err() {
                 tracing::error!("analysis task sender was dropped, exiting");
             }
         }
-        Ok(Ok(None)) => {}
+        // An already analyzed workspace
+        Ok(Ok(None)) => tally.skipped(),
         Ok(Err(e)) => {
             tracing::error!("analysis task failed: {}", unpack(&*e));
+            tally.analysis_failed();
         }
         Err(e) => {
             tracing::error!("analysis task join failed: {}", unpack(&e));
+            tally.analysis_failed();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn analysis_tasks_share_the_build_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("rustfmt");
+        std::fs::write(&binary, "").unwrap();
+        let builds = SharedBuilds::new(
+            prebuilt_rustfmt(&binary).await.unwrap(),
+            prebuilt_rustfmt(&binary).await.unwrap(),
+        );
+        let task = builds.clone();
+        assert!(Arc::ptr_eq(&builds.local, &task.local));
+        assert!(Arc::ptr_eq(&builds.upstream, &task.upstream));
+        // Not even the paths are copied
+        assert_eq!(
+            builds
+                .local
+                .built_binary_path
+                .as_os_str()
+                .as_encoded_bytes()
+                .as_ptr(),
+            task.local
+                .built_binary_path
+                .as_os_str()
+                .as_encoded_bytes()
+                .as_ptr()
+        );
+    }
+
+    #[test]
+    fn result_channel_is_sized_from_the_buffer_or_the_concurrency() {
+        let n = |n| NonZeroUsize::new(n).unwrap();
+        let (_send, recv) = result_channel(Some(n(5)), n(8));
+        assert_eq!(5, recv.max_capacity());
+        let (_send, recv) = result_channel(None, n(8));
+        assert_eq!(16, recv.max_capacity());
+    }
+}
@@ -1,20 +1,31 @@
 use crate::crates::crate_consumer::default::{CrateName, NormalPath, PrunedCrate, RepoName};
-use crate::git::CrateReadyForAnalysis;
-use crate::{ConsumerOpts, StopReceiver, unpack};
+use crate::git::{CrateOrigin, CrateReadyForAnalysis};
+use crate::sync::Tally;
+use crate::{ConsumerOpts, LocalCratesConfig, StopReceiver, unpack};
 use anyhow::{Context, bail};
+use rustc_hash::FxHashSet;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub fn local_crate_find_task(
-    path: PathBuf,
+    config: LocalCratesConfig,
     num_analysis_concurrent: NonZeroUsize,
     consumer_opts: ConsumerOpts,
+    tally: Arc<Tally>,
     mut stop_receiver: StopReceiver,
 ) -> tokio::sync::mpsc::Receiver<CrateReadyForAnalysis> {
     let (send, recv) = tokio::sync::mpsc::channel(num_analysis_concurrent.get() * 2);
     tokio::task::spawn(async move {
         if let Some(Err(e)) = stop_receiver
-            .with_stop(find_local_crates_in(&path, consumer_opts, send))
+            .with_stop(find_local_crates_in(
+                &config.crate_dir,
+                config.follow_symlinks,
+                &config.skip_dirs,
+                consumer_opts,
+                &tally,
+                send,
+            ))
             .await
         {
             tracing::error!("local crates task error: {}", unpack(&*e));
@@ -25,15 +36,55 @@ pub fn local_crate_find_task(
     recv
 }
 
+/// Find local crates the same way as when analyzing, but collect them instead of
+/// handing them on
+pub(crate) async fn collect_local_crates(
+    config: &LocalCratesConfig,
+    consumer_opts: ConsumerOpts,
+) -> anyhow::Result<Vec<PrunedCrate>> {
+    let (send, mut recv) = tokio::sync::mpsc::channel(32);
+    // Nothing is analyzed, so nothing to account for
+    let tally = Tally::default();
+    let find = find_local_crates_in(
+        &config.crate_dir,
+        config.follow_symlinks,
+        &config.skip_dirs,
+        consumer_opts,
+        &tally,
+        send,
+    );
+    let collect = async {
+        let mut found = Vec::new();
+        while let Some(crate_info) = recv.recv().await {
+            found.push(crate_info.pruned_crate);
+        }
+        found
+    };
+    let (res, found) = tokio::join!(find, collect);
+    res?;
+    Ok(found)
+}
+
 async fn find_local_crates_in(
     path: &Path,
+    follow_symlinks: bool,
+    skip_dirs: &[String],
     consumer_opts: ConsumerOpts,
+    tally: &Tally,
     sender: tokio::sync::mpsc::Sender<CrateReadyForAnalysis>,
 ) -> anyhow::Result<()> {
     let mut rd = tokio::fs::read_dir(path)
         .await
         .with_context(|| format!("failed to read dir {} searching for crates", path.display()))?;
     let mut max_crates = consumer_opts.max_crates;
+    // Canonical paths of directories already considered, a symlink pointing back into the tree
+    // (or at a sibling) would otherwise analyze the same crate twice, or loop
+    let mut visited = FxHashSet::default();
+    visited.insert(
+        tokio::fs::canonicalize(path)
+            .await
+            .with_context(|| format!("failed to canonicalize {}", path.display()))?,
+    );
     loop {
         let Some(next) = rd.next_entry().await.with_context(|| {
             format!(
@@ -45,13 +96,14 @@ async fn find_local_crates_in(
             break;
         };
         let ent_path = next.path();
-        let metadata = next.metadata().await.with_context(|| {
-            format!(
-                "failed to read metadata for {} searching for crates",
-                ent_path.display()
-            )
-        })?;
-        if !metadata.is_dir() {
+        if skip_dirs
+            .iter()
+            .any(|skip| next.file_name().as_os_str() == skip.as_str())
+        {
+            tracing::trace!("skipping {}", ent_path.display());
+            continue;
+        }
+        if !is_unvisited_dir(&next, follow_symlinks, &mut visited).await? {
             continue;
         }
         match verify_crate_in(ent_path.clone()).await {
@@ -82,6 +134,7 @@ async fn find_local_crates_in(
                 if skip {
                     continue;
                 }
+                tally.selected(1);
                 if sender.send(crate_info).await.is_err() {
                     bail!(
                         "failed to send crate info for local crate at: {}",
@@ -102,33 +155,177 @@ async fn find_local_crates_in(
     Ok(())
 }
 
-async fn verify_crate_in(path: PathBuf) -> anyhow::Result<CrateReadyForAnalysis> {
+async fn is_unvisited_dir(
+    ent: &tokio::fs::DirEntry,
+    follow_symlinks: bool,
+    visited: &mut FxHashSet<PathBuf>,
+) -> anyhow::Result<bool> {
+    let ent_path = ent.path();
+    // Does not follow symlinks
+    let file_type = ent.file_type().await.with_context(|| {
+        format!(
+            "failed to read file type for {} searching for crates",
+            ent_path.display()
+        )
+    })?;
+    if file_type.is_symlink() {
+        if !follow_symlinks {
+            tracing::debug!("skipping symlink at {}", ent_path.display());
+            return Ok(false);
+        }
+        let metadata = match tokio::fs::metadata(&ent_path).await {
+            Ok(md) => md,
+            Err(e) => {
+                tracing::warn!(
+                    "failed to follow symlink at {}: {}",
+                    ent_path.display(),
+                    unpack(&e)
+                );
+                return Ok(false);
+            }
+        };
+        if !metadata.is_dir() {
+            return Ok(false);
+        }
+    } else if !file_type.is_dir() {
+        return Ok(false);
+    }
+    let canonical = tokio::fs::canonicalize(&ent_path)
+        .await
+        .with_context(|| format!("failed to canonicalize {}", ent_path.display()))?;
+    if !visited.insert(canonical) {
+        tracing::debug!(
+            "skipping already visited directory at {}",
+            ent_path.display()
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+pub(crate) async fn verify_crate_in(path: PathBuf) -> anyhow::Result<CrateReadyForAnalysis> {
     let ct = path.join("Cargo.toml");
     let content = tokio::fs::read(&ct)
         .await
         .with_context(|| format!("failed to read Cargo.toml at {}", ct.display()))?;
-    let _parsed_cargo_toml = cargo_toml::Manifest::from_slice(&content)
+    let parsed_cargo_toml = cargo_toml::Manifest::from_slice(&content)
         .with_context(|| format!("failed to parse cargo toml at {}", ct.display()))?;
+    let (version, description, edition) = parsed_cargo_toml
+        .package
+        .as_ref()
+        .map(|pkg| {
+            (
+                pkg.version.get().ok().cloned(),
+                pkg.description.as_ref().and_then(|d| d.get().ok().cloned()),
+                pkg.edition.get().ok().map(ToString::to_string),
+            )
+        })
+        .unwrap_or_default();
     let p = path
         .components()
         .next_back()
         .with_context(|| format!("failed to get last path component of {}", path.display()))?;
     let crate_name = PathBuf::from(p.as_os_str());
     let crate_name = NormalPath::from_checked_path(crate_name);
-    let (git_repo, head_branch) = match crate::git::scan_git_repo(&path).await {
-        Ok((repo, head_branch)) => (Some(repo), Some(head_branch)),
+    let (git_repo, head_branch, head_commit) = match crate::git::scan_git_repo(&path).await {
+        Ok(scan) => (scan.repository, scan.head_branch, Some(scan.head_commit)),
         Err(e) => {
             tracing::debug!("failed to scan git repo at {}: {}", path.display(), e);
-            (None, None)
+            (None, None, None)
         }
     };
     Ok(CrateReadyForAnalysis {
+        crate_root: path.clone(),
         repo_root: path,
         head_branch,
+        head_commit,
         pruned_crate: PrunedCrate {
             crate_name: CrateName(crate_name.clone()),
             repository: git_repo,
             repo_dir_name: RepoName(crate_name),
+            subdir: None,
+            crate_size: None,
+            downloads: None,
+            version,
+            description,
+            edition,
         },
+        has_manifest: true,
+        origin: CrateOrigin::Local,
+        oversized_repo_bytes: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_LOCAL_SKIP_DIRS;
+
+    async fn unvisited_dirs(root: &Path, follow_symlinks: bool) -> Vec<String> {
+        let mut visited = FxHashSet::default();
+        visited.insert(std::fs::canonicalize(root).unwrap());
+        let mut rd = tokio::fs::read_dir(root).await.unwrap();
+        let mut found = Vec::new();
+        while let Some(ent) = rd.next_entry().await.unwrap() {
+            if is_unvisited_dir(&ent, follow_symlinks, &mut visited)
+                .await
+                .unwrap()
+            {
+                found.push(ent.file_name().to_string_lossy().into_owned());
+            }
+        }
+        found.sort();
+        found
+    }
+
+    #[tokio::test]
+    async fn symlink_cycle_is_visited_once() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("real")).unwrap();
+        // One link back at the searched dir, one at a sibling
+        std::os::unix::fs::symlink(".", dir.path().join("cycle")).unwrap();
+        std::os::unix::fs::symlink("real", dir.path().join("alias")).unwrap();
+        assert_eq!(vec!["real"], unvisited_dirs(dir.path(), false).await);
+        assert_eq!(1, unvisited_dirs(dir.path(), true).await.len());
+    }
+
+    async fn found_in(crate_dir: &Path, skip_dirs: &[&str]) -> Vec<String> {
+        let config = LocalCratesConfig {
+            crate_dir: crate_dir.to_path_buf(),
+            follow_symlinks: false,
+            skip_dirs: skip_dirs.iter().map(ToString::to_string).collect(),
+        };
+        let mut found = collect_local_crates(&config, ConsumerOpts::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|cr| cr.crate_name.to_string())
+            .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+
+    #[tokio::test]
+    async fn well_known_non_crate_dirs_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        // Look like crates, so that only the skip-list keeps them out
+        for name in ["real", "target", ".git"] {
+            let root = dir.path().join(name);
+            std::fs::create_dir_all(root.join("src")).unwrap();
+            std::fs::write(
+                root.join("Cargo.toml"),
+                "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\n",
+            )
+            .unwrap();
+            std::fs::write(root.join("src").join("lib.rs"), "").unwrap();
+        }
+        assert_eq!(
+            vec!["real"],
+            found_in(dir.path(), DEFAULT_LOCAL_SKIP_DIRS).await
+        );
+        assert_eq!(
+            vec![".git", "real", "target"],
+            found_in(dir.path(), &[]).await
+        );
+    }
+}
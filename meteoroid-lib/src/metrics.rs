@@ -0,0 +1,93 @@
+//! Live run metrics, served in Prometheus text format on `/metrics` with the `metrics` feature,
+//! recording is a no-op without it
+#[cfg(feature = "metrics")]
+mod server;
+
+#[cfg(feature = "metrics")]
+pub(crate) use server::serve;
+
+#[cfg(feature = "metrics")]
+struct Metrics {
+    registry: prometheus::Registry,
+    crates_analyzed: prometheus::IntCounter,
+    crates_diverged: prometheus::IntCounter,
+    clones_in_flight: prometheus::IntGauge,
+    analysis_queue_depth: prometheus::IntGauge,
+}
+
+#[cfg(feature = "metrics")]
+static METRICS: std::sync::LazyLock<Metrics> = std::sync::LazyLock::new(|| {
+    let registry = prometheus::Registry::new();
+    let crates_analyzed = prometheus::IntCounter::new(
+        "meteoroid_crates_analyzed_total",
+        "Crates that finished analysis",
+    )
+    .expect("valid metric");
+    let crates_diverged = prometheus::IntCounter::new(
+        "meteoroid_crates_diverged_total",
+        "Analyzed crates where local and upstream rustfmt diverged",
+    )
+    .expect("valid metric");
+    let clones_in_flight =
+        prometheus::IntGauge::new("meteoroid_clones_in_flight", "Git clones currently running")
+            .expect("valid metric");
+    let analysis_queue_depth = prometheus::IntGauge::new(
+        "meteoroid_analysis_queue_depth",
+        "Crates ready for analysis waiting to be picked up",
+    )
+    .expect("valid metric");
+    for metric in [
+        Box::new(crates_analyzed.clone()) as Box<dyn prometheus::core::Collector>,
+        Box::new(crates_diverged.clone()),
+        Box::new(clones_in_flight.clone()),
+        Box::new(analysis_queue_depth.clone()),
+    ] {
+        registry.register(metric).expect("metric names are unique");
+    }
+    Metrics {
+        registry,
+        crates_analyzed,
+        crates_diverged,
+        clones_in_flight,
+        analysis_queue_depth,
+    }
+});
+
+pub(crate) fn crate_analyzed(diverged: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        METRICS.crates_analyzed.inc();
+        if diverged {
+            METRICS.crates_diverged.inc();
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = diverged;
+}
+
+pub(crate) fn clone_started() {
+    #[cfg(feature = "metrics")]
+    METRICS.clones_in_flight.inc();
+}
+
+pub(crate) fn clone_finished() {
+    #[cfg(feature = "metrics")]
+    METRICS.clones_in_flight.dec();
+}
+
+pub(crate) fn set_analysis_queue_depth(depth: usize) {
+    #[cfg(feature = "metrics")]
+    METRICS
+        .analysis_queue_depth
+        .set(i64::try_from(depth).unwrap_or(i64::MAX));
+    #[cfg(not(feature = "metrics"))]
+    let _ = depth;
+}
+
+#[cfg(feature = "metrics")]
+fn render() -> anyhow::Result<String> {
+    use anyhow::Context;
+    prometheus::TextEncoder::new()
+        .encode_to_string(&METRICS.registry.gather())
+        .context("failed to encode metrics")
+}
@@ -0,0 +1,116 @@
+use crate::StopReceiver;
+use crate::error::unpack;
+use anyhow::Context;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Binds `addr` and serves `/metrics` until stopped
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    mut stop_receiver: StopReceiver,
+) -> anyhow::Result<tokio::task::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind metrics server to {addr}"))?;
+    tracing::info!("serving metrics on http://{addr}/metrics");
+    Ok(tokio::task::spawn(async move {
+        if stop_receiver
+            .with_stop(accept_loop(listener))
+            .await
+            .is_none()
+        {
+            tracing::debug!("metrics server stopped");
+        }
+    }))
+}
+
+async fn accept_loop(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::task::spawn(async move {
+                    if let Err(e) = respond(stream).await {
+                        tracing::debug!("failed to respond to metrics request: {}", unpack(&*e));
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::warn!("failed to accept metrics connection: {}", unpack(&e));
+            }
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> anyhow::Result<()> {
+    // Only the request line matters, it fits comfortably
+    let mut buf = [0u8; 1024];
+    let n = stream
+        .read(&mut buf)
+        .await
+        .context("failed to read request")?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default();
+    let (status, content_type, body) = if path == "/metrics" {
+        ("200 OK", "text/plain; version=0.0.4", super::render()?)
+    } else {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .context("failed to write response")?;
+    stream
+        .shutdown()
+        .await
+        .context("failed to close connection")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_the_run_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::task::spawn(accept_loop(listener));
+        crate::metrics::clone_started();
+        crate::metrics::clone_finished();
+        crate::metrics::set_analysis_queue_depth(3);
+        crate::metrics::crate_analyzed(true);
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        for name in [
+            "meteoroid_crates_analyzed_total",
+            "meteoroid_crates_diverged_total",
+            "meteoroid_clones_in_flight 0",
+            "meteoroid_analysis_queue_depth 3",
+        ] {
+            assert!(response.contains(name), "missing '{name}' in {response}");
+        }
+        let response = get(addr, "/other").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+        server.abort();
+    }
+}
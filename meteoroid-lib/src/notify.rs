@@ -0,0 +1,92 @@
+use crate::crates::http_client;
+use crate::error::unpack;
+use anyhow::Context;
+use std::fmt::Write;
+
+/// How many diverging crates are named in a notification
+const MAX_NAMED_CRATES: usize = 10;
+
+/// What a finished run looks like, enough to tell someone whether to look at the report
+#[derive(Debug, Clone)]
+pub(crate) struct RunSummary {
+    pub(crate) num_analyzed: usize,
+    pub(crate) num_diverging_diffs: usize,
+    pub(crate) diverged_crates: Vec<String>,
+    #[cfg(feature = "s3")]
+    pub(crate) output_dir: std::path::PathBuf,
+}
+
+pub struct NotifyOpts {
+    /// A Slack or Discord incoming webhook
+    pub webhook: url::Url,
+    /// Notify even if nothing diverged
+    pub always: bool,
+}
+
+/// Posts the summary to a Slack/Discord compatible incoming webhook,
+/// failures are logged, a run shouldn't fail because a chat was unreachable
+pub(crate) async fn notify(opts: &NotifyOpts, summary: &RunSummary) {
+    if summary.num_diverging_diffs == 0 && !opts.always {
+        tracing::debug!("no divergences, skipping webhook notification");
+        return;
+    }
+    if let Err(e) = post(&opts.webhook, &message(summary)).await {
+        tracing::error!("failed to send webhook notification: {}", unpack(&*e));
+    }
+}
+
+fn message(summary: &RunSummary) -> String {
+    if summary.num_diverging_diffs == 0 {
+        return format!(
+            "meteoroid: no diverging diffs in {} analyzed crates",
+            summary.num_analyzed
+        );
+    }
+    let mut msg = format!(
+        "meteoroid: {} diverging diffs in {} analyzed crates",
+        summary.num_diverging_diffs, summary.num_analyzed
+    );
+    if !summary.diverged_crates.is_empty() {
+        msg.push_str(": ");
+        msg.push_str(
+            &summary
+                .diverged_crates
+                .iter()
+                .take(MAX_NAMED_CRATES)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        if summary.diverged_crates.len() > MAX_NAMED_CRATES {
+            let _ = write!(
+                msg,
+                " and {} more",
+                summary.diverged_crates.len() - MAX_NAMED_CRATES
+            );
+        }
+    }
+    msg
+}
+
+fn body(message: &str) -> serde_json::Value {
+    // Slack reads `text`, Discord reads `content`, both ignore the other
+    serde_json::json!({
+        "text": message,
+        "content": message,
+    })
+}
+
+async fn post(webhook: &url::Url, message: &str) -> anyhow::Result<()> {
+    let client = http_client()?;
+    let resp = client
+        .post(webhook.as_str())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body(message).to_string())
+        .send()
+        .await
+        .context("failed to send webhook request")?;
+    resp.error_for_status()
+        .context("webhook responded with an error status")?;
+    tracing::info!("sent webhook notification");
+    Ok(())
+}
@@ -1,3 +1,7 @@
+use crate::crates::crate_consumer::default::CrateName;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
 pub struct StopSender {
     chan: tokio::sync::oneshot::Sender<tokio::sync::oneshot::Sender<()>>,
 }
@@ -36,3 +40,183 @@ impl StopReceiver {
         }
     }
 }
+
+/// Where the crates of a run ended up, so that crates lost along the way (rather than skipped
+/// or failed with a logged reason) are noticed when the run finishes
+#[derive(Debug, Default)]
+pub(crate) struct Tally {
+    selected: AtomicUsize,
+    skipped: AtomicUsize,
+    clone_failed: AtomicUsize,
+    unreachable: Mutex<Vec<CrateName>>,
+    analysis_failed: AtomicUsize,
+    analyzed: AtomicUsize,
+}
+
+impl Tally {
+    pub(crate) fn selected(&self, count: usize) {
+        self.selected.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn clone_failed(&self) {
+        self.clone_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unreachable(&self, crate_name: CrateName) {
+        self.unreachable
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(crate_name);
+    }
+
+    /// Crates that weren't cloned because their host was considered unreachable
+    pub(crate) fn unreachable_crates(&self) -> Vec<CrateName> {
+        self.unreachable
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn analysis_failed(&self) {
+        self.analysis_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn analyzed(&self) {
+        self.analyzed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Only meaningful once every selected crate had the chance to go through the pipeline
+    pub(crate) fn reconcile(&self) {
+        let selected = self.selected.load(Ordering::Relaxed);
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        let clone_failed = self.clone_failed.load(Ordering::Relaxed);
+        let unreachable = self.unreachable_crates().len();
+        let analysis_failed = self.analysis_failed.load(Ordering::Relaxed);
+        let analyzed = self.analyzed.load(Ordering::Relaxed);
+        let accounted = skipped + clone_failed + unreachable + analysis_failed + analyzed;
+        if accounted == selected {
+            tracing::debug!(
+                "all {selected} selected crates accounted for: {analyzed} analyzed, {skipped} skipped, {unreachable} skipped as unreachable, {clone_failed} failed to clone, {analysis_failed} failed analysis"
+            );
+        } else {
+            tracing::warn!(
+                "{selected} crates were selected, but {accounted} are accounted for: {analyzed} analyzed, {skipped} skipped, {unreachable} skipped as unreachable, {clone_failed} failed to clone, {analysis_failed} failed analysis. Crates were lost along the way, check the log for errors"
+            );
+        }
+    }
+}
+
+/// Releases values in sequence order, holding back values that arrive
+/// ahead of one that's still missing
+pub(crate) struct ReorderBuffer<T> {
+    next: u64,
+    pending: std::collections::BTreeMap<u64, T>,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: 0,
+            pending: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, seq: u64, value: T) {
+        self.pending.insert(seq, value);
+    }
+
+    pub(crate) fn pop_ready(&mut self) -> Option<T> {
+        let value = self.pending.remove(&self.next)?;
+        self.next += 1;
+        Some(value)
+    }
+}
+
+/// Forwards from both receivers until both are closed, or `max` values have been forwarded.
+/// The stop senders for the tasks feeding them are dropped when forwarding ends,
+/// which stops those tasks too
+pub(crate) fn merge_receivers<T: Send + 'static>(
+    mut first: tokio::sync::mpsc::Receiver<T>,
+    mut second: tokio::sync::mpsc::Receiver<T>,
+    feeding_stops: [StopSender; 2],
+    max: Option<usize>,
+    capacity: std::num::NonZeroUsize,
+    mut stop_receiver: StopReceiver,
+) -> tokio::sync::mpsc::Receiver<T> {
+    let (send, recv) = tokio::sync::mpsc::channel(capacity.get());
+    tokio::task::spawn(async move {
+        let _feeding_stops = feeding_stops;
+        let forward = async move {
+            let mut forwarded = 0;
+            let (mut first_open, mut second_open) = (true, true);
+            while (first_open || second_open) && max.is_none_or(|max| forwarded < max) {
+                let next = tokio::select! {
+                    next = first.recv(), if first_open => next.or_else(|| {
+                        first_open = false;
+                        None
+                    }),
+                    next = second.recv(), if second_open => next.or_else(|| {
+                        second_open = false;
+                        None
+                    }),
+                };
+                let Some(next) = next else {
+                    continue;
+                };
+                if send.send(next).await.is_err() {
+                    tracing::debug!("merged receiver dropped, stopping forwarding");
+                    return;
+                }
+                forwarded += 1;
+            }
+            tracing::debug!("finished forwarding {forwarded} values from merged sources");
+        };
+        if stop_receiver.with_stop(forward).await.is_none() {
+            tracing::info!("merging sources was stopped before finishing, exiting");
+        }
+    });
+    recv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `tally.reconcile()` logs
+    fn reconciled(tally: &Tally) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let file = std::fs::File::create(&log).unwrap();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(Mutex::new(file))
+            .finish();
+        tracing::subscriber::with_default(subscriber, || tally.reconcile());
+        std::fs::read_to_string(log).unwrap()
+    }
+
+    #[test]
+    fn dropped_crate_is_warned_about() {
+        let tally = Tally::default();
+        tally.selected(4);
+        tally.analyzed();
+        tally.skipped();
+        tally.clone_failed();
+        let log = reconciled(&tally);
+        assert!(log.contains("WARN"), "{log}");
+        assert!(
+            log.contains("4 crates were selected, but 3 are accounted for"),
+            "{log}"
+        );
+        // The dropped crate turns up after all
+        tally.analysis_failed();
+        let log = reconciled(&tally);
+        assert!(!log.contains("WARN"), "{log}");
+        assert!(log.contains("all 4 selected crates accounted for"), "{log}");
+    }
+}
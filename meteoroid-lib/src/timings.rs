@@ -0,0 +1,38 @@
+//! Overall time spent in each phase of a run, clones overlap so theirs is summed over every clone
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Phase {
+    FetchIndex,
+    ParseIndex,
+    Clone,
+    Build,
+    Analyze,
+}
+
+/// Kept per run, runs in the same process don't add to each other's timings
+#[derive(Debug, Default)]
+pub(crate) struct Timings {
+    phase_nanos: [AtomicU64; 5],
+}
+
+impl Timings {
+    pub(crate) fn record(&self, phase: Phase, elapsed: Duration) {
+        self.phase_nanos[phase as usize].fetch_add(
+            u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(crate) async fn timed<F: Future<Output = T>, T>(&self, phase: Phase, fut: F) -> T {
+        let start = Instant::now();
+        let out = fut.await;
+        self.record(phase, start.elapsed());
+        out
+    }
+
+    pub(crate) fn recorded(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.phase_nanos[phase as usize].load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Context;
+use aws_sdk_s3::primitives::ByteStream;
+use std::path::{Path, PathBuf};
+
+/// Where to upload outputs, the endpoint can be set for non-AWS S3-compatible storage
+#[derive(Debug, Clone)]
+pub struct S3UploadOpts {
+    pub bucket: String,
+    pub prefix: String,
+    pub endpoint: Option<String>,
+}
+
+/// Uploads every file under `output_dir`, keyed by their path relative to it under `prefix`,
+/// and any `extra_files` (like a report written outside the output dir) directly under `prefix`
+pub(crate) async fn upload_outputs(
+    opts: &S3UploadOpts,
+    output_dir: &Path,
+    extra_files: &[PathBuf],
+) -> anyhow::Result<()> {
+    let sdk_config = aws_config::load_from_env().await;
+    upload_with(
+        aws_sdk_s3::config::Builder::from(&sdk_config),
+        opts,
+        output_dir,
+        extra_files,
+    )
+    .await
+}
+
+async fn upload_with(
+    mut builder: aws_sdk_s3::config::Builder,
+    opts: &S3UploadOpts,
+    output_dir: &Path,
+    extra_files: &[PathBuf],
+) -> anyhow::Result<()> {
+    if let Some(endpoint) = &opts.endpoint {
+        // Most non-AWS implementations don't support virtual-hosted buckets
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    let client = aws_sdk_s3::Client::from_conf(builder.build());
+    let mut files = vec![];
+    collect_files(output_dir, output_dir, &mut files)
+        .await
+        .with_context(|| format!("failed to list outputs in {}", output_dir.display()))?;
+    for extra in extra_files {
+        if extra.starts_with(output_dir) {
+            continue;
+        }
+        let Some(name) = extra.file_name() else {
+            continue;
+        };
+        files.push((extra.clone(), PathBuf::from(name)));
+    }
+    let prefix = opts.prefix.trim_end_matches('/');
+    for (path, rel) in &files {
+        let rel = rel.to_string_lossy();
+        let key = if prefix.is_empty() {
+            rel.to_string()
+        } else {
+            format!("{prefix}/{rel}")
+        };
+        let body = ByteStream::from_path(path)
+            .await
+            .with_context(|| format!("failed to read {} for upload", path.display()))?;
+        client
+            .put_object()
+            .bucket(&opts.bucket)
+            .key(&key)
+            .content_type(content_type(path))
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("failed to upload {} to {key}", path.display()))?;
+        tracing::trace!("uploaded {} to {key}", path.display());
+    }
+    tracing::info!(
+        "uploaded {} files to bucket '{}' under '{prefix}'",
+        files.len(),
+        opts.bucket
+    );
+    Ok(())
+}
+
+async fn collect_files(
+    base: &Path,
+    dir: &Path,
+    out: &mut Vec<(PathBuf, PathBuf)>,
+) -> anyhow::Result<()> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| format!("failed to read dir at {}", dir.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("failed to read entry in {}", dir.display()))?
+    {
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .await
+            .with_context(|| format!("failed to get file type of {}", path.display()))?;
+        if file_type.is_dir() {
+            Box::pin(collect_files(base, &path, out)).await?;
+        } else if file_type.is_file() {
+            let rel = path
+                .strip_prefix(base)
+                .with_context(|| format!("{} is not under {}", path.display(), base.display()))?
+                .to_path_buf();
+            out.push((path, rel));
+        }
+    }
+    Ok(())
+}
+
+// Set so that the report renders when served straight from the bucket
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("json" | "sarif") => "application/json",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use std::io::{BufRead, Read, Write};
+
+    /// Answers `requests` requests with an empty `200 OK`, gives back each request's line
+    /// and `content-type`
+    fn mock_s3(requests: usize) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            (0..requests)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = std::io::BufReader::new(stream);
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_type = String::new();
+                    let mut len = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(':') else {
+                            break;
+                        };
+                        match name.to_ascii_lowercase().as_str() {
+                            "content-type" => content_type = value.trim().to_string(),
+                            "content-length" => len = value.trim().parse().unwrap(),
+                            _ => {}
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; len]).unwrap();
+                    reader
+                        .get_mut()
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        )
+                        .unwrap();
+                    (request_line.trim_end().to_string(), content_type)
+                })
+                .collect()
+        });
+        (endpoint, handle)
+    }
+
+    #[tokio::test]
+    async fn outputs_are_uploaded_under_the_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("output");
+        std::fs::create_dir_all(output_dir.join("diverging")).unwrap();
+        std::fs::write(output_dir.join("report.html"), "<html></html>").unwrap();
+        std::fs::write(output_dir.join("diverging").join("serde.diff"), "diff").unwrap();
+        let outside = dir.path().join("report.json");
+        std::fs::write(&outside, "{}").unwrap();
+        let (endpoint, server) = mock_s3(3);
+        let builder = aws_sdk_s3::config::Builder::new()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::for_tests());
+        let opts = S3UploadOpts {
+            bucket: "reports".to_string(),
+            prefix: "nightly/".to_string(),
+            endpoint: Some(endpoint),
+        };
+        upload_with(builder, &opts, &output_dir, &[outside])
+            .await
+            .unwrap();
+        let mut uploaded = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(request_line, content_type)| {
+                let path = request_line.split([' ', '?']).nth(1).unwrap().to_string();
+                (path, content_type)
+            })
+            .collect::<Vec<_>>();
+        uploaded.sort();
+        assert_eq!(
+            vec![
+                (
+                    "/reports/nightly/diverging/serde.diff".to_string(),
+                    "text/plain; charset=utf-8".to_string()
+                ),
+                (
+                    "/reports/nightly/report.html".to_string(),
+                    "text/html; charset=utf-8".to_string()
+                ),
+                (
+                    "/reports/nightly/report.json".to_string(),
+                    "application/json".to_string()
+                ),
+            ],
+            uploaded
+        );
+    }
+}
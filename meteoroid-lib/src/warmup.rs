@@ -0,0 +1,37 @@
+use crate::MeteroidError;
+use crate::cmd::install_rustfmt_toolchain;
+use crate::fs::{Workdir, lock_exclusive};
+use std::path::{Path, PathBuf};
+use tracing::Instrument;
+
+/// Installs the toolchains used by both `rustfmt` repos, if they aren't already installed.
+/// Returns the toolchain names, local first
+pub async fn warmup(
+    workdir: PathBuf,
+    rustfmt_repo: &Path,
+    rustfmt_upstream_repo: &Path,
+) -> Result<(String, String), MeteroidError> {
+    warmup_toolchains(&Workdir::new(workdir), rustfmt_repo, rustfmt_upstream_repo)
+        .await
+        .map_err(MeteroidError::setup)
+}
+
+// Installing a toolchain concurrently with something else that installs (or uses) it races,
+// so installs are made one at a time, holding a lock that other meteoroid processes
+// using the same workdir also respect
+pub(crate) async fn warmup_toolchains(
+    wd: &Workdir,
+    rustfmt_repo: &Path,
+    rustfmt_upstream_repo: &Path,
+) -> anyhow::Result<(String, String)> {
+    wd.ensure_workdir().await?;
+    let _lock = lock_exclusive(&wd.toolchain_lock).await?;
+    let local = install_rustfmt_toolchain(rustfmt_repo)
+        .instrument(tracing::info_span!("warmup", side = "local"))
+        .await?;
+    let upstream = install_rustfmt_toolchain(rustfmt_upstream_repo)
+        .instrument(tracing::info_span!("warmup", side = "upstream"))
+        .await?;
+    tracing::info!("toolchains ready, local: {local}, upstream: {upstream}");
+    Ok((local, upstream))
+}
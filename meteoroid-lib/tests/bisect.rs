@@ -0,0 +1,108 @@
+use meteoroid_lib::{BisectConfig, CargoCommand, bisect};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+// "Builds" rustfmt by copying the repo's `rustfmt.sh`, anything but a build runs the real `cargo`
+const FAKE_CARGO: &str = r#"#!/bin/sh
+if [ "$1" = "build" ]; then
+    mkdir -p target/release
+    cp rustfmt.sh target/release/rustfmt
+    chmod +x target/release/rustfmt
+    exit 0
+fi
+exec cargo "$@"
+"#;
+
+const FORMATTED: &str = "#!/bin/sh\nexit 0\n";
+
+const DIVERGING: &str = "#!/bin/sh\n\
+    echo \"Diff in src/lib.rs at line 1:\"\n\
+    echo \"-fn  fixture() {}\"\n\
+    echo \"+fn fixture() {}\"\n\
+    exit 1\n";
+
+fn git(cwd: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args([
+            "-c",
+            "user.name=fixture",
+            "-c",
+            "user.email=fixture@example.com",
+        ])
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn commit_rustfmt(repo: &Path, script: &str, message: &str) -> String {
+    std::fs::write(repo.join("rustfmt.sh"), script).unwrap();
+    git(repo, &["add", "-A"]);
+    git(repo, &["commit", "-q", "--allow-empty", "-m", message]);
+    git(repo, &["rev-parse", "HEAD"])
+}
+
+fn rustfmt_repo(repo: &Path) {
+    std::fs::create_dir_all(repo).unwrap();
+    // The built binary's toolchain is looked up with `rustup`
+    std::fs::write(
+        repo.join("rust-toolchain.toml"),
+        "[toolchain]\nchannel = \"stable\"\n",
+    )
+    .unwrap();
+    std::fs::write(repo.join(".gitignore"), "target\n").unwrap();
+    git(repo, &["init", "-q"]);
+}
+
+#[tokio::test]
+async fn bisect_converges_on_the_diverging_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = dir.path().join("upstream");
+    rustfmt_repo(&upstream);
+    commit_rustfmt(&upstream, FORMATTED, "upstream");
+
+    let local = dir.path().join("local");
+    rustfmt_repo(&local);
+    let good = commit_rustfmt(&local, FORMATTED, "first");
+    commit_rustfmt(&local, FORMATTED, "second");
+    let culprit = commit_rustfmt(&local, DIVERGING, "diverge");
+    commit_rustfmt(&local, DIVERGING, "fourth");
+    commit_rustfmt(&local, DIVERGING, "fifth");
+
+    let crate_dir = dir.path().join("diverging");
+    std::fs::create_dir_all(crate_dir.join("src")).unwrap();
+    std::fs::write(
+        crate_dir.join("Cargo.toml"),
+        "[package]\nname = \"diverging\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    std::fs::write(crate_dir.join("src").join("lib.rs"), "fn fixture() {}\n").unwrap();
+
+    let cargo = dir.path().join("cargo");
+    std::fs::write(&cargo, FAKE_CARGO).unwrap();
+    std::fs::set_permissions(&cargo, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let outcome = bisect(BisectConfig {
+        workdir: dir.path().join("workdir"),
+        rustfmt_repo: local,
+        rustfmt_upstream_repo: upstream,
+        crate_dir,
+        good: Some(good),
+        bad: None,
+        config: None,
+        unstable_features: false,
+        timeout: Duration::from_mins(1),
+        cargo: CargoCommand {
+            bin: cargo,
+            args: Vec::new(),
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(culprit, outcome.culprit);
+    assert!(outcome.summary.ends_with("diverge"), "{}", outcome.summary);
+}
@@ -0,0 +1,45 @@
+use meteoroid_lib::{CleanOpts, clean};
+use std::path::Path;
+
+fn workdir_with_index_and_clone(dir: &Path) {
+    std::fs::create_dir_all(dir.join("serde").join("src")).unwrap();
+    std::fs::write(dir.join("serde").join("src").join("lib.rs"), "").unwrap();
+    std::fs::write(dir.join("crates.csv"), "id,name\n").unwrap();
+    std::fs::write(dir.join("versions.csv"), "crate_id\n").unwrap();
+}
+
+#[tokio::test]
+async fn index_only_removes_the_csvs() {
+    let dir = tempfile::tempdir().unwrap();
+    let workdir = dir.path().join("workdir");
+    workdir_with_index_and_clone(&workdir);
+    let opts = CleanOpts {
+        index: true,
+        ..CleanOpts::default()
+    };
+    let removed = clean(workdir.clone(), opts).await.unwrap();
+    let names = removed
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(["crates.csv", "versions.csv"], names.as_slice());
+    assert!(!workdir.join("crates.csv").exists());
+    assert!(!workdir.join("versions.csv").exists());
+    assert!(workdir.join("serde").join("src").join("lib.rs").exists());
+}
+
+#[tokio::test]
+async fn dry_run_removes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let workdir = dir.path().join("workdir");
+    workdir_with_index_and_clone(&workdir);
+    let opts = CleanOpts {
+        all: true,
+        dry_run: true,
+        ..CleanOpts::default()
+    };
+    let listed = clean(workdir.clone(), opts).await.unwrap();
+    assert_eq!(3, listed.len());
+    assert!(workdir.join("crates.csv").exists());
+    assert!(workdir.join("serde").exists());
+}
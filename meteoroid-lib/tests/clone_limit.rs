@@ -0,0 +1,38 @@
+//! Separate from the pipeline tests, the clones are redirected to a local git host through the
+//! process' environment
+mod common;
+
+use common::{Harness, repo_urls, slow_git_server};
+use meteoroid_lib::CrateSource;
+use std::num::NonZeroUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+#[tokio::test]
+async fn clones_respect_their_concurrency_limit() {
+    let (host, most_clones) = slow_git_server(Duration::from_millis(500));
+    // SAFETY: the only test in this binary, nothing else reads the environment concurrently
+    unsafe {
+        std::env::set_var("GIT_CONFIG_COUNT", "1");
+        std::env::set_var("GIT_CONFIG_KEY_0", format!("url.{host}/.insteadOf"));
+        std::env::set_var("GIT_CONFIG_VALUE_0", "https://github.com/");
+    }
+    let harness = Harness::new();
+    harness
+        .run_with(|config| {
+            let mut source = repo_urls(&[
+                "https://github.com/fixture/one",
+                "https://github.com/fixture/two",
+                "https://github.com/fixture/three",
+                "https://github.com/fixture/four",
+                "https://github.com/fixture/five",
+            ]);
+            let CrateSource::GitUrls { git, .. } = &mut source else {
+                unreachable!()
+            };
+            git.git_clone_max_concurrent = NonZeroUsize::new(3).unwrap();
+            config.crate_source = source;
+        })
+        .await;
+    assert_eq!(3, most_clones.load(Ordering::SeqCst));
+}
@@ -0,0 +1,652 @@
+//! Runs the whole pipeline on fixture crates in a temporary directory, with two fake `rustfmt`
+//! binaries in place of local and upstream builds. What the fakes report for a file is decided
+//! by markers in it, `// fixture: <side>-diff` gives a diff and `// fixture: <side>-error`
+//! fails, where `<side>` is `local` or `upstream`. `// fixture: <side>-whitespace-diff` gives a
+//! diff that only removes trailing whitespace, `// fixture: <side>-crlf-diff` gives the same diff
+//! as `<side>-diff` with CRLF line endings. `// fixture: <side>-transient-error` fails the
+//! first time with an environmental error and succeeds after, `// fixture: <side>-panic` panics.
+//! `// fixture: <side>-flaky-diff` gives a diff on every other run, and
+//! `// fixture: <side>-output-diff` changes the `--emit stdout` output without a `--check` diff.
+//! `// fixture: <side>-config-diff` only gives a diff when run with `format_strings=true` in
+//! the config. `// fixture: <side>-slow` takes a second, see `Harness::max_concurrent_slow_runs`.
+//! Every file a fake is run on is logged, see `Harness::rustfmt_runs`, and so are the arguments
+//! of every run, see `Harness::rustfmt_invocations`. The fakes' default
+//! config is empty unless set with `Harness::set_default_config`.
+//! Repos that would be cloned can be added with `Harness::add_cloned_repo`, they're cloned
+//! from a local origin so that nothing is fetched over the network
+// Shared by several test binaries, each only uses some of it
+#![allow(dead_code)]
+use meteoroid_lib::{
+    AnalysisReport, AnalyzeArgs, CargoCommand, ConsumerOpts, CrateReport, CrateSource,
+    GitSyncConfig, LabeledRustfmtRepo, LevenshteinComparator, LocalCratesConfig, MeteroidConfig,
+    MeteroidError, OutputLayout, PrebuiltRustfmt, PublicPrunedCrate, RepoList, ReportFormat,
+    SetupFailurePolicy, StopReceiver, stop_channel,
+};
+use std::fmt::Write;
+use std::io::{BufRead, Write as _};
+use std::num::NonZeroUsize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Ignores everything but `.rs` files, `cargo fmt` passes the crate's entry points
+const FAKE_RUSTFMT: &str = r#"#!/bin/sh
+if [ "$1" = "--print-config" ]; then
+    cat "$0.config" 2>/dev/null
+    exit 0
+fi
+echo "$*" >> "$0.args"
+status=0
+for arg in "$@"; do
+    case "$arg" in
+        *.rs)
+            echo "$arg" >> "$0.runs"
+            if grep -q "fixture: SIDE-slow" "$arg"; then
+                mkdir -p "$0.active"
+                touch "$0.active/$$"
+                ls "$0.active" | wc -l >> "$0.concurrency"
+                sleep 1
+                rm "$0.active/$$"
+            fi
+            if grep -q "fixture: SIDE-transient-error" "$arg" && ! grep -qxF "$arg" "$0.failed" 2>/dev/null; then
+                echo "$arg" >> "$0.failed"
+                echo "error: No space left on device (os error 28)" >&2
+                exit 2
+            fi
+            if grep -q "fixture: SIDE-panic" "$arg"; then
+                echo "thread 'main' panicked at src/fixture.rs:1:1:" >&2
+                echo "fixture panic" >&2
+                exit 101
+            fi
+            if grep -q "fixture: SIDE-error" "$arg"; then
+                echo "error: expected item, found \`!\` in $arg" >&2
+                exit 2
+            fi
+            if grep -q "fixture: SIDE-output-diff" "$arg"; then
+                case " $* " in
+                    *" --emit stdout "*) echo "// formatted by SIDE" ;;
+                esac
+            fi
+            if grep -q "fixture: SIDE-flaky-diff" "$arg"; then
+                if grep -qxF "$arg" "$0.flaky" 2>/dev/null; then
+                    grep -vxF "$arg" "$0.flaky" > "$0.flaky.tmp"
+                    mv "$0.flaky.tmp" "$0.flaky"
+                else
+                    echo "$arg" >> "$0.flaky"
+                    echo "Diff in $arg at line 1:"
+                    echo "-fn  fixture() {}"
+                    echo "+fn fixture() {}"
+                    status=1
+                fi
+            fi
+            if grep -q "fixture: SIDE-config-diff" "$arg"; then
+                case " $* " in
+                    *format_strings=true*)
+                        echo "Diff in $arg at line 1:"
+                        echo "-fn  fixture() {}"
+                        echo "+fn fixture() {}"
+                        status=1
+                        ;;
+                esac
+            fi
+            if grep -q "fixture: SIDE-diff" "$arg"; then
+                echo "Diff in $arg at line 1:"
+                echo "-fn  fixture() {}"
+                echo "+fn fixture() {}"
+                status=1
+            fi
+            if grep -q "fixture: SIDE-crlf-diff" "$arg"; then
+                echo "Diff in $arg at line 1:"
+                printf '%s\r\n' "-fn  fixture() {}" "+fn fixture() {}"
+                status=1
+            fi
+            if grep -q "fixture: SIDE-whitespace-diff" "$arg"; then
+                echo "Diff in $arg at line 1:"
+                echo "-fn fixture() {}   "
+                echo "+fn fixture() {}"
+                status=1
+            fi
+            ;;
+    esac
+done
+exit $status
+"#;
+
+pub struct Harness {
+    dir: tempfile::TempDir,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("crates")).unwrap();
+        for side in ["local", "upstream"] {
+            let bin = dir.path().join(format!("rustfmt-{side}"));
+            std::fs::write(&bin, FAKE_RUSTFMT.replace("SIDE", side)).unwrap();
+            std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        Self { dir }
+    }
+
+    pub fn add_crate(&self, name: &str, lib_rs: &str) -> &Self {
+        let root = self.crates_dir().join(name);
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n"
+            ),
+        )
+        .unwrap();
+        std::fs::write(root.join("src").join("lib.rs"), lib_rs).unwrap();
+        self
+    }
+
+    /// Another file in a crate added with `add_crate`, `path` is relative to the crate root
+    pub fn add_crate_file(&self, name: &str, path: &str, content: &str) -> &Self {
+        let path = self.crates_dir().join(name).join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        self
+    }
+
+    /// What the `side` fake prints for `--print-config default`
+    pub fn set_default_config(&self, side: &str, config: &str) -> &Self {
+        let path = self.dir.path().join(format!("rustfmt-{side}.config"));
+        std::fs::write(path, config).unwrap();
+        self
+    }
+
+    /// A git repo with `files`, as if it had been cloned from `https://github.com/fixture/<name>`
+    /// on an earlier run
+    pub fn add_cloned_repo(&self, name: &str, files: &[(&str, &str)]) -> &Self {
+        let origin = self.dir.path().join("origins").join(name);
+        for (file, content) in files {
+            let path = origin.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        git(&origin, &["init", "--quiet", "--initial-branch=main"]);
+        git(&origin, &["add", "--all"]);
+        git(&origin, &["commit", "--quiet", "--message", "fixture"]);
+        let clone = self.cloned_repo_dir(name);
+        std::fs::create_dir_all(clone.parent().unwrap()).unwrap();
+        git(
+            self.dir.path(),
+            &[
+                "clone",
+                "--quiet",
+                origin.to_str().unwrap(),
+                clone.to_str().unwrap(),
+            ],
+        );
+        self
+    }
+
+    /// Makes the origin of a repo added with `add_cloned_repo` unreachable
+    pub fn remove_origin(&self, name: &str) -> &Self {
+        std::fs::remove_dir_all(self.dir.path().join("origins").join(name)).unwrap();
+        self
+    }
+
+    /// Where a repo added with `add_cloned_repo` is analyzed from
+    pub fn cloned_repo_dir(&self, name: &str) -> PathBuf {
+        self.workdir().join(name)
+    }
+
+    /// A crates index with one published version of each `(id, name, repository)`, fresh enough
+    /// to not be refetched
+    pub fn add_index(&self, crates: &[(u64, &str, &str)]) -> &Self {
+        let mut crates_csv = String::from(
+            "created_at,description,documentation,homepage,id,max_upload_size,readme,name\n",
+        );
+        let mut versions_csv = String::from(
+            "bin_names,categories,checksum,crate_id,crate_size,created_at,description,\
+             documentation,downloads,edition,features,has_lib,homepage,id,keywords,license,links,\
+             num,num_no_build,published_by,repository,rust_version,updated_at,yanked\n",
+        );
+        for (id, name, repository) in crates {
+            writeln!(crates_csv, ",,,,{id},,,{name}").unwrap();
+            writeln!(
+                versions_csv,
+                "{{}},{{}},,{id},30000,2024-01-01 00:00:00,The {name} fixture,,100,2021,{{}},t,,\
+                 {id},{{}},MIT,,0.1.0,0.1.0,,{repository},,2024-01-01 00:00:00,f"
+            )
+            .unwrap();
+        }
+        std::fs::create_dir_all(self.workdir()).unwrap();
+        std::fs::write(self.workdir().join("crates.csv"), crates_csv).unwrap();
+        std::fs::write(self.workdir().join("versions.csv"), versions_csv).unwrap();
+        self
+    }
+
+    pub fn workdir(&self) -> PathBuf {
+        self.dir.path().join("workdir")
+    }
+
+    /// A `cargo` wrapper that records its arguments before running the real `cargo`
+    pub fn cargo_wrapper(&self) -> PathBuf {
+        let bin = self.dir.path().join("cargo-wrapper");
+        std::fs::write(
+            &bin,
+            "#!/bin/sh\necho \"$*\" >> \"$0.args\"\nexec cargo \"$@\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    /// A `rustfmt` repo for `AnalyzeArgs::extra_rustfmt_repos`, whose build is a fake like the
+    /// local and upstream ones with `label` as its side, needs `building_cargo` to be built
+    pub fn add_rustfmt_repo(&self, label: &str) -> LabeledRustfmtRepo {
+        let repo = self.dir.path().join(format!("rustfmt-repo-{label}"));
+        std::fs::create_dir_all(&repo).unwrap();
+        // The toolchain running the tests is installed already
+        std::fs::write(
+            repo.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"stable\"\n",
+        )
+        .unwrap();
+        std::fs::write(repo.join("rustfmt.sh"), FAKE_RUSTFMT.replace("SIDE", label)).unwrap();
+        LabeledRustfmtRepo {
+            label: label.to_string(),
+            repo,
+        }
+    }
+
+    /// A `cargo` that "builds" repos added with `add_rustfmt_repo` by copying their fake,
+    /// anything but a build runs the real `cargo`
+    pub fn building_cargo(&self) -> PathBuf {
+        let bin = self.dir.path().join("building-cargo");
+        std::fs::write(
+            &bin,
+            "#!/bin/sh\nif [ \"$1\" = \"build\" ]; then\n    mkdir -p target/release\n    \
+             cp rustfmt.sh target/release/rustfmt\n    chmod +x target/release/rustfmt\n    \
+             exit 0\nfi\nexec cargo \"$@\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    /// A `cargo` whose builds fail, anything but a build runs the real `cargo`
+    pub fn failing_cargo(&self) -> PathBuf {
+        let bin = self.dir.path().join("failing-cargo");
+        std::fs::write(
+            &bin,
+            "#!/bin/sh\nif [ \"$1\" = \"build\" ]; then\n    echo \"fixture build failure\" >&2\n    \
+             exit 1\nfi\nexec cargo \"$@\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    /// The arguments of each run of the `cargo_wrapper`
+    pub fn cargo_invocations(&self) -> Vec<String> {
+        std::fs::read_to_string(self.dir.path().join("cargo-wrapper.args"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The arguments of each run of the `side` fake
+    pub fn rustfmt_invocations(&self, side: &str) -> Vec<String> {
+        std::fs::read_to_string(self.dir.path().join(format!("rustfmt-{side}.args")))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The most `<side>-slow` runs of the `side` fake that were in progress at once
+    pub fn max_concurrent_slow_runs(&self, side: &str) -> usize {
+        std::fs::read_to_string(self.dir.path().join(format!("rustfmt-{side}.concurrency")))
+            .unwrap_or_default()
+            .lines()
+            .map(|active| active.trim().parse().unwrap())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// How many times the `side` fake was run on the crate's files
+    pub fn rustfmt_runs(&self, side: &str, name: &str) -> usize {
+        self.rustfmt_runs_in(side, &self.crate_dir(name))
+    }
+
+    /// How many times the `side` fake was run on files under `dir`
+    pub fn rustfmt_runs_in(&self, side: &str, dir: &Path) -> usize {
+        let runs = self.dir.path().join(format!("rustfmt-{side}.runs"));
+        std::fs::read_to_string(runs)
+            .unwrap_or_default()
+            .lines()
+            .filter(|file| Path::new(file).starts_with(dir))
+            .count()
+    }
+
+    fn crates_dir(&self) -> PathBuf {
+        self.dir.path().join("crates")
+    }
+
+    /// Where a crate added with `add_crate` is
+    pub fn crate_dir(&self, name: &str) -> PathBuf {
+        self.crates_dir().join(name)
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        self.dir.path().join("output")
+    }
+
+    fn config(&self, stop_receiver: StopReceiver) -> MeteroidConfig {
+        let path = self.dir.path();
+        // Never built, the prebuilt fakes are used instead
+        let unused_repo = path.join("no-rustfmt-repo");
+        MeteroidConfig {
+            workdir: self.workdir(),
+            output_dir: Some(self.output_dir()),
+            consumer_opts: ConsumerOpts::default(),
+            crate_source: CrateSource::LocalCrates(LocalCratesConfig {
+                crate_dir: self.crates_dir(),
+                follow_symlinks: false,
+                skip_dirs: Vec::new(),
+            }),
+            analyze_args: AnalyzeArgs {
+                rustfmt_repo: unused_repo.clone(),
+                rustfmt_upstream_repo: unused_repo,
+                report_dest: None,
+                report_formats: vec![ReportFormat::Json],
+                report_filters: Vec::new(),
+                report_sort: meteoroid_lib::ReportSort::default(),
+                config: None,
+                write_outputs: true,
+                skip_non_diverging_diffs: false,
+                recheck_divergences: 0,
+                bisect_config: false,
+                write_repros: false,
+                diff_tool: None,
+                open_report: false,
+                extra_rustfmt_repos: Vec::new(),
+                compare_formatted_output: false,
+                max_file_bytes: None,
+                unstable_features: false,
+                respect_crate_config: false,
+                ignore_whitespace_diffs: false,
+                analysis_retries: 0,
+                checkpoint: false,
+                output_layout: OutputLayout::Flat,
+                prebuilt_rustfmt: Some(PrebuiltRustfmt {
+                    local: path.join("rustfmt-local"),
+                    upstream: path.join("rustfmt-upstream"),
+                }),
+                error_comparator: Arc::new(LevenshteinComparator),
+                cargo: CargoCommand::default(),
+            },
+            analysis_max_concurrent: NonZeroUsize::new(2).unwrap(),
+            result_buffer: None,
+            analysis_timeout: Duration::from_mins(1),
+            analysis_timeout_scaling: None,
+            ordered_output: false,
+            notify: None,
+            setup_failure_policy: SetupFailurePolicy::FailFast,
+            #[cfg(feature = "s3")]
+            s3_upload: None,
+            #[cfg(feature = "metrics")]
+            metrics_addr: None,
+            stop_receiver,
+        }
+    }
+
+    /// Runs the pipeline and reads back the written `report.json`
+    pub async fn run(&self) -> AnalysisReport {
+        self.run_with(|_| {}).await
+    }
+
+    /// Like `run`, with changes to the default config
+    pub async fn run_with(&self, configure: impl FnOnce(&mut MeteroidConfig)) -> AnalysisReport {
+        self.try_run_with(configure).await.unwrap()
+    }
+
+    /// Like `run_with`, for runs that are expected to fail
+    pub async fn try_run_with(
+        &self,
+        configure: impl FnOnce(&mut MeteroidConfig),
+    ) -> Result<AnalysisReport, MeteroidError> {
+        // Dropping the sender stops the run
+        let (_stop_send, stop_receiver) = stop_channel();
+        let mut config = self.config(stop_receiver);
+        configure(&mut config);
+        meteoroid_lib::meteoroid(config).await?;
+        let report = std::fs::read(self.output_dir().join("report.json")).unwrap();
+        Ok(serde_json::from_slice(&report).unwrap())
+    }
+
+    /// Like `run_with`, returns the names of the crates in the order they were handed to the sink
+    pub async fn run_streamed(&self, configure: impl FnOnce(&mut MeteroidConfig)) -> Vec<String> {
+        let (_stop_send, stop_receiver) = stop_channel();
+        let mut config = self.config(stop_receiver);
+        configure(&mut config);
+        let mut streamed = Vec::new();
+        meteoroid_lib::meteoroid_with_sink(config, |analysis| {
+            streamed.push(analysis.crate_name.clone());
+        })
+        .await
+        .unwrap();
+        streamed
+    }
+
+    /// Like `run_streamed`, but stops the run shortly after the first crate is handed to the sink
+    pub async fn run_stopped_after_first(
+        &self,
+        configure: impl FnOnce(&mut MeteroidConfig),
+    ) -> Vec<String> {
+        let (stop_send, stop_receiver) = stop_channel();
+        let mut config = self.config(stop_receiver);
+        configure(&mut config);
+        let mut stop_send = Some(stop_send);
+        let mut streamed = Vec::new();
+        meteoroid_lib::meteoroid_with_sink(config, |analysis| {
+            streamed.push(analysis.crate_name.clone());
+            if let Some(stop_send) = stop_send.take() {
+                // Leaves the first crate time to be added to the report before stopping
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    stop_send.stop().await;
+                });
+            }
+        })
+        .await
+        .unwrap();
+        streamed
+    }
+
+    /// Runs `fetch-index` with the default selection options
+    pub async fn fetch_index(&self) -> usize {
+        meteoroid_lib::fetch_index(
+            &self.workdir(),
+            &git_sync_config(),
+            &ConsumerOpts::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Only runs crate selection, like `--list-selected`
+    pub async fn select(&self) -> Vec<PublicPrunedCrate> {
+        self.select_with(|_| {}).await
+    }
+
+    /// Like `select`, with changes to the default config
+    pub async fn select_with(
+        &self,
+        configure: impl FnOnce(&mut MeteroidConfig),
+    ) -> Vec<PublicPrunedCrate> {
+        let (_stop_send, stop_receiver) = stop_channel();
+        let mut config = self.config(stop_receiver);
+        configure(&mut config);
+        meteoroid_lib::select_crates_in(
+            &config.workdir,
+            &config.crate_source,
+            &config.consumer_opts,
+        )
+        .await
+        .unwrap()
+    }
+}
+
+/// Selects crates from an index added with `add_index` instead of analyzing local crates
+pub fn index_source() -> CrateSource {
+    CrateSource::GitSync(git_sync_config())
+}
+
+/// Analyzes the repo urls instead of local crates, for repos added with `add_cloned_repo`
+pub fn repo_urls(urls: &[&str]) -> CrateSource {
+    CrateSource::GitUrls {
+        list: RepoList {
+            urls: urls.iter().map(|url| url.parse().unwrap()).collect(),
+            ..RepoList::default()
+        },
+        git: git_sync_config(),
+    }
+}
+
+fn git_sync_config() -> GitSyncConfig {
+    GitSyncConfig {
+        crates_index_max_age_days: 7,
+        git_resync_before: false,
+        git_clone_max_concurrent: NonZeroUsize::new(1).unwrap(),
+        prefetch_depth: None,
+        rustfmt_direct_fallback: false,
+        only_changed_crates: false,
+        refresh_selection: false,
+        warn_repo_size: None,
+        git_token: None,
+        allow_empty_selection: false,
+        unreachable_host: None,
+    }
+}
+
+fn git(cwd: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=fixture",
+            "-c",
+            "user.email=fixture@example.com",
+        ])
+        .args(args)
+        .current_dir(cwd)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed in {}", cwd.display());
+}
+
+/// A request received by `mock_http_server`
+pub struct MockRequest {
+    /// Ex: `PUT /bucket/key HTTP/1.1`
+    pub request_line: String,
+    /// Lowercased names
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Answers `requests` requests on localhost with an empty `200 OK`, the handle gives back what
+/// was received. The url is the server's root, without a trailing slash
+pub fn mock_http_server(requests: usize) -> (String, std::thread::JoinHandle<Vec<MockRequest>>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        (0..requests)
+            .map(|_| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream);
+                let request = read_request(&mut reader);
+                reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                request
+            })
+            .collect()
+    });
+    (url, handle)
+}
+
+/// A git host on localhost that holds every clone for `hold` before refusing it, the counter
+/// ends up at the most clones that were in progress at once. The url is the server's root,
+/// without a trailing slash
+pub fn slow_git_server(hold: Duration) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let active = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let most_seen = most.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = std::io::BufReader::new(stream.unwrap());
+            let active = active.clone();
+            let most = most.clone();
+            std::thread::spawn(move || {
+                read_request(&mut reader);
+                most.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(hold);
+                active.fetch_sub(1, Ordering::SeqCst);
+                let _ = reader.get_mut().write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            });
+        }
+    });
+    (url, most_seen)
+}
+
+fn read_request(reader: &mut impl BufRead) -> MockRequest {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let len = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map_or(0, |(_, len)| len.parse().unwrap());
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+    MockRequest {
+        request_line: request_line.trim_end().to_string(),
+        headers,
+        body,
+    }
+}
+
+pub fn crate_report<'a>(report: &'a AnalysisReport, name: &str) -> &'a CrateReport {
+    report
+        .crate_reports
+        .iter()
+        .find(|cr| cr.crate_name.to_string() == name)
+        .unwrap_or_else(|| panic!("no report for {name}"))
+}
+
+pub fn read_output(path: Option<&Path>) -> String {
+    std::fs::read_to_string(path.expect("output file should be written")).unwrap()
+}
@@ -0,0 +1,1609 @@
+mod common;
+
+use common::{Harness, crate_report, index_source, mock_http_server, read_output, repo_urls};
+use meteoroid_lib::{
+    AnalysisReport, CargoCommand, CrateOrigin, CrateSource, DefaultConfigChange, DivergingDiff,
+    ElapsedByOutcome, ErrorComparator, FmtOutcome, MeteroidConfig, MeteroidError, NotifyOpts,
+    OutputLayout, RenderOpts, ReportFilter, ReportFormat, ReportPercentages, ReportSort,
+    SetupFailurePolicy, UnreachableHostPolicy, read_allowlist, render_report, unpack,
+};
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn clean_crate_doesnt_diverge() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(0, report.num_diverging_diffs);
+    assert_eq!(1, report.num_local_successes);
+    assert_eq!(1, report.num_upstream_successes);
+    let cr = crate_report(&report, "clean");
+    assert!(!cr.diverged);
+    assert_eq!(DivergingDiff::None, cr.divergence);
+    assert_eq!(FmtOutcome::Success, cr.local_rustfmt_output.outcome);
+    assert_eq!(FmtOutcome::Success, cr.upstream_rustfmt_output.outcome);
+}
+
+#[tokio::test]
+async fn local_only_diff_diverges() {
+    let harness = Harness::new();
+    harness.add_crate("diverged", "// fixture: local-diff\npub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(1, report.num_diverging_diffs);
+    assert_eq!(1, report.num_local_diffs);
+    assert_eq!(1, report.num_upstream_successes);
+    let cr = crate_report(&report, "diverged");
+    assert!(cr.diverged);
+    assert_eq!(DivergingDiff::LocalOnly, cr.divergence);
+    assert_eq!(FmtOutcome::Diff, cr.local_rustfmt_output.outcome);
+    let diff = read_output(cr.local_rustfmt_output.diff_output_file.as_deref());
+    assert!(diff.contains("+fn fixture() {}"), "unexpected diff: {diff}");
+}
+
+#[tokio::test]
+async fn same_diff_on_both_sides_doesnt_diverge() {
+    let harness = Harness::new();
+    harness.add_crate(
+        "both",
+        "// fixture: local-diff\n// fixture: upstream-diff\npub fn fixture() {}\n",
+    );
+    let report = harness.run().await;
+    assert_eq!(0, report.num_diverging_diffs);
+    let cr = crate_report(&report, "both");
+    assert_eq!(DivergingDiff::None, cr.divergence);
+    assert_eq!(FmtOutcome::Diff, cr.local_rustfmt_output.outcome);
+    assert_eq!(FmtOutcome::Diff, cr.upstream_rustfmt_output.outcome);
+}
+
+#[tokio::test]
+async fn local_error_is_reported() {
+    let harness = Harness::new();
+    harness.add_crate("broken", "// fixture: local-error\npub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(1, report.num_local_failures);
+    assert_eq!(0, report.num_upstream_failures);
+    assert_eq!(1, report.num_local_only_failures);
+    let cr = crate_report(&report, "broken");
+    assert_eq!(FmtOutcome::Failure, cr.local_rustfmt_output.outcome);
+    assert_eq!(FmtOutcome::Success, cr.upstream_rustfmt_output.outcome);
+    let error = read_output(cr.local_rustfmt_output.error_output_file.as_deref());
+    assert!(error.contains("expected item"), "unexpected error: {error}");
+}
+
+#[tokio::test]
+async fn every_crate_is_reported() {
+    let harness = Harness::new();
+    harness
+        .add_crate("a-clean", "pub fn fixture() {}\n")
+        .add_crate(
+            "b-diverged",
+            "// fixture: upstream-diff\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "c-broken",
+            "// fixture: upstream-error\npub fn fixture() {}\n",
+        );
+    let report = harness.run().await;
+    assert_eq!(3, report.crate_reports.len());
+    assert_eq!(
+        DivergingDiff::UpstreamOnly,
+        crate_report(&report, "b-diverged").divergence
+    );
+    assert_eq!(
+        FmtOutcome::Failure,
+        crate_report(&report, "c-broken")
+            .upstream_rustfmt_output
+            .outcome
+    );
+}
+
+#[tokio::test]
+async fn alternating_divergence_is_flaky() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "alternating",
+            "// fixture: local-flaky-diff\npub fn fixture() {}\n",
+        )
+        .add_crate("steady", "// fixture: local-diff\npub fn fixture() {}\n");
+    let report = harness
+        .run_with(|config| config.analyze_args.recheck_divergences = 2)
+        .await;
+    assert_eq!(2, report.num_diverging_diffs);
+    assert_eq!(1, report.num_flaky_divergences);
+    assert!(crate_report(&report, "alternating").flaky);
+    assert!(!crate_report(&report, "steady").flaky);
+}
+
+#[tokio::test]
+async fn percentages_are_of_analyzed_crates() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "a-diverged",
+            "// fixture: local-diff\npub fn fixture() {}\n",
+        )
+        .add_crate("b-broken", "// fixture: local-error\npub fn fixture() {}\n")
+        .add_crate(
+            "c-both-broken",
+            "// fixture: local-error\n// fixture: upstream-error\npub fn fixture() {}\n",
+        )
+        .add_crate("d-clean", "pub fn fixture() {}\n");
+    let report = harness.run().await;
+    let expected = ReportPercentages {
+        diverged: 25.0,
+        local_only_failures: 25.0,
+        similar_errors: 25.0,
+    };
+    assert_eq!(expected, report.percentages);
+}
+
+#[tokio::test]
+async fn percentages_are_zero_without_analyzed_crates() {
+    let harness = Harness::new();
+    let report = harness.run().await;
+    assert!(report.crate_reports.is_empty());
+    assert_eq!(ReportPercentages::default(), report.percentages);
+}
+
+#[tokio::test]
+async fn divergences_are_broken_down_by_edition() {
+    let harness = Harness::new();
+    for (name, edition, lib_rs) in [
+        ("a", "2018", "// fixture: local-diff\npub fn fixture() {}\n"),
+        ("b", "2018", "pub fn fixture() {}\n"),
+        (
+            "c",
+            "2024",
+            "// fixture: upstream-diff\npub fn fixture() {}\n",
+        ),
+        ("d", "2021", "pub fn fixture() {}\n"),
+    ] {
+        harness.add_crate(name, lib_rs).add_crate_file(
+            name,
+            "Cargo.toml",
+            &format!(
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"{edition}\"\n\n[workspace]\n"
+            ),
+        );
+    }
+    let report = harness.run().await;
+    let by_edition = report
+        .by_edition
+        .iter()
+        .map(|(edition, counters)| {
+            (
+                edition.as_str(),
+                (counters.num_analyzed, counters.num_diverging_diffs),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        [("2018", (2, 1)), ("2021", (1, 0)), ("2024", (1, 1))],
+        by_edition.as_slice()
+    );
+}
+
+#[tokio::test]
+async fn report_filters_keep_only_matching_crates() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "local-failure",
+            "// fixture: local-error\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "upstream-failure",
+            "// fixture: upstream-error\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "local-diff",
+            "// fixture: local-diff\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "upstream-diff",
+            "// fixture: upstream-diff\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "diff-between",
+            "// fixture: local-diff\n// fixture: upstream-whitespace-diff\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "formatted-output",
+            "// fixture: local-output-diff\npub fn fixture() {}\n",
+        )
+        .add_crate("clean", "pub fn fixture() {}\n");
+    for (filter, name) in [
+        (ReportFilter::LocalOnlyFailure, "local-failure"),
+        (ReportFilter::UpstreamOnlyFailure, "upstream-failure"),
+        (ReportFilter::LocalOnlyDiff, "local-diff"),
+        (ReportFilter::UpstreamOnlyDiff, "upstream-diff"),
+        (ReportFilter::DiffBetween, "diff-between"),
+        (ReportFilter::FormattedOutput, "formatted-output"),
+    ] {
+        let report = harness
+            .run_with(|config| {
+                config.analyze_args.compare_formatted_output = true;
+                config.analyze_args.report_filters = vec![filter];
+            })
+            .await;
+        let names = report
+            .crate_reports
+            .iter()
+            .map(|cr| cr.crate_name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            [name],
+            names.as_slice(),
+            "unexpected crates with {filter:?}"
+        );
+        // The counters are of every crate
+        assert_eq!(
+            7,
+            report.num_upstream_successes
+                + report.num_upstream_diffs
+                + report.num_upstream_failures
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_similar_failures_are_expected() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "both-broken",
+            "// fixture: local-error\n// fixture: upstream-error\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "local-broken",
+            "// fixture: local-error\npub fn fixture() {}\n",
+        );
+    let report = harness.run().await;
+    let expected = report
+        .expected_failures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["both-broken".to_string()], expected);
+    assert!(crate_report(&report, "both-broken").similar_errors);
+    assert!(!crate_report(&report, "local-broken").similar_errors);
+}
+
+struct AlwaysSimilar;
+
+impl ErrorComparator for AlwaysSimilar {
+    fn similar(&self, _a: &str, _b: &str) -> bool {
+        true
+    }
+}
+
+#[tokio::test]
+async fn custom_error_comparator_decides_similarity() {
+    let harness = Harness::new();
+    harness.add_crate(
+        "differently-broken",
+        "// fixture: local-error\n// fixture: upstream-panic\npub fn fixture() {}\n",
+    );
+    let report = harness.run().await;
+    assert!(!crate_report(&report, "differently-broken").similar_errors);
+    let report = harness
+        .run_with(|config| {
+            config.analyze_args.error_comparator = Arc::new(AlwaysSimilar);
+        })
+        .await;
+    assert_eq!(1, report.num_similar_errors);
+    assert!(crate_report(&report, "differently-broken").similar_errors);
+}
+
+#[tokio::test]
+async fn repro_bundle_only_has_the_diverging_files() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "diverged",
+            "// fixture: local-diff\nmod clean;\npub fn fixture() {}\n",
+        )
+        .add_crate_file("diverged", "src/clean.rs", "pub fn clean() {}\n");
+    let report = harness
+        .run_with(|config| config.analyze_args.write_repros = true)
+        .await;
+    let repro_dir = crate_report(&report, "diverged").repro_dir.clone().unwrap();
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![repro_dir.clone()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.insert(path.strip_prefix(&repro_dir).unwrap().to_path_buf());
+            }
+        }
+    }
+    let expected = ["README.md", "src/lib.rs"]
+        .into_iter()
+        .map(std::path::PathBuf::from)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(expected, files);
+}
+
+#[tokio::test]
+async fn sarif_report_has_a_result_per_divergence() {
+    let harness = Harness::new();
+    harness
+        .add_crate("a-local", "// fixture: local-diff\npub fn fixture() {}\n")
+        .add_crate(
+            "b-upstream",
+            "// fixture: upstream-diff\npub fn fixture() {}\n",
+        )
+        .add_crate("c-clean", "pub fn fixture() {}\n");
+    harness
+        .run_with(|config| {
+            config.analyze_args.report_formats.push(ReportFormat::Sarif);
+        })
+        .await;
+    let sarif = std::fs::read(harness.output_dir().join("report.sarif")).unwrap();
+    let sarif: serde_json::Value = serde_json::from_slice(&sarif).unwrap();
+    assert_eq!("2.1.0", sarif["version"]);
+    assert!(sarif["$schema"].as_str().unwrap().contains("sarif"));
+    let run = &sarif["runs"][0];
+    assert_eq!("meteoroid", run["tool"]["driver"]["name"]);
+    let rule_ids = run["tool"]["driver"]["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rule| rule["id"].as_str().unwrap())
+        .collect::<BTreeSet<_>>();
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(2, results.len());
+    for result in results {
+        assert!(rule_ids.contains(result["ruleId"].as_str().unwrap()));
+        assert_eq!("warning", result["level"]);
+        assert!(!result["message"]["text"].as_str().unwrap().is_empty());
+        let uri = &result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"];
+        assert!(!uri.as_str().unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn line_ending_only_divergence_is_tagged() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "crlf",
+            "// fixture: local-crlf-diff\n// fixture: upstream-diff\npub fn fixture() {}\n",
+        )
+        .add_crate(
+            "content",
+            "// fixture: local-diff\n// fixture: upstream-whitespace-diff\npub fn fixture() {}\n",
+        );
+    let report = harness.run().await;
+    assert_eq!(2, report.num_diverging_diffs);
+    assert_eq!(1, report.num_line_ending_divergences);
+    let crlf = crate_report(&report, "crlf");
+    assert_eq!(DivergingDiff::DiffBetween, crlf.divergence);
+    assert!(crlf.line_ending_divergence);
+    assert!(!crate_report(&report, "content").line_ending_divergence);
+}
+
+#[tokio::test]
+async fn whitespace_only_divergence_can_be_ignored() {
+    let harness = Harness::new();
+    harness.add_crate(
+        "trailing",
+        "// fixture: local-whitespace-diff\npub fn fixture() {}\n",
+    );
+    let report = harness.run().await;
+    assert_eq!(1, report.num_diverging_diffs);
+    let report = harness
+        .run_with(|config| config.analyze_args.ignore_whitespace_diffs = true)
+        .await;
+    assert_eq!(0, report.num_diverging_diffs);
+    let cr = crate_report(&report, "trailing");
+    assert_eq!(DivergingDiff::None, cr.divergence);
+    // Still written as is
+    let diff = read_output(cr.local_rustfmt_output.diff_output_file.as_deref());
+    assert!(
+        diff.contains("-fn fixture() {}   "),
+        "unexpected diff: {diff}"
+    );
+}
+
+#[tokio::test]
+async fn spacing_divergence_is_kept_when_ignoring_whitespace() {
+    let harness = Harness::new();
+    // `-fn  fixture() {}` against `+fn fixture() {}`, the spacing within the line changes
+    harness.add_crate("spacing", "// fixture: local-diff\npub fn fixture() {}\n");
+    let report = harness
+        .run_with(|config| config.analyze_args.ignore_whitespace_diffs = true)
+        .await;
+    assert_eq!(1, report.num_diverging_diffs);
+    assert_eq!(
+        DivergingDiff::LocalOnly,
+        crate_report(&report, "spacing").divergence
+    );
+}
+
+#[tokio::test]
+async fn environmental_failure_is_retried() {
+    let harness = Harness::new();
+    harness.add_crate(
+        "transient",
+        "// fixture: local-transient-error\npub fn fixture() {}\n",
+    );
+    let report = harness
+        .run_with(|config| config.analyze_args.analysis_retries = 2)
+        .await;
+    let cr = crate_report(&report, "transient");
+    assert_eq!(FmtOutcome::Success, cr.local_rustfmt_output.outcome);
+    assert_eq!(0, report.num_local_failures);
+    assert_eq!(2, harness.rustfmt_runs("local", "transient"));
+}
+
+#[tokio::test]
+async fn rustfmt_panic_is_not_retried() {
+    let harness = Harness::new();
+    harness.add_crate("panics", "// fixture: local-panic\npub fn fixture() {}\n");
+    let report = harness
+        .run_with(|config| config.analyze_args.analysis_retries = 2)
+        .await;
+    let cr = crate_report(&report, "panics");
+    assert_eq!(FmtOutcome::Panic, cr.local_rustfmt_output.outcome);
+    assert_eq!(1, harness.rustfmt_runs("local", "panics"));
+}
+
+#[tokio::test]
+async fn selected_crates_serialize_to_the_listed_shape() {
+    let harness = Harness::new();
+    harness.add_crate("listed", "pub fn fixture() {}\n");
+    let selected = harness.select().await;
+    let json = serde_json::to_value(&selected).unwrap();
+    let listed = json.as_array().unwrap();
+    assert_eq!(1, listed.len());
+    let cr = listed[0].as_object().unwrap();
+    for key in ["name", "repo", "downloads", "size", "edition"] {
+        assert!(cr.contains_key(key), "missing '{key}' in {json}");
+    }
+    assert_eq!("listed", cr["name"]);
+    assert_eq!("2021", cr["edition"]);
+    // Only known for crates from the index
+    assert!(cr["downloads"].is_null());
+    assert!(cr["size"].is_null());
+}
+
+#[tokio::test]
+async fn selection_from_an_index_returns_the_indexed_crates() {
+    let harness = Harness::new();
+    harness.add_index(&[
+        (1, "first", "https://github.com/fixture/first"),
+        (2, "second", "https://github.com/fixture/second"),
+    ]);
+    let mut selected = harness
+        .select_with(|config| config.crate_source = index_source())
+        .await;
+    selected.sort_by(|a, b| a.crate_name.cmp(&b.crate_name));
+    let names = selected
+        .iter()
+        .map(|c| c.crate_name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["first", "second"], names);
+    for cr in &selected {
+        assert_eq!(
+            Some(format!("https://github.com/fixture/{}", cr.crate_name)),
+            cr.repository
+        );
+        assert_eq!(Some(100), cr.downloads);
+        assert_eq!(Some(30000), cr.crate_size);
+        assert_eq!(Some("0.1.0"), cr.version.as_deref());
+    }
+    // Selecting doesn't clone anything
+    assert!(!harness.cloned_repo_dir("first").exists());
+}
+
+// Neither the root nor the subdirectory crate diffs on its own, so a divergence shows which ran
+const MONOREPO: &[(&str, &str)] = &[
+    (
+        "Cargo.toml",
+        "[package]\nname = \"root\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    ),
+    (
+        "src/lib.rs",
+        "// fixture: upstream-diff\npub fn fixture() {}\n",
+    ),
+    (
+        "inner/Cargo.toml",
+        "[package]\nname = \"inner\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    ),
+    (
+        "inner/src/lib.rs",
+        "// fixture: local-diff\npub fn fixture() {}\n",
+    ),
+];
+
+#[tokio::test]
+async fn repo_subdirectory_is_formatted() {
+    let harness = Harness::new();
+    harness.add_cloned_repo("mono", MONOREPO);
+    let report = harness
+        .run_with(|config| {
+            config.crate_source = repo_urls(&["https://github.com/fixture/mono/tree/main/inner"]);
+        })
+        .await;
+    let cr = crate_report(&report, "mono");
+    assert_eq!(DivergingDiff::LocalOnly, cr.divergence);
+    let inner = harness.cloned_repo_dir("mono").join("inner");
+    assert_eq!(1, harness.rustfmt_runs_in("local", &inner));
+    assert_eq!(
+        1,
+        harness.rustfmt_runs_in("local", &harness.cloned_repo_dir("mono"))
+    );
+}
+
+#[tokio::test]
+async fn unreachable_host_skips_its_remaining_crates() {
+    let harness = Harness::new();
+    harness.add_cloned_repo("reachable", MONOREPO);
+    for name in ["first", "second", "third"] {
+        harness.add_cloned_repo(name, MONOREPO).remove_origin(name);
+    }
+    let report = harness
+        .run_with(|config| {
+            let mut source = repo_urls(&[
+                "https://github.com/fixture/reachable",
+                "https://github.com/fixture/first",
+                "https://github.com/fixture/second",
+                "https://github.com/fixture/third",
+            ]);
+            let CrateSource::GitUrls { git, .. } = &mut source else {
+                unreachable!()
+            };
+            git.unreachable_host = Some(UnreachableHostPolicy {
+                failure_threshold: NonZeroUsize::new(2).unwrap(),
+                cooldown: Duration::from_mins(10),
+            });
+            config.crate_source = source;
+        })
+        .await;
+    let skipped = report
+        .skipped_unreachable
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["third".to_string()], skipped);
+    assert_eq!(1, report.crate_reports.len());
+    crate_report(&report, "reachable");
+}
+
+#[tokio::test]
+async fn crates_sharing_an_unreachable_host_are_all_skipped() {
+    let harness = Harness::new();
+    let names = ["a", "b", "c", "d", "e", "f"];
+    for name in names {
+        harness.add_cloned_repo(name, MONOREPO).remove_origin(name);
+    }
+    let urls = names.map(|name| format!("https://github.com/fixture/{name}"));
+    let report = harness
+        .run_with(|config| {
+            let mut source = repo_urls(&urls.iter().map(String::as_str).collect::<Vec<_>>());
+            let CrateSource::GitUrls { git, .. } = &mut source else {
+                unreachable!()
+            };
+            git.git_clone_max_concurrent = NonZeroUsize::new(3).unwrap();
+            git.unreachable_host = Some(UnreachableHostPolicy {
+                failure_threshold: NonZeroUsize::new(2).unwrap(),
+                cooldown: Duration::from_mins(10),
+            });
+            config.crate_source = source;
+        })
+        .await;
+    // Only the failures that made the host unreachable count as clone failures, whether the
+    // rest were still queued or already cloning when it happened
+    assert_eq!(names.len() - 2, report.skipped_unreachable.len());
+    assert!(report.crate_reports.is_empty());
+}
+
+#[tokio::test]
+async fn missing_repo_subdirectory_falls_back_to_the_root() {
+    let harness = Harness::new();
+    harness.add_cloned_repo("mono", MONOREPO);
+    let report = harness
+        .run_with(|config| {
+            config.crate_source = repo_urls(&["https://github.com/fixture/mono/tree/main/missing"]);
+        })
+        .await;
+    let cr = crate_report(&report, "mono");
+    assert_eq!(DivergingDiff::UpstreamOnly, cr.divergence);
+    let inner = harness.cloned_repo_dir("mono").join("inner");
+    assert_eq!(0, harness.rustfmt_runs_in("local", &inner));
+}
+
+#[tokio::test]
+async fn fetch_index_caches_the_selection_without_cloning() {
+    let harness = Harness::new();
+    harness.add_index(&[
+        (1, "indexed", "https://github.com/fixture/indexed"),
+        (2, "no-repo", ""),
+    ]);
+    assert_eq!(1, harness.fetch_index().await);
+    assert!(harness.workdir().join("selection.json").exists());
+    assert!(!harness.cloned_repo_dir("indexed").exists());
+}
+
+#[tokio::test]
+async fn custom_cargo_runs_cargo_fmt() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let bin = harness.cargo_wrapper();
+    let report = harness
+        .run_with(|config| {
+            config.analyze_args.cargo = CargoCommand {
+                bin,
+                args: vec!["--offline".to_string()],
+            };
+        })
+        .await;
+    assert_eq!(1, report.num_local_successes);
+    let invocations = harness.cargo_invocations();
+    assert_eq!(2, invocations.len(), "{invocations:?}");
+    for args in invocations {
+        assert!(args.starts_with("--offline fmt --all --check"), "{args}");
+    }
+}
+
+#[tokio::test]
+async fn empty_selection_fails_unless_allowed() {
+    let harness = Harness::new();
+    harness.add_index(&[(1, "indexed", "https://github.com/fixture/indexed")]);
+    let select_nothing = |allow_empty_selection: bool| {
+        move |config: &mut MeteroidConfig| {
+            let mut source = index_source();
+            let CrateSource::GitSync(git) = &mut source else {
+                unreachable!()
+            };
+            git.allow_empty_selection = allow_empty_selection;
+            config.crate_source = source;
+            config.consumer_opts.only_crate_names = BTreeSet::from(["indexd".to_string()]);
+        }
+    };
+    let Err(err) = harness.try_run_with(select_nothing(false)).await else {
+        panic!("selecting no crates should fail the run");
+    };
+    let message = unpack(&err).to_string();
+    assert!(message.contains("no crates were selected"), "{message}");
+    assert!(message.contains("allowlist: indexd"), "{message}");
+    let report = harness.run_with(select_nothing(true)).await;
+    assert!(report.crate_reports.is_empty());
+}
+
+#[tokio::test]
+async fn changed_default_config_is_recorded() {
+    let harness = Harness::new();
+    harness
+        .add_crate("clean", "pub fn fixture() {}\n")
+        .set_default_config(
+            "upstream",
+            "max_width = 100\nhard_tabs = false\nversion = \"One\"\n",
+        )
+        .set_default_config(
+            "local",
+            "max_width = 120\nhard_tabs = false\nstyle_edition = \"2015\"\n",
+        );
+    let report = harness.run().await;
+    let change = |option: &str, upstream: Option<&str>, local: Option<&str>| DefaultConfigChange {
+        option: option.to_string(),
+        upstream: upstream.map(ToString::to_string),
+        local: local.map(ToString::to_string),
+    };
+    assert_eq!(
+        Some(vec![
+            change("max_width", Some("100"), Some("120")),
+            change("style_edition", None, Some("\"2015\"")),
+            change("version", Some("\"One\""), None),
+        ]),
+        report.default_config_changes
+    );
+    let diff = std::fs::read_to_string(harness.output_dir().join("config-diff.txt")).unwrap();
+    assert!(
+        diff.contains("-max_width = 100\n+max_width = 120\n"),
+        "unexpected diff: {diff}"
+    );
+    assert!(!diff.contains("hard_tabs"), "unexpected diff: {diff}");
+}
+
+#[tokio::test]
+async fn render_applies_the_sort_to_a_saved_report() {
+    let harness = Harness::new();
+    harness
+        .add_crate("aaa", "// fixture: local-diff\npub fn fixture() {}\n")
+        .add_crate("zzz", "// fixture: local-panic\npub fn fixture() {}\n");
+    harness.run().await;
+    let rendered = harness.output_dir().join("rendered");
+    // Panics first
+    for (sort, name_order) in [(ReportSort::Severity, false), (ReportSort::Name, true)] {
+        render_report(RenderOpts {
+            report: harness.output_dir().join("report.json"),
+            output_dir: Some(rendered.clone()),
+            formats: vec![ReportFormat::Html],
+            filters: Vec::new(),
+            sort,
+            open: false,
+        })
+        .await
+        .unwrap();
+        let html = std::fs::read_to_string(rendered.join("report.html")).unwrap();
+        assert_eq!(
+            name_order,
+            html.find("aaa").unwrap() < html.find("zzz").unwrap(),
+            "unexpected order with {sort:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn render_summarizes_only_the_filtered_crates() {
+    let harness = Harness::new();
+    harness
+        .add_crate("diverged", "// fixture: local-diff\npub fn fixture() {}\n")
+        .add_crate("broken", "// fixture: local-error\npub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(1, report.num_local_only_failures);
+    let rendered = harness.output_dir().join("rendered");
+    render_report(RenderOpts {
+        report: harness.output_dir().join("report.json"),
+        output_dir: Some(rendered.clone()),
+        formats: vec![ReportFormat::Html],
+        filters: vec![ReportFilter::LocalOnlyFailure],
+        sort: ReportSort::Name,
+        open: false,
+    })
+    .await
+    .unwrap();
+    let html = std::fs::read_to_string(rendered.join("report.html")).unwrap();
+    // Failed on local only, of the one crate left
+    assert!(html.contains("<div class=\"stat-value\">100.0%</div>"));
+    assert!(!html.contains("<div class=\"stat-value\">50.0%</div>"));
+}
+
+#[tokio::test]
+async fn version_and_description_reach_the_report() {
+    let harness = Harness::new();
+    harness
+        .add_crate("local", "pub fn fixture() {}\n")
+        .add_crate_file(
+            "local",
+            "Cargo.toml",
+            "[package]\nname = \"local\"\nversion = \"1.2.3\"\nedition = \"2021\"\n\
+         description = \"A local fixture\"\n\n[workspace]\n",
+        );
+    let report = harness.run().await;
+    let cr = crate_report(&report, "local");
+    assert_eq!(Some("1.2.3"), cr.version.as_deref());
+    assert_eq!(Some("A local fixture"), cr.description.as_deref());
+
+    let harness = Harness::new();
+    harness
+        .add_index(&[(1, "indexed", "https://github.com/fixture/indexed")])
+        .add_cloned_repo(
+            "indexed",
+            &[
+                (
+                    "Cargo.toml",
+                    "[package]\nname = \"indexed\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                     [workspace]\n",
+                ),
+                ("src/lib.rs", "pub fn fixture() {}\n"),
+            ],
+        );
+    let report = harness
+        .run_with(|config| config.crate_source = index_source())
+        .await;
+    let cr = crate_report(&report, "indexed");
+    assert_eq!(Some("0.1.0"), cr.version.as_deref());
+    assert_eq!(Some("The indexed fixture"), cr.description.as_deref());
+}
+
+#[tokio::test]
+async fn config_bisect_isolates_the_triggering_option() {
+    let harness = Harness::new();
+    harness.add_crate(
+        "configured",
+        "// fixture: local-config-diff\npub fn fixture() {}\n",
+    );
+    let report = harness
+        .run_with(|config| {
+            config.analyze_args.config = Some(
+                "max_width=80,format_strings=true,wrap_comments=true,tab_spaces=2".to_string(),
+            );
+            config.analyze_args.bisect_config = true;
+        })
+        .await;
+    let cr = crate_report(&report, "configured");
+    assert_eq!(DivergingDiff::LocalOnly, cr.divergence);
+    assert_eq!(
+        Some(vec!["format_strings=true".to_string()]),
+        cr.triggering_config
+    );
+}
+
+#[tokio::test]
+async fn failed_builds_abort_unless_keeping_going() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let broken = harness.add_rustfmt_repo("broken");
+    let configure = |config: &mut MeteroidConfig, policy| {
+        config.analyze_args.extra_rustfmt_repos = vec![broken.clone()];
+        config.analyze_args.cargo = CargoCommand {
+            bin: harness.failing_cargo(),
+            args: Vec::new(),
+        };
+        config.setup_failure_policy = policy;
+    };
+    let fail_fast = harness
+        .try_run_with(|config| configure(config, SetupFailurePolicy::FailFast))
+        .await
+        .err();
+    assert!(
+        matches!(fail_fast, Some(MeteroidError::Setup(_))),
+        "{fail_fast:?}"
+    );
+    let report = harness
+        .try_run_with(|config| configure(config, SetupFailurePolicy::KeepGoing))
+        .await
+        .unwrap();
+    assert_eq!(1, report.setup_failures.len());
+    assert!(
+        report.setup_failures[0].contains("broken rustfmt failed to build"),
+        "{:?}",
+        report.setup_failures
+    );
+    let cr = crate_report(&report, "clean");
+    assert!(cr.build_agreement.is_none());
+}
+
+#[tokio::test]
+async fn builds_that_agree_share_a_class() {
+    let harness = Harness::new();
+    harness.add_crate("split", "// fixture: local-diff\npub fn fixture() {}\n");
+    let release = harness.add_rustfmt_repo("release");
+    let report = harness
+        .run_with(|config| {
+            config.analyze_args.extra_rustfmt_repos = vec![release];
+            config.analyze_args.cargo = CargoCommand {
+                bin: harness.building_cargo(),
+                args: Vec::new(),
+            };
+        })
+        .await;
+    let agreement = crate_report(&report, "split")
+        .build_agreement
+        .as_ref()
+        .expect("extra builds should be compared");
+    let outcomes = agreement
+        .outcomes
+        .iter()
+        .map(|o| (o.label.as_str(), o.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("local", FmtOutcome::Diff),
+            ("upstream", FmtOutcome::Success),
+            ("release", FmtOutcome::Success),
+        ],
+        outcomes
+    );
+    assert_eq!(
+        vec![
+            vec!["local".to_string()],
+            vec!["upstream".to_string(), "release".to_string()],
+        ],
+        agreement.classes
+    );
+}
+
+#[tokio::test]
+async fn webhook_is_sent_the_divergence_summary() {
+    let harness = Harness::new();
+    harness
+        .add_crate("diverges", "// fixture: local-diff\npub fn fixture() {}\n")
+        .add_crate("formatted", "pub fn fixture() {}\n");
+    let (url, server) = mock_http_server(1);
+    harness
+        .run_with(|config| {
+            config.notify = Some(NotifyOpts {
+                webhook: format!("{url}/hook").parse().unwrap(),
+                always: false,
+            });
+        })
+        .await;
+    let requests = server.join().unwrap();
+    assert_eq!("POST /hook HTTP/1.1", requests[0].request_line);
+    assert_eq!(Some("application/json"), requests[0].header("content-type"));
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let message = "meteoroid: 1 diverging diffs in 2 analyzed crates: diverges";
+    assert_eq!(message, body["text"]);
+    assert_eq!(message, body["content"]);
+}
+
+#[tokio::test]
+async fn manifest_less_repo_is_formatted_directly_with_each_files_edition() {
+    let harness = Harness::new();
+    harness.add_cloned_repo(
+        "loose",
+        &[
+            (
+                "src/lib.rs",
+                "// fixture: local-diff\npub fn fixture() {}\n",
+            ),
+            (
+                "old/Cargo.toml",
+                "[package]\nname = \"old\"\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+            ),
+            ("old/src/lib.rs", "pub fn fixture() {}\n"),
+        ],
+    );
+    let report = harness
+        .run_with(|config| {
+            let mut source = repo_urls(&["https://github.com/fixture/loose"]);
+            let CrateSource::GitUrls { git, .. } = &mut source else {
+                unreachable!()
+            };
+            git.rustfmt_direct_fallback = true;
+            config.crate_source = source;
+        })
+        .await;
+    assert_eq!(
+        DivergingDiff::LocalOnly,
+        crate_report(&report, "loose").divergence
+    );
+    let mut runs = harness
+        .rustfmt_invocations("local")
+        .into_iter()
+        .filter(|args| args.contains("--check"))
+        .collect::<Vec<_>>();
+    runs.sort();
+    assert_eq!(
+        vec![
+            "--edition 2018 --check old/src/lib.rs".to_string(),
+            "--edition 2021 --check src/lib.rs".to_string(),
+        ],
+        runs
+    );
+}
+
+#[tokio::test]
+async fn formatted_outputs_diverge_where_the_diffs_match() {
+    let harness = Harness::new();
+    harness
+        .add_crate(
+            "rewritten",
+            "// fixture: local-output-diff\npub fn fixture() {}\n",
+        )
+        .add_crate_file("rewritten", "src/other.rs", "pub fn other() {}\n");
+    let report = harness.run().await;
+    assert_eq!(
+        DivergingDiff::None,
+        crate_report(&report, "rewritten").divergence
+    );
+    let report = harness
+        .run_with(|config| config.analyze_args.compare_formatted_output = true)
+        .await;
+    let cr = crate_report(&report, "rewritten");
+    assert_eq!(DivergingDiff::FormattedOutput, cr.divergence);
+    assert_eq!(
+        vec![std::path::PathBuf::from("src/lib.rs")],
+        cr.output_diverged_files
+    );
+}
+
+#[tokio::test]
+async fn oversized_files_are_skipped_and_noted() {
+    let harness = Harness::new();
+    let generated = format!(
+        "// fixture: local-diff\npub fn fixture() {{}}\n{}",
+        "// generated\n".repeat(100)
+    );
+    harness.add_cloned_repo(
+        "generated",
+        &[
+            ("src/lib.rs", "pub fn fixture() {}\n"),
+            ("src/big.rs", &generated),
+        ],
+    );
+    let report = harness
+        .run_with(|config| {
+            let mut source = repo_urls(&["https://github.com/fixture/generated"]);
+            let CrateSource::GitUrls { git, .. } = &mut source else {
+                unreachable!()
+            };
+            git.rustfmt_direct_fallback = true;
+            config.crate_source = source;
+            config.analyze_args.max_file_bytes = Some(1000);
+        })
+        .await;
+    let cr = crate_report(&report, "generated");
+    assert_eq!(DivergingDiff::None, cr.divergence);
+    assert_eq!(
+        vec![std::path::PathBuf::from("src/big.rs")],
+        cr.skipped_large_files
+    );
+    let invocations = harness.rustfmt_invocations("local");
+    assert!(!invocations.is_empty());
+    assert!(
+        invocations.iter().all(|args| !args.contains("big.rs")),
+        "{invocations:?}"
+    );
+}
+
+#[tokio::test]
+async fn unstable_features_are_passed_to_both_builds() {
+    let harness = Harness::new();
+    harness.add_crate("unstable", "pub fn fixture() {}\n");
+    harness.run().await;
+    assert!(
+        harness
+            .rustfmt_invocations("local")
+            .iter()
+            .all(|args| !args.contains("--unstable-features"))
+    );
+    let harness = Harness::new();
+    harness.add_crate("unstable", "pub fn fixture() {}\n");
+    harness
+        .run_with(|config| config.analyze_args.unstable_features = true)
+        .await;
+    for side in ["local", "upstream"] {
+        let invocations = harness.rustfmt_invocations(side);
+        assert!(
+            invocations.iter().any(|args| args.contains("--check")),
+            "{side}: {invocations:?}"
+        );
+        assert!(
+            invocations
+                .iter()
+                .all(|args| args.contains("--unstable-features")),
+            "{side}: {invocations:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_allowlisted_crates_are_selected() {
+    let harness = Harness::new();
+    harness.add_index(&[
+        (1, "first", "https://github.com/fixture/first"),
+        (2, "second", "https://github.com/fixture/second"),
+        (3, "third", "https://github.com/fixture/third"),
+    ]);
+    let allowlist = harness.workdir().join("allowlist");
+    std::fs::write(&allowlist, "# curated\nthird\nfirst # first one\nmissing\n").unwrap();
+    let allowed = read_allowlist(&allowlist).unwrap();
+    let mut selected = harness
+        .select_with(|config| {
+            config.crate_source = index_source();
+            config.consumer_opts.only_crate_names = allowed;
+        })
+        .await
+        .into_iter()
+        .map(|c| c.crate_name)
+        .collect::<Vec<_>>();
+    selected.sort();
+    assert_eq!(vec!["first", "third"], selected);
+}
+
+#[tokio::test]
+async fn cached_selection_is_reused_until_the_opts_change() {
+    let harness = &Harness::new();
+    harness.add_index(&[(1, "first", "https://github.com/fixture/first")]);
+    let select = |configure: fn(&mut MeteroidConfig)| async move {
+        harness
+            .select_with(configure)
+            .await
+            .into_iter()
+            .map(|c| c.crate_name)
+            .collect::<Vec<_>>()
+    };
+    let from_index = |config: &mut MeteroidConfig| config.crate_source = index_source();
+    assert_eq!(vec!["first"], select(from_index).await);
+    // A different index, left with the same modification times
+    let csvs = ["crates.csv", "versions.csv"].map(|csv| harness.workdir().join(csv));
+    let modified = csvs
+        .clone()
+        .map(|csv| std::fs::metadata(csv).unwrap().modified().unwrap());
+    harness.add_index(&[(2, "second", "https://github.com/fixture/second")]);
+    for (csv, modified) in csvs.iter().zip(modified) {
+        let file = std::fs::File::options().write(true).open(csv).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+    assert_eq!(vec!["first"], select(from_index).await);
+    let other_opts = |config: &mut MeteroidConfig| {
+        config.crate_source = index_source();
+        config.consumer_opts.min_size = 1;
+    };
+    assert_eq!(vec!["second"], select(other_opts).await);
+    let refresh = |config: &mut MeteroidConfig| {
+        let mut source = index_source();
+        let CrateSource::GitSync(git) = &mut source else {
+            unreachable!()
+        };
+        git.refresh_selection = true;
+        config.crate_source = source;
+    };
+    assert_eq!(vec!["second"], select(refresh).await);
+}
+
+#[tokio::test]
+async fn ordered_output_streams_in_submission_order() {
+    let harness = Harness::new();
+    let manifest = "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                    [workspace]\n";
+    harness
+        .add_cloned_repo(
+            "slow",
+            &[
+                ("Cargo.toml", manifest),
+                (
+                    "src/lib.rs",
+                    "// fixture: local-slow\npub fn fixture() {}\n",
+                ),
+            ],
+        )
+        .add_cloned_repo(
+            "fast",
+            &[
+                ("Cargo.toml", manifest),
+                ("src/lib.rs", "pub fn fixture() {}\n"),
+            ],
+        );
+    let urls = || {
+        repo_urls(&[
+            "https://github.com/fixture/slow",
+            "https://github.com/fixture/fast",
+        ])
+    };
+    let unordered = harness
+        .run_streamed(|config| config.crate_source = urls())
+        .await;
+    // The slow crate is submitted first but finishes last
+    assert_eq!(vec!["fast", "slow"], unordered);
+    let ordered = harness
+        .run_streamed(|config| {
+            config.crate_source = urls();
+            config.ordered_output = true;
+        })
+        .await;
+    assert_eq!(vec!["slow", "fast"], ordered);
+}
+
+#[tokio::test]
+async fn provided_repo_urls_are_validated_and_analyzed() {
+    let harness = Harness::new();
+    let manifest = "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\nedition = \"2021\"\n";
+    for name in ["first", "second"] {
+        harness.add_cloned_repo(
+            name,
+            &[
+                ("Cargo.toml", manifest),
+                ("src/lib.rs", "pub fn fixture() {}\n"),
+            ],
+        );
+    }
+    let report = harness
+        .run_with(|config| {
+            config.crate_source = repo_urls(&[
+                "https://github.com/fixture/first",
+                "https://github.com/fixture/second",
+            ]);
+        })
+        .await;
+    for name in ["first", "second"] {
+        let cr = crate_report(&report, name);
+        assert_eq!(CrateOrigin::Git, cr.origin);
+        assert_eq!(
+            format!("https://github.com/fixture/{name}"),
+            cr.repo_url.as_ref().unwrap().to_string()
+        );
+    }
+    let invalid = harness
+        .try_run_with(|config| {
+            config.crate_source = repo_urls(&[
+                "https://github.com/fixture/first",
+                "http://github.com/fixture/second",
+            ]);
+        })
+        .await
+        .err();
+    assert!(
+        matches!(invalid, Some(MeteroidError::Fetch(_))),
+        "{invalid:?}"
+    );
+}
+
+#[tokio::test]
+async fn analyses_respect_their_concurrency_limit() {
+    let harness = Harness::new();
+    for name in ["one", "two", "three", "four", "five"] {
+        harness.add_crate(name, "// fixture: local-slow\npub fn fixture() {}\n");
+    }
+    harness
+        .run_with(|config| config.analysis_max_concurrent = NonZeroUsize::new(2).unwrap())
+        .await;
+    assert_eq!(2, harness.max_concurrent_slow_runs("local"));
+}
+
+#[tokio::test]
+async fn crate_config_is_respected_instead_of_the_override() {
+    let harness = Harness::new();
+    for name in ["configured", "unconfigured"] {
+        harness.add_crate(name, "// fixture: local-config-diff\npub fn fixture() {}\n");
+    }
+    harness.add_crate_file("configured", "rustfmt.toml", "max_width = 80\n");
+    let overridden = harness
+        .run_with(|config| config.analyze_args.config = Some("format_strings=true".to_string()))
+        .await;
+    let respected = harness
+        .run_with(|config| {
+            config.analyze_args.config = Some("format_strings=true".to_string());
+            config.analyze_args.respect_crate_config = true;
+        })
+        .await;
+    for name in ["configured", "unconfigured"] {
+        let cr = crate_report(&overridden, name);
+        assert!(cr.diverged);
+        assert!(!cr.used_crate_config);
+    }
+    let configured = crate_report(&respected, "configured");
+    assert!(!configured.diverged);
+    assert!(configured.used_crate_config);
+    let unconfigured = crate_report(&respected, "unconfigured");
+    assert!(unconfigured.diverged);
+    assert!(!unconfigured.used_crate_config);
+}
+
+#[tokio::test]
+async fn combined_sources_both_feed_the_analysis() {
+    let harness = Harness::new();
+    harness.add_crate("local", "pub fn fixture() {}\n");
+    harness.add_index(&[(1, "indexed", "https://github.com/fixture/indexed")]);
+    harness.add_cloned_repo("indexed", MONOREPO);
+    let report = harness
+        .run_with(|config| {
+            let CrateSource::LocalCrates(local) =
+                std::mem::replace(&mut config.crate_source, index_source())
+            else {
+                unreachable!()
+            };
+            let CrateSource::GitSync(git) = index_source() else {
+                unreachable!()
+            };
+            config.crate_source = CrateSource::Combined {
+                local,
+                git,
+                max_crates_global: false,
+            };
+        })
+        .await;
+    assert_eq!(2, report.crate_reports.len());
+    assert_eq!(CrateOrigin::Local, crate_report(&report, "local").origin);
+    assert_eq!(CrateOrigin::Git, crate_report(&report, "indexed").origin);
+}
+
+#[tokio::test]
+async fn virtual_workspace_members_are_formatted_individually() {
+    let harness = Harness::new();
+    harness.add_crate("workspace", "").add_crate_file(
+        "workspace",
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"first\", \"second\"]\nresolver = \"2\"\n",
+    );
+    for member in ["first", "second"] {
+        harness
+            .add_crate_file(
+                "workspace",
+                &format!("{member}/Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{member}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n"
+                ),
+            )
+            .add_crate_file(
+                "workspace",
+                &format!("{member}/src/lib.rs"),
+                "pub fn fixture() {}\n",
+            );
+    }
+    harness.add_crate_file(
+        "workspace",
+        "second/src/lib.rs",
+        "// fixture: local-diff\npub fn fixture() {}\n",
+    );
+    let report = harness.run().await;
+    let cr = crate_report(&report, "workspace");
+    assert_eq!(Some(2), cr.virtual_members);
+    assert_eq!(DivergingDiff::LocalOnly, cr.divergence);
+    for member in ["first", "second"] {
+        let root = harness.crate_dir("workspace").join(member);
+        assert_eq!(1, harness.rustfmt_runs_in("local", &root), "{member}");
+    }
+}
+
+#[tokio::test]
+async fn workspace_members_are_formatted_with_their_own_edition() {
+    let harness = Harness::new();
+    harness.add_crate("workspace", "").add_crate_file(
+        "workspace",
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"old\", \"new\"]\nresolver = \"2\"\n",
+    );
+    for (member, edition) in [("old", "2018"), ("new", "2021")] {
+        harness
+            .add_crate_file(
+                "workspace",
+                &format!("{member}/Cargo.toml"),
+                &format!(
+                    "[package]\nname = \"{member}\"\nversion = \"0.1.0\"\nedition = \"{edition}\"\n"
+                ),
+            )
+            .add_crate_file(
+                "workspace",
+                &format!("{member}/src/lib.rs"),
+                "pub fn fixture() {}\n",
+            );
+    }
+    harness.run().await;
+    let invocations = harness.rustfmt_invocations("local");
+    for (member, edition) in [("old", "2018"), ("new", "2021")] {
+        let lib_rs = harness
+            .crate_dir("workspace")
+            .join(member)
+            .join("src/lib.rs");
+        let expected = format!("{} --edition {edition}", lib_rs.display());
+        assert!(
+            invocations.iter().any(|args| args.contains(&expected)),
+            "{expected} in {invocations:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn phase_timings_are_kept_per_run() {
+    let harness = Harness::new();
+    harness.add_crate("slow", "// fixture: local-slow\npub fn fixture() {}\n");
+    let analyze_secs = |report: &AnalysisReport| -> f64 {
+        let analyze = &report.phase_timings.analyze;
+        analyze.strip_suffix('s').unwrap().parse().unwrap()
+    };
+    let first = harness.run().await;
+    let second = harness.run().await;
+    assert!(analyze_secs(&first) >= 1.0, "{:?}", first.phase_timings);
+    // One slow run each, the second would take at least 2s if they added up
+    assert!(analyze_secs(&second) >= 1.0, "{:?}", second.phase_timings);
+    assert!(analyze_secs(&second) < 2.0, "{:?}", second.phase_timings);
+}
+
+#[tokio::test]
+async fn sink_is_called_once_per_analyzed_crate() {
+    let harness = Harness::new();
+    for name in ["first", "second", "third"] {
+        harness.add_crate(name, "pub fn fixture() {}\n");
+    }
+    let mut streamed = harness.run_streamed(|_| {}).await;
+    streamed.sort();
+    assert_eq!(vec!["first", "second", "third"], streamed);
+}
+
+#[tokio::test]
+async fn report_records_when_and_where_it_was_generated() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(env!("CARGO_PKG_VERSION"), report.meteoroid_version);
+    assert!(report.host.as_ref().is_some_and(|host| !host.is_empty()));
+    // `YYYY-MM-DDTHH:MM:SSZ`
+    let (date, time) = report
+        .generated_at
+        .strip_suffix('Z')
+        .and_then(|ts| ts.split_once('T'))
+        .unwrap_or_else(|| panic!("not RFC3339: {}", report.generated_at));
+    let date = date
+        .split('-')
+        .map(|part| part.parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+    let time = time
+        .split(':')
+        .map(|part| part.parse::<u32>().unwrap())
+        .collect::<Vec<_>>();
+    let [year, month, day] = date[..] else {
+        panic!("bad date in {}", report.generated_at)
+    };
+    let [hour, minute, second] = time[..] else {
+        panic!("bad time in {}", report.generated_at)
+    };
+    assert!(year >= 2024, "{}", report.generated_at);
+    assert!((1..=12).contains(&month) && (1..=31).contains(&day));
+    assert!(hour < 24 && minute < 60 && second < 60);
+}
+
+#[tokio::test]
+async fn restart_skips_crates_completed_before_the_checkpoint() {
+    let harness = Harness::new();
+    harness.add_crate("fast", "pub fn fixture() {}\n");
+    harness.add_crate("slow", "// fixture: local-slow\npub fn fixture() {}\n");
+    let streamed = harness
+        .run_stopped_after_first(|config| config.analyze_args.checkpoint = true)
+        .await;
+    assert_eq!(vec!["fast"], streamed);
+    assert!(harness.output_dir().join("checkpoint.json").exists());
+    let fast_runs = harness.rustfmt_runs("local", "fast");
+    let report = harness
+        .run_with(|config| config.analyze_args.checkpoint = true)
+        .await;
+    assert_eq!(fast_runs, harness.rustfmt_runs("local", "fast"));
+    // The restart's report includes the crates from before the checkpoint
+    crate_report(&report, "fast");
+    crate_report(&report, "slow");
+    assert!(!harness.output_dir().join("checkpoint.json").exists());
+}
+
+#[tokio::test]
+async fn output_layouts_place_files_where_expected() {
+    for layout in [OutputLayout::Flat, OutputLayout::ByCrate] {
+        let harness = Harness::new();
+        harness.add_crate("split", "// fixture: local-diff\npub fn fixture() {}\n");
+        harness.add_crate("broken", "// fixture: local-error\npub fn fixture() {}\n");
+        let report = harness
+            .run_with(|config| config.analyze_args.output_layout = layout)
+            .await;
+        let out = harness.output_dir();
+        let split = crate_report(&report, "split");
+        let broken = crate_report(&report, "broken");
+        let broken_dir = if broken.diverged {
+            "diverged"
+        } else {
+            "nondiverged"
+        };
+        let (diff, error) = match layout {
+            OutputLayout::Flat => (
+                out.join("diverged").join("split-local.diff"),
+                out.join("errors").join("broken-local-error.txt"),
+            ),
+            OutputLayout::ByCrate => (
+                out.join("diverged").join("split").join("local.diff"),
+                out.join(broken_dir).join("broken").join("local-error.txt"),
+            ),
+        };
+        assert_eq!(
+            Some(&diff),
+            split.local_rustfmt_output.diff_output_file.as_ref(),
+            "{layout:?}"
+        );
+        assert_eq!(
+            Some(&error),
+            broken.local_rustfmt_output.error_output_file.as_ref(),
+            "{layout:?}"
+        );
+        assert!(diff.is_file(), "{}", diff.display());
+        assert!(error.is_file(), "{}", error.display());
+    }
+}
+
+#[tokio::test]
+async fn elapsed_is_summed_per_outcome_and_side() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    harness.add_crate("slow", "// fixture: local-slow\npub fn fixture() {}\n");
+    harness.add_crate("split", "// fixture: local-diff\npub fn fixture() {}\n");
+    harness.add_crate(
+        "broken",
+        "// fixture: upstream-error\npub fn fixture() {}\n",
+    );
+    let report = harness.run().await;
+    let mut expected = ElapsedByOutcome::default();
+    for cr in &report.crate_reports {
+        for (sum, output) in [
+            (&mut expected.upstream, &cr.upstream_rustfmt_output),
+            (&mut expected.local, &cr.local_rustfmt_output),
+        ] {
+            let secs = output
+                .elapsed
+                .strip_suffix('s')
+                .unwrap()
+                .parse::<f64>()
+                .unwrap();
+            match output.outcome {
+                FmtOutcome::Success => sum.success += secs,
+                FmtOutcome::Diff => sum.diff += secs,
+                FmtOutcome::Failure => sum.failure += secs,
+                FmtOutcome::Panic => sum.panic += secs,
+                FmtOutcome::Timeout => sum.timeout += secs,
+            }
+        }
+    }
+    let summed = report.elapsed_by_outcome;
+    assert!(summed.local.success >= 1.0, "{summed:?}");
+    assert!(
+        summed.local.diff > 0.0 && summed.upstream.failure > 0.0,
+        "{summed:?}"
+    );
+    // Each crate's elapsed is rounded to hundredths in the report
+    let close = |a: f64, b: f64| (a - b).abs() <= 0.005 * 4.0;
+    for (side, sum, want) in [
+        ("upstream", summed.upstream, expected.upstream),
+        ("local", summed.local, expected.local),
+    ] {
+        assert!(
+            close(sum.success, want.success)
+                && close(sum.diff, want.diff)
+                && close(sum.failure, want.failure)
+                && close(sum.panic, want.panic)
+                && close(sum.timeout, want.timeout),
+            "{side}: {sum:?} != {want:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn populated_report_round_trips() {
+    let harness = Harness::new();
+    harness.add_crate("diverging", "// fixture: local-diff\npub fn fixture() {}\n");
+    harness.add_crate(
+        "failing",
+        "// fixture: upstream-error\npub fn fixture() {}\n",
+    );
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let report = harness.run().await;
+    assert_eq!(3, report.crate_reports.len());
+    let serialized = serde_json::to_value(&report).unwrap();
+    let deserialized: AnalysisReport = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serialized, serde_json::to_value(&deserialized).unwrap());
+    assert_eq!(
+        crate_report(&report, "diverging"),
+        crate_report(&deserialized, "diverging")
+    );
+}
+
+#[tokio::test]
+async fn failures_map_to_their_error_variant() {
+    let harness = Harness::new();
+    harness.add_crate("clean", "pub fn fixture() {}\n");
+    let setup = harness
+        .try_run_with(|config| config.consumer_opts.max_crates = 0)
+        .await
+        .err();
+    assert!(matches!(setup, Some(MeteroidError::Setup(_))), "{setup:?}");
+    let fetch = harness
+        .try_run_with(|config| {
+            config.crate_source = repo_urls(&[
+                "https://github.com/first/same",
+                "https://github.com/second/same",
+            ]);
+        })
+        .await
+        .err();
+    assert!(matches!(fetch, Some(MeteroidError::Fetch(_))), "{fetch:?}");
+    let not_a_dir = harness.workdir().with_file_name("not-a-dir");
+    std::fs::write(&not_a_dir, "").unwrap();
+    let analysis = harness
+        .try_run_with(|config| config.output_dir = Some(not_a_dir.join("output")))
+        .await
+        .err();
+    assert!(
+        matches!(analysis, Some(MeteroidError::Analysis(_))),
+        "{analysis:?}"
+    );
+    let render = render_report(RenderOpts {
+        report: not_a_dir.join("report.json"),
+        output_dir: None,
+        formats: vec![ReportFormat::Html],
+        filters: Vec::new(),
+        sort: ReportSort::default(),
+        open: false,
+    })
+    .await
+    .err();
+    assert!(
+        matches!(render, Some(MeteroidError::Setup(_))),
+        "{render:?}"
+    );
+}
@@ -0,0 +1,20 @@
+use meteoroid_lib::warmup;
+
+#[tokio::test]
+async fn warmup_is_idempotent_when_the_toolchain_is_installed() {
+    let dir = tempfile::tempdir().unwrap();
+    // The toolchain running the tests is installed already
+    let repo = dir.path().join("rustfmt");
+    std::fs::create_dir(&repo).unwrap();
+    std::fs::write(
+        repo.join("rust-toolchain.toml"),
+        "[toolchain]\nchannel = \"stable\"\n",
+    )
+    .unwrap();
+    let workdir = dir.path().join("workdir");
+    let first = warmup(workdir.clone(), &repo, &repo).await.unwrap();
+    assert!(first.0.starts_with("stable"), "{first:?}");
+    assert_eq!(first.0, first.1);
+    let second = warmup(workdir, &repo, &repo).await.unwrap();
+    assert_eq!(first, second);
+}
@@ -0,0 +1,93 @@
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+
+/// Per-module log levels, parsed from `RUST_LOG`-style directives,
+/// ex: `meteoroid_lib::git=trace,meteoroid_lib=info,warn`.
+/// The most specific module directive matching a target decides its level,
+/// a bare level sets the level for targets that no directive matches (defaults to `warn`)
+#[derive(Debug, Clone)]
+pub(crate) struct LogDirectives {
+    // Sorted by descending specificity so that the first match wins
+    modules: Vec<(String, LevelFilter)>,
+    default: LevelFilter,
+}
+
+impl LogDirectives {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        for (module, level) in &self.modules {
+            if target
+                .strip_prefix(module.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            {
+                return *level;
+            }
+        }
+        self.default
+    }
+}
+
+impl FromStr for LogDirectives {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modules = vec![];
+        let mut default = LevelFilter::WARN;
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            if let Some((module, level)) = directive.split_once('=') {
+                let level = LevelFilter::from_str(level.trim())
+                    .map_err(|e| format!("invalid level in directive '{directive}': {e}"))?;
+                modules.push((module.trim().to_string(), level));
+            } else {
+                default = LevelFilter::from_str(directive)
+                    .map_err(|e| format!("invalid level directive '{directive}': {e}"))?;
+            }
+        }
+        modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(Self { modules, default })
+    }
+}
+
+impl<S: Subscriber> Filter<S> for LogDirectives {
+    fn enabled(&self, meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        self.level_for(meta.target()) >= *meta.level()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_module_directive_wins() {
+        let directives: LogDirectives = "meteoroid_lib=info,meteoroid_lib::git=trace,error"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            LevelFilter::TRACE,
+            directives.level_for("meteoroid_lib::git")
+        );
+        assert_eq!(
+            LevelFilter::TRACE,
+            directives.level_for("meteoroid_lib::git::lfs")
+        );
+        assert_eq!(
+            LevelFilter::INFO,
+            directives.level_for("meteoroid_lib::analyze")
+        );
+        // Prefixes only match whole module path segments
+        assert_eq!(
+            LevelFilter::INFO,
+            directives.level_for("meteoroid_lib::gitx")
+        );
+        assert_eq!(LevelFilter::ERROR, directives.level_for("hyper::client"));
+    }
+
+    #[test]
+    fn unmatched_targets_default_to_warn() {
+        let directives: LogDirectives = "meteoroid_lib=debug".parse().unwrap();
+        assert_eq!(LevelFilter::WARN, directives.level_for("tokio"));
+        assert!("meteoroid_lib=loud".parse::<LogDirectives>().is_err());
+    }
+}
@@ -1,36 +1,69 @@
-use clap::Parser;
+use crate::log_filter::LogDirectives;
+use clap::{CommandFactory, Parser};
 use meteoroid_lib::{
-    AnalyzeArgs, ConsumerOpts, CrateSource, GitSyncConfig, LocalCratesConfig, MeteroidConfig,
-    stop_channel, unpack,
+    AnalyzeArgs, BisectConfig, CargoCommand, CleanOpts, ConsumerOpts, CrateKind, CrateSource,
+    DEFAULT_LOCAL_SKIP_DIRS, GitSyncConfig, GitToken, LabeledRustfmtRepo, LevenshteinComparator,
+    LocalCratesConfig, MeteroidConfig, NotifyOpts, OutputLayout, RenderOpts, RepoList,
+    ReportFilter, ReportFormat, ReportSort, SampleOpts, SelectBy, SetupFailurePolicy, StopSender,
+    TimeoutScaling, UnreachableHostPolicy, bisect, clean, doctor, fetch_index, read_allowlist,
+    read_denylist, read_repo_list, render_report, select_crates_in, stop_channel, unpack, warmup,
 };
+use std::ffi::OsStr;
+use std::io::IsTerminal;
 use std::marker::PhantomData;
-use std::num::{NonZeroU32, NonZeroUsize};
-use std::path::PathBuf;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::Arc;
 use tracing::{Level, Metadata, Subscriber};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard, TraceStyle};
 use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::{Context, Filter, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 
+mod log_filter;
+#[cfg(feature = "otel")]
+mod otel;
+
 #[derive(Debug, clap::Parser)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Args {
     /// Path to the working directory for meteoroid
     /// This is where the crates index is downloaded to, where crates are cloned into, etc.
-    /// It works as a cache as well as a place to store the output files
+    /// It works as a cache as well as a place to store the output files.
+    /// Required for all subcommands except `completions` and `render`
     #[clap(long, short)]
-    workdir: PathBuf,
+    workdir: Option<PathBuf>,
     /// Path to where analysis results are stored.
     /// Diff files, complete error outputs, and the run-report
     /// If unset, a temporary directory will be used
     #[clap(long, short)]
     output_dir: Option<PathBuf>,
-    /// Path to the local/modified rustfmt repository that should be tested
+    /// Path to the local/modified rustfmt repository that should be tested,
+    /// required when analyzing crates
+    #[clap(long)]
+    rustfmt_local_repo: Option<PathBuf>,
+    /// Path to the unmodified rustfmt repository that should be used as a baseline,
+    /// required when analyzing crates
     #[clap(long)]
-    rustfmt_local_repo: PathBuf,
-    /// Path to the unmodified rustfmt repository that should be used as a baseline
+    rustfmt_upstream_repo: Option<PathBuf>,
+    /// More rustfmt repositories to compare against, as `<label>=<path>`, can be repeated.
+    /// Each crate is also formatted with these builds, and the report shows
+    /// which builds agree with each other
+    #[clap(long, value_parser = parse_extra_repo)]
+    rustfmt_extra_repo: Vec<LabeledRustfmtRepo>,
+    /// When the `--check` diffs of local and upstream match, also format every file with
+    /// both builds and compare the formatted output.
+    /// Catches divergences that the diffs hide, but is a lot slower
+    #[clap(long, default_value_t = false)]
+    compare_formatted_output: bool,
+    /// Leave `.rs` files larger than this many bytes out of formatting, these are usually
+    /// generated or vendored. Only applies where `rustfmt` is run on files directly,
+    /// otherwise diffs of files larger than this are warned about
     #[clap(long)]
-    rustfmt_upstream_repo: PathBuf,
+    max_file_bytes: Option<u64>,
     /// If set to a directory, instead of fetching crates from git,
     /// the tool will use crates from that directory instead.
     /// The tool will assume that each sub-directory in the supplied directory
@@ -39,38 +72,187 @@ pub struct Args {
     /// The maximum amount of crates to pull
     #[clap(long, default_value_t = 100)]
     max_crates: usize,
+    /// Only select crates, then print them to stdout as a JSON array and exit, without cloning
+    /// or analyzing anything. Each crate has `name`, `repo`, `downloads`, `size`, and `edition`,
+    /// `null` where it isn't known. Logs go to stderr instead of stdout
+    #[clap(long, default_value_t = false)]
+    list_selected: bool,
     /// The minimum size of a crate to be pulled
     #[clap(long, default_value_t = 20_000)]
     min_size: u64,
     /// Exclude crates that contains strings supplied here
     #[clap(long)]
     exclude_crate_name_contains: Vec<String>,
+    /// File with crate names (or substrings of) to exclude, one per line, `#` starts a comment.
+    /// Merged with `--exclude-crate-name-contains`
+    #[clap(long)]
+    denylist_file: Option<PathBuf>,
+    /// File with the exact names of the only crates that may be selected, one per line,
+    /// `#` starts a comment. Other selection options still apply to them
+    #[clap(long)]
+    allowlist_file: Option<PathBuf>,
     /// Exclude repositories that contains strings supplied here
     #[clap(long)]
     exclude_repository_contains: Vec<String>,
+    /// Exclude crates whose license contains strings supplied here (ex: `GPL`)
+    #[clap(long)]
+    exclude_license: Vec<String>,
+    /// Only keep crates whose license contains one of the strings supplied here (ex: `MIT`)
+    #[clap(long)]
+    only_license: Vec<String>,
+    /// Only keep crates with a keyword containing one of the strings supplied here,
+    /// combined with `--only-category` a crate is kept if either matches
+    #[clap(long)]
+    only_keyword: Vec<String>,
+    /// Only keep crates with a category containing one of the strings supplied here (ex: `parser`)
+    #[clap(long)]
+    only_category: Vec<String>,
+    /// Only keep crates that have a library target
+    #[clap(long, conflicts_with = "only_bin")]
+    only_lib: bool,
+    /// Only keep crates that have at least one binary
+    #[clap(long)]
+    only_bin: bool,
+    /// Skip crates declaring a `rust-version` below this (ex: `1.85`), crates
+    /// without one are kept unless `--require-rust-version` is set
+    #[clap(long, value_parser = parse_min_rust_version)]
+    min_rust_version: Option<semver::Version>,
+    /// Skip crates that don't declare a `rust-version`
+    #[clap(long)]
+    require_rust_version: bool,
+    /// What to rank crates by when selecting which ones to pull
+    #[clap(long, value_enum, default_value_t = SelectByArg::Downloads)]
+    select_by: SelectByArg,
+    /// Select the most popular crates per category (the first category a crate lists),
+    /// alternating between categories, instead of the most popular crates overall.
+    /// Gives a corpus that covers more domains
+    #[clap(long, default_value_t = false)]
+    stratify_by_category: bool,
+    /// The maximum amount of crates to pull whose repositories belong to the same org,
+    /// to avoid a few orgs dominating the selection
+    #[clap(long)]
+    max_per_org: Option<usize>,
+    /// Select a random sample of the crates passing the filters instead of the most popular ones.
+    /// The same seed on the same crates index selects the same crates
+    #[clap(long, conflicts_with_all = ["stratify_by_category", "max_per_org"])]
+    sample_seed: Option<u64>,
+    /// The number of crates to sample when `sample-seed` is set, defaults to `max-crates`
+    #[clap(long, requires = "sample_seed")]
+    sample_size: Option<usize>,
     /// Don't output any files (except the report)
     #[clap(long, default_value_t = false)]
     no_output_files: bool,
     /// Where to output the report (defaults to `output-dir`)
     #[clap(long)]
     report_dest: Option<PathBuf>,
+    /// Which reports to write to the output directory when finished
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = [ReportFormatArg::Json, ReportFormatArg::Html])]
+    report_format: Vec<ReportFormatArg>,
+    /// How diffs and errors are laid out in the output directory
+    #[clap(long, value_enum, default_value_t = OutputLayoutArg::Flat)]
+    output_layout: OutputLayoutArg,
+    /// Only include crates matching any of these in the reports,
+    /// the summary counters still cover every analyzed crate
+    #[clap(long, value_enum, value_delimiter = ',')]
+    report_filter: Vec<ReportFilterArg>,
+    /// How crates are ordered in the html report
+    #[clap(long, value_enum, default_value_t = ReportSortArg::Severity)]
+    report_sort: ReportSortArg,
+    /// Open the html report in the default browser when finished,
+    /// scrolled to the first divergence if there is one
+    #[clap(long, default_value_t = false)]
+    open: bool,
+    /// Slack/Discord compatible incoming webhook, posted a summary of divergences
+    /// when the run finishes
+    #[clap(long)]
+    notify_webhook: Option<url::Url>,
+    /// Post to `notify-webhook` even if nothing diverged
+    #[clap(long, requires = "notify_webhook", default_value_t = false)]
+    notify_always: bool,
+    /// If a `rustfmt` build fails, use the previous build in its repo (if any) and keep going,
+    /// extra builds that fail are left out. Noted in the report
+    #[clap(long, overrides_with = "fail_fast", default_value_t = false)]
+    keep_going: bool,
+    /// Abort the run if a `rustfmt` build fails, the default
+    #[clap(long, overrides_with = "keep_going", default_value_t = false)]
+    fail_fast: bool,
     /// Maximum crates to analyze concurrently,
     /// defaults to available parallelism (usually the number of cores),
     /// if that is unavailable `2` will be used
     #[clap(long)]
     analysis_max_concurrent: Option<NonZeroUsize>,
+    /// How many finished analyses can wait to be added to the report before analysis
+    /// is held back, defaults to twice `--analysis-max-concurrent`
+    #[clap(long)]
+    result_buffer: Option<NonZeroUsize>,
+    /// Pass on analyzed crates in the order they were queued rather than as they finish,
+    /// a slow crate holds back the ones queued after it
+    #[clap(long)]
+    ordered_output: bool,
     /// How long to maximally wait for a `rustfmt` process to finish once started.
     #[clap(long, default_value = "30")]
     analysis_task_timeout_seconds: NonZeroU32,
+    /// Add this many seconds to the timeout for every MiB of the published crate,
+    /// crates without a published size (local crates) get the base timeout
+    #[clap(long)]
+    analysis_timeout_per_mib_seconds: Option<NonZeroU32>,
+    /// The most a size-scaled timeout can grow to
+    #[clap(
+        long,
+        default_value = "600",
+        requires = "analysis_timeout_per_mib_seconds"
+    )]
+    analysis_timeout_max_seconds: NonZeroU32,
     /// Don't send non-diverging diffs for further processing.
     /// Overall stats will still be reported, but detailed data won't be available.
     /// This is mainly useful if running on a large amount of crates, to keep the html report
     /// reasonably sized.
     #[clap(long, default_value_t = false)]
     skip_non_diverging_diffs: bool,
+    /// Re-run `rustfmt` this many times on each diverging crate, marking the crate as flaky
+    /// in the report if the divergence doesn't reproduce every time
+    #[clap(long, default_value_t = 0)]
+    recheck_divergences: usize,
+    /// For each diverging crate, search for the smallest subset of the options in `config`
+    /// that still diverges, and include it in the report
+    #[clap(long, default_value_t = false, requires = "config")]
+    bisect_config: bool,
+    /// For each diverging crate, copy only the files that diverged (and the crate's `rustfmt`
+    /// config) into `repros` in the output directory, with a README on how to reproduce.
+    /// Useful when filing a bug
+    #[clap(long, default_value_t = false)]
+    write_repros: bool,
     /// Extra command-line `config` variables, passed directly to `rustfmt`
     #[clap(long)]
     config: Option<String>,
+    /// Pass `--unstable-features` to both `rustfmt` builds, required for unstable `config` options
+    #[clap(long, default_value_t = false)]
+    unstable_features: bool,
+    /// `cargo` to build `rustfmt` and run `cargo fmt` with, ex: a wrapper script
+    #[clap(long, default_value = "cargo")]
+    cargo_bin: PathBuf,
+    /// Extra argument passed to `cargo` before the subcommand, ex: `--offline`, can be repeated
+    #[clap(long, allow_hyphen_values = true)]
+    cargo_arg: Vec<String>,
+    /// Don't apply `--config` to crates that ship their own `rustfmt.toml`,
+    /// so they're formatted the way their authors configured them
+    #[clap(long, default_value_t = false, requires = "config")]
+    respect_crate_config: bool,
+    /// Don't count divergences that only change trailing whitespace or blank lines, like a
+    /// final newline. Spacing within a line still counts, and the diffs are still written to
+    /// the output directory
+    #[clap(long, default_value_t = false)]
+    ignore_whitespace_diffs: bool,
+    /// Redo the analysis of a crate up to this many times when `rustfmt` fails on it for what
+    /// looks like an environmental reason, like a held lock or a full disk.
+    /// Panics and other `rustfmt` failures are never retried
+    #[clap(long, default_value_t = 0)]
+    analysis_retries: usize,
+    /// Keep a `checkpoint.json` of analyzed crates in the output directory while running,
+    /// if a run dies or is stopped, rerunning with the same output directory skips the crates
+    /// it already analyzed and merges them into the report
+    #[clap(long, requires = "output_dir", default_value_t = false)]
+    checkpoint: bool,
     /// The verbosity of this tool,
     /// - `0` is no output except errors
     /// - `1` is low verbosity, `info` and more severe
@@ -78,98 +260,899 @@ pub struct Args {
     /// - `3` is unrestricted verbosity, `trace` and up
     #[clap(long, short, default_value_t = 2)]
     verbosity: u8,
+    /// Per-module log levels as comma-separated directives, overrides `verbosity` for
+    /// the log lines written to the terminal,
+    /// ex: `meteoroid_lib::git=trace,meteoroid_lib=info`.
+    /// A bare level, ex: `info`, applies to modules without a directive, defaults to `warn`
+    #[clap(long, env = "METEOROID_LOG")]
+    log_filter: Option<LogDirectives>,
+    /// The format of log lines, written to stdout, or to stderr with `--list-selected`
+    #[clap(long, value_enum, default_value_t = LogFormatArg::Pretty)]
+    log_format: LogFormatArg,
+    /// When to color terminal output, `auto` colors only when writing to a terminal
+    /// and the `NO_COLOR` env var isn't set
+    #[clap(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
     /// Which diff tool to use for meta-diffing (the diff of the diffs between a local
     /// version of `rustfmt` and upstream. If none are supplied `diff` will be used,
     /// if not present, the meta diff won't be displayed (only relevant for the `html` report).
     #[clap(long, env = "METEOROID_DIFF_TOOL")]
     meteoroid_diff_tool: Option<PathBuf>,
+    /// Write a Chrome trace (viewable in `chrome://tracing` or Perfetto) of each pipeline phase
+    /// and analyzed crate to this path
+    #[clap(long)]
+    trace_file: Option<PathBuf>,
+    /// Export tracing spans for each pipeline phase and analyzed crate to this
+    /// OTLP/HTTP endpoint, ex: `http://localhost:4318/v1/traces`
+    #[cfg(feature = "otel")]
+    #[clap(long)]
+    otel_endpoint: Option<String>,
+    /// Upload the output directory (and report) to this S3 bucket when finished,
+    /// credentials and region are read from the standard AWS environment
+    #[cfg(feature = "s3")]
+    #[clap(long)]
+    s3_upload: Option<String>,
+    /// Key prefix for uploaded files
+    #[cfg(feature = "s3")]
+    #[clap(long, requires = "s3_upload", default_value = "")]
+    s3_prefix: String,
+    /// Endpoint for non-AWS S3-compatible storage, ex: `http://localhost:9000`
+    #[cfg(feature = "s3")]
+    #[clap(long, requires = "s3_upload")]
+    s3_endpoint: Option<String>,
+    /// Serve live metrics in Prometheus text format on `/metrics` at this address while running,
+    /// ex: `127.0.0.1:9898`
+    #[cfg(feature = "metrics")]
+    #[clap(long)]
+    metrics_addr: Option<std::net::SocketAddr>,
 
     #[clap(subcommand)]
     command: Subcommand,
 }
 
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum SelectByArg {
+    /// The most downloaded crates
+    Downloads,
+    /// The most recently created crates, these tend to use newer language features
+    Newest,
+    /// The crates with the most downloads per day since they were created,
+    /// these tend to be actively growing
+    Velocity,
+}
+
+impl From<SelectByArg> for SelectBy {
+    fn from(value: SelectByArg) -> Self {
+        match value {
+            SelectByArg::Downloads => SelectBy::Downloads,
+            SelectByArg::Newest => SelectBy::Newest,
+            SelectByArg::Velocity => SelectBy::Velocity,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum ReportFormatArg {
+    /// `report.json`, or `report-dest` if set
+    Json,
+    /// `report.html`
+    Html,
+    /// `report.sarif`, for code-scanning tools
+    Sarif,
+}
+
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum OutputLayoutArg {
+    /// Files named after their crate, under `diverged`, `nondiverged`, and `errors`
+    Flat,
+    /// A directory per crate under `diverged` or `nondiverged`, with all of its diffs and errors
+    ByCrate,
+}
+
+impl From<OutputLayoutArg> for OutputLayout {
+    fn from(value: OutputLayoutArg) -> Self {
+        match value {
+            OutputLayoutArg::Flat => OutputLayout::Flat,
+            OutputLayoutArg::ByCrate => OutputLayout::ByCrate,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum ReportFilterArg {
+    /// Local rustfmt failed while upstream didn't, a likely regression
+    LocalOnlyFailure,
+    /// Upstream rustfmt failed while local didn't
+    UpstreamOnlyFailure,
+    /// Only local rustfmt produced a diff
+    LocalOnlyDiff,
+    /// Only upstream rustfmt produced a diff
+    UpstreamOnlyDiff,
+    /// Both produced diffs, but they differ
+    DiffBetween,
+    /// The diffs matched, but the formatted output differed,
+    /// only found with `--compare-formatted-output`
+    FormattedOutput,
+}
+
+impl From<ReportFilterArg> for ReportFilter {
+    fn from(value: ReportFilterArg) -> Self {
+        match value {
+            ReportFilterArg::LocalOnlyFailure => ReportFilter::LocalOnlyFailure,
+            ReportFilterArg::UpstreamOnlyFailure => ReportFilter::UpstreamOnlyFailure,
+            ReportFilterArg::LocalOnlyDiff => ReportFilter::LocalOnlyDiff,
+            ReportFilterArg::UpstreamOnlyDiff => ReportFilter::UpstreamOnlyDiff,
+            ReportFilterArg::DiffBetween => ReportFilter::DiffBetween,
+            ReportFilterArg::FormattedOutput => ReportFilter::FormattedOutput,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum ReportSortArg {
+    /// Panics first, then divergences, errors, and diffs
+    Severity,
+    /// By crate name
+    Name,
+}
+
+impl From<ReportSortArg> for ReportSort {
+    fn from(value: ReportSortArg) -> Self {
+        match value {
+            ReportSortArg::Severity => ReportSort::Severity,
+            ReportSortArg::Name => ReportSort::Name,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormatArg {
+    /// Human-readable lines
+    Pretty,
+    /// One json-object per line, for log collectors
+    Json,
+}
+
+impl From<ReportFormatArg> for ReportFormat {
+    fn from(value: ReportFormatArg) -> Self {
+        match value {
+            ReportFormatArg::Json => ReportFormat::Json,
+            ReportFormatArg::Html => ReportFormat::Html,
+            ReportFormatArg::Sarif => ReportFormat::Sarif,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RemoteArgs {
+    /// How old the cached crates index is allowed to be before a new database dump is fetched.
+    #[clap(long, short, default_value_t = 7)]
+    crates_index_max_age: u8,
+
+    /// Whether to resync previously cloned crates before running analysis
+    #[clap(long, default_value_t = false)]
+    git_resync_before: bool,
+
+    /// The number of git-clones (or refetches) that are allowed to run concurrently
+    #[clap(long, default_value = "2")]
+    git_sync_max_concurrent: NonZeroUsize,
+
+    /// How many cloned crates to keep ready ahead of the analysis, so that it doesn't have
+    /// to wait for clones. Defaults to `--git-sync-max-concurrent`
+    #[clap(long)]
+    prefetch_depth: Option<NonZeroUsize>,
+
+    /// Skip fetching the crates index and use a small curated list of crates shipped with
+    /// meteoroid instead, useful for quick smoke-tests.
+    /// Crate selection options are ignored
+    #[clap(long, default_value_t = false)]
+    builtin_corpus: bool,
+
+    /// Skip fetching the crates index and only analyze this repo, can be given more than once.
+    /// Crate selection options are ignored
+    #[clap(long, conflicts_with = "builtin_corpus")]
+    repo_url: Vec<url::Url>,
+
+    /// Like `--repo-url`, but reads repo urls and crate names from stdin, one per line.
+    /// Crate names are looked up in the crates index, empty lines and `#` comments are ignored
+    #[clap(long, conflicts_with = "builtin_corpus", default_value_t = false)]
+    stdin: bool,
+
+    /// Repos without a top-level `Cargo.toml` are skipped by default,
+    /// with this `rustfmt` is run directly on every `.rs` file in them instead
+    #[clap(long, default_value_t = false)]
+    rustfmt_direct_fallback: bool,
+
+    /// Keep the previous crates index when refetching it, and only select crates that are
+    /// new or have changed since. Needs to be set on the run that refetches the index too
+    #[clap(long, default_value_t = false)]
+    only_changed_crates: bool,
+
+    /// The crates selected from the index are cached in the workdir and reused while the
+    /// index and selection options are unchanged, this forces re-parsing the index
+    #[clap(long, default_value_t = false)]
+    refresh_selection: bool,
+
+    /// Warn when a cloned repo takes up more than this many MiB on disk,
+    /// the size is noted in the report
+    #[clap(long)]
+    warn_repo_size: Option<NonZeroU64>,
+
+    /// Name of an env var holding a token for cloning private repos over https,
+    /// it's only sent to the hosts given with `--git-token-host`
+    #[clap(long, requires = "git_token_host")]
+    git_token: Option<String>,
+
+    /// Host to send the `--git-token` to, ex: `github.com`, can be repeated
+    #[clap(long, requires = "git_token")]
+    git_token_host: Vec<String>,
+
+    /// Selecting no crates from the index fails the run, since it's usually a misconfigured
+    /// filter, with this it's only a warning
+    #[clap(long, default_value_t = false)]
+    allow_empty_selection: bool,
+
+    /// Consider a host unreachable after this many clones from it failed in a row,
+    /// and skip its crates for `--unreachable-host-cooldown` seconds
+    #[clap(long)]
+    unreachable_host_threshold: Option<NonZeroUsize>,
+
+    /// Seconds to skip the crates of an unreachable host for
+    #[clap(long, default_value = "600", requires = "unreachable_host_threshold")]
+    unreachable_host_cooldown: u64,
+}
+
+impl RemoteArgs {
+    fn crate_source(&self) -> Result<CrateSource, String> {
+        let gs = self.git_sync_config()?;
+        if self.builtin_corpus {
+            return Ok(CrateSource::BuiltinCorpus(gs));
+        }
+        if !self.stdin && self.repo_url.is_empty() {
+            return Ok(CrateSource::GitSync(gs));
+        }
+        let mut list = if self.stdin {
+            read_repo_list(std::io::stdin().lock())
+                .map_err(|e| format!("failed to read repos from stdin: {}", unpack(&*e)))?
+        } else {
+            RepoList::default()
+        };
+        list.urls.extend(self.repo_url.iter().cloned());
+        Ok(CrateSource::GitUrls { list, git: gs })
+    }
+
+    fn git_sync_config(&self) -> Result<GitSyncConfig, String> {
+        let git_token = if let Some(var) = &self.git_token {
+            let token = std::env::var(var)
+                .map_err(|e| format!("failed to read the `--git-token` env var '{var}': {e}"))?;
+            Some(GitToken {
+                token,
+                hosts: self.git_token_host.clone(),
+            })
+        } else {
+            None
+        };
+        Ok(GitSyncConfig {
+            crates_index_max_age_days: self.crates_index_max_age,
+            git_resync_before: self.git_resync_before,
+            git_clone_max_concurrent: self.git_sync_max_concurrent,
+            prefetch_depth: self.prefetch_depth,
+            rustfmt_direct_fallback: self.rustfmt_direct_fallback,
+            only_changed_crates: self.only_changed_crates,
+            refresh_selection: self.refresh_selection,
+            warn_repo_size: self
+                .warn_repo_size
+                .map(|mib| mib.get().saturating_mul(1024 * 1024)),
+            git_token,
+            allow_empty_selection: self.allow_empty_selection,
+            unreachable_host: self.unreachable_host_threshold.map(|failure_threshold| {
+                UnreachableHostPolicy {
+                    failure_threshold,
+                    cooldown: std::time::Duration::from_secs(self.unreachable_host_cooldown),
+                }
+            }),
+        })
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct LocalArgs {
+    /// The path to search for crates to analyze in.
+    /// Should be a directory containing subdirectories with crates.
+    #[clap(long, short)]
+    path: PathBuf,
+
+    /// Follow symlinked directories when searching for crates, by default they are skipped.
+    /// Directories that have already been visited are skipped, so symlink cycles terminate
+    #[clap(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Directory names that are never considered crates, passing any replaces the defaults
+    #[clap(long, default_values = DEFAULT_LOCAL_SKIP_DIRS)]
+    local_skip_dir: Vec<String>,
+}
+
+impl LocalArgs {
+    fn local_crates_config(&self) -> LocalCratesConfig {
+        LocalCratesConfig {
+            crate_dir: self.path.clone(),
+            follow_symlinks: self.follow_symlinks,
+            skip_dirs: self.local_skip_dir.clone(),
+        }
+    }
+}
+
 #[derive(Debug, clap::Subcommand)]
 pub enum Subcommand {
     /// Fetch crate metadata from `crates.io` then try to sync crates with `git`
-    Remote {
-        /// How old the cached crates index is allowed to be before a new database dump is fetched.
-        #[clap(long, short, default_value_t = 7)]
-        crates_index_max_age: u8,
-
-        /// Whether to resync previously cloned crates before running analysis
+    Remote(RemoteArgs),
+    /// Analyze crates locally
+    Local(LocalArgs),
+    /// Refresh the crates index if it's stale and cache the crates selected from it, then exit.
+    /// Later `remote` runs with the same selection options reuse the cached selection
+    FetchIndex(RemoteArgs),
+    /// Analyze local crates together with crates fetched like with `remote`
+    Combined {
+        #[clap(flatten)]
+        remote: RemoteArgs,
+        #[clap(flatten)]
+        local: LocalArgs,
+        /// Apply `--max-crates` to both sources together, instead of to each of them
         #[clap(long, default_value_t = false)]
-        git_resync_before: bool,
-
-        /// The number of git-clones (or refetches) that are allowed to run concurrently
-        #[clap(long, default_value = "2")]
-        git_sync_max_concurrent: NonZeroUsize,
+        max_crates_global: bool,
     },
-    /// Analyze crates locally
-    Local {
-        /// The path to search for crates to analyze in.
-        /// Should be a directory containing subdirectories with crates.
-        #[clap(long, short)]
-        path: PathBuf,
+    /// Check that required tools are installed, that the `rustfmt` repos are git repos,
+    /// and that the workdir is writable, then exit
+    Doctor,
+    /// Install the toolchains used by both `rustfmt` repos, then exit.
+    /// This also happens automatically before building `rustfmt`
+    Warmup,
+    /// Find the first commit in the local `rustfmt` repo that makes a crate diverge from upstream
+    #[clap(group(clap::ArgGroup::new("bisect_target").required(true)))]
+    Bisect {
+        /// The diverging crate's directory
+        #[clap(long, group = "bisect_target")]
+        crate_dir: Option<PathBuf>,
+        /// Bisect the first diverging crate in this `report.json` from a previous run
+        #[clap(long, group = "bisect_target")]
+        from_report: Option<PathBuf>,
+        /// A commit where the crate doesn't diverge, defaults to where the local repo
+        /// branched off of the upstream repo's `HEAD`
+        #[clap(long)]
+        good: Option<String>,
+        /// A commit where the crate diverges, defaults to `HEAD`
+        #[clap(long)]
+        bad: Option<String>,
     },
+    /// Remove cached data from the workdir, then exit
+    #[clap(group(clap::ArgGroup::new("clean_target").required(true).multiple(true)))]
+    Clean {
+        /// Remove cloned repositories
+        #[clap(long, group = "clean_target")]
+        clones: bool,
+        /// Remove the downloaded crates index (`crates.csv` and `versions.csv`)
+        #[clap(long, group = "clean_target")]
+        index: bool,
+        /// Remove everything in the workdir
+        #[clap(long, group = "clean_target")]
+        all: bool,
+        /// Only list what would be removed
+        #[clap(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Render the reports of a previous run again from its `report.json`, with the current
+    /// `--report-format`, `--report-filter`, and `--report-sort`, without analyzing anything.
+    /// Written next to the `report.json`, or to `--output-dir` if supplied
+    Render {
+        /// The `report.json` of the previous run
+        report: PathBuf,
+    },
+    /// Print a completion script for the supplied shell to stdout, then exit
+    Completions { shell: clap_complete::Shell },
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    const TWO: NonZeroUsize = NonZeroUsize::new(2).unwrap();
-    let args = Args::parse();
-    match args.verbosity {
-        0 => setup_tracing::<VerbosityNone>(),
-        1 => setup_tracing::<VerbosityLow>(),
-        2 => setup_tracing::<VerbosityNormal>(),
-        3 => setup_tracing::<VerbosityVery>(),
+    let args = require_workdir(Args::parse()).unwrap_or_else(|e| e.exit());
+    // Before setting up tracing, logs shouldn't end up in the script
+    if let Subcommand::Completions { shell } = args.command {
+        clap_complete::generate(
+            shell,
+            &mut Args::command(),
+            "meteoroid",
+            &mut std::io::stdout(),
+        );
+        return ExitCode::SUCCESS;
+    }
+    let tracing_setup = match args.verbosity {
+        0 => setup_tracing::<VerbosityNone>(&args),
+        1 => setup_tracing::<VerbosityLow>(&args),
+        2 => setup_tracing::<VerbosityNormal>(&args),
+        3 => setup_tracing::<VerbosityVery>(&args),
         unk => {
             eprintln!("unrecognized verbosity level: {unk}");
             return ExitCode::FAILURE;
         }
+    };
+    let _tracing_guard = match tracing_setup {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("failed to set up tracing: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Subcommand::Render { report } = &args.command {
+        return run_render(report.clone(), &args).await;
     }
-    let num_parallel = args
-        .analysis_max_concurrent
-        .unwrap_or_else(|| std::thread::available_parallelism().unwrap_or(TWO));
-    let opts = ConsumerOpts {
+    let workdir = args.workdir.clone().expect("checked by `require_workdir`");
+    let crate_source = match &args.command {
+        Subcommand::Remote(remote) => match remote.crate_source() {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::error!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        Subcommand::Local(local) => CrateSource::LocalCrates(local.local_crates_config()),
+        Subcommand::Combined {
+            remote,
+            local,
+            max_crates_global,
+        } => {
+            if remote.builtin_corpus {
+                tracing::error!("`--builtin-corpus` can't be combined with local crates");
+                return ExitCode::FAILURE;
+            }
+            if remote.stdin || !remote.repo_url.is_empty() {
+                tracing::error!("`--repo-url` and `--stdin` can't be combined with local crates");
+                return ExitCode::FAILURE;
+            }
+            let gs = match remote.git_sync_config() {
+                Ok(gs) => gs,
+                Err(e) => {
+                    tracing::error!("{e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            CrateSource::Combined {
+                local: local.local_crates_config(),
+                git: gs,
+                max_crates_global: *max_crates_global,
+            }
+        }
+        Subcommand::FetchIndex(remote) => return run_fetch_index(&workdir, &args, remote).await,
+        Subcommand::Doctor => return run_doctor(&workdir, &args).await,
+        Subcommand::Warmup => return run_warmup(workdir, &args).await,
+        Subcommand::Bisect { .. } => return run_bisect(workdir, args).await,
+        Subcommand::Clean {
+            clones,
+            index,
+            all,
+            dry_run,
+        } => {
+            let opts = CleanOpts {
+                clones: *clones,
+                index: *index,
+                all: *all,
+                dry_run: *dry_run,
+            };
+            return run_clean(workdir, opts).await;
+        }
+        Subcommand::Completions { .. } => unreachable!("completions are generated before this"),
+        Subcommand::Render { .. } => unreachable!("reports are rendered before this"),
+    };
+    if args.list_selected {
+        return list_selected(&workdir, &args, &crate_source).await;
+    }
+    run(workdir, args, crate_source).await
+}
+
+/// `--workdir` can't be required through clap alone, since it's taken before the subcommand and
+/// only `completions` and `render` go without it, this errors the same way clap would
+fn require_workdir(args: Args) -> Result<Args, clap::Error> {
+    let needs_workdir = !matches!(
+        args.command,
+        Subcommand::Completions { .. } | Subcommand::Render { .. }
+    );
+    if needs_workdir && args.workdir.is_none() {
+        return Err(Args::command().error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            "the following required arguments were not provided:\n  --workdir <WORKDIR>",
+        ));
+    }
+    Ok(args)
+}
+
+async fn list_selected(workdir: &Path, args: &Args, crate_source: &CrateSource) -> ExitCode {
+    let opts = match consumer_opts(args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            tracing::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let selected = match select_crates_in(workdir, crate_source, &opts).await {
+        Ok(selected) => selected,
+        Err(e) => {
+            tracing::error!("failed to select crates: {}", unpack(&e));
+            return ExitCode::FAILURE;
+        }
+    };
+    match serde_json::to_string_pretty(&selected) {
+        Ok(json) => {
+            println!("{json}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            tracing::error!("failed to serialize selected crates: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_doctor(workdir: &Path, args: &Args) -> ExitCode {
+    let cargo = CargoCommand {
+        bin: args.cargo_bin.clone(),
+        args: args.cargo_arg.clone(),
+    };
+    let checks = doctor(
+        workdir,
+        &cargo,
+        args.rustfmt_local_repo.as_deref(),
+        args.rustfmt_upstream_repo.as_deref(),
+    )
+    .await;
+    let mut all_passed = true;
+    for check in &checks {
+        match &check.outcome {
+            Ok(details) => println!("[pass] {}: {details}", check.name),
+            Err(e) => {
+                all_passed = false;
+                println!("[FAIL] {}: {e}", check.name);
+            }
+        }
+    }
+    if all_passed {
+        println!("all {} checks passed", checks.len());
+        ExitCode::SUCCESS
+    } else {
+        let failed = checks.iter().filter(|c| !c.passed()).count();
+        println!("{failed}/{} checks failed", checks.len());
+        ExitCode::FAILURE
+    }
+}
+
+async fn run_fetch_index(workdir: &Path, args: &Args, remote: &RemoteArgs) -> ExitCode {
+    if remote.builtin_corpus || remote.stdin || !remote.repo_url.is_empty() {
+        tracing::error!(
+            "`fetch-index` can't be combined with `--builtin-corpus`, `--repo-url`, or `--stdin`"
+        );
+        return ExitCode::FAILURE;
+    }
+    let config = remote
+        .git_sync_config()
+        .and_then(|gs| Ok((gs, consumer_opts(args)?)));
+    let (gs, opts) = match config {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match fetch_index(workdir, &gs, &opts).await {
+        Ok(selected) => {
+            tracing::info!("fetched the crates index, {selected} crates selected and cached");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            tracing::error!("failed to fetch the crates index: {}", unpack(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_render(report: PathBuf, args: &Args) -> ExitCode {
+    let opts = RenderOpts {
+        report,
+        output_dir: args.output_dir.clone(),
+        formats: args.report_format.iter().copied().map(Into::into).collect(),
+        filters: args.report_filter.iter().copied().map(Into::into).collect(),
+        sort: args.report_sort.into(),
+        open: args.open,
+    };
+    match render_report(opts).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("failed to render report: {}", unpack(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_warmup(workdir: PathBuf, args: &Args) -> ExitCode {
+    let (Some(rustfmt_local_repo), Some(rustfmt_upstream_repo)) = (
+        args.rustfmt_local_repo.as_deref(),
+        args.rustfmt_upstream_repo.as_deref(),
+    ) else {
+        tracing::error!(
+            "both `--rustfmt-local-repo` and `--rustfmt-upstream-repo` are required to warm up"
+        );
+        return ExitCode::FAILURE;
+    };
+    match warmup(workdir, rustfmt_local_repo, rustfmt_upstream_repo).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("failed to warm up toolchains: {}", unpack(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_bisect(workdir: PathBuf, args: Args) -> ExitCode {
+    let Subcommand::Bisect {
+        crate_dir,
+        from_report,
+        good,
+        bad,
+    } = args.command
+    else {
+        unreachable!("only called for the bisect subcommand");
+    };
+    let (Some(rustfmt_repo), Some(rustfmt_upstream_repo)) =
+        (args.rustfmt_local_repo, args.rustfmt_upstream_repo)
+    else {
+        tracing::error!(
+            "both `--rustfmt-local-repo` and `--rustfmt-upstream-repo` are required to bisect"
+        );
+        return ExitCode::FAILURE;
+    };
+    let crate_dir = match (crate_dir, from_report) {
+        (Some(crate_dir), _) => crate_dir,
+        (None, Some(report)) => match first_diverging_crate(&report) {
+            Ok(crate_dir) => crate_dir,
+            Err(e) => {
+                tracing::error!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    let config = BisectConfig {
+        workdir,
+        rustfmt_repo,
+        rustfmt_upstream_repo,
+        crate_dir,
+        good,
+        bad,
+        config: args.config,
+        unstable_features: args.unstable_features,
+        cargo: CargoCommand {
+            bin: args.cargo_bin,
+            args: args.cargo_arg,
+        },
+        timeout: std::time::Duration::from_secs(u64::from(
+            args.analysis_task_timeout_seconds.get(),
+        )),
+    };
+    match bisect(config).await {
+        Ok(outcome) => {
+            println!("first diverging commit: {}", outcome.summary);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            tracing::error!("bisect failed: {}", unpack(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_extra_repo(s: &str) -> Result<LabeledRustfmtRepo, String> {
+    let (label, repo) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<label>=<path>`, got '{s}'"))?;
+    if label.is_empty() || repo.is_empty() {
+        return Err(format!("expected `<label>=<path>`, got '{s}'"));
+    }
+    if label == "local" || label == "upstream" {
+        return Err(format!("label '{label}' is reserved"));
+    }
+    Ok(LabeledRustfmtRepo {
+        label: label.to_string(),
+        repo: PathBuf::from(repo),
+    })
+}
+
+fn parse_min_rust_version(s: &str) -> Result<semver::Version, String> {
+    meteoroid_lib::parse_rust_version(s).map_err(|e| unpack(&*e).to_string())
+}
+
+fn first_diverging_crate(report: &Path) -> Result<PathBuf, String> {
+    let content = std::fs::read(report)
+        .map_err(|e| format!("failed to read report at {}: {e}", report.display()))?;
+    let report_json: serde_json::Value = serde_json::from_slice(&content)
+        .map_err(|e| format!("failed to parse report at {}: {e}", report.display()))?;
+    report_json["crate_reports"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|cr| cr["diverged"].as_bool() == Some(true))
+        .and_then(|cr| cr["local_root"].as_str())
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("found no diverging crate in {}", report.display()))
+}
+
+async fn run_clean(workdir: PathBuf, opts: CleanOpts) -> ExitCode {
+    match clean(workdir, opts).await {
+        Ok(removed) => {
+            let verb = if opts.dry_run {
+                "would remove"
+            } else {
+                "removed"
+            };
+            for path in &removed {
+                println!("{verb} {}", path.display());
+            }
+            println!("{verb} {} entries", removed.len());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            tracing::error!("failed to clean workdir: {}", unpack(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn consumer_opts(args: &Args) -> Result<ConsumerOpts, String> {
+    let mut exclude_crate_name_contains = args.exclude_crate_name_contains.clone();
+    if let Some(denylist_file) = &args.denylist_file {
+        let denied = read_denylist(denylist_file).map_err(|e| unpack(&*e).to_string())?;
+        exclude_crate_name_contains.extend(denied);
+    }
+    let mut only_crate_names = std::collections::BTreeSet::new();
+    if let Some(allowlist_file) = &args.allowlist_file {
+        only_crate_names = read_allowlist(allowlist_file).map_err(|e| unpack(&*e).to_string())?;
+        // An empty allowlist would otherwise allow every crate
+        if only_crate_names.is_empty() {
+            return Err(format!(
+                "allowlist at {} has no crate names",
+                allowlist_file.display()
+            ));
+        }
+    }
+    Ok(ConsumerOpts {
         min_size: args.min_size,
         max_crates: args.max_crates,
-        exclude_crate_name_contains: args.exclude_crate_name_contains,
-        exclude_repository_contains: args.exclude_repository_contains,
+        only_crate_names,
+        exclude_crate_name_contains,
+        exclude_repository_contains: args.exclude_repository_contains.clone(),
+        exclude_license_contains: args.exclude_license.clone(),
+        include_license_contains: args.only_license.clone(),
+        include_keyword_contains: args.only_keyword.clone(),
+        include_category_contains: args.only_category.clone(),
+        only_kind: if args.only_lib {
+            Some(CrateKind::Lib)
+        } else if args.only_bin {
+            Some(CrateKind::Bin)
+        } else {
+            None
+        },
+        min_rust_version: args.min_rust_version.clone(),
+        require_rust_version: args.require_rust_version,
+        stratify_by_category: args.stratify_by_category,
+        max_per_org: args.max_per_org,
+        sample: args.sample_seed.map(|seed| SampleOpts {
+            seed,
+            size: args.sample_size.unwrap_or(args.max_crates),
+        }),
+        select_by: args.select_by.into(),
+    })
+}
+
+#[allow(clippy::too_many_lines)]
+async fn run(workdir: PathBuf, args: Args, crate_source: CrateSource) -> ExitCode {
+    const TWO: NonZeroUsize = NonZeroUsize::new(2).unwrap();
+    let opts = match consumer_opts(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            tracing::error!("{e}");
+            return ExitCode::FAILURE;
+        }
     };
+    let (Some(rustfmt_local_repo), Some(rustfmt_upstream_repo)) =
+        (args.rustfmt_local_repo, args.rustfmt_upstream_repo)
+    else {
+        tracing::error!(
+            "both `--rustfmt-local-repo` and `--rustfmt-upstream-repo` are required to analyze crates"
+        );
+        return ExitCode::FAILURE;
+    };
+    let mut labels = std::collections::HashSet::new();
+    if let Some(dup) = args
+        .rustfmt_extra_repo
+        .iter()
+        .find(|extra| !labels.insert(extra.label.as_str()))
+    {
+        tracing::error!("`--rustfmt-extra-repo` label '{}' is used twice", dup.label);
+        return ExitCode::FAILURE;
+    }
+    let num_parallel = args
+        .analysis_max_concurrent
+        .unwrap_or_else(|| std::thread::available_parallelism().unwrap_or(TWO));
     let (stop_send, stop_recv) = stop_channel();
     let config = MeteroidConfig {
-        workdir: args.workdir,
+        workdir,
         output_dir: args.output_dir,
-        crate_source: match args.command {
-            Subcommand::Remote {
-                crates_index_max_age,
-                git_resync_before,
-                git_sync_max_concurrent,
-            } => CrateSource::GitSync(GitSyncConfig {
-                crates_index_max_age_days: crates_index_max_age,
-                git_resync_before,
-                git_clone_max_concurrent: git_sync_max_concurrent,
-            }),
-            Subcommand::Local { path } => {
-                CrateSource::LocalCrates(LocalCratesConfig { crate_dir: path })
-            }
-        },
+        crate_source,
         consumer_opts: opts,
         analyze_args: AnalyzeArgs {
-            rustfmt_repo: args.rustfmt_local_repo,
-            rustfmt_upstream_repo: args.rustfmt_upstream_repo,
+            rustfmt_repo: rustfmt_local_repo,
+            rustfmt_upstream_repo,
             report_dest: args.report_dest,
+            report_formats: args.report_format.into_iter().map(Into::into).collect(),
+            report_filters: args.report_filter.into_iter().map(Into::into).collect(),
+            report_sort: args.report_sort.into(),
             config: args.config,
             write_outputs: !args.no_output_files,
             skip_non_diverging_diffs: args.skip_non_diverging_diffs,
+            write_repros: args.write_repros,
+            recheck_divergences: args.recheck_divergences,
+            bisect_config: args.bisect_config,
             diff_tool: args.meteoroid_diff_tool,
+            extra_rustfmt_repos: args.rustfmt_extra_repo,
+            open_report: args.open,
+            compare_formatted_output: args.compare_formatted_output,
+            max_file_bytes: args.max_file_bytes,
+            unstable_features: args.unstable_features,
+            respect_crate_config: args.respect_crate_config,
+            ignore_whitespace_diffs: args.ignore_whitespace_diffs,
+            analysis_retries: args.analysis_retries,
+            checkpoint: args.checkpoint,
+            output_layout: args.output_layout.into(),
+            prebuilt_rustfmt: None,
+            error_comparator: Arc::new(LevenshteinComparator),
+            cargo: CargoCommand {
+                bin: args.cargo_bin,
+                args: args.cargo_arg,
+            },
         },
         analysis_max_concurrent: num_parallel,
+        result_buffer: args.result_buffer,
+        ordered_output: args.ordered_output,
         analysis_timeout: std::time::Duration::from_secs(u64::from(
             args.analysis_task_timeout_seconds.get(),
         )),
+        analysis_timeout_scaling: args.analysis_timeout_per_mib_seconds.map(|per_mib| {
+            TimeoutScaling {
+                per_mib: std::time::Duration::from_secs(u64::from(per_mib.get())),
+                max: std::time::Duration::from_secs(u64::from(
+                    args.analysis_timeout_max_seconds.get(),
+                )),
+            }
+        }),
+        notify: args.notify_webhook.map(|webhook| NotifyOpts {
+            webhook,
+            always: args.notify_always,
+        }),
+        setup_failure_policy: if args.keep_going {
+            SetupFailurePolicy::KeepGoing
+        } else {
+            SetupFailurePolicy::FailFast
+        },
+        #[cfg(feature = "s3")]
+        s3_upload: args.s3_upload.map(|bucket| meteoroid_lib::S3UploadOpts {
+            bucket,
+            prefix: args.s3_prefix,
+            endpoint: args.s3_endpoint,
+        }),
+        #[cfg(feature = "metrics")]
+        metrics_addr: args.metrics_addr,
         stop_receiver: stop_recv,
     };
+    run_until_done_or_stopped(config, stop_send).await
+}
+
+async fn run_until_done_or_stopped(config: MeteroidConfig, stop_send: StopSender) -> ExitCode {
     let mut meteoroid_task = tokio::task::spawn(meteoroid_lib::meteoroid(config));
     let mut stop_send = Some(stop_send);
 
@@ -182,7 +1165,7 @@ async fn main() -> ExitCode {
                         break ExitCode::SUCCESS;
                     }
                     Ok(Err(e)) => {
-                        eprintln!("meteoroid run failed: {}", unpack(&*e));
+                        eprintln!("meteoroid run failed: {}", unpack(&e));
                         break ExitCode::FAILURE;
                     }
                     Err(e) => {
@@ -205,10 +1188,137 @@ async fn main() -> ExitCode {
     }
 }
 
-fn setup_tracing<V: VerbosityFilter>() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_filter(LogFilter::<V>::new()))
-        .init();
+fn setup_tracing<V: VerbosityFilter>(
+    args: &Args,
+) -> Result<TracingGuard, Box<dyn std::error::Error + Send + Sync>> {
+    let (subscriber, guard) = build_subscriber::<V>(args)?;
+    subscriber.init();
+    Ok(guard)
+}
+
+/// The subscriber `setup_tracing` installs, the guard has to outlive it
+fn build_subscriber<V: VerbosityFilter>(
+    args: &Args,
+) -> Result<
+    (impl Subscriber + Send + Sync + 'static, TracingGuard),
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let mut guard = TracingGuard::default();
+    let fmt_layer = if let Some(directives) = args.log_filter.clone() {
+        fmt_layer(args).with_filter(directives).boxed()
+    } else {
+        fmt_layer(args).with_filter(LogFilter::<V>::new()).boxed()
+    };
+    let registry = tracing_subscriber::registry().with(fmt_layer);
+    #[cfg(feature = "otel")]
+    let registry = {
+        let otel_layer = if let Some(endpoint) = args.otel_endpoint.as_deref() {
+            let (layer, provider) = otel::otel_layer(endpoint)?;
+            guard.otel_provider = Some(provider);
+            Some(layer.with_filter(LogFilter::<VerbosityLow>::new()))
+        } else {
+            None
+        };
+        registry.with(otel_layer)
+    };
+    let chrome_layer = if let Some(trace_file) = args.trace_file.as_deref() {
+        let file = std::fs::File::create(trace_file).map_err(|e| {
+            format!(
+                "failed to create trace file at {}: {e}",
+                trace_file.display()
+            )
+        })?;
+        let (layer, flush_guard) = ChromeLayerBuilder::new()
+            .writer(file)
+            .include_args(true)
+            .trace_style(TraceStyle::Async)
+            .build();
+        guard.chrome_flush_guard = Some(flush_guard);
+        Some(layer.with_filter(LogFilter::<VerbosityLow>::new()))
+    } else {
+        None
+    };
+    Ok((registry.with(chrome_layer), guard))
+}
+
+/// When to color log lines, see <https://no-color.org>
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and `NO_COLOR` isn't set
+    Auto,
+    /// Color even when piped or redirected, or when `NO_COLOR` is set
+    Always,
+    /// Plain text only
+    Never,
+}
+
+impl ColorChoice {
+    /// `no_color` is the value of the `NO_COLOR` env var, which only counts when not empty
+    fn enabled(self, is_terminal: bool, no_color: Option<&OsStr>) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => is_terminal && no_color.is_none_or(OsStr::is_empty),
+        }
+    }
+}
+
+/// Logs go to stdout, unless it's kept for output meant to be piped
+fn fmt_layer<S>(args: &Args) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let (writer, is_terminal) = if args.list_selected {
+        (
+            BoxMakeWriter::new(std::io::stderr),
+            std::io::stderr().is_terminal(),
+        )
+    } else {
+        (
+            BoxMakeWriter::new(std::io::stdout),
+            std::io::stdout().is_terminal(),
+        )
+    };
+    formatted_layer(args, writer, is_terminal)
+}
+
+fn formatted_layer<S>(
+    args: &Args,
+    writer: BoxMakeWriter,
+    is_terminal: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let ansi = args
+        .color
+        .enabled(is_terminal, std::env::var_os("NO_COLOR").as_deref());
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match args.log_format {
+        LogFormatArg::Pretty => layer.boxed(),
+        LogFormatArg::Json => layer.json().boxed(),
+    }
+}
+
+/// Flushes any buffered telemetry when dropped
+#[derive(Default)]
+struct TracingGuard {
+    chrome_flush_guard: Option<FlushGuard>,
+    #[cfg(feature = "otel")]
+    otel_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        // Dropping flushes
+        self.chrome_flush_guard.take();
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.otel_provider.take() {
+            otel::shutdown(&provider);
+        }
+    }
 }
 
 trait VerbosityFilter: Send + Sync + 'static {
@@ -277,3 +1387,155 @@ where
         meta.level() < &Level::INFO
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(extra: &[&str]) -> Args {
+        let mut argv = vec!["meteoroid", "--workdir", "unused"];
+        argv.extend_from_slice(extra);
+        argv.push("doctor");
+        Args::parse_from(argv)
+    }
+
+    /// Whether spans made under the built subscriber get an OpenTelemetry span context
+    #[cfg(feature = "otel")]
+    fn exports_spans(args: &Args) -> bool {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let (subscriber, _guard) = build_subscriber::<VerbosityLow>(args).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(target: "meteoroid_lib", "fetch_index");
+            span.context().span().span_context().is_valid()
+        })
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn otel_layer_is_only_installed_with_an_endpoint() {
+        // Nothing listens there, exporting on shutdown fails without affecting the test
+        let endpoint = "http://127.0.0.1:1/v1/traces";
+        assert!(exports_spans(&args(&["--otel-endpoint", endpoint])));
+        assert!(!exports_spans(&args(&[])));
+    }
+
+    /// What the fmt layer writes for a single warning
+    fn formatted_warning(args: &Args) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let file = std::fs::File::create(&log).unwrap();
+        let writer = BoxMakeWriter::new(std::sync::Mutex::new(file));
+        let subscriber = tracing_subscriber::registry().with(formatted_layer(args, writer, false));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(target: "meteoroid_lib", "fixture warning");
+        });
+        std::fs::read_to_string(log).unwrap()
+    }
+
+    #[test]
+    fn json_log_format_writes_json_lines() {
+        let json = formatted_warning(&args(&["--log-format", "json"]));
+        let line: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+        assert_eq!("WARN", line["level"]);
+        assert_eq!("fixture warning", line["fields"]["message"]);
+        let pretty = formatted_warning(&args(&[]));
+        assert!(pretty.contains("fixture warning"), "{pretty}");
+        assert!(serde_json::from_str::<serde_json::Value>(pretty.trim()).is_err());
+    }
+
+    #[test]
+    fn workdir_is_required_unless_not_used() {
+        let parse = |argv: &[&str]| require_workdir(Args::try_parse_from(argv).unwrap());
+        let err = parse(&["meteoroid", "doctor"]).unwrap_err();
+        assert_eq!(clap::error::ErrorKind::MissingRequiredArgument, err.kind());
+        assert_eq!(2, err.exit_code());
+        parse(&["meteoroid", "completions", "bash"]).unwrap();
+        parse(&["meteoroid", "render", "report.json"]).unwrap();
+        parse(&["meteoroid", "--workdir", "wd", "doctor"]).unwrap();
+    }
+
+    #[test]
+    fn completions_generate_for_every_shell() {
+        use clap::ValueEnum;
+        for shell in clap_complete::Shell::value_variants() {
+            let mut script = Vec::new();
+            clap_complete::generate(*shell, &mut Args::command(), "meteoroid", &mut script);
+            assert!(!script.is_empty(), "no completions for {shell}");
+        }
+    }
+
+    #[test]
+    fn trace_file_has_the_spans_of_a_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace_file = dir.path().join("trace.json");
+        let args = args(&["--trace-file", trace_file.to_str().unwrap()]);
+        let (subscriber, guard) = build_subscriber::<VerbosityLow>(&args).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!(target: "meteoroid_lib", "fetch_index").entered();
+        });
+        // Flushes the trace
+        drop(guard);
+        let trace = std::fs::read(&trace_file).unwrap();
+        let events: Vec<serde_json::Value> = serde_json::from_slice(&trace).unwrap();
+        assert!(
+            events.iter().any(|event| event["name"] == "fetch_index"),
+            "no span events in {events:?}"
+        );
+    }
+
+    #[test]
+    fn denylist_file_is_merged_with_the_cli_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        let denylist = dir.path().join("denylist");
+        std::fs::write(&denylist, "# curated\nsys\n\nbindings # generated\n").unwrap();
+        let opts = consumer_opts(&args(&[
+            "--exclude-crate-name-contains",
+            "test",
+            "--denylist-file",
+            denylist.to_str().unwrap(),
+        ]))
+        .unwrap();
+        assert_eq!(
+            ["test", "sys", "bindings"],
+            opts.exclude_crate_name_contains.as_slice()
+        );
+    }
+
+    #[test]
+    fn allowlist_file_restricts_selection_unless_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let allowlist = dir.path().join("allowlist");
+        std::fs::write(&allowlist, "# curated\nserde\n\nanyhow # errors\n").unwrap();
+        let opts =
+            consumer_opts(&args(&["--allowlist-file", allowlist.to_str().unwrap()])).unwrap();
+        assert_eq!(
+            std::collections::BTreeSet::from(["anyhow".to_string(), "serde".to_string()]),
+            opts.only_crate_names
+        );
+        std::fs::write(&allowlist, "# nothing yet\n").unwrap();
+        let err =
+            consumer_opts(&args(&["--allowlist-file", allowlist.to_str().unwrap()])).unwrap_err();
+        assert!(err.contains("has no crate names"), "{err}");
+    }
+
+    #[test]
+    fn auto_colors_only_terminals_without_no_color() {
+        assert!(ColorChoice::Auto.enabled(true, None));
+        assert!(!ColorChoice::Auto.enabled(false, None));
+        assert!(!ColorChoice::Auto.enabled(true, Some(OsStr::new("1"))));
+        // An empty `NO_COLOR` doesn't count
+        assert!(ColorChoice::Auto.enabled(true, Some(OsStr::new(""))));
+    }
+
+    #[test]
+    fn never_suppresses_color() {
+        assert!(!ColorChoice::Never.enabled(true, None));
+        assert!(!ColorChoice::Never.enabled(false, None));
+    }
+
+    #[test]
+    fn always_overrides_no_color() {
+        assert!(ColorChoice::Always.enabled(false, Some(OsStr::new("1"))));
+    }
+}
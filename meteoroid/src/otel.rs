@@ -0,0 +1,33 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Subscriber;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Exports spans over OTLP/HTTP to `endpoint`, ex: `http://localhost:4318/v1/traces`
+pub(crate) fn otel_layer<S>(
+    endpoint: &str,
+) -> Result<(impl Layer<S>, SdkTracerProvider), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("meteoroid").build())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("meteoroid"));
+    Ok((layer, provider))
+}
+
+/// Flushes remaining spans, blocks until the exporter is done
+pub(crate) fn shutdown(provider: &SdkTracerProvider) {
+    if let Err(e) = provider.shutdown() {
+        eprintln!("failed to shut down opentelemetry exporter: {e}");
+    }
+}